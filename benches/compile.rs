@@ -0,0 +1,35 @@
+//! Benchmarks of building large scripts, run with `cargo bench`. An argument only runs the
+//! inputs whose names contain it, as in `cargo bench -- sql`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gel_lang::{
+    compile_str,
+    testing::{huge_sql, long_script, nested_dicts},
+    CompileOptions,
+};
+
+fn compile(c: &mut Criterion) {
+    let inputs = [
+        ("nested dicts", nested_dicts(64)),
+        ("long script", long_script(1000)),
+        ("huge sql", huge_sql(2000)),
+    ];
+
+    let options = CompileOptions::default();
+    let mut group = c.benchmark_group("compile");
+    for (name, source) in &inputs {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), source, |b, source| {
+            b.iter(|| {
+                compile_str(black_box(source), &options)
+                    .unwrap_or_else(|err| panic!("{name} doesn't build: {err:?}"))
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, compile);
+criterion_main!(benches);
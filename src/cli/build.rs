@@ -0,0 +1,564 @@
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    fmt::Write as _,
+    io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use miette::{IntoDiagnostic, NamedSource};
+use tracing::span;
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    fmt::format::FmtSpan,
+    layer::{Context, SubscriberExt},
+    registry::LookupSpan,
+    Layer,
+};
+
+use super::Args;
+use crate::{
+    decode, errors,
+    gelatin::{
+        self,
+        dce::{self, Removal},
+        hoist::{self, Hoisting},
+        intern, secrets, types, Parser,
+    },
+    log_statements, parse, read_snippet, read_source, scripts_in, transpile, Expanders, Layout,
+    Level, TranspileOptions, Warning, WarningCode,
+};
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Emit {
+    /// The tree of pairs pest parses the script into.
+    Tokens,
+    /// The ast, before its macros are expanded.
+    Ast,
+    /// The ast, after its macros are expanded.
+    ExpandedAst,
+    /// The script.
+    #[default]
+    Xml,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    /// The custom script step of a process XOG document, ready to import.
+    Process,
+}
+
+/// A script to build and where to write it.
+#[derive(Debug, Clone)]
+pub struct Build {
+    /// The script, stdin if `-`.
+    pub file: PathBuf,
+    /// The file name to report diagnostics at instead of the path.
+    pub name: Option<String>,
+    /// The file to write the script to, stdout if `None`.
+    pub output: Option<PathBuf>,
+}
+
+impl Args {
+    /// The scripts to build, directories are searched for them recursively.
+    ///
+    /// # Errors
+    /// Returns `Err` if a directory can't be read, or there are several scripts and no
+    /// `--out-dir`.
+    pub fn builds(&self) -> miette::Result<Vec<Build>> {
+        let Some(ref out_dir) = self.out_dir else {
+            return match self.files.as_slice() {
+                [file] if !file.is_dir() => Ok(vec![Build {
+                    file: file.clone(),
+                    name: self.name_of(file),
+                    output: self.output.clone(),
+                }]),
+                _ => Err(errors::Error::NoOutDir.into()),
+            };
+        };
+
+        let mut builds = vec![];
+        for input in &self.files {
+            if input.is_dir() {
+                for file in scripts_in(input).into_diagnostic()? {
+                    let relative = file.strip_prefix(input).unwrap_or(&file);
+                    let output = out_dir.join(relative).with_extension("xml");
+                    builds.push(Build {
+                        file,
+                        name: None,
+                        output: Some(output),
+                    });
+                }
+            } else {
+                let output = match self.name {
+                    _ if input != Path::new("-") => input.file_name().unwrap_or(input.as_os_str()),
+                    Some(ref name) => OsStr::new(name),
+                    None => OsStr::new("stdin"),
+                };
+                builds.push(Build {
+                    file: input.clone(),
+                    name: self.name_of(input),
+                    output: Some(out_dir.join(output).with_extension("xml")),
+                });
+            }
+        }
+
+        Ok(builds)
+    }
+
+    /// The name to report the diagnostics of the script at `file` at, if it's stdin.
+    fn name_of(&self, file: &Path) -> Option<String> {
+        (file == Path::new("-")).then(|| self.name.clone().unwrap_or_else(|| "<stdin>".into()))
+    }
+
+    /// Builds the scripts, a failing one doesn't stop the rest when there are several.
+    /// Several scripts are built in parallel, the diagnostics of each of them are printed
+    /// together in the order of the scripts.
+    ///
+    /// # Errors
+    /// Returns `Err` if a script can't be built.
+    pub fn run(&self) -> miette::Result<()> {
+        let builds = self.builds()?;
+        let started = Instant::now();
+        let times = TimeReport::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(
+                self.time_report
+                    .then(|| times.clone().with_filter(crate_targets(LevelFilter::TRACE))),
+            )
+            .with(self.log());
+
+        let result = tracing::subscriber::with_default(subscriber, || self.build_each(&builds));
+
+        if self.time_report {
+            times.lock().elapsed = started.elapsed();
+            eprint!("{}", times.lock());
+        }
+        result
+    }
+
+    /// The layer logging the phases of the builds to stderr with `--verbose`, with how
+    /// long they took once they end. `-vv` also logs how many statements the script has
+    /// after each of them.
+    fn log<S>(&self) -> Option<impl Layer<S>>
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let level = match self.verbose {
+            0 => return None,
+            1 => LevelFilter::INFO,
+            _ => LevelFilter::DEBUG,
+        };
+        let layer = tracing_subscriber::fmt::layer()
+            .with_writer(io::stderr)
+            .event_format(
+                tracing_subscriber::fmt::format()
+                    .with_target(false)
+                    .without_time(),
+            )
+            .with_span_events(FmtSpan::CLOSE)
+            .with_filter(crate_targets(level));
+        Some(layer)
+    }
+
+    /// Builds `builds`, printing the diagnostics of each of them.
+    fn build_each(&self, builds: &[Build]) -> miette::Result<()> {
+        if let [build] = builds {
+            self.build_into(build, &mut |warnings| eprint!("{warnings}"))
+        } else {
+            let mut failed = 0;
+            for (diagnostics, result) in self.build_all(builds) {
+                eprint!("{diagnostics}");
+                if let Err(err) = result {
+                    eprintln!("{err:?}");
+                    failed += 1;
+                }
+            }
+
+            if failed > 0 {
+                Err(errors::Error::Failed {
+                    failed,
+                    total: builds.len(),
+                }
+                .into())
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    /// Builds `builds` on `--jobs` threads, returning the warnings and the result of each
+    /// of them in their order. The phases are traced to the subscriber of the caller.
+    fn build_all(&self, builds: &[Build]) -> Vec<(String, miette::Result<()>)> {
+        let jobs = self
+            .jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+            .min(builds.len());
+        let next = AtomicUsize::new(0);
+        let dispatch = tracing::dispatcher::get_default(Clone::clone);
+
+        let mut results: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..jobs)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut results = vec![];
+                        tracing::dispatcher::with_default(&dispatch, || loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(build) = builds.get(i) else {
+                                break results;
+                            };
+                            let mut diagnostics = String::new();
+                            let result = self.build_into(build, &mut |warnings| {
+                                diagnostics.push_str(warnings);
+                            });
+                            results.push((i, diagnostics, result));
+                        })
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("builds don't panic"))
+                .collect()
+        });
+
+        results.sort_by_key(|(i, ..)| *i);
+        results
+            .into_iter()
+            .map(|(_, diagnostics, result)| (diagnostics, result))
+            .collect()
+    }
+
+    /// Builds the script of `build`, reporting its warnings.
+    ///
+    /// # Errors
+    /// Returns `Err` if the script can't be read, parsed or written.
+    pub fn build(&self, build: &Build) -> miette::Result<()> {
+        self.build_into(build, &mut |warnings| eprint!("{warnings}"))
+    }
+
+    /// Builds the script of `build`, passing its rendered warnings to `report` before
+    /// writing it. Its phases run in `tracing` spans, within a `build` one.
+    fn build_into(&self, build: &Build, report: &mut dyn FnMut(&str)) -> miette::Result<()> {
+        let _names = intern::Scope::enter();
+        let file_name = build.file_name().to_string();
+        let _build = tracing::info_span!("build", file = file_name).entered();
+        let source = build.read_source()?;
+
+        let nodes = match self.emit {
+            Emit::Tokens => {
+                let tokens = gelatin::tokens(&file_name, &source)?;
+                return build.write(&tokens);
+            }
+            Emit::Ast => {
+                let mut parser =
+                    Parser::new(&file_name, &source, self.runtime.dialect.to_dialect());
+                let nodes = parser.parse()?;
+                let mut warnings = parser.take_warnings();
+                warnings.extend(types::check(&nodes));
+                warnings.extend(secrets::check(&nodes));
+                self.render_warnings(&file_name, &source, warnings, report)?;
+                return build.write(&format!("{nodes:#?}\n"));
+            }
+            Emit::ExpandedAst | Emit::Xml => {
+                let (mut nodes, mut warnings) = parse(
+                    &file_name,
+                    &source,
+                    self.runtime.dialect,
+                    &Expanders::default(),
+                )?;
+                tracing::info_span!("lint")
+                    .in_scope(|| warnings.extend(self.lints.check_attributes(&nodes)));
+                self.render_warnings(&file_name, &source, warnings, report)?;
+
+                let optimization = (self.eliminate_dead_code || self.hoist_repeated_expressions)
+                    .then(|| tracing::info_span!("optimization").entered());
+                if self.eliminate_dead_code {
+                    let mut removed = String::new();
+                    for Removal { at, what } in dce::eliminate(&mut nodes) {
+                        let _ = writeln!(
+                            removed,
+                            "{file_name}:{}:{}: removed {what}",
+                            at.line, at.col
+                        );
+                    }
+                    report(&removed);
+                }
+                if self.hoist_repeated_expressions {
+                    let mut hoisted = String::new();
+                    for Hoisting {
+                        at,
+                        name,
+                        expr,
+                        count,
+                    } in hoist::hoist(&mut nodes)
+                    {
+                        let _ = writeln!(
+                            hoisted,
+                            "{file_name}:{}:{}: hoisted `${{{expr}}}`, written {count} times in a loop, into `{name}`",
+                            at.line, at.col
+                        );
+                    }
+                    report(&hoisted);
+                }
+                if optimization.is_some() {
+                    log_statements(&nodes);
+                }
+                nodes
+            }
+        };
+        if self.emit == Emit::ExpandedAst {
+            return build.write(&format!("{nodes:#?}\n"));
+        }
+
+        let prologue = self
+            .prologue
+            .as_deref()
+            .map(|path| read_snippet(path, self.runtime.dialect))
+            .transpose()?;
+        let epilogue = self
+            .epilogue
+            .as_deref()
+            .map(|path| read_snippet(path, self.runtime.dialect))
+            .transpose()?;
+        let classes = self.runtime.classes()?;
+        let options = TranspileOptions {
+            layout: self.layout(),
+            provenance: self.provenance.then_some(file_name.as_str()),
+            source_map: self.source_map.then_some(file_name.as_str()),
+            namespaces: &self.namespace,
+            sql_format: self.sql_format,
+            target: self.target,
+            platform: self.runtime.platform,
+            classes: classes.as_ref(),
+            process: self.process(),
+            prologue: prologue.as_ref(),
+            epilogue: epilogue.as_ref(),
+        };
+        let mut writer = build.writer().into_diagnostic()?;
+        tracing::info_span!("emission")
+            .in_scope(|| transpile(nodes, &mut writer, &options))
+            .map_err(|err| err.with_source_code(NamedSource::new(&file_name, source)))?;
+        Ok(())
+    }
+
+    /// How the xml of the scripts is laid out.
+    #[must_use]
+    pub fn layout(&self) -> Layout {
+        let indent = self.indent.map(|spaces| " ".repeat(spaces));
+        Layout {
+            prettify: self.prettify || indent.is_some() || self.attrs_per_line,
+            indent: indent.unwrap_or_else(|| Layout::default().indent),
+            line_ending: self.line_ending,
+            attrs_per_line: self.attrs_per_line,
+            escape_cdata: self.escape_cdata,
+        }
+    }
+
+    /// The code of the process the script is wrapped in, if it is.
+    #[must_use]
+    pub fn process(&self) -> Option<&str> {
+        match self.wrap {
+            Some(Wrap::Process) => self.process_code.as_deref(),
+            None => None,
+        }
+    }
+
+    /// The level `code` was given on the command line, `--deny` wins over `--warn`,
+    /// which wins over `--allow`.
+    #[must_use]
+    pub fn level(&self, code: WarningCode) -> Level {
+        self.lints.level(code)
+    }
+
+    /// Prints the `warnings` in `source` that aren't allowed to stderr.
+    ///
+    /// # Errors
+    /// Returns `Err` if any of them is denied.
+    pub fn report_warnings(
+        &self,
+        file_name: &str,
+        source: &str,
+        warnings: Vec<Warning>,
+    ) -> miette::Result<()> {
+        self.render_warnings(file_name, source, warnings, &mut |warnings| {
+            eprint!("{warnings}");
+        })
+    }
+
+    /// Like [`Self::report_warnings`], but the rendered warnings are passed to `report`.
+    fn render_warnings(
+        &self,
+        file_name: &str,
+        source: &str,
+        warnings: Vec<Warning>,
+        report: &mut dyn FnMut(&str),
+    ) -> miette::Result<()> {
+        let mut diagnostics = String::new();
+        let denied = self
+            .lints
+            .render(warnings, file_name, source, &mut diagnostics);
+        report(&diagnostics);
+
+        if denied > 0 {
+            return Err(errors::Error::DeniedWarnings { count: denied }.into());
+        }
+
+        Ok(())
+    }
+}
+
+impl Build {
+    /// The input name for display, invalid utf-8 in its path is replaced.
+    #[must_use]
+    pub fn file_name(&self) -> Cow<'_, str> {
+        self.name
+            .as_deref()
+            .map_or_else(|| self.file.to_string_lossy(), Cow::Borrowed)
+    }
+
+    /// # Errors
+    /// Returns `Err` if the input cannot be read or isn't valid utf-8.
+    pub fn read_source(&self) -> miette::Result<String> {
+        if self.file != Path::new("-") {
+            return read_source(&self.file);
+        }
+
+        let mut bytes = vec![];
+        io::Read::read_to_end(&mut io::stdin().lock(), &mut bytes).into_diagnostic()?;
+        decode(&self.file_name(), bytes)
+    }
+
+    /// Writes `output` to the output of the build.
+    ///
+    /// # Errors
+    /// Returns `Err` if the output file can't be written.
+    pub fn write(&self, output: &str) -> miette::Result<()> {
+        let mut writer = self.writer().into_diagnostic()?;
+        io::Write::write_all(&mut writer, output.as_bytes()).into_diagnostic()
+    }
+
+    /// # Errors
+    /// Returns `Err` if the output file or its directory cannot be created.
+    pub fn writer(&self) -> io::Result<io::BufWriter<Box<dyn io::Write>>> {
+        if let Some(ref output) = self.output {
+            if let Some(dir) = output.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let output = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(output)?;
+            Ok(io::BufWriter::new(Box::new(output)))
+        } else {
+            let stdout = std::io::stdout();
+            let stdout = stdout.lock();
+            Ok(io::BufWriter::new(Box::new(stdout)))
+        }
+    }
+}
+
+impl std::fmt::Display for Emit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+/// The spans and events of this crate up to `level`, ignoring those of its dependencies.
+fn crate_targets(level: LevelFilter) -> Targets {
+    Targets::new().with_target(env!("CARGO_CRATE_NAME"), level)
+}
+
+/// The layer adding up how long the spans of each phase took over all the scripts built,
+/// printed with `--time-report`. Its clones share the times.
+#[derive(Clone, Default)]
+struct TimeReport(Arc<Mutex<Times>>);
+
+#[derive(Default)]
+struct Times {
+    scripts: usize,
+    /// How long the phases took, in the order they first ran.
+    phases: Vec<(&'static str, Duration)>,
+    /// How long building all the scripts took.
+    elapsed: Duration,
+}
+
+/// When a span started, kept in its extensions.
+struct Started(Instant);
+
+impl TimeReport {
+    fn lock(&self) -> MutexGuard<'_, Times> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<S> Layer<S> for TimeReport
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(Started(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+        let Some(took) = span
+            .extensions()
+            .get::<Started>()
+            .map(|Started(started)| started.elapsed())
+        else {
+            return;
+        };
+
+        let mut times = self.lock();
+        match span.name() {
+            "build" => times.scripts += 1,
+            name => match times.phases.iter_mut().find(|(phase, _)| *phase == name) {
+                Some((_, total)) => *total += took,
+                None => times.phases.push((name, took)),
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Times {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total: Duration = self.phases.iter().map(|(_, took)| *took).sum();
+        let scripts = if self.scripts == 1 {
+            "script"
+        } else {
+            "scripts"
+        };
+        writeln!(
+            f,
+            "built {} {scripts} in {:.2?}, {total:.2?} over all the phases:",
+            self.scripts, self.elapsed
+        )?;
+        for (name, took) in &self.phases {
+            let share = took.as_secs_f64() / total.as_secs_f64().max(f64::EPSILON) * 100.0;
+            writeln!(
+                f,
+                "  {name:<12} {:>10} {share:>5.1}%",
+                format!("{took:.2?}")
+            )?;
+        }
+        Ok(())
+    }
+}
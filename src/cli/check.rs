@@ -0,0 +1,191 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use miette::NamedSource;
+
+use super::{expand_dirs, Lints, Runtime};
+use crate::{
+    errors,
+    gelatin::{Import, Parser},
+    parse, read_source,
+    transpiler::{Backend, Transpiler},
+    Expanders, Layout, Target, Warning,
+};
+
+#[derive(Debug, clap::Args)]
+pub struct CheckArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// The kind of script to check for.
+    #[arg(long, default_value_t = Target::Gel)]
+    pub target: Target,
+
+    /// Checks the files as the scripts of a project, directories included, also reporting
+    /// the functions and templates none of them call and the imports whose templates aren't
+    /// called.
+    #[arg(long)]
+    pub project: bool,
+
+    #[command(flatten)]
+    pub runtime: Runtime,
+
+    #[command(flatten)]
+    pub lints: Lints,
+}
+
+impl CheckArgs {
+    /// Checks the files, printing the errors and warnings of each of them to stderr.
+    ///
+    /// # Errors
+    /// Returns `Err` if any of them has errors or denied warnings.
+    pub fn run(&self) -> miette::Result<()> {
+        let files = if self.project {
+            expand_dirs(&self.files)?
+        } else {
+            self.files.clone()
+        };
+
+        let mut failed = 0;
+        for file in &files {
+            if let Err(err) = self.check(file) {
+                eprintln!("{err:?}");
+                failed += 1;
+            }
+        }
+        let denied = if self.project {
+            self.check_project(&files)
+        } else {
+            0
+        };
+
+        if failed > 0 {
+            return Err(errors::Error::Failed {
+                failed,
+                total: files.len(),
+            }
+            .into());
+        }
+        if denied > 0 {
+            return Err(errors::Error::DeniedWarnings { count: denied }.into());
+        }
+        Ok(())
+    }
+
+    /// Reports the functions and templates no script of `files` calls, and the imports
+    /// whose templates they don't call, returning how many of them are denied. The
+    /// scripts with errors are left out, [`Self::check`] reports them.
+    fn check_project(&self, files: &[PathBuf]) -> usize {
+        let mut scripts = vec![];
+        for file in files {
+            let Ok(source) = read_source(file) else {
+                continue;
+            };
+            let file_name = file.to_string_lossy();
+            let mut parser = Parser::new(&file_name, &source, self.runtime.dialect.to_dialect());
+            if parser.parse().is_ok() {
+                let usage = parser.usage().clone();
+                scripts.push((file, source, usage));
+            }
+        }
+
+        // the templates some script calls, by the file defining them
+        let mut called = HashSet::new();
+        for (file, _, usage) in &scripts {
+            let path = canonical(file);
+            for template in usage.templates.iter().filter(|t| t.is_called()) {
+                called.insert((path.clone(), template.name.to_string()));
+            }
+            for import in &usage.imports {
+                called_templates(import, &mut called);
+            }
+        }
+
+        let mut denied = 0;
+        for (file, source, usage) in scripts {
+            let path = canonical(file);
+            let functions = usage
+                .functions
+                .iter()
+                .filter(|function| !function.is_called())
+                .map(|function| ("function", &function.name));
+            let templates = usage
+                .templates
+                .iter()
+                .filter(|template| !called.contains(&(path.clone(), template.name.to_string())))
+                .map(|template| ("template", &template.name));
+
+            let mut warnings: Vec<Warning> = functions
+                .chain(templates)
+                .filter(|(_, name)| !name.as_str().starts_with('_'))
+                .map(|(what, name)| Warning::UnusedFunction {
+                    what: what.to_string(),
+                    name: name.to_string(),
+                    at: name.span().into(),
+                })
+                .collect();
+            warnings.extend(usage.imports.iter().filter(|import| !import.is_used()).map(
+                |import| Warning::UnusedImport {
+                    path: import.path.display().to_string(),
+                    at: import.at.into(),
+                },
+            ));
+
+            denied += self
+                .lints
+                .report(warnings, &file.to_string_lossy(), &source);
+        }
+        denied
+    }
+
+    /// Checks the script at `path`, its warnings are printed as they are found.
+    fn check(&self, path: &Path) -> miette::Result<()> {
+        let file_name = path.to_string_lossy();
+        let source = read_source(path)?;
+        let (nodes, mut warnings) = parse(
+            &file_name,
+            &source,
+            self.runtime.dialect,
+            &Expanders::default(),
+        )?;
+        warnings.extend(self.lints.check_attributes(&nodes));
+
+        let denied = self.lints.report(warnings, &file_name, &source);
+
+        let mut t = Transpiler::new(io::sink(), &Layout::default()).with_target(self.target);
+        if let Some(platform) = self.runtime.platform {
+            t = t.with_platform(platform);
+        }
+        if let Some(classes) = self.runtime.classes()? {
+            t = t.with_class_policy(classes);
+        }
+        t.transpile(nodes).map_err(|err| {
+            miette::Report::new(err).with_source_code(NamedSource::new(&file_name, source.clone()))
+        })?;
+
+        if denied > 0 {
+            return Err(errors::Error::DeniedWarnings { count: denied }.into());
+        }
+        Ok(())
+    }
+}
+
+/// The canonical form of `path`, so the paths of the same file are equal.
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Adds the templates of `import` and of the files it imports that are called to `called`,
+/// by the file defining them.
+fn called_templates(import: &Import, called: &mut HashSet<(PathBuf, String)>) {
+    let path = canonical(&import.path);
+    for template in import.templates.iter().filter(|t| t.is_called()) {
+        called.insert((path.clone(), template.name.to_string()));
+    }
+    for import in &import.imports {
+        called_templates(import, called);
+    }
+}
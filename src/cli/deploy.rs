@@ -0,0 +1,59 @@
+use std::path::PathBuf;
+
+use super::Runtime;
+use crate::{compile_file, deploy, CompileOptions, Endpoint};
+
+#[derive(Debug, clap::Args)]
+pub struct DeployArgs {
+    pub file: PathBuf,
+
+    /// The code of the process to write.
+    #[arg(long)]
+    pub process_code: String,
+
+    #[command(flatten)]
+    pub runtime: Runtime,
+
+    /// The XOG url, such as `https://clarity.example.com/niku/xog`.
+    #[arg(long, env = "GELATIN_XOG_URL")]
+    pub url: String,
+
+    /// The user to log into XOG as.
+    #[arg(long, env = "GELATIN_XOG_USERNAME")]
+    pub username: String,
+
+    /// The password of the user, better left to the environment than the command line.
+    #[arg(long, env = "GELATIN_XOG_PASSWORD", hide_env_values = true)]
+    pub password: String,
+}
+
+impl DeployArgs {
+    /// Builds the script as the custom script step of the process and writes it to XOG,
+    /// printing what XOG reported.
+    ///
+    /// # Errors
+    /// Returns `Err` if the script can't be built, or XOG doesn't take it.
+    pub fn run(&self) -> miette::Result<()> {
+        let options = CompileOptions {
+            dialect: self.runtime.dialect,
+            process: Some(self.process_code.clone()),
+            platform: self.runtime.platform,
+            classes: self.runtime.classes()?,
+            ..CompileOptions::default()
+        };
+        let document = compile_file(&self.file, &options)?;
+
+        let endpoint = Endpoint {
+            url: self.url.clone(),
+            username: self.username.clone(),
+            password: self.password.clone(),
+        };
+        let output = deploy(&document, &endpoint)?;
+        println!(
+            "deployed {}: {}, {} of {} records inserted, {} updated",
+            self.process_code, output.state, output.inserted, output.total, output.updated
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use super::Runtime;
+use crate::{compile_file, diff, errors, read_source, CompileOptions, SqlFormat, Target};
+
+#[derive(Debug, clap::Args)]
+pub struct DiffArgs {
+    /// The existing GEL script.
+    pub old: PathBuf,
+
+    /// The gelatin script to build and compare with it.
+    pub new: PathBuf,
+
+    #[command(flatten)]
+    pub runtime: Runtime,
+
+    /// How to write the sql of queries.
+    #[arg(long, default_value_t = SqlFormat::Compact)]
+    pub sql_format: SqlFormat,
+
+    /// The kind of script to generate.
+    #[arg(long, default_value_t = Target::Gel)]
+    pub target: Target,
+}
+
+impl DiffArgs {
+    /// Prints where the scripts differ, a line per difference.
+    ///
+    /// # Errors
+    /// Returns `Err` if either script can't be read or built, or they differ.
+    pub fn run(&self) -> miette::Result<()> {
+        let old = read_source(&self.old)?;
+        let options = CompileOptions {
+            dialect: self.runtime.dialect,
+            platform: self.runtime.platform,
+            classes: self.runtime.classes()?,
+            sql_format: self.sql_format,
+            target: self.target,
+            ..CompileOptions::default()
+        };
+        let new = compile_file(&self.new, &options)?;
+
+        let differences = diff(&old, &new).map_err(|err| errors::Error::Diff { err })?;
+        for difference in &differences {
+            println!("{difference}");
+        }
+
+        if !differences.is_empty() {
+            return Err(errors::Error::Different {
+                count: differences.len(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
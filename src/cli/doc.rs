@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use super::expand_dirs;
+use crate::{
+    gelatin::{doc, Parser},
+    read_source, SqlDialect,
+};
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// A Graphviz digraph.
+    #[default]
+    Dot,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct GraphArgs {
+    /// Scripts, or directories searched for `.gel` scripts.
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// SQL dialect to parse queries.
+    #[arg(short, long, default_value_t = SqlDialect::Generic)]
+    pub dialect: SqlDialect,
+
+    /// How to write the graph.
+    #[arg(long, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DocFormat {
+    /// A document with a heading per script.
+    #[default]
+    Markdown,
+    /// A page with a section per script.
+    Html,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DocArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// SQL dialect to parse queries.
+    #[arg(short, long, default_value_t = SqlDialect::Generic)]
+    pub dialect: SqlDialect,
+
+    /// How to write the reports.
+    #[arg(long, default_value_t = DocFormat::Markdown)]
+    pub format: DocFormat,
+}
+
+impl DocArgs {
+    /// Prints the report of each file, the scripts' warnings aren't reported.
+    ///
+    /// # Errors
+    /// Returns `Err` if a file can't be read or parsed.
+    pub fn run(&self) -> miette::Result<()> {
+        let mut reports = vec![];
+        for file in &self.files {
+            let file_name = file.to_string_lossy();
+            let report = doc_report(file, self.dialect)?;
+            reports.push(match self.format {
+                DocFormat::Markdown => report.markdown(&file_name),
+                DocFormat::Html => report.html(&file_name),
+            });
+        }
+
+        match self.format {
+            DocFormat::Markdown => print!("{}", reports.join("\n")),
+            DocFormat::Html => print!(
+                "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>gelatin</title></head>\n<body>\n{}</body>\n</html>\n",
+                reports.concat()
+            ),
+        }
+        Ok(())
+    }
+}
+
+impl GraphArgs {
+    /// Prints the graph of the scripts, the ones in the directories included. Their
+    /// warnings aren't reported.
+    ///
+    /// # Errors
+    /// Returns `Err` if a file can't be read or parsed.
+    pub fn run(&self) -> miette::Result<()> {
+        let mut reports = vec![];
+        for script in expand_dirs(&self.files)? {
+            let report = doc_report(&script, self.dialect)?;
+            reports.push((script.display().to_string(), report));
+        }
+
+        match self.format {
+            GraphFormat::Dot => print!("{}", doc::dot(&reports)),
+        }
+        Ok(())
+    }
+}
+
+/// The report of what the script at `path` touches, see [`doc::report`].
+fn doc_report(path: &Path, dialect: SqlDialect) -> miette::Result<doc::Report> {
+    let file_name = path.to_string_lossy();
+    let source = read_source(path)?;
+    let mut parser = Parser::new(&file_name, &source, dialect.to_dialect());
+    let nodes = parser.parse()?;
+    Ok(doc::report(&nodes, parser.imports()))
+}
+
+impl std::fmt::Display for DocFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl std::fmt::Display for GraphFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
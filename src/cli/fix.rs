@@ -0,0 +1,46 @@
+use std::path::PathBuf;
+
+use super::expand_dirs;
+use crate::{errors, fix_file, SqlDialect};
+
+#[derive(Debug, clap::Args)]
+pub struct FixArgs {
+    /// Scripts, or directories searched for `.gel` scripts.
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// SQL dialect to parse queries.
+    #[arg(short, long, default_value_t = SqlDialect::Generic)]
+    pub dialect: SqlDialect,
+
+    /// Only report the files that have fixes, failing if there's any.
+    #[arg(long)]
+    pub check: bool,
+}
+
+impl FixArgs {
+    /// Fixes the files, printing how many fixes each of them has.
+    ///
+    /// # Errors
+    /// Returns `Err` if a file can't be parsed or formatted, or has fixes with `--check`.
+    pub fn run(&self) -> miette::Result<()> {
+        let mut unfixed = 0;
+        for file in expand_dirs(&self.files)? {
+            let fixes = fix_file(&file, self.dialect, self.check)?;
+            if fixes == 0 {
+                continue;
+            }
+            if self.check {
+                println!("{} has {fixes} fixes", file.display());
+                unfixed += 1;
+            } else {
+                println!("fixed {fixes} warnings of {}", file.display());
+            }
+        }
+
+        if unfixed > 0 {
+            return Err(errors::Error::Unfixed { count: unfixed }.into());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use crate::{errors, format_file};
+
+#[derive(Debug, clap::Args)]
+pub struct FmtArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// Only report the files that aren't formatted, failing if there's any.
+    #[arg(long)]
+    pub check: bool,
+}
+
+impl FmtArgs {
+    /// Formats the files, printing the ones that aren't formatted with `--check`.
+    ///
+    /// # Errors
+    /// Returns `Err` if a file can't be formatted, or isn't formatted with `--check`.
+    pub fn run(&self) -> miette::Result<()> {
+        let mut unformatted = 0;
+        for file in &self.files {
+            if format_file(file, self.check)? && self.check {
+                println!("{} isn't formatted", file.display());
+                unformatted += 1;
+            }
+        }
+
+        if unformatted > 0 {
+            return Err(errors::Error::Unformatted { count: unformatted }.into());
+        }
+        Ok(())
+    }
+}
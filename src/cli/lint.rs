@@ -0,0 +1,79 @@
+use std::path::{Path, PathBuf};
+
+use miette::{IntoDiagnostic, NamedSource, SourceOffset, SourceSpan};
+
+use super::{expand_dirs, CheckArgs, Lints, Runtime};
+use crate::{config, errors, read_source, LintConfig};
+
+#[derive(Debug, clap::Args)]
+pub struct LintArgs {
+    /// Scripts, or directories searched for `.gel` scripts.
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+
+    /// The configuration to lint with, by default the `gelatin.toml` of the working
+    /// directory or of the closest of its parents that has one.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub lints: Lints,
+}
+
+impl LintArgs {
+    /// Checks the files as [`CheckArgs::run`] does, configured by the `gelatin.toml`.
+    ///
+    /// # Errors
+    /// Returns `Err` if the configuration can't be read, or any of the files has errors or
+    /// denied warnings.
+    pub fn run(&self) -> miette::Result<()> {
+        let path = if let Some(ref path) = self.config {
+            Some(path.clone())
+        } else {
+            let cwd = std::env::current_dir().into_diagnostic()?;
+            cwd.ancestors()
+                .map(|dir| dir.join(config::FILE_NAME))
+                .find(|path| path.is_file())
+        };
+        let config = path
+            .as_deref()
+            .map(read_config)
+            .transpose()?
+            .unwrap_or_default();
+
+        let mut lints = self.lints.clone();
+        lints.configured = config.rules;
+        lints.max_attribute_length = lints.max_attribute_length.or(config.max_attribute_length);
+
+        CheckArgs {
+            files: expand_dirs(&self.files)?,
+            target: config.target.unwrap_or_default(),
+            runtime: Runtime {
+                dialect: config.dialect.unwrap_or_default(),
+                platform: config.platform,
+                class_policy: config.class_policy,
+                allow_class: config.allow_classes,
+            },
+            project: config.project,
+            lints,
+        }
+        .run()
+    }
+}
+
+/// Reads the `gelatin.toml` at `path`.
+fn read_config(path: &Path) -> miette::Result<LintConfig> {
+    let source = read_source(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    LintConfig::parse(&source, dir).map_err(|(line, err)| {
+        let at = SourceOffset::from_location(&source, line + 1, 1);
+        let len = source.lines().nth(line).map_or(1, str::len);
+        errors::Error::Config {
+            at: SourceSpan::new(at, len),
+            source_code: NamedSource::new(path.to_string_lossy(), source.as_str().into()),
+            err,
+        }
+        .into()
+    })
+}
@@ -0,0 +1,352 @@
+//! The command line: the arguments of `gelatin` and of its subcommands, and what each of
+//! them runs.
+
+mod build;
+mod check;
+mod deploy;
+mod diff;
+mod doc;
+mod fix;
+mod fmt;
+mod lint;
+mod repl;
+
+use std::{fmt::Write as _, num::NonZeroUsize, path::PathBuf};
+
+use clap::{Parser as ClapParser, Subcommand, ValueEnum};
+use miette::{IntoDiagnostic, NamedSource};
+
+pub use build::{Build, Emit, Wrap};
+pub use check::CheckArgs;
+pub use deploy::DeployArgs;
+pub use diff::DiffArgs;
+pub use doc::{DocArgs, DocFormat, GraphArgs, GraphFormat};
+pub use fix::FixArgs;
+pub use fmt::FmtArgs;
+pub use lint::LintArgs;
+pub use repl::ReplArgs;
+
+use crate::{
+    ast::{Node, Spanned},
+    gelatin::attributes,
+    read_class_policy, scripts_in, ClassPolicy, Level, LineEnding, Platform, SqlDialect, SqlFormat,
+    Target, Warning, WarningCode,
+};
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Rendered for a terminal.
+    #[default]
+    Human,
+    /// One JSON object per diagnostic, for tools.
+    Json,
+}
+
+/// Builds gelatin scripts into the GEL scripts Clarity runs.
+#[derive(Debug, ClapParser)]
+#[allow(clippy::struct_excessive_bools)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Scripts, or directories searched for `.gel` scripts, `-` reads a script from stdin.
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// The file name to report diagnostics of the script read from stdin at.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Where to output the GEL script of a single file to.
+    #[arg(short, long, conflicts_with = "out_dir")]
+    output: Option<PathBuf>,
+
+    /// The directory to write the scripts to, an `.xml` per input mirroring the tree of
+    /// the input directories.
+    #[arg(long)]
+    pub out_dir: Option<PathBuf>,
+
+    /// How many scripts to build at once, as many as there are cpus by default.
+    #[arg(short, long)]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Whether to prettify the output or not.
+    #[arg(short, long)]
+    pub prettify: bool,
+
+    /// How many spaces to indent a level of tags with, implies `--prettify`.
+    #[arg(long, value_name = "SPACES")]
+    pub indent: Option<usize>,
+
+    /// The line ending of the prettified output.
+    #[arg(long, default_value_t = LineEnding::Lf)]
+    pub line_ending: LineEnding,
+
+    /// Whether to write each attribute of tags with several on its own line, implies
+    /// `--prettify`.
+    #[arg(long)]
+    pub attrs_per_line: bool,
+
+    /// Whether to write the sql of queries as escaped text rather than CDATA sections.
+    #[arg(long)]
+    pub escape_cdata: bool,
+
+    /// Whether to write a comment with the source file, gelatin version and time of
+    /// generation at the top of the output.
+    #[arg(long)]
+    pub provenance: bool,
+
+    /// Whether to write a comment pointing at the source line before each top-level tag.
+    #[arg(long)]
+    pub source_map: bool,
+
+    /// A namespace to declare on the script besides the ones it uses, as in
+    /// `xsi=http://www.w3.org/2001/XMLSchema-instance`.
+    #[arg(long, value_name = "PREFIX=URI", value_parser = parse_namespace)]
+    pub namespace: Vec<(String, String)>,
+
+    /// How to write the sql of queries.
+    #[arg(long, default_value_t = SqlFormat::Compact)]
+    pub sql_format: SqlFormat,
+
+    /// The kind of script to generate.
+    #[arg(long, default_value_t = Target::Gel)]
+    pub target: Target,
+
+    /// What to write, the script or one of the steps building it.
+    #[arg(long, default_value_t = Emit::Xml)]
+    pub emit: Emit,
+
+    /// Whether to remove the `let`s of names that are never read and the blocks without
+    /// statements, printing what was removed to stderr.
+    #[arg(long)]
+    pub eliminate_dead_code: bool,
+
+    /// Whether to bind the method calls written several times in the body of a loop before
+    /// it, if they don't read the names the loop sets, printing what was hoisted to stderr.
+    /// The calls are assumed to have no effects.
+    #[arg(long)]
+    pub hoist_repeated_expressions: bool,
+
+    /// What to embed the script in.
+    #[arg(long, requires = "process_code")]
+    pub wrap: Option<Wrap>,
+
+    /// The code of the process `--wrap process` writes.
+    #[arg(long, requires = "wrap")]
+    pub process_code: Option<String>,
+
+    /// A file whose tags are written at the start of every script, inside its root tag.
+    /// A `.gel` file is a gelatin script, anything else an xml fragment.
+    #[arg(long, value_name = "FILE")]
+    pub prologue: Option<PathBuf>,
+
+    /// A file whose tags are written at the end of every script, like `--prologue`.
+    #[arg(long, value_name = "FILE")]
+    pub epilogue: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub runtime: Runtime,
+
+    #[command(flatten)]
+    pub lints: Lints,
+
+    /// How to write errors and warnings to stderr.
+    #[arg(long, global = true, default_value_t = ErrorFormat::Human)]
+    pub error_format: ErrorFormat,
+
+    /// Logs each phase of building a script to stderr with how long it took, `-vv` also
+    /// logs how many statements the script has after it.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Prints how long each phase took over all the scripts once they're built.
+    #[arg(long)]
+    pub time_report: bool,
+}
+
+/// The levels warnings were given on the command line.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Lints {
+    /// Warnings to report.
+    #[arg(short = 'W', long, value_name = "CODE")]
+    pub warn: Vec<WarningCode>,
+
+    /// Warnings to ignore.
+    #[arg(short = 'A', long, value_name = "CODE")]
+    pub allow: Vec<WarningCode>,
+
+    /// Warnings to fail on.
+    #[arg(short = 'D', long, value_name = "CODE")]
+    pub deny: Vec<WarningCode>,
+
+    /// How many characters a `value` or `test` attribute can have before
+    /// `invalid-attribute` reports it, 2000 by default.
+    #[arg(long, value_name = "CHARS")]
+    pub max_attribute_length: Option<usize>,
+
+    /// The levels of the rules of a `gelatin.toml`, the ones given on the command line win
+    /// over them.
+    #[arg(skip)]
+    pub configured: Vec<(WarningCode, Level)>,
+}
+
+/// What the scripts run on: the database their queries are parsed for, the Clarity and the
+/// Java classes they may use.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Runtime {
+    /// SQL dialect to parse queries.
+    #[arg(short, long, default_value_t = SqlDialect::Generic)]
+    pub dialect: SqlDialect,
+
+    /// The Clarity the script runs on, `saas`, `onprem` or `onprem:<version>`, rejecting
+    /// what it doesn't allow, such as file access on Clarity `SaaS`.
+    #[arg(long, value_name = "PLATFORM")]
+    pub platform: Option<Platform>,
+
+    /// A file of the Java classes scripts may create and call, each line an `allow` or a
+    /// `deny` and a class or package, as in `deny java.io`.
+    #[arg(long, value_name = "FILE")]
+    pub class_policy: Option<PathBuf>,
+
+    /// A class or package to allow whatever `--class-policy` says.
+    #[arg(long, value_name = "CLASS", requires = "class_policy")]
+    pub allow_class: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Writes the script to Clarity as the custom script step of a process.
+    Deploy(DeployArgs),
+    /// Rewrites scripts in the canonical style.
+    Fmt(FmtArgs),
+    /// Applies the fixes of the warnings that have a mechanical one, such as prefixing the
+    /// unused variables with an underscore, and rewrites the scripts in the canonical style.
+    Fix(FixArgs),
+    /// Parses, validates and transpiles scripts without writing them, reporting the
+    /// diagnostics of all of them.
+    Check(CheckArgs),
+    /// Checks scripts with the rules, their levels and the restrictions of a `gelatin.toml`.
+    Lint(LintArgs),
+    /// Compares the script built from a gelatin file with an existing GEL script, by their
+    /// xml rather than their text.
+    Diff(DiffArgs),
+    /// Reads statements from stdin, printing the tags each of them is written as.
+    Repl(ReplArgs),
+    /// Prints a report of what each script touches: its parameters, the files it imports,
+    /// the tables its queries read and write, the endpoints it calls, the XOG objects it
+    /// reads and writes, the emails it sends and the files it writes.
+    Doc(DocArgs),
+    /// Prints a graph of the files the scripts import and the datasources, endpoints and
+    /// XOG objects they use.
+    Graph(GraphArgs),
+}
+
+impl Runtime {
+    /// The classes of `--class-policy`, with the `--allow-class`es allowed.
+    ///
+    /// # Errors
+    /// Returns `Err` if the policy can't be read.
+    pub fn classes(&self) -> miette::Result<Option<ClassPolicy>> {
+        self.class_policy
+            .as_deref()
+            .map(|path| read_class_policy(path, &self.allow_class))
+            .transpose()
+    }
+}
+
+impl Lints {
+    /// The level of `code`, `--deny` wins over `--warn`, which wins over `--allow`.
+    #[must_use]
+    pub fn level(&self, code: WarningCode) -> Level {
+        if self.deny.contains(&code) {
+            Level::Deny
+        } else if self.warn.contains(&code) {
+            Level::Warn
+        } else if self.allow.contains(&code) {
+            Level::Allow
+        } else if let Some(&(_, level)) = self.configured.iter().find(|(rule, _)| *rule == code) {
+            level
+        } else {
+            code.default_level()
+        }
+    }
+
+    /// The warnings about the attributes the expanded `nodes` are written with.
+    fn check_attributes(&self, nodes: &[Spanned<Node>]) -> Vec<Warning> {
+        let max_length = self.max_attribute_length.unwrap_or(attributes::MAX_LENGTH);
+        attributes::check(nodes, max_length)
+    }
+
+    /// Prints the `warnings` in `source` that aren't allowed to stderr, returning how many
+    /// of them are denied.
+    fn report(&self, warnings: Vec<Warning>, file_name: &str, source: &str) -> usize {
+        let mut diagnostics = String::new();
+        let denied = self.render(warnings, file_name, source, &mut diagnostics);
+        eprint!("{diagnostics}");
+        denied
+    }
+
+    /// Like [`Self::report`], but the warnings are written to `diagnostics`.
+    fn render(
+        &self,
+        warnings: Vec<Warning>,
+        file_name: &str,
+        source: &str,
+        diagnostics: &mut String,
+    ) -> usize {
+        let mut denied = 0;
+
+        for warning in warnings {
+            match self.level(warning.code()) {
+                Level::Allow => continue,
+                Level::Warn => {}
+                Level::Deny => denied += 1,
+            }
+
+            let report = miette::Report::new(warning)
+                .with_source_code(NamedSource::new(file_name, source.to_string()));
+            let _ = writeln!(diagnostics, "{report:?}");
+        }
+
+        denied
+    }
+}
+
+impl std::fmt::Display for ErrorFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+/// The `files`, with the directories among them replaced by the scripts under them.
+fn expand_dirs(files: &[PathBuf]) -> miette::Result<Vec<PathBuf>> {
+    let mut scripts = vec![];
+    for file in files {
+        if file.is_dir() {
+            scripts.extend(scripts_in(file).into_diagnostic()?);
+        } else {
+            scripts.push(file.clone());
+        }
+    }
+    Ok(scripts)
+}
+
+/// The `(prefix, uri)` of a `--namespace`.
+fn parse_namespace(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((prefix, uri)) if !prefix.is_empty() && !uri.is_empty() => {
+            Ok((prefix.to_string(), uri.to_string()))
+        }
+        _ => Err(format!("`{value}` isn't a `PREFIX=URI` namespace")),
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::{self, IsTerminal, Write};
+
+use miette::{IntoDiagnostic, NamedSource};
+
+use super::Runtime;
+use crate::{gelatin, parse, transpiler::Transpiler, ClassPolicy, Expanders, SqlFormat, Target};
+
+#[derive(Debug, clap::Args)]
+pub struct ReplArgs {
+    #[command(flatten)]
+    pub runtime: Runtime,
+
+    /// How to write the sql of queries.
+    #[arg(long, default_value_t = SqlFormat::Compact)]
+    pub sql_format: SqlFormat,
+
+    /// The kind of script to write the tags for.
+    #[arg(long, default_value_t = Target::Gel)]
+    pub target: Target,
+}
+
+impl ReplArgs {
+    /// Reads statements until stdin ends, printing the tags of each of them or its errors.
+    /// Lines are read until they make a whole statement, or a blank line gives up on it.
+    /// What the statements bind stays in scope of the ones after them, warnings aren't
+    /// reported.
+    ///
+    /// # Errors
+    /// Returns `Err` if stdin or stdout fail.
+    pub fn run(&self) -> miette::Result<()> {
+        let classes = self.runtime.classes()?;
+        let stdin = io::stdin();
+        let interactive = stdin.is_terminal();
+        // the statements that were written, read again with every new one
+        let mut history = String::new();
+        let mut input = String::new();
+
+        loop {
+            if interactive {
+                print!("{}", if input.is_empty() { "> " } else { ". " });
+                io::stdout().flush().into_diagnostic()?;
+            }
+
+            let mut line = String::new();
+            if stdin.read_line(&mut line).into_diagnostic()? == 0 {
+                break;
+            }
+            let blank = line.trim().is_empty();
+            input.push_str(&line);
+
+            if input.trim().is_empty() {
+                input.clear();
+                continue;
+            }
+            if !blank && gelatin::is_incomplete(&input) {
+                continue;
+            }
+
+            match self.evaluate(&history, &input, classes.as_ref()) {
+                Ok(tags) => {
+                    // aliases and namespaces have no tags of their own
+                    if !tags.trim().is_empty() {
+                        println!("{}", tags.trim());
+                    }
+                    history.push_str(&input);
+                }
+                Err(err) => eprintln!("{err:?}"),
+            }
+            input.clear();
+        }
+
+        Ok(())
+    }
+
+    /// The tags of the statements in `input`, following the ones in `history`.
+    fn evaluate(
+        &self,
+        history: &str,
+        input: &str,
+        classes: Option<&ClassPolicy>,
+    ) -> miette::Result<String> {
+        let source = format!("{history}{input}");
+        let (nodes, _) = parse(
+            "<repl>",
+            &source,
+            self.runtime.dialect,
+            &Expanders::default(),
+        )?;
+
+        let mut t = Transpiler::fragments(Vec::new())
+            .with_sql_format(self.sql_format)
+            .with_target(self.target);
+        if let Some(platform) = self.runtime.platform {
+            t = t.with_platform(platform);
+        }
+        if let Some(classes) = classes {
+            t = t.with_class_policy(classes.clone());
+        }
+        let (old, new): (Vec<_>, Vec<_>) = nodes
+            .into_iter()
+            .partition(|node| node.span.offset < history.len());
+
+        // the history is written again for the aliases it declares
+        for node in old {
+            t.write_fragment(node)?;
+        }
+        t.take_output();
+
+        for node in new {
+            t.write_fragment(node).map_err(|err| {
+                miette::Report::new(err)
+                    .with_source_code(NamedSource::new("<repl>", source.clone()))
+            })?;
+        }
+
+        String::from_utf8(t.take_output()).into_diagnostic()
+    }
+}
@@ -0,0 +1,236 @@
+//! The `gelatin.toml` file configuring `gelatin lint`, such as
+//!
+//! ```toml
+//! [lint]
+//! dialect = "oracle"
+//! platform = "saas"
+//! class-policy = "classes.txt"
+//! allow-classes = ["com.acme.Util"]
+//! max-attribute-length = 1000
+//! project = true
+//!
+//! [lint.rules]
+//! shadowing = "warn"
+//! unused-variable = "deny"
+//! hardcoded-secret = "deny"
+//! ```
+//!
+//! Only the part of TOML these need is read: tables, `key = value` pairs whose values are
+//! strings, integers, bools or arrays of strings on a single line, and `#` comments.
+
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+
+use crate::{Level, Platform, SqlDialect, Target, WarningCode};
+
+/// The name of the file `gelatin lint` looks for.
+pub const FILE_NAME: &str = "gelatin.toml";
+
+/// How `gelatin lint` checks scripts, the flags of the command line win over it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintConfig {
+    pub dialect: Option<SqlDialect>,
+    pub target: Option<Target>,
+    pub platform: Option<Platform>,
+    /// The class policy, relative to the directory of the file.
+    pub class_policy: Option<PathBuf>,
+    pub allow_classes: Vec<String>,
+    pub max_attribute_length: Option<usize>,
+    /// Whether the scripts are checked as a project, see `check --project`.
+    pub project: bool,
+    /// The level of each rule it sets.
+    pub rules: Vec<(WarningCode, Level)>,
+}
+
+/// A value of a `key = value` pair.
+enum TomlValue {
+    Str(String),
+    Int(usize),
+    Bool(bool),
+    Array(Vec<String>),
+}
+
+impl TomlValue {
+    const fn kind(&self) -> &'static str {
+        match self {
+            Self::Str(_) => "a string",
+            Self::Int(_) => "an integer",
+            Self::Bool(_) => "a bool",
+            Self::Array(_) => "an array",
+        }
+    }
+}
+
+impl LintConfig {
+    /// Reads the configuration `source` of the file in `dir`.
+    ///
+    /// # Errors
+    /// Returns the index of the first line that can't be read, with what's wrong with it.
+    pub(crate) fn parse(source: &str, dir: &Path) -> Result<Self, (usize, String)> {
+        let mut config = Self::default();
+        let mut table = String::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let (name, rest) = name
+                    .split_once(']')
+                    .ok_or_else(|| (index, "expected a `]`".into()))?;
+                if !is_comment(rest) {
+                    return Err((index, "expected the end of the line".into()));
+                }
+                table = name.trim().to_string();
+                if !matches!(table.as_str(), "lint" | "lint.rules") {
+                    return Err((
+                        index,
+                        format!("unknown table `{table}`, expected `lint` or `lint.rules`"),
+                    ));
+                }
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| (index, "expected a `key = value` pair".into()))?;
+            let key = key.trim();
+            let value = parse_value(value.trim()).map_err(|err| (index, err))?;
+            config
+                .set(&table, key, &value)
+                .map_err(|err| (index, err))?;
+        }
+
+        if let Some(ref mut policy) = config.class_policy {
+            *policy = dir.join(&*policy);
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, table: &str, key: &str, value: &TomlValue) -> Result<(), String> {
+        let expected = |what: &str| format!("`{key}` is {what}, not {}", value.kind());
+
+        match (table, key, value) {
+            ("lint", "dialect", TomlValue::Str(name)) => {
+                self.dialect = Some(SqlDialect::from_str(name, false)?);
+            }
+            ("lint", "target", TomlValue::Str(name)) => {
+                self.target = Some(Target::from_str(name, false)?);
+            }
+            ("lint", "platform", TomlValue::Str(name)) => self.platform = Some(name.parse()?),
+            ("lint", "class-policy", TomlValue::Str(path)) => {
+                self.class_policy = Some(PathBuf::from(path));
+            }
+            ("lint", "allow-classes", TomlValue::Array(classes)) => {
+                self.allow_classes.clone_from(classes);
+            }
+            ("lint", "max-attribute-length", TomlValue::Int(length)) => {
+                self.max_attribute_length = Some(*length);
+            }
+            ("lint", "project", TomlValue::Bool(project)) => self.project = *project,
+            ("lint", "dialect" | "target" | "platform" | "class-policy", _) => {
+                return Err(expected("a string"));
+            }
+            ("lint", "allow-classes", _) => return Err(expected("an array of strings")),
+            ("lint", "max-attribute-length", _) => return Err(expected("an integer")),
+            ("lint", "project", _) => return Err(expected("a bool")),
+            ("lint.rules", code, TomlValue::Str(level)) => {
+                let code = WarningCode::from_str(code, false)
+                    .map_err(|_| format!("unknown rule `{code}`"))?;
+                let level = match level.as_str() {
+                    "allow" => Level::Allow,
+                    "warn" => Level::Warn,
+                    "deny" => Level::Deny,
+                    _ => {
+                        return Err(format!(
+                            "unknown level `{level}`, expected `allow`, `warn` or `deny`"
+                        ))
+                    }
+                };
+                self.rules.retain(|(rule, _)| *rule != code);
+                self.rules.push((code, level));
+            }
+            ("lint.rules", _, _) => return Err(expected("a string, `allow`, `warn` or `deny`")),
+            ("", _, _) => return Err(format!("`{key}` has to be in the `lint` table")),
+            _ => return Err(format!("unknown key `{key}`")),
+        }
+        Ok(())
+    }
+}
+
+fn is_comment(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+fn parse_value(value: &str) -> Result<TomlValue, String> {
+    if let Some(items) = value.strip_prefix('[') {
+        let mut strings = vec![];
+        let mut rest = items.trim_start();
+        loop {
+            if let Some(after) = rest.strip_prefix(']') {
+                if !is_comment(after) {
+                    return Err("expected the end of the line".to_string());
+                }
+                return Ok(TomlValue::Array(strings));
+            }
+
+            let (string, after) = parse_string(rest)?;
+            strings.push(string);
+            rest = after.trim_start();
+            if let Some(after) = rest.strip_prefix(',') {
+                rest = after.trim_start();
+            } else if !rest.starts_with(']') {
+                return Err("expected a `,` or a `]`".to_string());
+            }
+        }
+    }
+
+    let (value, rest) = if value.starts_with('"') {
+        let (string, rest) = parse_string(value)?;
+        (TomlValue::Str(string), rest)
+    } else {
+        let end = value.find(['#', ' ', '\t']).unwrap_or(value.len());
+        let (word, rest) = value.split_at(end);
+        let value = match word {
+            "true" => TomlValue::Bool(true),
+            "false" => TomlValue::Bool(false),
+            _ => TomlValue::Int(word.replace('_', "").parse().map_err(|_| {
+                format!("expected a string, an integer, a bool or an array, not `{word}`")
+            })?),
+        };
+        (value, rest)
+    };
+
+    if !is_comment(rest) {
+        return Err("expected the end of the line".to_string());
+    }
+    Ok(value)
+}
+
+/// Reads the basic string `value` starts with, returning it and what follows it.
+fn parse_string(value: &str) -> Result<(String, &str), String> {
+    let Some(rest) = value.strip_prefix('"') else {
+        return Err("expected a string".to_string());
+    };
+
+    let mut string = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &rest[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => string.push('"'),
+                Some((_, '\\')) => string.push('\\'),
+                Some((_, 'n')) => string.push('\n'),
+                Some((_, 't')) => string.push('\t'),
+                _ => return Err("unknown escape, expected `\\\"`, `\\\\`, `\\n` or `\\t`".into()),
+            },
+            c => string.push(c),
+        }
+    }
+    Err("expected the closing `\"` of the string".to_string())
+}
@@ -0,0 +1,197 @@
+use std::{
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use xml::{escape::escape_str_pcdata, reader::XmlEvent};
+
+use crate::{
+    errors::Error,
+    gelatin::{SOAPENV_NS, XOG_NS},
+};
+
+/// The XOG service a document is written to.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    /// The XOG url, such as `https://clarity.example.com/niku/xog`.
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+/// What XOG reported after writing a document.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XogOutput {
+    /// `SUCCESS`, `PARTIAL` or `FAILURE`.
+    pub state: String,
+    pub inserted: usize,
+    pub updated: usize,
+    pub failed: usize,
+    pub total: usize,
+    /// The descriptions of the errors XOG found in the document.
+    pub errors: Vec<String>,
+}
+
+/// Posts the process XOG `document` to `endpoint`, authenticating with its credentials.
+///
+/// It's meant for `gelatin deploy`: the request is made by `curl`, which has to be on the
+/// `PATH`, rather than by an http client every user of the library would build.
+///
+/// # Errors
+/// Returns `Err` if curl isn't on the `PATH`, the request fails, or XOG doesn't report a
+/// `SUCCESS`.
+pub fn deploy(document: &str, endpoint: &Endpoint) -> miette::Result<XogOutput> {
+    let deploy_error = |err: String| Error::Deploy {
+        url: endpoint.url.clone(),
+        err,
+    };
+
+    let response = post(&endpoint.url, &envelope(document, endpoint))?;
+    let output = parse_output(&response).map_err(deploy_error)?;
+
+    if output.state != "SUCCESS" {
+        return Err(Error::XogFailure {
+            state: output.state,
+            errors: (!output.errors.is_empty()).then(|| output.errors.join("\n")),
+        }
+        .into());
+    }
+
+    Ok(output)
+}
+
+/// Wraps `document` in the soap request writing it, with the credentials of `endpoint` in
+/// its `Auth` header.
+fn envelope(document: &str, endpoint: &Endpoint) -> String {
+    // the declaration can only be at the start of the request
+    let document = document
+        .trim_start()
+        .strip_prefix("<?xml")
+        .and_then(|rest| rest.split_once("?>"))
+        .map_or(document, |(_, rest)| rest);
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <soapenv:Envelope xmlns:soapenv=\"{SOAPENV_NS}\" xmlns:xog=\"{XOG_NS}\">\
+         <soapenv:Header><xog:Auth>\
+         <xog:Username>{username}</xog:Username>\
+         <xog:Password>{password}</xog:Password>\
+         </xog:Auth></soapenv:Header>\
+         <soapenv:Body>{document}</soapenv:Body>\
+         </soapenv:Envelope>",
+        username = escape_str_pcdata(&endpoint.username),
+        password = escape_str_pcdata(&endpoint.password),
+    )
+}
+
+/// Posts the soap `body` to `url`, returning the body of the response. Soap faults come
+/// with a 500, so the status isn't checked.
+fn post(url: &str, body: &str) -> miette::Result<String> {
+    let failed = |err: String| {
+        miette::Report::new(Error::Deploy {
+            url: url.to_string(),
+            err,
+        })
+    };
+
+    // the body goes through stdin to keep the password out of the arguments
+    let mut curl = Command::new("curl")
+        .args([
+            "--silent",
+            "--show-error",
+            "--header",
+            "Content-Type: text/xml; charset=utf-8",
+            "--header",
+            "SOAPAction: \"\"",
+            "--data-binary",
+            "@-",
+            url,
+        ])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => Error::CurlMissing.into(),
+            _ => failed(format!("could not run curl: {err}")),
+        })?;
+
+    curl.stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(body.as_bytes())
+        .map_err(|err| failed(format!("could not send the request to curl: {err}")))?;
+
+    let output = curl
+        .wait_with_output()
+        .map_err(|err| failed(format!("curl failed: {err}")))?;
+    if !output.status.success() {
+        return Err(failed(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|err| failed(format!("the response isn't utf-8: {err}")))
+}
+
+/// Reads the `XOGOutput` of a soap `response`.
+fn parse_output(response: &str) -> Result<XogOutput, String> {
+    let mut output = XogOutput::default();
+    let mut status = false;
+    // the local name of the element whose text is read, if its text is wanted
+    let mut reading: Option<&str> = None;
+    let mut in_error = false;
+    let mut fault = None;
+
+    for event in xml::EventReader::from_str(response) {
+        match event.map_err(|err| format!("the response isn't xml: {err}"))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let attr = |name: &str| {
+                    attributes
+                        .iter()
+                        .find(|attr| attr.name.local_name == name)
+                        .map(|attr| attr.value.as_str())
+                };
+                let count = |name: &str| attr(name).and_then(|value| value.parse().ok());
+
+                match name.local_name.as_str() {
+                    "Status" => {
+                        status = true;
+                        output.state = attr("state").unwrap_or_default().to_string();
+                    }
+                    "Statistics" => {
+                        output.inserted = count("insertedRecords").unwrap_or_default();
+                        output.updated = count("updatedRecords").unwrap_or_default();
+                        output.failed = count("failureRecords").unwrap_or_default();
+                        output.total = count("totalNumberOfRecords").unwrap_or_default();
+                    }
+                    "ErrorInformation" => in_error = true,
+                    "Description" if in_error => reading = Some("Description"),
+                    "faultstring" => reading = Some("faultstring"),
+                    _ => {}
+                }
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => match reading {
+                Some("Description") => output.errors.push(text.trim().to_string()),
+                Some("faultstring") => fault = Some(text.trim().to_string()),
+                _ => {}
+            },
+            XmlEvent::EndElement { name } => {
+                if name.local_name == "ErrorInformation" {
+                    in_error = false;
+                }
+                reading = None;
+            }
+            _ => {}
+        }
+    }
+
+    match fault {
+        Some(fault) => Err(format!("XOG answered with a fault: {fault}")),
+        None if !status => Err("the response has no XOG status".to_string()),
+        None => Ok(output),
+    }
+}
@@ -0,0 +1,79 @@
+use sqlparser::{
+    ast::Expr,
+    dialect::{dialect_from_str, Dialect},
+    parser::{Parser, ParserError},
+    tokenizer::Token,
+};
+
+/// A [`Dialect`] for Oracle, which is what Clarity on-premise mostly runs on.
+///
+/// `sqlparser` has no Oracle dialect, `ROWNUM` and `NVL` parse as a plain name and
+/// function anywhere, but the `(+)` outer joins are only parsed for a few dialects, so
+/// this one parses them itself.
+#[derive(Debug, Default)]
+pub struct OracleDialect;
+
+impl Dialect for OracleDialect {
+    /// Parses a column marked as the outer side of a join, as in `a.id = b.id(+)`, leaving
+    /// anything else to the parser.
+    fn parse_prefix(&self, parser: &mut Parser) -> Option<Result<Expr, ParserError>> {
+        let is_word = |token: Token| matches!(token, Token::Word(_));
+        if !is_word(parser.peek_token().token) {
+            return None;
+        }
+        // the tokens of the name, its words and the periods between them
+        let mut len = 1;
+        while parser.peek_nth_token(len).token == Token::Period
+            && is_word(parser.peek_nth_token(len + 1).token)
+        {
+            len += 2;
+        }
+        let marked = [Token::LParen, Token::Plus, Token::RParen]
+            .iter()
+            .enumerate()
+            .all(|(i, token)| parser.peek_nth_token(len + i).token == *token);
+        if !marked {
+            return None;
+        }
+
+        let mut idents = vec![];
+        for _ in 0..len {
+            if let Token::Word(word) = parser.next_token().token {
+                idents.push(word.to_ident());
+            }
+        }
+        // peeked above
+        let _ = parser.consume_tokens(&[Token::LParen, Token::Plus, Token::RParen]);
+
+        let column = match <[_; 1]>::try_from(idents) {
+            Ok([ident]) => Expr::Identifier(ident),
+            Err(idents) => Expr::CompoundIdentifier(idents),
+        };
+        Some(Ok(Expr::OuterJoin(Box::new(column))))
+    }
+
+    fn is_delimited_identifier_start(&self, ch: char) -> bool {
+        ch == '"'
+    }
+
+    fn is_identifier_start(&self, ch: char) -> bool {
+        ch.is_alphabetic()
+    }
+
+    fn is_identifier_part(&self, ch: char) -> bool {
+        ch.is_alphanumeric() || ch == '_' || ch == '$' || ch == '#'
+    }
+
+    fn supports_group_by_expr(&self) -> bool {
+        true
+    }
+}
+
+/// The dialect called `name`, as given to `--dialect` or any that `sqlparser` knows about.
+pub fn dialect_from_name(name: &str) -> Option<Box<dyn Dialect>> {
+    match name.to_lowercase().as_str() {
+        "oracle" => Some(Box::new(OracleDialect)),
+        "pg" => dialect_from_str("postgresql"),
+        name => dialect_from_str(name),
+    }
+}
@@ -0,0 +1,327 @@
+use std::{collections::BTreeMap, fmt};
+
+use xml::{name::OwnedName, reader::XmlEvent, ParserConfig};
+
+/// A node of a document, compared by name and namespace rather than prefix.
+enum Tree {
+    Element {
+        name: OwnedName,
+        /// By `(namespace, local name)`, their order doesn't matter.
+        attributes: BTreeMap<(Option<String>, String), (String, String)>,
+        children: Vec<Self>,
+    },
+    /// Text with its runs of whitespace collapsed, cdata included.
+    Text(String),
+}
+
+/// Where two documents differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// The xpath-like path of the node differing, such as `/gel:script/core:set[2]`.
+    pub path: String,
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The node is only in the old document.
+    Removed,
+    /// The node is only in the new document.
+    Added,
+    /// The attribute `name` is `old` in the old document and `new` in the new one, `None` if
+    /// it's missing.
+    Attribute {
+        name: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    Text {
+        old: String,
+        new: String,
+    },
+}
+
+/// The differences between the xml documents `old` and `new`. Prefixes, the order of the
+/// attributes, comments and whitespace don't count.
+///
+/// # Errors
+/// Returns `Err` with the reason if either of them isn't well-formed xml.
+pub fn diff(old: &str, new: &str) -> Result<Vec<Difference>, String> {
+    let old = parse(old).map_err(|err| format!("the old script isn't xml: {err}"))?;
+    let new = parse(new).map_err(|err| format!("the new script isn't xml: {err}"))?;
+
+    let mut differences = vec![];
+    diff_children(&old, &new, "", &mut differences);
+    Ok(differences)
+}
+
+/// The root nodes of `document`.
+fn parse(document: &str) -> Result<Vec<Tree>, xml::reader::Error> {
+    let config = ParserConfig::new()
+        .trim_whitespace(true)
+        .cdata_to_characters(true)
+        .ignore_comments(true)
+        .coalesce_characters(true);
+
+    let mut roots = vec![];
+    // the elements being read, innermost last
+    let mut open: Vec<Tree> = vec![];
+
+    for event in config.create_reader(document.as_bytes()) {
+        let node = match event? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let attributes = attributes
+                    .into_iter()
+                    .map(|attr| {
+                        let key = (attr.name.namespace.clone(), attr.name.local_name.clone());
+                        (key, (display(&attr.name), attr.value))
+                    })
+                    .collect();
+                open.push(Tree::Element {
+                    name,
+                    attributes,
+                    children: vec![],
+                });
+                continue;
+            }
+            XmlEvent::EndElement { .. } => open.pop().expect("elements are balanced"),
+            XmlEvent::Characters(text) => {
+                Tree::Text(text.split_whitespace().collect::<Vec<_>>().join(" "))
+            }
+            _ => continue,
+        };
+
+        match open.last_mut() {
+            Some(Tree::Element { children, .. }) => children.push(node),
+            _ => roots.push(node),
+        }
+    }
+
+    Ok(roots)
+}
+
+/// Aligns the identical nodes of `old` and `new`, the rest are compared with the nodes of
+/// the same name in between them.
+fn diff_children(old: &[Tree], new: &[Tree], path: &str, differences: &mut Vec<Difference>) {
+    let old_paths = paths(old, path);
+    let new_paths = paths(new, path);
+
+    let (mut i, mut j) = (0, 0);
+    let anchors = common(old, new, same_tree);
+    for (next_i, next_j) in anchors.into_iter().chain([(old.len(), new.len())]) {
+        let (old_gap, new_gap) = (i..next_i, j..next_j);
+        let pairs = common(&old[old_gap.clone()], &new[new_gap.clone()], same_node);
+
+        let (mut k, mut l) = (0, 0);
+        for (next_k, next_l) in pairs.into_iter().chain([(old_gap.len(), new_gap.len())]) {
+            for removed in &old_paths[i + k..i + next_k] {
+                differences.push(Difference {
+                    path: removed.clone(),
+                    change: Change::Removed,
+                });
+            }
+            for added in &new_paths[j + l..j + next_l] {
+                differences.push(Difference {
+                    path: added.clone(),
+                    change: Change::Added,
+                });
+            }
+            if next_k < old_gap.len() {
+                diff_node(
+                    &old[i + next_k],
+                    &new[j + next_l],
+                    &old_paths[i + next_k],
+                    differences,
+                );
+            }
+            (k, l) = (next_k + 1, next_l + 1);
+        }
+
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+}
+
+/// The indices of the longest common subsequence of `old` and `new`, by `same`.
+fn common(old: &[Tree], new: &[Tree], same: fn(&Tree, &Tree) -> bool) -> Vec<(usize, usize)> {
+    // the length of the longest one from each pair of indices on
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if same(&old[i], &new[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if same(&old[i], &new[j]) {
+            pairs.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    pairs
+}
+
+fn diff_node(old: &Tree, new: &Tree, path: &str, differences: &mut Vec<Difference>) {
+    match (old, new) {
+        (
+            Tree::Element {
+                attributes: old_attributes,
+                children: old_children,
+                ..
+            },
+            Tree::Element {
+                attributes: new_attributes,
+                children: new_children,
+                ..
+            },
+        ) => {
+            for (key, (name, old)) in old_attributes {
+                let new = new_attributes.get(key).map(|(_, value)| value);
+                if new != Some(old) {
+                    differences.push(Difference {
+                        path: path.to_string(),
+                        change: Change::Attribute {
+                            name: name.clone(),
+                            old: Some(old.clone()),
+                            new: new.cloned(),
+                        },
+                    });
+                }
+            }
+            for (key, (name, new)) in new_attributes {
+                if !old_attributes.contains_key(key) {
+                    differences.push(Difference {
+                        path: path.to_string(),
+                        change: Change::Attribute {
+                            name: name.clone(),
+                            old: None,
+                            new: Some(new.clone()),
+                        },
+                    });
+                }
+            }
+
+            diff_children(old_children, new_children, path, differences);
+        }
+        (Tree::Text(old), Tree::Text(new)) if old != new => differences.push(Difference {
+            path: path.to_string(),
+            change: Change::Text {
+                old: old.clone(),
+                new: new.clone(),
+            },
+        }),
+        _ => {}
+    }
+}
+
+/// Whether `old` and `new` are the same, down to their children.
+fn same_tree(old: &Tree, new: &Tree) -> bool {
+    match (old, new) {
+        (
+            Tree::Element {
+                attributes: old_attributes,
+                children: old_children,
+                ..
+            },
+            Tree::Element {
+                attributes: new_attributes,
+                children: new_children,
+                ..
+            },
+        ) => {
+            same_node(old, new)
+                && old_attributes.len() == new_attributes.len()
+                && old_attributes.iter().all(|(key, (_, value))| {
+                    new_attributes.get(key).map(|(_, value)| value) == Some(value)
+                })
+                && old_children.len() == new_children.len()
+                && old_children
+                    .iter()
+                    .zip(new_children)
+                    .all(|(old, new)| same_tree(old, new))
+        }
+        (Tree::Text(old), Tree::Text(new)) => old == new,
+        _ => false,
+    }
+}
+
+/// Whether `old` and `new` are the same kind of node, their contents aside.
+fn same_node(old: &Tree, new: &Tree) -> bool {
+    match (old, new) {
+        (Tree::Element { name: old, .. }, Tree::Element { name: new, .. }) => {
+            old.namespace == new.namespace && old.local_name == new.local_name
+        }
+        (Tree::Text(_), Tree::Text(_)) => true,
+        _ => false,
+    }
+}
+
+/// The path of each of the `nodes` under `parent`, numbered among the siblings of the same
+/// name.
+fn paths(nodes: &[Tree], parent: &str) -> Vec<String> {
+    let mut seen: BTreeMap<String, usize> = BTreeMap::new();
+
+    nodes
+        .iter()
+        .map(|node| {
+            let name = match node {
+                Tree::Element { name, .. } => display(name),
+                Tree::Text(_) => "text()".to_string(),
+            };
+            let count = seen.entry(name.clone()).or_default();
+            *count += 1;
+            format!("{parent}/{name}[{count}]")
+        })
+        .collect()
+}
+
+fn display(name: &OwnedName) -> String {
+    name.prefix.as_ref().map_or_else(
+        || name.local_name.clone(),
+        |prefix| format!("{prefix}:{}", name.local_name),
+    )
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match self.change {
+            Change::Removed => write!(f, "{path}: only in the old script"),
+            Change::Added => write!(f, "{path}: only in the new script"),
+            Change::Attribute {
+                ref name,
+                old: Some(ref old),
+                new: Some(ref new),
+            } => write!(
+                f,
+                "{path}: `{name}` is {old:?} in the old script, {new:?} in the new one"
+            ),
+            Change::Attribute {
+                ref name,
+                new: None,
+                ..
+            } => write!(f, "{path}: `{name}` is only in the old script"),
+            Change::Attribute { ref name, .. } => {
+                write!(f, "{path}: `{name}` is only in the new script")
+            }
+            Change::Text { ref old, ref new } => {
+                write!(
+                    f,
+                    "{path}: the text is {old:?} in the old script, {new:?} in the new one"
+                )
+            }
+        }
+    }
+}
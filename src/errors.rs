@@ -1,13 +1,17 @@
+use std::sync::Arc;
+
 use miette::{Diagnostic, NamedSource, SourceSpan};
 use thiserror::Error;
 
+use clap::ValueEnum;
+
 #[derive(Error, Diagnostic, Debug)]
 pub enum Error {
     #[error("syntax error")]
     #[diagnostic(code(gelatin::syntax_error))]
     Syntax {
         #[source_code]
-        source_code: NamedSource<String>,
+        source_code: NamedSource<Arc<str>>,
         #[label("here")]
         at: SourceSpan,
 
@@ -19,7 +23,7 @@ pub enum Error {
     #[diagnostic(code(gelatin::sql_syntax_error))]
     SqlSyntax {
         #[source_code]
-        source_code: NamedSource<String>,
+        source_code: NamedSource<Arc<str>>,
         #[label("here")]
         at: SourceSpan,
 
@@ -31,7 +35,31 @@ pub enum Error {
     #[diagnostic(code(gelatin::xml_syntax_error))]
     XmlSyntax {
         #[source_code]
-        source_code: NamedSource<String>,
+        source_code: NamedSource<Arc<str>>,
+        #[label("here")]
+        at: SourceSpan,
+
+        #[help]
+        err: String,
+    },
+
+    #[error("invalid configuration")]
+    #[diagnostic(code(gelatin::invalid_config))]
+    Config {
+        #[source_code]
+        source_code: NamedSource<Arc<str>>,
+        #[label("here")]
+        at: SourceSpan,
+
+        #[help]
+        err: String,
+    },
+
+    #[error("invalid class policy")]
+    #[diagnostic(code(gelatin::invalid_class_policy))]
+    ClassPolicy {
+        #[source_code]
+        source_code: NamedSource<Arc<str>>,
         #[label("here")]
         at: SourceSpan,
 
@@ -43,7 +71,7 @@ pub enum Error {
     #[diagnostic(code(gelatin::sql_parameter_mismatch))]
     SqlParamErr {
         #[source_code]
-        source_code: NamedSource<String>,
+        source_code: NamedSource<Arc<str>>,
         #[label("here")]
         at: SourceSpan,
 
@@ -51,11 +79,34 @@ pub enum Error {
         err: String,
     },
 
+    #[error("{message}")]
+    #[diagnostic(code(gelatin::invalid))]
+    Invalid {
+        #[source_code]
+        source_code: NamedSource<Arc<str>>,
+        #[label("here")]
+        at: SourceSpan,
+        message: String,
+    },
+
+    #[error("unknown directive `{directive}`")]
+    #[diagnostic(code(gelatin::unknown_directive))]
+    UnknownDirective {
+        #[source_code]
+        source_code: NamedSource<Arc<str>>,
+        #[label("not a directive")]
+        at: SourceSpan,
+        directive: String,
+
+        #[help]
+        supported: String,
+    },
+
     #[error("alias is unbound error")]
     #[diagnostic(code(gelatin::unbound_alias))]
     UnboundAlias {
         #[source_code]
-        source_code: String,
+        source_code: NamedSource<Arc<str>>,
         #[label("alias here")]
         at: SourceSpan,
     },
@@ -64,12 +115,413 @@ pub enum Error {
     #[diagnostic(code(gelatin::unbound_name))]
     UnboundName {
         #[source_code]
-        source_code: String,
+        source_code: NamedSource<Arc<str>>,
         #[label("name here")]
         at: SourceSpan,
     },
 
+    #[error("could not read {path}")]
+    #[diagnostic(code(gelatin::read_error))]
+    Read {
+        #[source_code]
+        source_code: NamedSource<Arc<str>>,
+        #[label("here")]
+        at: SourceSpan,
+        path: String,
+
+        #[help]
+        err: String,
+    },
+
+    #[error("found {count} errors")]
+    #[diagnostic(code(gelatin::multiple_errors))]
+    Multiple {
+        count: usize,
+        #[related]
+        errors: Vec<Self>,
+    },
+
+    #[error("aborting due to {count} denied warnings")]
+    #[diagnostic(
+        code(gelatin::denied_warnings),
+        help("use `--warn <CODE>` or `--allow <CODE>` to let them through")
+    )]
+    DeniedWarnings { count: usize },
+
+    #[error("invalid utf-8 at byte {offset}")]
+    #[diagnostic(
+        code(gelatin::invalid_utf8),
+        help("gelatin scripts have to be utf-8 encoded")
+    )]
+    InvalidUtf8 {
+        #[source_code]
+        source_code: NamedSource<Arc<str>>,
+        #[label("here")]
+        at: SourceSpan,
+        offset: usize,
+    },
+
+    #[error("could not deploy to {url}")]
+    #[diagnostic(code(gelatin::deploy_error))]
+    Deploy {
+        url: String,
+
+        #[help]
+        err: String,
+    },
+
+    #[error("deploying needs curl, which isn't on the PATH")]
+    #[diagnostic(
+        code(gelatin::curl_missing),
+        help("install curl, `gelatin deploy` posts the document to XOG with it")
+    )]
+    CurlMissing,
+
+    #[error("XOG finished the write with status {state}")]
+    #[diagnostic(code(gelatin::xog_failure))]
+    XogFailure {
+        state: String,
+
+        #[help]
+        errors: Option<String>,
+    },
+
+    #[error("found errors in {failed} of {total} files")]
+    #[diagnostic(code(gelatin::failed_files))]
+    Failed { failed: usize, total: usize },
+
+    #[error("{count} files have warnings `gelatin fix` fixes")]
+    #[diagnostic(code(gelatin::unfixed), help("run `gelatin fix` on them to fix them"))]
+    Unfixed { count: usize },
+
+    #[error("fixing {path} would stop it from building, so it was left as is")]
+    #[diagnostic(code(gelatin::broken_fix))]
+    BrokenFix {
+        path: String,
+
+        #[help]
+        err: String,
+    },
+
+    #[error("building several scripts needs an `--out-dir`")]
+    #[diagnostic(
+        code(gelatin::no_out_dir),
+        help("the script of each input is written to it, named after the input")
+    )]
+    NoOutDir,
+
+    #[error("could not compare the scripts")]
+    #[diagnostic(code(gelatin::diff_error))]
+    Diff {
+        #[help]
+        err: String,
+    },
+
+    #[error("the scripts differ in {count} places")]
+    #[diagnostic(code(gelatin::different_scripts))]
+    Different { count: usize },
+
+    #[error("{count} files aren't formatted")]
+    #[diagnostic(
+        code(gelatin::unformatted),
+        help("run `gelatin fmt` on them to fix them")
+    )]
+    Unformatted { count: usize },
+
     #[error("value error: {message}")]
     #[diagnostic(code(gelatin::value_error))]
     Value { message: String },
 }
+
+/// Errors found while writing the script, the source code is attached by the caller.
+#[derive(Error, Diagnostic, Debug)]
+pub enum TranspileError {
+    #[error("{message}")]
+    #[diagnostic(code(gelatin::transpile_error))]
+    Unsupported {
+        #[label("here")]
+        at: SourceSpan,
+        message: String,
+    },
+
+    #[error(transparent)]
+    #[diagnostic(code(gelatin::xml_write_error))]
+    Xml(#[from] xml::writer::Error),
+}
+
+/// Likely mistakes that still transpile, the source code is attached by the caller.
+#[derive(Error, Diagnostic, Debug)]
+pub enum Warning {
+    #[error("`{name}` shadows an earlier binding")]
+    #[diagnostic(
+        code(gelatin::shadowing),
+        severity(Warning),
+        help("the previous value of `{name}` is overwritten from here on")
+    )]
+    Shadowing {
+        name: String,
+        #[label("rebound here")]
+        at: SourceSpan,
+    },
+
+    #[error("`{name}` is never used")]
+    #[diagnostic(
+        code(gelatin::unused_variable),
+        severity(Warning),
+        help("remove it or prefix it with an underscore, as in `_{name}`")
+    )]
+    UnusedVariable {
+        name: String,
+        #[label("bound here")]
+        at: SourceSpan,
+    },
+
+    #[error("alias `{name}` is never used")]
+    #[diagnostic(code(gelatin::unused_alias), severity(Warning))]
+    UnusedAlias {
+        name: String,
+        #[label("aliased here")]
+        at: SourceSpan,
+    },
+
+    #[error("{what} `{name}` is never called")]
+    #[diagnostic(
+        code(gelatin::unused_function),
+        severity(Warning),
+        help("no script of the project calls it, remove it or prefix it with an underscore, as in `_{name}`")
+    )]
+    UnusedFunction {
+        /// `function` or `template`.
+        what: String,
+        name: String,
+        #[label("defined here")]
+        at: SourceSpan,
+    },
+
+    #[error("none of the templates of `{path}` are called")]
+    #[diagnostic(
+        code(gelatin::unused_import),
+        severity(Warning),
+        help("remove the import")
+    )]
+    UnusedImport {
+        path: String,
+        #[label("imported here")]
+        at: SourceSpan,
+    },
+
+    #[error("suspicious sql: {message}")]
+    #[diagnostic(code(gelatin::suspicious_sql), severity(Warning))]
+    SuspiciousSql {
+        message: String,
+        #[label("in this query")]
+        at: SourceSpan,
+    },
+
+    #[error("{message}")]
+    #[diagnostic(code(gelatin::sql_lint), severity(Warning))]
+    SqlLint {
+        message: String,
+        #[label("here")]
+        at: SourceSpan,
+    },
+
+    #[error("this select has no row limit and runs in a loop that updates rows")]
+    #[diagnostic(
+        code(gelatin::unbounded_query),
+        severity(Warning),
+        help("page it with `limit = ...` and `offset = ...`, or a sql `LIMIT`")
+    )]
+    UnboundedQuery {
+        #[label("unbounded")]
+        at: SourceSpan,
+    },
+
+    #[error("unknown column `{column}`")]
+    #[diagnostic(
+        code(gelatin::unknown_column),
+        severity(Warning),
+        help("the query selects {columns}")
+    )]
+    UnknownColumn {
+        column: String,
+        columns: String,
+        #[label("not a column of the query")]
+        at: SourceSpan,
+        #[label("the query")]
+        query: SourceSpan,
+    },
+
+    #[error("mismatched types: {message}")]
+    #[diagnostic(
+        code(gelatin::type_mismatch),
+        severity(Warning),
+        help("Clarity fails on this or gives a wrong result when the script runs")
+    )]
+    TypeMismatch {
+        message: String,
+        #[label("here")]
+        at: SourceSpan,
+    },
+
+    #[error("testing {ty}, which isn't a bool")]
+    #[diagnostic(
+        code(gelatin::non_boolean_test),
+        severity(Warning),
+        help("Jelly tests the text of the value, which is true only for `true`, compare it instead, as in `{fix}`")
+    )]
+    NonBooleanTest {
+        ty: String,
+        /// The test written as a comparison.
+        fix: String,
+        #[label("tested here")]
+        at: SourceSpan,
+        /// The test, if `at` is all of it, so `fix` can be written in its place.
+        test: Option<String>,
+    },
+
+    #[error("the `{attribute}` attribute {reason}")]
+    #[diagnostic(
+        code(gelatin::invalid_attribute),
+        severity(Warning),
+        help("GEL may fail to read it, bind parts of it to variables with `let`s, or write long text with `out`, whose text isn't an attribute")
+    )]
+    InvalidAttribute {
+        attribute: String,
+        reason: String,
+        #[label("written with it")]
+        at: SourceSpan,
+    },
+
+    #[error("{what} looks like a secret written in the script")]
+    #[diagnostic(
+        code(gelatin::hardcoded_secret),
+        severity(Warning),
+        help("anyone who can read the script can read it, pass it as a parameter of the process or a system property and read it with `secret \"NAME\"`")
+    )]
+    HardcodedSecret {
+        what: String,
+        #[label("written here")]
+        at: SourceSpan,
+    },
+}
+
+/// A change of the source fixing a warning, which can be applied without reviewing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    pub at: SourceSpan,
+    /// The text at `at` the fix was made for, it doesn't apply to any other.
+    pub original: String,
+    pub replacement: String,
+}
+
+impl Warning {
+    /// The fix of the warning, if it's a mechanical one.
+    #[must_use]
+    pub fn fix(&self) -> Option<Fix> {
+        match self {
+            Self::UnusedVariable { name, at } => Some(Fix {
+                at: *at,
+                original: name.clone(),
+                replacement: format!("_{name}"),
+            }),
+            Self::NonBooleanTest {
+                fix,
+                at,
+                test: Some(test),
+                ..
+            } => Some(Fix {
+                at: *at,
+                original: test.clone(),
+                replacement: fix.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn code(&self) -> WarningCode {
+        match self {
+            Self::Shadowing { .. } => WarningCode::Shadowing,
+            Self::UnusedVariable { .. } => WarningCode::UnusedVariable,
+            Self::UnusedAlias { .. } => WarningCode::UnusedAlias,
+            Self::UnusedFunction { .. } => WarningCode::UnusedFunction,
+            Self::UnusedImport { .. } => WarningCode::UnusedImport,
+            Self::SuspiciousSql { .. } => WarningCode::SuspiciousSql,
+            Self::SqlLint { .. } => WarningCode::SqlLint,
+            Self::UnboundedQuery { .. } => WarningCode::UnboundedQuery,
+            Self::UnknownColumn { .. } => WarningCode::UnknownColumn,
+            Self::TypeMismatch { .. } => WarningCode::TypeMismatch,
+            Self::NonBooleanTest { .. } => WarningCode::NonBooleanTest,
+            Self::InvalidAttribute { .. } => WarningCode::InvalidAttribute,
+            Self::HardcodedSecret { .. } => WarningCode::HardcodedSecret,
+        }
+    }
+}
+
+/// Names the kind of a [`Warning`] on the command line.
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WarningCode {
+    /// A `let` rebinding a name that is already bound.
+    Shadowing,
+    /// A `let` or function parameter that is never read.
+    UnusedVariable,
+    /// An `alias` that is never used.
+    UnusedAlias,
+    /// A function or template no script of the project calls, reported by `check
+    /// --project`.
+    UnusedFunction,
+    /// A `template from` whose templates aren't called, reported by `check --project`.
+    UnusedImport,
+    /// An `update` or `delete` without a `where` clause.
+    SuspiciousSql,
+    /// Sql that works but is easy to get wrong: `select *`, joins without a condition and
+    /// ordered subqueries.
+    SqlLint,
+    /// A select without a row limit in a loop that updates rows.
+    UnboundedQuery,
+    /// A column read from the rows of a select that doesn't select it.
+    UnknownColumn,
+    /// A value of a type that can't work where it's used, such as a string compared with an
+    /// int or a `for` over a number.
+    TypeMismatch,
+    /// An `if` or `while` testing a value that isn't a bool, such as a string or the rows of
+    /// a query.
+    NonBooleanTest,
+    /// A `value` or `test` attribute with control characters, such as newlines, or longer
+    /// than `--max-attribute-length`.
+    InvalidAttribute,
+    /// A password or token written as a literal in a soap or http request, a XOG login, or
+    /// the tags of the email and FTP libraries.
+    HardcodedSecret,
+}
+
+/// What to do when a warning fires.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl WarningCode {
+    #[must_use]
+    pub const fn default_level(self) -> Level {
+        match self {
+            // rebinding is how scripts update a variable, and the sql lints are a matter of
+            // style, so they're opt-in.
+            Self::Shadowing | Self::SqlLint => Level::Allow,
+            Self::UnusedVariable
+            | Self::UnusedAlias
+            | Self::UnusedFunction
+            | Self::UnusedImport
+            | Self::SuspiciousSql
+            | Self::UnboundedQuery
+            | Self::UnknownColumn
+            | Self::TypeMismatch
+            | Self::NonBooleanTest
+            | Self::InvalidAttribute
+            | Self::HardcodedSecret => Level::Warn,
+        }
+    }
+}
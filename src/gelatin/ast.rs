@@ -1,25 +1,40 @@
-use std::{borrow::Cow, collections::HashMap, fmt::Write, sync::Arc};
+use std::{borrow::Cow, fmt::Write, sync::Arc};
 
 use sqlparser::ast::Statement;
 
+use super::intern::intern;
 use crate::gelatin::Error;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct Ident(Arc<str>);
+/// A name, along with where it was written if it comes from the source.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ident {
+    name: Arc<str>,
+    span: Span,
+}
+
+// where an ident was written doesn't make it a different one
+impl PartialEq for Ident {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Ident {}
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Name {
     Ident(Ident),
-    Dotted { parent: Box<Name>, attrs: Vec<Name> },
+    Dotted { parent: Box<Self>, attrs: Vec<Self> },
 }
 
 impl<S> From<S> for Name
 where
-    S: Into<Arc<str>>,
+    S: AsRef<str>,
 {
     fn from(value: S) -> Self {
-        let value: Arc<str> = value.into();
-        let mut names = value.split('.');
+        let mut names = value.as_ref().split('.');
         let parent = Self::Ident(Ident::from(names.next().expect("expected a name")));
 
         let mut attrs = Vec::new();
@@ -58,7 +73,7 @@ impl From<i64> for Value {
 
 impl From<Arc<str>> for Expr {
     fn from(value: Arc<str>) -> Self {
-        Self::Value(Value::Str(value))
+        Self::from(Value::Str(value))
     }
 }
 
@@ -67,11 +82,15 @@ where
     V: Into<Value>,
 {
     fn from(value: V) -> Self {
-        Self::Value(value.into())
+        Self::Value {
+            value: value.into(),
+            span: Span::default(),
+        }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     // null
     Nothing,
@@ -79,10 +98,13 @@ pub enum Value {
     Unit,
     Bool(bool),
     Int(i64),
+    /// Text as it's written in an attribute, its `${}`s are evaluated and `$${` is a literal
+    /// `${`, as `$$${` is a `$` before an expression.
     Str(Arc<str>),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Datasource {
     Niku,
     Dwh,
@@ -90,6 +112,7 @@ pub enum Datasource {
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 #[allow(clippy::upper_case_acronyms)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HttpVerb {
     POST,
     GET,
@@ -98,6 +121,7 @@ pub enum HttpVerb {
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 #[allow(clippy::upper_case_acronyms)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QueryType {
     SELECT,
     UPDATE,
@@ -105,13 +129,31 @@ pub enum QueryType {
     DELETE,
 }
 
+/// A value given to a `?` of a query, `type` is the sql type it is cast to with `as`.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SqlParam {
+    pub value: Expr,
+    pub r#type: Option<Arc<str>>,
+}
+
+/// An argument of an [`Expr::Invoke`], `type` is the java type it is passed as with `as`.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TypedArg {
+    pub value: Expr,
+    pub r#type: Option<Arc<str>>,
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Call {
     pub name: Name,
     pub args: Vec<Expr>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InfixOp {
     Add,
     Sub,
@@ -123,16 +165,91 @@ pub enum InfixOp {
     Gt,
     Lte,
     Gte,
+    And,
+    Or,
+}
+
+/// The entries of a dict, in the order they were written so the output is stable.
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Dict(Vec<(Arc<str>, Expr)>);
+
+impl Dict {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Sets `key` to `value`, a key that's already there keeps its position.
+    pub fn insert(&mut self, key: Arc<str>, value: Expr) -> Option<Expr> {
+        if let Some((_, old)) = self.0.iter_mut().find(|(k, _)| *k == key) {
+            return Some(std::mem::replace(old, value));
+        }
+
+        self.0.push((key, value));
+        None
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.0.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Expr> {
+        self.0.iter().map(|(_, v)| v)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Expr> {
+        self.0.iter_mut().map(|(_, v)| v)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Arc<str>, &Expr)> {
+        self.0.iter().map(|(k, v)| (k, v))
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl IntoIterator for Dict {
+    type Item = (Arc<str>, Expr);
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl Extend<(Arc<str>, Expr)> for Dict {
+    fn extend<T: IntoIterator<Item = (Arc<str>, Expr)>>(&mut self, iter: T) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[allow(clippy::large_enum_variant)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     Infix {
-        lhs: Box<Expr>,
+        lhs: Box<Self>,
         op: InfixOp,
-        rhs: Box<Expr>,
+        rhs: Box<Self>,
+        /// From the start of `lhs` to the end of `rhs`, the default span if generated.
+        span: Span,
+    },
+    Value {
+        value: Value,
+        /// Where the literal was written, the default span if it was generated.
+        span: Span,
     },
-    Value(Value),
     Call(Call),
     Func {
         params: Vec<Ident>,
@@ -147,33 +264,96 @@ pub enum Expr {
     },
     Ident(Name),
     Alias(Ident),
-    Dict(HashMap<Arc<str>, Expr>),
+    /// A `name = value` keyword argument.
+    Named {
+        name: Ident,
+        value: Box<Self>,
+    },
+    Dict(Dict),
     Query {
         datasource: Datasource,
         r#type: QueryType,
         query: Statement,
-        params: Vec<Expr>,
+        /// The sql `query` was parsed from.
+        text: Arc<str>,
+        params: Vec<SqlParam>,
+        /// The `limit` of a select, rendered as `maxRows`.
+        max_rows: Option<Box<Self>>,
+        /// The `offset` of a select, rendered as `startRow`.
+        start_row: Option<Box<Self>>,
     },
+    /// The [`Expr::Query`]s of a query block holding several `insert`/`update`/`delete`
+    /// statements, run one after another.
+    Batch(Vec<Self>),
     Http {
         verb: HttpVerb,
-        url: Box<Expr>,
+        url: Box<Self>,
         body: Vec<Stmt>,
     },
     Json {
         expr: Ident,
     },
+    /// Walks into a parsed JSON value, e.g. `json_get! resp "data.items[0].name"`.
+    JsonPath {
+        root: Name,
+        path: Vec<PathSegment>,
+    },
     Instance {
         class: Name,
-        args: Vec<Expr>,
+        args: Vec<Self>,
+    },
+    /// Calls `method` on `object` with a tag, rather than an EL expression, so the types of
+    /// the arguments pick the overload.
+    Invoke {
+        object: Name,
+        method: Ident,
+        args: Vec<TypedArg>,
+    },
+    /// Logs into XOG, binding the session id.
+    XogLogin {
+        endpoint: Box<Self>,
+        username: Box<Self>,
+        password: Box<Self>,
+    },
+    /// Logs out the session created by a [`Expr::XogLogin`].
+    XogLogout {
+        session: Ident,
+    },
+    /// The lines of the file at `path`, only iterable by a `for`.
+    ReadLines {
+        path: Box<Self>,
+    },
+    /// The rows of the csv file at `path`, only iterable by a `for`.
+    Csv {
+        path: Box<Self>,
+        delimiter: Box<Self>,
+        headers: bool,
+    },
+    /// Selects `path` out of the XML document `source`.
+    XPath {
+        source: Ident,
+        path: Arc<str>,
     },
     Soap {
-        endpoint: Arc<str>,
+        endpoint: Box<Self>,
+        #[cfg_attr(feature = "serde", serde(with = "xml_events"))]
         header: Option<Vec<xml::reader::XmlEvent>>,
+        #[cfg_attr(feature = "serde", serde(with = "xml_events"))]
         body: Option<Vec<xml::reader::XmlEvent>>,
+        /// An [`Expr::Func`] run when the response holds a SOAP fault.
+        on_fault: Option<Box<Self>>,
     },
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PathSegment {
+    Key(Arc<str>),
+    Index(usize),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LogLevel {
     Debug,
     Info,
@@ -182,6 +362,7 @@ pub enum LogLevel {
 }
 
 impl LogLevel {
+    #[must_use]
     pub const fn as_str(&self) -> &'static str {
         match self {
             Self::Error => "ERROR",
@@ -192,55 +373,171 @@ impl LogLevel {
     }
 }
 
+/// The type of the value a process sets a [`Stmt::Param`] to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParamType {
+    String,
+    Int,
+    Float,
+    Bool,
+    Date,
+}
+
+impl ParamType {
+    #[must_use]
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::String => "string",
+            Self::Int => "int",
+            Self::Float => "float",
+            Self::Bool => "bool",
+            Self::Date => "date",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Stmt {
-    Block(Vec<Stmt>),
+    Block(Vec<Self>),
     Catch {
         name: Ident,
-        body: Vec<Stmt>,
+        body: Vec<Self>,
     },
     Let(Ident, Expr),
     Alias {
         alias: Ident,
         cls: Expr,
     },
+    /// Declares the `xmlns:{prefix}` namespace on the `gel:script`.
+    Namespace {
+        prefix: Ident,
+        uri: Arc<str>,
+    },
     Expr {
         expr: Expr,
     },
     ForEach {
         var: Ident,
         items: Expr,
-        body: Vec<Stmt>,
+        body: Vec<Self>,
     },
     While {
         test: Expr,
-        body: Vec<Stmt>,
+        body: Vec<Self>,
     },
     If {
         test: Expr,
-        body: Vec<Stmt>,
-        alt: Option<Vec<Stmt>>,
+        body: Vec<Self>,
+        alt: Option<Vec<Self>>,
     },
     Log {
         level: LogLevel,
         message: Arc<str>,
     },
+    /// Writes everything `body` outputs into the file at `path`.
+    WriteFile {
+        path: Expr,
+        escape_xml: bool,
+        body: Vec<Self>,
+    },
+    /// An xml comment kept in the generated script.
+    Comment(Arc<str>),
+    /// Runs `body` preserving the whitespace of its output.
+    Whitespace(Vec<Self>),
+    /// Runs the queries in `body` on one connection to `datasource`, committing them at the
+    /// end or rolling all of them back if any fails.
+    Transaction {
+        datasource: Datasource,
+        body: Vec<Self>,
+    },
+    /// Sets each of `props` on the bean `object`.
+    SetProperties {
+        object: Name,
+        props: Dict,
+    },
+    /// Outputs `value` as text, see [`Stmt::WriteFile`].
+    Out {
+        value: Expr,
+    },
+    /// Defines the template `name`, whose calls are written as the tags of its body with
+    /// the arguments in place of `params`.
+    Template {
+        name: Ident,
+        params: Vec<Ident>,
+    },
+    /// A parameter of the script, which the process running it sets.
+    Param {
+        name: Ident,
+        r#type: Option<ParamType>,
+        /// What the process is expected to set it to.
+        description: Option<Arc<str>>,
+    },
+    /// Tags written as they are, from an `xml!` or the body of a template call.
+    Xml(#[cfg_attr(feature = "serde", serde(with = "xml_tags"))] Vec<xml::reader::XmlEvent>),
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Node {
     Expr(Expr),
     Stmt(Stmt),
 }
 
+/// A region of the source, `line` and `col` are 1-based.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub offset: usize,
+    pub len: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+/// A `node` along with the region of the source it was parsed from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl From<pest::Span<'_>> for Span {
+    fn from(span: pest::Span<'_>) -> Self {
+        let (line, col) = span.start_pos().line_col();
+
+        Self {
+            offset: span.start(),
+            len: span.end() - span.start(),
+            line,
+            col,
+        }
+    }
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `end`.
+    #[must_use]
+    pub const fn to(self, end: Self) -> Self {
+        Self {
+            len: end.offset + end.len - self.offset,
+            ..self
+        }
+    }
+}
+
+impl From<Span> for miette::SourceSpan {
+    fn from(span: Span) -> Self {
+        Self::new(span.offset.into(), span.len)
+    }
+}
+
 impl std::fmt::Display for Name {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Ident(ident) => write!(f, "{ident}"),
-            Self::Dotted {
-                parent: box parent,
-                attrs,
-            } => {
+            Self::Dotted { parent, attrs } => {
                 write!(f, "{parent}")?;
 
                 for attr in attrs {
@@ -253,18 +550,45 @@ impl std::fmt::Display for Name {
     }
 }
 
+impl Name {
+    /// Where the name was written, see [`Ident::span`].
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Ident(ident) => ident.span(),
+            Self::Dotted { parent, .. } => parent.span(),
+        }
+    }
+}
+
 impl Ident {
+    #[must_use]
     pub fn as_str(&self) -> &str {
-        &self.0
+        &self.name
+    }
+
+    /// Where the ident was written, the default span if it was generated.
+    #[must_use]
+    pub const fn span(&self) -> Span {
+        self.span
+    }
+
+    #[must_use]
+    pub const fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
     }
 }
 
 impl<S> From<S> for Ident
 where
-    S: Into<Arc<str>>,
+    S: AsRef<str>,
 {
     fn from(value: S) -> Self {
-        Self(value.into())
+        Self {
+            name: intern(value.as_ref()),
+            span: Span::default(),
+        }
     }
 }
 
@@ -307,6 +631,7 @@ pub enum Context {
 
 impl Name {
     /// Returns a string representation of the `Value` as a java value.
+    #[must_use]
     pub fn as_value(&self, ctx: Context) -> Cow<'_, str> {
         if matches!(ctx, Context::Text) {
             // use an expr to get the value out of the ident
@@ -329,19 +654,57 @@ impl std::fmt::Display for InfixOp {
             Self::Gt => write!(f, ">"),
             Self::Lte => write!(f, "<="),
             Self::Gte => write!(f, ">="),
+            Self::And => write!(f, "&&"),
+            Self::Or => write!(f, "||"),
         }
     }
 }
 
 impl Expr {
+    #[must_use]
     pub fn infix(lhs: Self, op: InfixOp, rhs: Self) -> Self {
         Self::Infix {
             lhs: Box::new(lhs),
             op,
             rhs: Box::new(rhs),
+            span: Span::default(),
         }
     }
 
+    /// Sets where a literal or an infix expression was written if it isn't set yet, other
+    /// expressions are spanned by their names.
+    #[must_use]
+    pub fn with_span(mut self, at: Span) -> Self {
+        if let Self::Value { span, .. } | Self::Infix { span, .. } = &mut self {
+            if *span == Span::default() {
+                *span = at;
+            }
+        }
+        self
+    }
+
+    /// Where the expression was written, or for those spanned by their names, the first of
+    /// them. `None` if it was generated.
+    #[must_use]
+    pub fn span(&self) -> Option<Span> {
+        let span = match self {
+            Self::Value { span, .. } | Self::Infix { span, .. } if *span != Span::default() => {
+                *span
+            }
+            Self::Infix { lhs, rhs, .. } => return lhs.span().or_else(|| rhs.span()),
+            Self::Ident(name)
+            | Self::StaticField(name)
+            | Self::Call(Call { name, .. })
+            | Self::Static(Call { name, .. })
+            | Self::Instance { class: name, .. }
+            | Self::Invoke { object: name, .. }
+            | Self::JsonPath { root: name, .. } => name.span(),
+            Self::Named { name, .. } | Self::Alias(name) => name.span(),
+            _ => return None,
+        };
+        (span != Span::default()).then_some(span)
+    }
+
     pub fn get_static<N: Into<Name>>(name: N) -> Self {
         Self::StaticField(name.into())
     }
@@ -353,6 +716,28 @@ impl Expr {
         })
     }
 
+    /// Reads `field` from `class`, see [`Expr::static_method`].
+    #[must_use]
+    pub fn static_field(class: &str, field: &str) -> Self {
+        Self::StaticField(Name::Dotted {
+            parent: Box::new(Name::Ident(class.into())),
+            attrs: vec![field.into()],
+        })
+    }
+
+    /// Invokes `method` on `class`, keeping the whole class name as the parent so it
+    /// can be emitted as a `core:invokeStatic` `className`.
+    #[must_use]
+    pub fn static_method(class: &str, method: &str, args: Vec<Self>) -> Self {
+        Self::Static(Call {
+            name: Name::Dotted {
+                parent: Box::new(Name::Ident(class.into())),
+                attrs: vec![method.into()],
+            },
+            args,
+        })
+    }
+
     pub fn call<N: Into<Name>>(name: N, args: Vec<Self>) -> Self {
         Self::Call(Call {
             name: name.into(),
@@ -360,10 +745,23 @@ impl Expr {
         })
     }
 
+    /// Returns `true` if the expression can be rendered with [`Expr::as_value`] rather than
+    /// having to be bound with a `let`. Dicts aren't, EL has no literal for them.
+    pub fn is_inline(&self) -> bool {
+        match self {
+            Self::Value { value, .. } => !matches!(value, Value::Unit),
+            Self::Ident(_) | Self::StaticField(_) | Self::JsonPath { .. } => true,
+            Self::Call(Call { args, .. }) => args.iter().all(Self::is_inline),
+            Self::Infix { lhs, rhs, .. } => lhs.is_inline() && rhs.is_inline(),
+            _ => false,
+        }
+    }
+
     /// Returns a string representation of the `Value` as a java value.
+    #[must_use]
     pub fn as_value(&self, ctx: Context) -> Cow<'_, str> {
         match self {
-            Self::Value(v) => v.as_value(ctx),
+            Self::Value { value: v, .. } => v.as_value(ctx),
             Self::Ident(v) => v.as_value(ctx),
             Self::Call(Call { name: func, args }) => {
                 let mut buff = String::new();
@@ -378,12 +776,14 @@ impl Expr {
                     func.as_value(ctx)
                 );
 
-                buff.extend(
-                    args.iter()
+                buff.push_str(
+                    &args
+                        .iter()
                         .map(|arg| arg.as_value(ctx))
-                        .intersperse(", ".into()),
+                        .collect::<Vec<_>>()
+                        .join(", "),
                 );
-                let _ = write!(buff, "){}", if should_expr { "}" } else { "" },);
+                let _ = write!(buff, "){}", if should_expr { "}" } else { "" });
 
                 buff.into()
             }
@@ -391,17 +791,18 @@ impl Expr {
                 let mut buff = String::new();
                 let _ = write!(buff, "{{");
 
-                buff.extend(
-                    map.iter()
+                buff.push_str(
+                    &map.iter()
                         .map(|(k, v)| format!("{k}: {}", v.as_value(ctx)))
-                        .intersperse(", ".to_string()),
+                        .collect::<Vec<_>>()
+                        .join(", "),
                 );
 
                 let _ = write!(buff, "}}");
 
                 buff.into()
             }
-            Self::Infix { lhs, op, rhs } => {
+            Self::Infix { lhs, op, rhs, .. } => {
                 if matches!(ctx, Context::Text) {
                     return Cow::Owned(format!(
                         "${{({} {op} {})}}",
@@ -416,35 +817,75 @@ impl Expr {
                 ))
             }
             Self::StaticField(name) => name.as_value(ctx),
+            Self::JsonPath { root, path } => {
+                let mut buff = root.as_value(Context::Expr).into_owned();
+
+                // Every segment but the last one knows if it yields an object or an array.
+                for (i, segment) in path.iter().enumerate() {
+                    let getter = match path.get(i + 1) {
+                        Some(PathSegment::Key(_)) => "getJSONObject",
+                        Some(PathSegment::Index(_)) => "getJSONArray",
+                        None => "get",
+                    };
+
+                    let _ = match segment {
+                        PathSegment::Key(key) => write!(buff, ".{getter}({})", el_string(key)),
+                        PathSegment::Index(idx) => write!(buff, ".{getter}({idx})"),
+                    };
+                }
+
+                if matches!(ctx, Context::Text) {
+                    return Cow::Owned(format!("${{{buff}}}"));
+                }
+                Cow::Owned(buff)
+            }
             Self::Query { .. }
+            | Self::Batch(_)
             | Self::Http { .. }
             | Self::Json { .. }
+            | Self::XogLogin { .. }
+            | Self::XogLogout { .. }
+            | Self::XPath { .. }
+            | Self::ReadLines { .. }
+            | Self::Csv { .. }
             | Self::Instance { .. }
+            | Self::Invoke { .. }
             | Self::Range { .. }
             | Self::Alias(_)
+            | Self::Named { .. }
             | Self::Static(_)
             | Self::Func { .. }
-            | Self::Soap { .. } => unreachable!("{self:?}"),
+            | Self::Soap { .. } => {
+                unreachable!("{self:?} isn't inline, `Expr::is_inline` is checked before rendering")
+            }
         }
     }
 }
 
 impl Value {
+    /// The string `text`, its `${`s escaped so they're not evaluated.
+    #[must_use]
+    pub fn literal(text: &str) -> Self {
+        Self::Str(text.replace("${", "$${").into())
+    }
+
     /// Returns a string representation of the `Value` as a java value.
+    #[must_use]
     pub fn as_value(&self, ctx: Context) -> Cow<'_, str> {
         match self {
             Self::Nothing => Cow::Borrowed("null"),
             Self::Bool(b) => Cow::Owned(format!("{b}")),
             Self::Int(n) => {
                 if matches!(ctx, Context::Text) {
-                    Cow::Owned(format!("${{n}}"))
+                    Cow::Owned(format!("${{{n}}}"))
                 } else {
                     Cow::Owned(format!("{n}"))
                 }
-            },
+            }
             Self::Str(str) => {
+                // a literal of an EL expression isn't evaluated, so it's written unescaped
                 if matches!(ctx, Context::Expr) {
-                    Cow::Owned(format!("\"{str}\""))
+                    Cow::Owned(el_string(&str.replace("$${", "${")))
                 } else {
                     Cow::Borrowed(str)
                 }
@@ -454,9 +895,28 @@ impl Value {
     }
 }
 
+/// `text` quoted as an EL string, with the characters EL strings can't hold escaped.
+fn el_string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                literal.push('\\');
+                literal.push(c);
+            }
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
 impl std::fmt::Display for Ident {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.name)
     }
 }
 
@@ -470,6 +930,7 @@ impl std::fmt::Display for Datasource {
 }
 
 impl HttpVerb {
+    #[must_use]
     pub const fn as_str(&self) -> &'static str {
         match self {
             Self::POST => "POST",
@@ -484,3 +945,436 @@ impl std::fmt::Display for HttpVerb {
         self.as_str().fmt(f)
     }
 }
+
+/// How many statements the top-level `nodes` are made of, the ones of bodies and functions
+/// included.
+#[must_use]
+pub fn statements(nodes: &[Spanned<Node>]) -> usize {
+    nodes
+        .iter()
+        .map(|Spanned { node, .. }| match node {
+            Node::Stmt(stmt) => stmt_statements(stmt),
+            Node::Expr(expr) => 1 + expr_statements(expr),
+        })
+        .sum()
+}
+
+fn stmt_statements(stmt: &Stmt) -> usize {
+    let nested = match stmt {
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::ForEach { body, .. }
+        | Stmt::While { body, .. }
+        | Stmt::WriteFile { body, .. }
+        | Stmt::Transaction { body, .. } => body.iter().map(stmt_statements).sum(),
+        Stmt::If { body, alt, .. } => body
+            .iter()
+            .chain(alt.iter().flatten())
+            .map(stmt_statements)
+            .sum(),
+        Stmt::Let(_, expr) | Stmt::Expr { expr } => expr_statements(expr),
+        _ => 0,
+    };
+    1 + nested
+}
+
+/// How many statements the bodies of `expr` are made of.
+fn expr_statements(expr: &Expr) -> usize {
+    match expr {
+        Expr::Func { body, .. } | Expr::Http { body, .. } => body.iter().map(stmt_statements).sum(),
+        _ => 0,
+    }
+}
+
+/// The names the `var` attributes of the tags of `events` set.
+pub fn xml_vars(events: &[xml::reader::XmlEvent]) -> impl Iterator<Item = &str> {
+    events.iter().filter_map(|event| match event {
+        xml::reader::XmlEvent::StartElement { attributes, .. } => attributes
+            .iter()
+            .find(|attribute| attribute.name.local_name == "var")
+            .map(|attribute| attribute.value.as_str()),
+        _ => None,
+    })
+}
+
+/// Serializes the xml of a soap `header`/`body` as its text.
+#[cfg(feature = "serde")]
+mod xml_events {
+    use serde::{de::Error as _, ser::Error as _, Deserialize, Deserializer, Serializer};
+    use xml::reader::XmlEvent;
+
+    // `serde(with)` passes the field by reference
+    #[allow(clippy::ref_option)]
+    pub fn serialize<S: Serializer>(
+        events: &Option<Vec<XmlEvent>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let Some(events) = events else {
+            return serializer.serialize_none();
+        };
+
+        let mut writer = xml::EmitterConfig::new()
+            .write_document_declaration(false)
+            .create_writer(Vec::new());
+        for event in events.iter().filter_map(XmlEvent::as_writer_event) {
+            writer.write(event).map_err(S::Error::custom)?;
+        }
+
+        let xml = String::from_utf8(writer.into_inner()).map_err(S::Error::custom)?;
+        serializer.serialize_some(&xml)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<XmlEvent>>, D::Error> {
+        let Some(xml) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+
+        // the xml may have several roots, they are read in a wrapper that is then skipped
+        let source = format!("<gelatin>{xml}</gelatin>");
+        let mut events = xml::reader::ParserConfig::new()
+            .trim_whitespace(true)
+            .create_reader(source.as_bytes())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(D::Error::custom)?;
+
+        // StartDocument and the wrapper, then their closing events
+        events.truncate(events.len() - 2);
+        events.drain(..2);
+
+        Ok(Some(events))
+    }
+}
+
+/// Serializes the tags of a [`Stmt::Xml`] as their text, like [`xml_events`].
+#[cfg(feature = "serde")]
+mod xml_tags {
+    use serde::{Deserializer, Serializer};
+    use xml::reader::XmlEvent;
+
+    // `serde(with)` passes the field by reference
+    #[allow(clippy::ptr_arg)]
+    pub fn serialize<S: Serializer>(
+        events: &Vec<XmlEvent>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        super::xml_events::serialize(&Some(events.clone()), serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<XmlEvent>, D::Error> {
+        super::xml_events::deserialize(deserializer).map(Option::unwrap_or_default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Context, Dict, Expr, InfixOp, Name, PathSegment, Value};
+
+    /// How `expr` is written as an attribute and inside an EL expression.
+    fn rendered(expr: &Expr) -> (String, String) {
+        (
+            expr.as_value(Context::Text).into_owned(),
+            expr.as_value(Context::Expr).into_owned(),
+        )
+    }
+
+    fn ident(name: &str) -> Expr {
+        Expr::Ident(Name::from(name))
+    }
+
+    #[test]
+    fn renders_values() {
+        let cases = [
+            (Expr::from(Value::Nothing), ("null", "null")),
+            (true.into(), ("true", "true")),
+            (5.into(), ("${5}", "5")),
+            ("text".into(), ("text", "\"text\"")),
+            (
+                Expr::from(Value::literal("a ${b}")),
+                ("a $${b}", "\"a ${b}\""),
+            ),
+        ];
+
+        for (expr, (text, el)) in cases {
+            assert_eq!(rendered(&expr), (text.into(), el.into()), "{expr:?}");
+        }
+    }
+
+    #[test]
+    fn renders_names() {
+        assert_eq!(rendered(&ident("row")), ("${row}".into(), "row".into()));
+        assert_eq!(
+            rendered(&ident("row.name")),
+            ("${row.name}".into(), "row.name".into())
+        );
+        assert_eq!(
+            rendered(&Expr::StaticField(Name::from(
+                "java.lang.Integer.MAX_VALUE"
+            ))),
+            (
+                "${java.lang.Integer.MAX_VALUE}".into(),
+                "java.lang.Integer.MAX_VALUE".into()
+            )
+        );
+    }
+
+    #[test]
+    fn renders_calls() {
+        let call = Expr::call("row.get", vec!["name".into(), 1.into(), ident("i")]);
+
+        assert_eq!(
+            rendered(&call),
+            (
+                "${row.get(\"name\", 1, i)}".into(),
+                "row.get(\"name\", 1, i)".into()
+            )
+        );
+        assert_eq!(
+            rendered(&Expr::call("now", vec![])),
+            ("${now()}".into(), "now()".into())
+        );
+    }
+
+    #[test]
+    fn renders_infix_expressions() {
+        let sum = Expr::infix(ident("a"), InfixOp::Add, 1.into());
+        assert_eq!(rendered(&sum), ("${(a + 1)}".into(), "(a + 1)".into()));
+
+        let test = Expr::infix(
+            Expr::infix(ident("a"), InfixOp::Gte, 1.into()),
+            InfixOp::And,
+            Expr::infix(ident("b"), InfixOp::Neq, "x".into()),
+        );
+        assert_eq!(
+            rendered(&test),
+            (
+                "${((a >= 1) && (b != \"x\"))}".into(),
+                "((a >= 1) && (b != \"x\"))".into()
+            )
+        );
+    }
+
+    #[test]
+    fn renders_json_paths() {
+        let path = Expr::JsonPath {
+            root: Name::from("data"),
+            path: vec![
+                PathSegment::Key("items".into()),
+                PathSegment::Index(0),
+                PathSegment::Key("name".into()),
+            ],
+        };
+
+        let el = "data.getJSONArray(\"items\").getJSONObject(0).get(\"name\")";
+        assert_eq!(rendered(&path), (format!("${{{el}}}"), el.to_string()));
+    }
+
+    #[test]
+    fn renders_dicts() {
+        let mut dict = Dict::new();
+        dict.insert("a".into(), 1.into());
+        dict.insert("b".into(), ident("b"));
+
+        // they're only rendered for reports, not as attributes
+        let dict = Expr::Dict(dict);
+        assert!(!dict.is_inline());
+        assert_eq!(
+            rendered(&dict),
+            ("{a: ${1}, b: ${b}}".into(), "{a: 1, b: b}".into())
+        );
+    }
+
+    #[test]
+    fn unit_and_macros_are_not_inline() {
+        assert!(!Expr::from(Value::Unit).is_inline());
+        assert!(!Expr::call("f", vec![Expr::from(Value::Unit)]).is_inline());
+        assert!(!Expr::Instance {
+            class: Name::from("java.lang.Object"),
+            args: vec![],
+        }
+        .is_inline());
+    }
+}
+
+/// Property tests of the rendering, over expressions generated by proptest.
+#[cfg(test)]
+mod properties {
+    use proptest::{collection::vec, prelude::*, sample::select};
+
+    use super::{Context, Expr, InfixOp, Name, PathSegment, Value};
+
+    /// Text full of the characters EL and the escaping of `${` treat specially.
+    fn text() -> impl Strategy<Value = String> {
+        const CHARS: [char; 12] = [
+            'a', 'b', ' ', '"', '\'', '\\', '$', '{', '}', '\n', '\t', 'é',
+        ];
+        vec(select(&CHARS[..]), 0..8).prop_map(String::from_iter)
+    }
+
+    fn name() -> impl Strategy<Value = Name> {
+        select(&["row", "i", "data.items", "java.lang.Integer.MAX_VALUE"][..]).prop_map(Name::from)
+    }
+
+    fn value() -> impl Strategy<Value = Value> {
+        prop_oneof![
+            Just(Value::Nothing),
+            any::<bool>().prop_map(Value::Bool),
+            (-8000..8000i64).prop_map(Value::Int),
+            text().prop_map(|text| Value::literal(&text)),
+        ]
+    }
+
+    fn segment() -> impl Strategy<Value = PathSegment> {
+        prop_oneof![
+            (0..10usize).prop_map(PathSegment::Index),
+            text().prop_map(|key| PathSegment::Key(key.into())),
+        ]
+    }
+
+    /// Inline expressions up to three levels deep.
+    fn expr() -> impl Strategy<Value = Expr> {
+        const OPS: [InfixOp; 6] = [
+            InfixOp::Add,
+            InfixOp::Eq,
+            InfixOp::Lt,
+            InfixOp::Gte,
+            InfixOp::And,
+            InfixOp::Or,
+        ];
+
+        let leaf = prop_oneof![
+            value().prop_map(Expr::from),
+            name().prop_map(Expr::Ident),
+            name().prop_map(Expr::StaticField),
+            (name(), vec(segment(), 1..=3)).prop_map(|(root, path)| Expr::JsonPath { root, path }),
+        ];
+        leaf.prop_recursive(3, 32, 3, |inner| {
+            prop_oneof![
+                (name(), vec(inner.clone(), 0..3))
+                    .prop_map(|(name, args)| Expr::call(format!("{name}.get"), args)),
+                (inner.clone(), select(&OPS[..]), inner)
+                    .prop_map(|(lhs, op, rhs)| Expr::infix(lhs, op, rhs)),
+            ]
+        })
+    }
+
+    /// Reads the EL expression at the start of `chars`, up to the `}` closing it when
+    /// `closed`, returning how many characters it takes.
+    fn read_el(chars: &[char], closed: bool) -> Result<usize, String> {
+        let mut depth = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '"' | '\'' => i += read_string(&chars[i..])?,
+                '{' => depth += 1,
+                '}' if depth > 0 => depth -= 1,
+                '}' if closed => return Ok(i + 1),
+                '}' => return Err(format!("unbalanced `}}` at {i}")),
+                _ => {}
+            }
+            i += 1;
+        }
+        if closed {
+            return Err("unclosed `${`".into());
+        }
+        Ok(i)
+    }
+
+    /// Reads the quoted EL string at the start of `chars`, returning the index of its closing
+    /// quote.
+    fn read_string(chars: &[char]) -> Result<usize, String> {
+        let quote = chars[0];
+        let mut i = 1;
+        while i < chars.len() {
+            match chars[i] {
+                '\\' => i += 1,
+                '\n' | '\r' => return Err("a line break in an EL string".into()),
+                c if c == quote => return Ok(i),
+                _ => {}
+            }
+            i += 1;
+        }
+        Err("unclosed EL string".into())
+    }
+
+    /// Checks that the `${`s of `text` are closed, skipping the escaped `$${`s.
+    fn check_text(text: &str) -> Result<(), String> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i..].starts_with(&['$', '$', '{']) {
+                i += 3;
+            } else if chars[i..].starts_with(&['$', '{']) {
+                i += 2 + read_el(&chars[i + 2..], true)?;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// The text of the EL string `literal`.
+    fn unquote(literal: &str) -> String {
+        let mut chars = literal[1..literal.len() - 1].chars();
+        let mut text = String::new();
+        while let Some(c) = chars.next() {
+            text.push(match (c, c == '\\') {
+                (_, true) => match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some(c) => c,
+                    None => panic!("{literal} ends with an escape"),
+                },
+                (c, false) => c,
+            });
+        }
+        text
+    }
+
+    /// Checks that `el` holds no `${` outside of its strings, as EL doesn't nest them.
+    fn check_el(el: &str) -> Result<(), String> {
+        let chars: Vec<char> = el.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '"' | '\'' => i += read_string(&chars[i..])?,
+                '$' if chars.get(i + 1) == Some(&'{') => return Err(format!("a `${{` at {i}")),
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(2000))]
+
+        #[test]
+        fn expressions_render_balanced(expr in expr()) {
+            let text = expr.as_value(Context::Text);
+            prop_assert_eq!(check_text(&text), Ok(()), "the text {:?} of {:?}", text, expr);
+
+            let el = expr.as_value(Context::Expr);
+            let chars: Vec<char> = el.chars().collect();
+            prop_assert!(read_el(&chars, false).is_ok(), "the EL {:?} of {:?}", el, expr);
+            prop_assert_eq!(check_el(&el), Ok(()), "the EL {:?} of {:?}", el, expr);
+        }
+
+        #[test]
+        fn strings_keep_their_text(text in text()) {
+            let value = Value::literal(&text);
+
+            let el = value.as_value(Context::Expr);
+            prop_assert_eq!(unquote(&el), text.as_str(), "the EL string {:?}", el);
+
+            let attribute = value.as_value(Context::Text).replace("$${", "${");
+            prop_assert_eq!(attribute, text);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Checks of the `value` and `test` attributes the script is written with, which GEL fails
+//! to read when they hold raw control characters such as newlines, or are very long.
+//!
+//! This runs over the expanded script, on the attributes as the transpiler renders them.
+
+use super::ast::{Call, Context, Expr, Node, Span, Spanned, SqlParam, Stmt, TypedArg};
+use crate::errors::Warning;
+
+/// How many characters an attribute can have before it's reported, if not configured.
+pub const MAX_LENGTH: usize = 2000;
+
+/// Checks the attributes of the top-level `nodes`, reporting the ones longer than
+/// `max_length` characters.
+#[must_use]
+pub fn check(nodes: &[Spanned<Node>], max_length: usize) -> Vec<Warning> {
+    let mut checker = Checker {
+        max_length,
+        warnings: vec![],
+        at: Span::default(),
+    };
+    for Spanned { node, span } in nodes {
+        checker.at = *span;
+        if let Node::Stmt(stmt) = node {
+            checker.stmt(stmt);
+        }
+    }
+    checker.warnings
+}
+
+struct Checker {
+    max_length: usize,
+    warnings: Vec<Warning>,
+    /// The top-level statement being checked, the warnings point at it when the expression
+    /// has no span of its own.
+    at: Span,
+}
+
+impl Checker {
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let(_, Expr::Func { body, .. })
+            | Stmt::Block(body)
+            | Stmt::Whitespace(body)
+            | Stmt::Catch { body, .. }
+            | Stmt::ForEach { body, .. }
+            | Stmt::WriteFile { body, .. }
+            | Stmt::Transaction { body, .. } => self.body(body),
+            Stmt::While { test, body } => {
+                self.attribute("test", test);
+                self.body(body);
+            }
+            Stmt::If { test, body, alt } => {
+                self.attribute("test", test);
+                self.body(body);
+                if let Some(alt) = alt {
+                    self.body(alt);
+                }
+            }
+            Stmt::Let(_, expr) | Stmt::Expr { expr } => self.value(expr),
+            _ => {}
+        }
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            self.stmt(stmt);
+        }
+    }
+
+    /// Checks the attributes the value of a `let` or an expression is written with.
+    fn value(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Query { params, .. } => {
+                for SqlParam { value, .. } in params {
+                    self.attribute("value", value);
+                }
+            }
+            Expr::Batch(queries) => {
+                for query in queries {
+                    self.value(query);
+                }
+            }
+            expr if expr.is_inline() => self.attribute("value", expr),
+            // the arguments of the calls written as tags
+            Expr::Call(Call { args, .. })
+            | Expr::Static(Call { args, .. })
+            | Expr::Instance { args, .. } => {
+                for arg in args {
+                    self.attribute("value", arg);
+                }
+            }
+            Expr::Invoke { args, .. } => {
+                for TypedArg { value, .. } in args {
+                    self.attribute("value", value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn attribute(&mut self, attribute: &str, expr: &Expr) {
+        // the transpiler rejects what can't be written as an attribute
+        if !expr.is_inline() {
+            return;
+        }
+        let value = expr.as_value(Context::Text);
+
+        let reason = if let Some(c) = value.chars().find(|c| c.is_control()) {
+            format!("has the control character {c:?}")
+        } else {
+            let length = value.chars().count();
+            if length <= self.max_length {
+                return;
+            }
+            format!(
+                "is {length} characters long, over the limit of {}",
+                self.max_length
+            )
+        };
+
+        self.warnings.push(Warning::InvalidAttribute {
+            attribute: attribute.to_string(),
+            reason,
+            at: expr.span().unwrap_or(self.at).into(),
+        });
+    }
+}
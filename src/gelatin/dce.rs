@@ -0,0 +1,326 @@
+//! Removal of the statements that don't do anything: the `let`s of names that are never
+//! read and the blocks left without statements.
+//!
+//! This runs over the expanded script. A name counts as read if it's used anywhere, or
+//! appears in an EL expression of a string, so a name in a nested scope keeps the `let`s
+//! of all the names like it.
+
+use std::collections::HashSet;
+
+use super::ast::{Call, Expr, Name, Node, Span, Spanned, Stmt, Value};
+
+/// A statement removed from the script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Removal {
+    /// The removed statement, or the top-level one holding it if it has no span.
+    pub at: Span,
+    /// What was removed, such as "`let x`, whose value is never read".
+    pub what: String,
+}
+
+/// Removes the dead statements of the top-level `nodes`, until removing them leaves none.
+pub fn eliminate(nodes: &mut Vec<Spanned<Node>>) -> Vec<Removal> {
+    let mut removals = vec![];
+
+    loop {
+        let mut reads = HashSet::new();
+        for Spanned { node, .. } in nodes.iter() {
+            match node {
+                Node::Stmt(stmt) => read_stmt(stmt, &mut reads),
+                Node::Expr(expr) => read_expr(expr, &mut reads),
+            }
+        }
+
+        let removed = removals.len();
+        nodes.retain_mut(|Spanned { node, span }| match node {
+            Node::Stmt(stmt) => !is_dead(stmt, *span, &reads, &mut removals),
+            Node::Expr(_) => true,
+        });
+
+        if removals.len() == removed {
+            return removals;
+        }
+    }
+}
+
+/// Removes the dead statements of `body`, `at` is the top-level statement holding it.
+fn prune(body: &mut Vec<Stmt>, at: Span, reads: &HashSet<String>, removals: &mut Vec<Removal>) {
+    body.retain_mut(|stmt| !is_dead(stmt, at, reads, removals));
+}
+
+/// Prunes the bodies of `stmt`, returning whether `stmt` itself does nothing, in which case
+/// its removal is recorded.
+fn is_dead(
+    stmt: &mut Stmt,
+    at: Span,
+    reads: &HashSet<String>,
+    removals: &mut Vec<Removal>,
+) -> bool {
+    match stmt {
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::ForEach { body, .. }
+        | Stmt::While { body, .. }
+        | Stmt::WriteFile { body, .. }
+        | Stmt::Transaction { body, .. }
+        | Stmt::Let(_, Expr::Func { body, .. }) => prune(body, at, reads, removals),
+        Stmt::If { body, alt, .. } => {
+            prune(body, at, reads, removals);
+            if let Some(statements) = alt {
+                prune(statements, at, reads, removals);
+                if statements.is_empty() && !body.is_empty() {
+                    *alt = None;
+                    removals.push(Removal {
+                        at,
+                        what: "an `else` without statements".to_string(),
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let (span, what) = match stmt {
+        Stmt::Let(name, expr) if !reads.contains(name.as_str()) && is_pure(expr) => (
+            name.span(),
+            format!("`let {name}`, whose value is never read"),
+        ),
+        Stmt::Block(body) | Stmt::Whitespace(body) if body.is_empty() => {
+            (Span::default(), "a block without statements".to_string())
+        }
+        Stmt::Catch { body, .. } if body.is_empty() => {
+            (Span::default(), "a `catch` without statements".to_string())
+        }
+        Stmt::Transaction { body, .. } if body.is_empty() => (
+            Span::default(),
+            "a `transaction!` without statements".to_string(),
+        ),
+        Stmt::ForEach { items, body, .. } if body.is_empty() && is_pure(items) => {
+            (Span::default(), "a `for` without statements".to_string())
+        }
+        Stmt::If { test, body, alt } if body.is_empty() && alt.is_none() && is_pure(test) => {
+            (Span::default(), "an `if` without statements".to_string())
+        }
+        _ => return false,
+    };
+
+    removals.push(Removal {
+        at: if span == Span::default() { at } else { span },
+        what,
+    });
+    true
+}
+
+/// Whether evaluating `expr` can't have effects, so it can be dropped if nothing reads it.
+fn is_pure(expr: &Expr) -> bool {
+    match expr {
+        // the EL of a string can call methods
+        Expr::Value {
+            value: Value::Str(text),
+            ..
+        } => !text.contains('('),
+        Expr::Value { .. }
+        | Expr::Ident(_)
+        | Expr::Alias(_)
+        | Expr::StaticField(_)
+        | Expr::Range { .. }
+        | Expr::Func { .. } => true,
+        Expr::Infix { lhs, rhs, .. } => is_pure(lhs) && is_pure(rhs),
+        Expr::Named { value, .. } => is_pure(value),
+        Expr::Dict(dict) => dict.values().all(is_pure),
+        _ => false,
+    }
+}
+
+fn read_stmt(stmt: &Stmt, reads: &mut HashSet<String>) {
+    match stmt {
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::Transaction { body, .. } => read_body(body, reads),
+        Stmt::Let(_, expr)
+        | Stmt::Alias { cls: expr, .. }
+        | Stmt::Expr { expr }
+        | Stmt::Out { value: expr } => read_expr(expr, reads),
+        Stmt::ForEach { items, body, .. } => {
+            read_expr(items, reads);
+            read_body(body, reads);
+        }
+        Stmt::While { test, body } => {
+            read_expr(test, reads);
+            read_body(body, reads);
+        }
+        Stmt::If { test, body, alt } => {
+            read_expr(test, reads);
+            read_body(body, reads);
+            if let Some(alt) = alt {
+                read_body(alt, reads);
+            }
+        }
+        Stmt::Log { message, .. } => read_text(message, reads),
+        Stmt::WriteFile { path, body, .. } => {
+            read_expr(path, reads);
+            read_body(body, reads);
+        }
+        Stmt::SetProperties { object, props } => {
+            read_name(object, reads);
+            for value in props.values() {
+                read_expr(value, reads);
+            }
+        }
+        Stmt::Xml(events) => {
+            for event in events {
+                read_event(event, reads);
+            }
+        }
+        Stmt::Namespace { .. } | Stmt::Comment(_) | Stmt::Template { .. } | Stmt::Param { .. } => {}
+    }
+}
+
+fn read_body(body: &[Stmt], reads: &mut HashSet<String>) {
+    for stmt in body {
+        read_stmt(stmt, reads);
+    }
+}
+
+fn read_expr(expr: &Expr, reads: &mut HashSet<String>) {
+    match expr {
+        Expr::Value {
+            value: Value::Str(text),
+            ..
+        } => read_text(text, reads),
+        Expr::Value { .. } | Expr::Range { .. } => {}
+        Expr::Ident(name) | Expr::StaticField(name) | Expr::JsonPath { root: name, .. } => {
+            read_name(name, reads);
+        }
+        Expr::Alias(ident) | Expr::Json { expr: ident } | Expr::XogLogout { session: ident } => {
+            reads.insert(ident.to_string());
+        }
+        Expr::Infix { lhs, rhs, .. } => {
+            read_expr(lhs, reads);
+            read_expr(rhs, reads);
+        }
+        Expr::Call(Call { name, args }) | Expr::Static(Call { name, args }) => {
+            read_name(name, reads);
+            read_exprs(args, reads);
+        }
+        Expr::Instance { class, args } => {
+            read_name(class, reads);
+            read_exprs(args, reads);
+        }
+        Expr::Invoke { object, args, .. } => {
+            read_name(object, reads);
+            for arg in args {
+                read_expr(&arg.value, reads);
+            }
+        }
+        Expr::Func { body, .. } => read_body(body, reads),
+        Expr::Named { value, .. } => read_expr(value, reads),
+        Expr::Dict(dict) => {
+            for value in dict.values() {
+                read_expr(value, reads);
+            }
+        }
+        Expr::Query {
+            text,
+            params,
+            max_rows,
+            start_row,
+            ..
+        } => {
+            read_text(text, reads);
+            for param in params {
+                read_expr(&param.value, reads);
+            }
+            for expr in max_rows.iter().chain(start_row) {
+                read_expr(expr, reads);
+            }
+        }
+        Expr::Batch(queries) => read_exprs(queries, reads),
+        Expr::Http { url, body, .. } => {
+            read_expr(url, reads);
+            read_body(body, reads);
+        }
+        Expr::XogLogin {
+            endpoint,
+            username,
+            password,
+        } => {
+            read_expr(endpoint, reads);
+            read_expr(username, reads);
+            read_expr(password, reads);
+        }
+        Expr::ReadLines { path } => read_expr(path, reads),
+        Expr::Csv {
+            path, delimiter, ..
+        } => {
+            read_expr(path, reads);
+            read_expr(delimiter, reads);
+        }
+        Expr::XPath { source, path } => {
+            reads.insert(source.to_string());
+            read_text(path, reads);
+        }
+        Expr::Soap {
+            endpoint,
+            header,
+            body,
+            on_fault,
+        } => {
+            read_expr(endpoint, reads);
+            for event in header.iter().chain(body).flatten() {
+                read_event(event, reads);
+            }
+            if let Some(on_fault) = on_fault {
+                read_expr(on_fault, reads);
+            }
+        }
+    }
+}
+
+fn read_exprs(exprs: &[Expr], reads: &mut HashSet<String>) {
+    for expr in exprs {
+        read_expr(expr, reads);
+    }
+}
+
+/// Reads the variable `name` starts with, the rest are its properties.
+fn read_name(name: &Name, reads: &mut HashSet<String>) {
+    match name {
+        Name::Ident(ident) => {
+            reads.insert(ident.to_string());
+        }
+        Name::Dotted { parent, .. } => read_name(parent, reads),
+    }
+}
+
+fn read_event(event: &xml::reader::XmlEvent, reads: &mut HashSet<String>) {
+    match event {
+        xml::reader::XmlEvent::StartElement { attributes, .. } => {
+            for attribute in attributes {
+                read_text(&attribute.value, reads);
+            }
+        }
+        xml::reader::XmlEvent::Characters(text) | xml::reader::XmlEvent::CData(text) => {
+            read_text(text, reads);
+        }
+        _ => {}
+    }
+}
+
+/// Reads every word of the `${}` EL expressions in `text`, be it a variable or not.
+fn read_text(text: &str, reads: &mut HashSet<String>) {
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        rest = &rest[start + 2..];
+        let end = rest.find('}').unwrap_or(rest.len());
+
+        let words = rest[..end]
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .filter(|word| !word.is_empty());
+        reads.extend(words.map(ToString::to_string));
+
+        rest = &rest[end..];
+    }
+}
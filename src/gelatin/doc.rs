@@ -0,0 +1,516 @@
+//! Reports of what a script touches: its parameters, the files it imports, the tables its
+//! queries read and write, the endpoints it calls, the XOG objects it reads and writes, the
+//! emails it sends and the files it writes. They're written as Markdown, html or a dot
+//! graph of several scripts.
+//!
+//! This runs over the script before its macros are expanded, so the `http!` and `soap!`
+//! calls are still there. The tables are the ones named in the parsed sql, a name only in
+//! a `${}` of the query isn't known.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    path::PathBuf,
+};
+
+use sqlparser::ast::{Query, Statement};
+
+use super::ast::{Context, Expr, Node, ParamType, Spanned, Stmt};
+
+/// A parameter the script declares with `param`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub r#type: Option<ParamType>,
+    pub description: Option<String>,
+}
+
+/// What a statement does to a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Operation {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Select => "select",
+            Self::Insert => "insert",
+            Self::Update => "update",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// Everything a script touches, each of them once and in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub params: Vec<Param>,
+    /// The files read when the script is parsed, see [`super::Parser::imports`].
+    pub imports: Vec<String>,
+    /// The operations on each table, by datasource and table.
+    pub tables: BTreeMap<(String, String), BTreeSet<Operation>>,
+    /// The endpoints with how they're called, such as `GET` or `SOAP`, as the text of the
+    /// attribute they're written in.
+    pub endpoints: Vec<(String, String)>,
+    /// The `objectType`s of the XOG requests, with their `action`.
+    pub objects: Vec<(String, String)>,
+    /// The recipients of the emails the `jelly:email` tags send.
+    pub emails: Vec<String>,
+    /// The paths of the files written.
+    pub files: Vec<String>,
+}
+
+/// The report of the top-level `nodes`, which shouldn't be expanded, whose parsing read the
+/// `imports`.
+#[must_use]
+pub fn report(nodes: &[Spanned<Node>], imports: &[PathBuf]) -> Report {
+    let mut report = Report {
+        imports: imports
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        ..Report::default()
+    };
+    for Spanned { node, .. } in nodes {
+        match node {
+            Node::Stmt(stmt) => report.stmt(stmt),
+            Node::Expr(expr) => report.expr(expr),
+        }
+    }
+    report
+}
+
+impl Report {
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Param {
+                name,
+                r#type,
+                description,
+            } => self.params.push(Param {
+                name: name.to_string(),
+                r#type: *r#type,
+                description: description.as_deref().map(ToString::to_string),
+            }),
+            Stmt::Let(_, expr) | Stmt::Expr { expr } | Stmt::Out { value: expr } => {
+                self.expr(expr);
+            }
+            Stmt::WriteFile { path, body, .. } => {
+                push_new(&mut self.files, path.as_value(Context::Text).into_owned());
+                self.body(body);
+            }
+            Stmt::Block(body)
+            | Stmt::Whitespace(body)
+            | Stmt::Catch { body, .. }
+            | Stmt::ForEach { body, .. }
+            | Stmt::While { body, .. }
+            | Stmt::Transaction { body, .. } => self.body(body),
+            Stmt::If { body, alt, .. } => {
+                self.body(body);
+                if let Some(alt) = alt {
+                    self.body(alt);
+                }
+            }
+            Stmt::Xml(events) => self.xml(events),
+            _ => {}
+        }
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            self.stmt(stmt);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Query {
+                datasource, query, ..
+            } => {
+                for (table, operation) in tables(query) {
+                    self.tables
+                        .entry((datasource.to_string(), table))
+                        .or_default()
+                        .insert(operation);
+                }
+            }
+            Expr::Batch(queries) => {
+                for query in queries {
+                    self.expr(query);
+                }
+            }
+            Expr::Http { verb, url, body } => {
+                self.endpoint(verb.as_str(), url);
+                self.body(body);
+            }
+            Expr::Soap {
+                endpoint,
+                header,
+                body,
+                on_fault,
+            } => {
+                self.endpoint("SOAP", endpoint);
+                for events in header.iter().chain(body) {
+                    self.xml(events);
+                }
+                if let Some(on_fault) = on_fault {
+                    self.expr(on_fault);
+                }
+            }
+            Expr::XogLogin { endpoint, .. } => self.endpoint("XOG", endpoint),
+            Expr::Func { body, .. } => self.body(body),
+            Expr::Named { value, .. } => self.expr(value),
+            _ => {}
+        }
+    }
+
+    fn endpoint(&mut self, how: &str, url: &Expr) {
+        push_new(
+            &mut self.endpoints,
+            (how.to_string(), url.as_value(Context::Text).into_owned()),
+        );
+    }
+
+    /// Records the XOG objects, emails and files of the tags of `events`.
+    fn xml(&mut self, events: &[xml::reader::XmlEvent]) {
+        for event in events {
+            let xml::reader::XmlEvent::StartElement {
+                name, attributes, ..
+            } = event
+            else {
+                continue;
+            };
+            let attribute = |local_name: &str| {
+                attributes
+                    .iter()
+                    .find(|attribute| attribute.name.local_name == local_name)
+                    .map(|attribute| attribute.value.clone())
+            };
+
+            match (name.namespace.as_deref(), name.local_name.as_str()) {
+                (Some("jelly:email"), "email") => {
+                    push_new(&mut self.emails, attribute("to").unwrap_or_default());
+                }
+                (Some("jelly:core"), "file") => {
+                    push_new(&mut self.files, attribute("name").unwrap_or_default());
+                }
+                (None, "Header") => {
+                    if let Some(object) = attribute("objectType") {
+                        push_new(
+                            &mut self.objects,
+                            (object, attribute("action").unwrap_or_default()),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The report as Markdown, under a `title` heading.
+    #[must_use]
+    pub fn markdown(&self, title: &str) -> String {
+        let mut out = format!("# {title}\n");
+        for (heading, items) in self.sections(false) {
+            let _ = write!(out, "\n## {heading}\n\n");
+            if items.is_empty() {
+                out.push_str("None.\n");
+            }
+            for item in items {
+                let _ = writeln!(out, "- {item}");
+            }
+        }
+        out
+    }
+
+    /// The report as an html `section`, under a `title` heading.
+    #[must_use]
+    pub fn html(&self, title: &str) -> String {
+        let mut out = format!("<section>\n<h1>{}</h1>\n", escape_html(title));
+        for (heading, items) in self.sections(true) {
+            let _ = writeln!(out, "<h2>{heading}</h2>");
+            if items.is_empty() {
+                out.push_str("<p>None.</p>\n");
+                continue;
+            }
+            out.push_str("<ul>\n");
+            for item in items {
+                let _ = writeln!(out, "<li>{item}</li>");
+            }
+            out.push_str("</ul>\n");
+        }
+        out.push_str("</section>\n");
+        out
+    }
+
+    /// The headings and items of the report, as html if `html` and Markdown otherwise.
+    fn sections(&self, html: bool) -> [(&'static str, Vec<String>); 7] {
+        let plain = |text: &str| {
+            if html {
+                escape_html(text)
+            } else {
+                text.to_string()
+            }
+        };
+        let code = |text: &str| {
+            if html {
+                format!("<code>{}</code>", escape_html(text))
+            } else {
+                format!("`{text}`")
+            }
+        };
+
+        let params = self
+            .params
+            .iter()
+            .map(|param| {
+                let mut item = code(&param.name);
+                if let Some(r#type) = param.r#type {
+                    let _ = write!(item, " ({})", r#type.as_str());
+                }
+                if let Some(description) = &param.description {
+                    let _ = write!(item, ": {}", plain(description));
+                }
+                item
+            })
+            .collect();
+        let tables = self
+            .tables
+            .iter()
+            .map(|((datasource, table), operations)| {
+                let operations: Vec<&str> = operations.iter().map(|op| op.as_str()).collect();
+                format!("{} on {datasource}: {}", code(table), operations.join(", "))
+            })
+            .collect();
+        let endpoints = self
+            .endpoints
+            .iter()
+            .map(|(how, url)| format!("{how} {}", code(url)))
+            .collect();
+        let objects = self
+            .objects
+            .iter()
+            .map(|(object, action)| format!("{}: {action}", code(object)))
+            .collect();
+        let imports = self.imports.iter().map(|path| code(path)).collect();
+        let emails = self.emails.iter().map(|to| code(to)).collect();
+        let files = self.files.iter().map(|path| code(path)).collect();
+
+        [
+            ("Parameters", params),
+            ("Imports", imports),
+            ("Tables", tables),
+            ("Endpoints", endpoints),
+            ("XOG objects", objects),
+            ("Emails", emails),
+            ("Files", files),
+        ]
+    }
+}
+
+/// A dot graph of the `reports` of several scripts, by their name. Each script points at
+/// the files it imports, the tables it uses, which point at their datasource, the endpoints
+/// it calls and the XOG objects it reads and writes. The nodes of several scripts are
+/// shared, so what they have in common can be seen.
+#[must_use]
+pub fn dot(reports: &[(String, Report)]) -> String {
+    let mut nodes: Vec<String> = vec![];
+    let mut edges: Vec<String> = vec![];
+    let mut node = |id: String, label: &str, shape: &str| {
+        let line = format!("  {} [label={}, shape={shape}];", quote(&id), quote(label));
+        push_new(&mut nodes, line);
+        id
+    };
+    let mut edge = |from: &str, to: &str, label: Option<&str>| {
+        let mut line = format!("  {} -> {}", quote(from), quote(to));
+        if let Some(label) = label {
+            let _ = write!(line, " [label={}]", quote(label));
+        }
+        line.push(';');
+        push_new(&mut edges, line);
+    };
+
+    for (name, report) in reports {
+        let script = node(format!("script:{name}"), name, "box");
+        for import in &report.imports {
+            let import = node(format!("file:{import}"), import, "note");
+            edge(&script, &import, None);
+        }
+        for ((datasource, table), operations) in &report.tables {
+            let db = node(format!("datasource:{datasource}"), datasource, "cylinder");
+            let table = node(format!("table:{datasource}.{table}"), table, "ellipse");
+            let operations: Vec<&str> = operations.iter().map(|op| op.as_str()).collect();
+            edge(&script, &table, Some(&operations.join(", ")));
+            edge(&table, &db, None);
+        }
+        for (how, url) in &report.endpoints {
+            let endpoint = node(format!("endpoint:{url}"), url, "hexagon");
+            edge(&script, &endpoint, Some(how));
+        }
+        for (object, action) in &report.objects {
+            let object = node(format!("object:{object}"), object, "component");
+            edge(&script, &object, Some(action));
+        }
+    }
+
+    let mut out = String::from("digraph gelatin {\n  rankdir=LR;\n");
+    for line in nodes.iter().chain(&edges) {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// `text` as a quoted dot id.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn push_new<T: PartialEq>(items: &mut Vec<T>, item: T) {
+    if !items.contains(&item) {
+        items.push(item);
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The tables `stmt` names, with what it does to each of them. The tables of its `from`,
+/// joins and subqueries are selected, save for the ones its `with` defines.
+#[allow(clippy::too_many_lines)]
+fn tables(stmt: &Statement) -> Vec<(String, Operation)> {
+    fn visit_tables(tables: &[sqlparser::ast::TableWithJoins], found: &mut Vec<String>) {
+        for table in tables {
+            let joins = table.joins.iter().map(|join| &join.relation);
+            for relation in std::iter::once(&table.relation).chain(joins) {
+                match relation {
+                    sqlparser::ast::TableFactor::Table { name, .. } => {
+                        found.push(name.to_string());
+                    }
+                    sqlparser::ast::TableFactor::Derived { subquery, .. } => {
+                        visit_query(subquery, found);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn visit_query(query: &Query, found: &mut Vec<String>) {
+        let ctes = query.with.iter().flat_map(|with| &with.cte_tables);
+        for cte in ctes.clone() {
+            visit_query(&cte.query, found);
+        }
+
+        // a table the `with` defines isn't one of the database
+        let mut body = vec![];
+        visit_body(&query.body, &mut body);
+        found.extend(body.into_iter().filter(|name| {
+            !ctes
+                .clone()
+                .any(|cte| cte.alias.name.value.eq_ignore_ascii_case(name))
+        }));
+    }
+
+    fn visit_body(body: &sqlparser::ast::SetExpr, found: &mut Vec<String>) {
+        match body {
+            sqlparser::ast::SetExpr::Select(select) => {
+                visit_tables(&select.from, found);
+                if let Some(selection) = &select.selection {
+                    visit_expr(selection, found);
+                }
+            }
+            sqlparser::ast::SetExpr::Query(query) => visit_query(query, found),
+            sqlparser::ast::SetExpr::SetOperation { left, right, .. } => {
+                visit_body(left, found);
+                visit_body(right, found);
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_expr(expr: &sqlparser::ast::Expr, found: &mut Vec<String>) {
+        match expr {
+            sqlparser::ast::Expr::Subquery(subquery)
+            | sqlparser::ast::Expr::InSubquery { subquery, .. }
+            | sqlparser::ast::Expr::Exists { subquery, .. } => visit_query(subquery, found),
+            sqlparser::ast::Expr::BinaryOp { left, right, .. } => {
+                visit_expr(left, found);
+                visit_expr(right, found);
+            }
+            sqlparser::ast::Expr::UnaryOp { expr, .. } | sqlparser::ast::Expr::Nested(expr) => {
+                visit_expr(expr, found);
+            }
+            _ => {}
+        }
+    }
+
+    let mut written = vec![];
+    let mut read = vec![];
+    let operation = match stmt {
+        Statement::Query(query) => {
+            visit_query(query, &mut read);
+            Operation::Select
+        }
+        Statement::Insert {
+            table_name, source, ..
+        } => {
+            written.push(table_name.to_string());
+            if let Some(source) = source {
+                visit_query(source, &mut read);
+            }
+            Operation::Insert
+        }
+        Statement::Update {
+            table,
+            from,
+            selection,
+            ..
+        } => {
+            visit_tables(std::slice::from_ref(table), &mut written);
+            visit_tables(from.as_slice(), &mut read);
+            if let Some(selection) = selection {
+                visit_expr(selection, &mut read);
+            }
+            Operation::Update
+        }
+        Statement::Delete {
+            tables,
+            from,
+            using,
+            selection,
+            ..
+        } => {
+            let (sqlparser::ast::FromTable::WithFromKeyword(from)
+            | sqlparser::ast::FromTable::WithoutKeyword(from)) = from;
+            if tables.is_empty() {
+                visit_tables(from, &mut written);
+            } else {
+                written.extend(tables.iter().map(ToString::to_string));
+                visit_tables(from, &mut read);
+            }
+            visit_tables(using.as_deref().unwrap_or_default(), &mut read);
+            if let Some(selection) = selection {
+                visit_expr(selection, &mut read);
+            }
+            Operation::Delete
+        }
+        _ => return vec![],
+    };
+
+    written
+        .into_iter()
+        .map(|table| (table, operation))
+        .chain(read.into_iter().map(|table| (table, Operation::Select)))
+        .collect()
+}
@@ -1,14 +1,59 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub enum Env<T> {
-    Parent(HashMap<String, T>),
+    Parent(HashMap<String, Binding<T>>),
     Child {
-        parent: Box<Env<T>>,
-        bindings: HashMap<String, T>,
+        /// Shared with the clones of the scope, so cloning it doesn't copy its parents.
+        parent: Arc<Self>,
+        bindings: HashMap<String, Binding<T>>,
     },
 }
 
+/// What a name is bound as, which decides where it can be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A value, bound with `let`, by a loop or as a parameter.
+    Variable,
+    /// A constant of a class, bound with `use static`.
+    Alias,
+    /// A java class, bound with `alias`. Unbound qualified names are classes too where a
+    /// class is expected.
+    Class,
+    /// A function of the script.
+    Function,
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Variable => "variable",
+            Self::Alias => "constant",
+            Self::Class => "class",
+            Self::Function => "function",
+        })
+    }
+}
+
+/// A bound value and whether it was ever resolved.
+///
+/// The flag is shared between clones so reads from a scoped environment are seen by the
+/// one that made the binding.
+#[derive(Debug, Clone)]
+pub struct Binding<T> {
+    value: T,
+    kind: Kind,
+    read: Arc<AtomicBool>,
+}
+
+impl<T> Default for Env<T> {
+    fn default() -> Self {
+        Self::Parent(HashMap::new())
+    }
+}
+
 impl<T> Env<T>
 where
     T: Clone,
@@ -19,32 +64,114 @@ where
     }
 
     /// Create a scoped environment.
-    pub(crate) fn scoped(&self) -> Self {
+    pub(crate) fn scoped(self) -> Self {
         Self::Child {
-            parent: Box::new(self.clone()),
+            parent: Arc::new(self),
             bindings: HashMap::new(),
         }
     }
 
+    /// The environment this one was scoped from, dropping the bindings of the scope.
+    pub(crate) fn unscoped(self) -> Self {
+        match self {
+            Self::Parent(_) => self,
+            Self::Child { parent, .. } => Arc::unwrap_or_clone(parent),
+        }
+    }
+
+    /// The environment this one was scoped from, with the bindings of the scope moved to it.
+    pub(crate) fn merged(self) -> Self {
+        match self {
+            Self::Parent(_) => self,
+            Self::Child { parent, bindings } => {
+                let mut parent = Arc::unwrap_or_clone(parent);
+                match parent {
+                    Self::Parent(ref mut outer)
+                    | Self::Child {
+                        bindings: ref mut outer,
+                        ..
+                    } => outer.extend(bindings),
+                }
+                parent
+            }
+        }
+    }
+
+    /// Resolves `key`, marking it as read.
     pub fn resolve(&self, key: &str) -> Option<&T> {
+        let binding = self.lookup(key)?;
+        binding.read.store(true, Ordering::Relaxed);
+        Some(&binding.value)
+    }
+
+    /// Whether `key` is bound, without marking it as read.
+    pub fn is_bound(&self, key: &str) -> bool {
+        self.lookup(key).is_some()
+    }
+
+    /// What `key` is bound as, without marking it as read.
+    pub fn kind(&self, key: &str) -> Option<Kind> {
+        self.lookup(key).map(|binding| binding.kind)
+    }
+
+    fn lookup(&self, key: &str) -> Option<&Binding<T>> {
         match self {
             Self::Parent(bindings) => bindings.get(key),
             Self::Child { parent, bindings } => {
-                if let Some(expr) = bindings.get(key) {
-                    return Some(expr);
+                if let Some(binding) = bindings.get(key) {
+                    return Some(binding);
                 }
-                parent.resolve(key)
+                parent.lookup(key)
             }
         }
     }
 
+    /// Binds `key` as a [`Kind::Variable`].
     pub fn bind(&mut self, key: String, val: T) -> Option<T> {
+        self.bind_tracked(key, val, Kind::Variable).1
+    }
+
+    /// Binds `key` as a [`Kind::Variable`] derived from another binding, sharing `read`, the
+    /// flag of that binding, so resolving it reads that binding too.
+    pub fn bind_derived(&mut self, key: String, val: T, read: &Arc<AtomicBool>) {
+        let binding = Binding {
+            value: val,
+            kind: Kind::Variable,
+            read: Arc::clone(read),
+        };
+
+        match self {
+            Self::Parent(bindings)
+            | Self::Child {
+                parent: _,
+                bindings,
+            } => {
+                bindings.insert(key, binding);
+            }
+        }
+    }
+
+    /// Binds `key` as `kind` and returns a flag that's set once it's resolved, along with the
+    /// value it replaced.
+    pub fn bind_tracked(
+        &mut self,
+        key: String,
+        val: T,
+        kind: Kind,
+    ) -> (Arc<AtomicBool>, Option<T>) {
+        let read = Arc::new(AtomicBool::new(false));
+        let binding = Binding {
+            value: val,
+            kind,
+            read: Arc::clone(&read),
+        };
+
         match self {
             Self::Parent(bindings)
             | Self::Child {
                 parent: _,
                 bindings,
-            } => bindings.insert(key, val),
+            } => (read, bindings.insert(key, binding).map(|old| old.value)),
         }
     }
 }
@@ -0,0 +1,1238 @@
+//! Lowering of the high-level constructs, like `http!` and json objects, into the
+//! statements the transpiler knows.
+//!
+//! This runs as a pass over the parsed script. Library users can add their own
+//! constructs by registering an [`Expander`].
+
+use std::fmt::Write;
+use std::sync::Arc;
+
+use super::ast::{
+    self, Call, Dict, Expr, HttpVerb, Ident, InfixOp, Name, Node, Spanned, Stmt, Value,
+};
+use super::{xog_element, xog_request};
+
+/// Lowers a construct of a script into the statements it stands for.
+///
+/// A library user can implement this for their own constructs, for example a call to a
+/// company-specific `audit_log(...)`, and register it with [`Expanders::register`].
+pub trait Expander: Send + Sync {
+    /// Whether `node` is a construct this expander lowers.
+    fn expands(&self, node: &Node) -> bool;
+
+    /// Lowers `node`, which [`expands`].
+    ///
+    /// # Errors
+    /// Returns `Err` if `node` can't be lowered.
+    fn expand(&self, node: Node) -> miette::Result<Node>;
+}
+
+/// The expanders run over a script, the registered ones run before the built-in ones so
+/// what they lower to can use `http!`, json objects and the like.
+#[derive(Clone, Default)]
+pub struct Expanders {
+    registered: Vec<Arc<dyn Expander>>,
+}
+
+/// The constructs gelatin lowers itself.
+const BUILTINS: [&dyn Expander; 5] = [&Http, &Json, &JsonObject, &XogSession, &SoapFault];
+
+impl Expanders {
+    /// Runs `expander` over the script, after the ones registered before it.
+    pub fn register<E: Expander + 'static>(&mut self, expander: E) {
+        self.registered.push(Arc::new(expander));
+    }
+
+    /// Lowers the constructs in the top-level `nodes` and the statements they hold.
+    ///
+    /// # Errors
+    /// Returns `Err` if an expander fails.
+    pub fn expand_all(&self, nodes: Vec<Spanned<Node>>) -> miette::Result<Vec<Spanned<Node>>> {
+        nodes
+            .into_iter()
+            .map(|Spanned { node, span }| {
+                Ok(Spanned {
+                    node: self.expand(node)?,
+                    span,
+                })
+            })
+            .collect()
+    }
+
+    /// Lowers `node` and then the statements it holds.
+    ///
+    /// # Errors
+    /// Returns `Err` if an expander fails.
+    pub fn expand(&self, mut node: Node) -> miette::Result<Node> {
+        let expanders = self.registered.iter().map(AsRef::as_ref).chain(BUILTINS);
+        for expander in expanders {
+            if expander.expands(&node) {
+                node = expander.expand(node)?;
+            }
+        }
+
+        match node {
+            Node::Stmt(stmt) => self.expand_inner(stmt).map(Node::Stmt),
+            node @ Node::Expr(_) => Ok(node),
+        }
+    }
+
+    fn expand_stmt(&self, stmt: Stmt) -> miette::Result<Stmt> {
+        match self.expand(Node::Stmt(stmt))? {
+            Node::Stmt(stmt) => Ok(stmt),
+            Node::Expr(expr) => Ok(Stmt::Expr { expr }),
+        }
+    }
+
+    fn expand_body(&self, body: Vec<Stmt>) -> miette::Result<Vec<Stmt>> {
+        body.into_iter()
+            .map(|stmt| self.expand_stmt(stmt))
+            .collect()
+    }
+
+    /// Lowers the statements in the body of `stmt`.
+    fn expand_inner(&self, stmt: Stmt) -> miette::Result<Stmt> {
+        Ok(match stmt {
+            Stmt::Block(body) => Stmt::Block(self.expand_body(body)?),
+            Stmt::Whitespace(body) => Stmt::Whitespace(self.expand_body(body)?),
+            Stmt::Catch { name, body } => Stmt::Catch {
+                name,
+                body: self.expand_body(body)?,
+            },
+            Stmt::ForEach { var, items, body } => Stmt::ForEach {
+                var,
+                items,
+                body: self.expand_body(body)?,
+            },
+            Stmt::While { test, body } => Stmt::While {
+                test,
+                body: self.expand_body(body)?,
+            },
+            Stmt::If { test, body, alt } => Stmt::If {
+                test,
+                body: self.expand_body(body)?,
+                alt: alt.map(|alt| self.expand_body(alt)).transpose()?,
+            },
+            Stmt::WriteFile {
+                path,
+                escape_xml,
+                body,
+            } => Stmt::WriteFile {
+                path,
+                escape_xml,
+                body: self.expand_body(body)?,
+            },
+            Stmt::Transaction { datasource, body } => Stmt::Transaction {
+                datasource,
+                body: self.expand_body(body)?,
+            },
+            Stmt::Let(name, Expr::Func { params, body }) => Stmt::Let(
+                name,
+                Expr::Func {
+                    params,
+                    body: self.expand_body(body)?,
+                },
+            ),
+            stmt => stmt,
+        })
+    }
+}
+
+impl std::fmt::Debug for Expanders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Expanders")
+            .field("registered", &self.registered.len())
+            .finish()
+    }
+}
+
+/// The expression of a `let` or expression `node`.
+const fn bound_expr(node: &Node) -> Option<&Expr> {
+    match node {
+        Node::Expr(expr) | Node::Stmt(Stmt::Let(_, expr) | Stmt::Expr { expr }) => Some(expr),
+        Node::Stmt(_) => None,
+    }
+}
+
+/// Takes the expression out of a `node` that [`bound_expr`] returned one for, along with
+/// the name it is bound to, `_` if it isn't.
+fn into_bound(node: Node) -> (Ident, Expr) {
+    match node {
+        Node::Stmt(Stmt::Let(name, expr)) => (name, expr),
+        Node::Expr(expr) | Node::Stmt(Stmt::Expr { expr }) => ("_".into(), expr),
+        Node::Stmt(_) => unreachable!("checked by `expands`"),
+    }
+}
+
+/// `http!` requests, made through a `java.net.HttpURLConnection`.
+struct Http;
+
+impl Expander for Http {
+    fn expands(&self, node: &Node) -> bool {
+        matches!(bound_expr(node), Some(Expr::Http { .. }))
+    }
+
+    fn expand(&self, node: Node) -> miette::Result<Node> {
+        let (name, Expr::Http { verb, url, body }) = into_bound(node) else {
+            unreachable!("checked by `expands`")
+        };
+
+        Ok(http(&name, &verb, *url, body))
+    }
+}
+
+/// `json!` responses, read into an `org.json` value.
+struct Json;
+
+impl Expander for Json {
+    fn expands(&self, node: &Node) -> bool {
+        matches!(bound_expr(node), Some(Expr::Json { .. }))
+    }
+
+    fn expand(&self, node: Node) -> miette::Result<Node> {
+        let (name, Expr::Json { expr }) = into_bound(node) else {
+            unreachable!("checked by `expands`")
+        };
+
+        Ok(json(&name, &expr))
+    }
+}
+
+/// Dicts, built as an `org.json.JSONObject`.
+struct JsonObject;
+
+impl Expander for JsonObject {
+    fn expands(&self, node: &Node) -> bool {
+        matches!(bound_expr(node), Some(Expr::Dict(_)))
+    }
+
+    fn expand(&self, node: Node) -> miette::Result<Node> {
+        let (name, Expr::Dict(dict)) = into_bound(node) else {
+            unreachable!("checked by `expands`")
+        };
+
+        let mut tags = vec![];
+        create_json_tags(dict, name.as_str(), &mut tags);
+
+        Ok(Node::Stmt(Stmt::Block(tags)))
+    }
+}
+
+/// `xog_login!` and `xog_logout!`, sent as XOG soap requests.
+struct XogSession;
+
+impl Expander for XogSession {
+    fn expands(&self, node: &Node) -> bool {
+        matches!(
+            bound_expr(node),
+            Some(Expr::XogLogin { .. } | Expr::XogLogout { .. })
+        )
+    }
+
+    fn expand(&self, node: Node) -> miette::Result<Node> {
+        match into_bound(node) {
+            (
+                name,
+                Expr::XogLogin {
+                    endpoint,
+                    username,
+                    password,
+                },
+            ) => Ok(xog_login(name, *endpoint, &username, &password)),
+            (_, Expr::XogLogout { session }) => Ok(xog_logout(&session)),
+            _ => unreachable!("checked by `expands`"),
+        }
+    }
+}
+
+/// The `on_fault` handler of a `soap!` request, run if the response is a fault.
+struct SoapFault;
+
+impl Expander for SoapFault {
+    fn expands(&self, node: &Node) -> bool {
+        matches!(
+            node,
+            Node::Stmt(Stmt::Let(
+                _,
+                Expr::Soap {
+                    on_fault: Some(_),
+                    ..
+                }
+            ))
+        )
+    }
+
+    fn expand(&self, node: Node) -> miette::Result<Node> {
+        let Node::Stmt(Stmt::Let(
+            name,
+            Expr::Soap {
+                endpoint,
+                header,
+                body,
+                on_fault: Some(on_fault),
+            },
+        )) = node
+        else {
+            unreachable!("checked by `expands`")
+        };
+
+        let Expr::Func {
+            params,
+            body: handler,
+        } = *on_fault
+        else {
+            unreachable!("on_fault is parsed as a handler")
+        };
+        let fault: Ident = format!("{name}_fault").into();
+
+        let mut fault_handler = params
+            .into_iter()
+            .map(|param| Stmt::Let(param, Expr::Ident(Name::Ident(fault.clone()))))
+            .collect::<Vec<_>>();
+        fault_handler.extend(handler);
+
+        Ok(Node::Stmt(Stmt::Block(vec![
+            Stmt::Let(
+                name.clone(),
+                Expr::Soap {
+                    endpoint,
+                    header,
+                    body,
+                    on_fault: None,
+                },
+            ),
+            Stmt::Let(
+                fault.clone(),
+                Expr::XPath {
+                    source: name,
+                    path: "//soapenv:Fault/faultstring/text()".into(),
+                },
+            ),
+            Stmt::If {
+                test: Expr::infix(Expr::Ident(Name::Ident(fault)), InfixOp::Neq, "".into()),
+                body: fault_handler,
+                alt: None,
+            },
+        ])))
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn http(name: &Ident, verb: &HttpVerb, url: Expr, body: Vec<Stmt>) -> Node {
+    let mut tags = vec![];
+
+    // query parameters have to be known before the URL is built.
+    let (query, body): (Vec<_>, Vec<_>) = body
+        .into_iter()
+        .partition(|stmt| is_directive(stmt, "query"));
+
+    // an explicit content-type header wins over the one implied by the payload.
+    let explicit_content_type = body.iter().any(|stmt| match stmt {
+        Stmt::Expr {
+            expr: Expr::Call(Call { args, .. }),
+        } if is_directive(stmt, "headers") => args.iter().any(|arg| {
+            matches!(arg, Expr::Dict(dict) if dict.keys().any(|k| k.eq_ignore_ascii_case("content-type")))
+        }),
+        _ => false,
+    });
+    let content_type = |content_type: &'static str| {
+        (!explicit_content_type).then(|| set_request_property(name, "content-type", content_type))
+    };
+
+    let mut url = url;
+    for directive in query {
+        let Stmt::Expr {
+            expr: Expr::Call(Call { mut args, .. }),
+        } = directive
+        else {
+            unreachable!("partitioned with `is_directive`")
+        };
+
+        let Some(Expr::Dict(params)) = args.pop() else {
+            unreachable!("`Parser::check_directive` only lets `query` take a dict")
+        };
+
+        url = url_with_query(name, url, params, &mut tags);
+    }
+
+    // everything from here till the status is part of a (possibly retried) request.
+    let request_start = tags.len();
+
+    tags.extend([
+        Stmt::Let(
+            "remoteURL".into(),
+            Expr::Instance {
+                class: "java.net.URL".into(),
+                args: vec![url],
+            },
+        ),
+        Stmt::Let(name.clone(), Expr::call("remoteURL.openConnection", vec![])),
+        Stmt::Expr {
+            expr: Expr::call(
+                format!("{name}.setRequestMethod"),
+                vec![verb.as_str().into()],
+            ),
+        },
+        Stmt::Expr {
+            expr: Expr::call(format!("{name}.setDoOutput"), vec![true.into()]),
+        },
+    ]);
+
+    if !matches!(verb, HttpVerb::GET) {
+        tags.push(Stmt::Expr {
+            expr: Expr::call(format!("{name}.setDoInput"), vec![true.into()]),
+        });
+    }
+
+    let mut capture_status = false;
+    let mut on_error = None;
+    let mut captured_headers = Dict::new();
+    let mut retry = None;
+    let mut save_to = None;
+
+    for expr in body {
+        let Stmt::Expr {
+            expr:
+                Expr::Call(Call {
+                    name: Name::Ident(func),
+                    mut args,
+                }),
+        } = expr
+        else {
+            unreachable!("`Parser::check_directive` only lets directives into `http!` bodies")
+        };
+
+        match func.as_str() {
+            "timeout" => {
+                // a positional timeout applies to whichever one isn't named.
+                let (mut connect, mut read) = (None, None);
+                for arg in args {
+                    match arg {
+                        Expr::Named { name, value } if name.as_str() == "connect" => {
+                            connect = Some(*value);
+                        }
+                        Expr::Named { name, value } if name.as_str() == "read" => {
+                            read = Some(*value);
+                        }
+                        Expr::Named { .. } => {
+                            unreachable!("`Parser::check_directive` only lets `timeout` take `connect` and `read`")
+                        }
+                        timeout => {
+                            connect.get_or_insert_with(|| timeout.clone());
+                            read.get_or_insert(timeout);
+                        }
+                    }
+                }
+
+                if let Some(connect) = connect {
+                    tags.push(Stmt::Expr {
+                        expr: Expr::call(format!("{name}.setConnectTimeout"), vec![connect]),
+                    });
+                }
+                if let Some(read) = read {
+                    tags.push(Stmt::Expr {
+                        expr: Expr::call(format!("{name}.setReadTimeout"), vec![read]),
+                    });
+                }
+            }
+            "headers" => {
+                let Some(Expr::Dict(dict)) = args.pop() else {
+                    unreachable!("`Parser::check_directive` only lets `headers` take a dict")
+                };
+
+                tags.extend(
+                    dict.into_iter()
+                        .map(|(k, v)| set_request_property(name, k, v)),
+                );
+            }
+            "auth_basic" => {
+                let (Some(pass), Some(user)) = (args.pop(), args.pop()) else {
+                    unreachable!(
+                        "`Parser::check_directive` gives `auth_basic` a user and a password"
+                    )
+                };
+
+                let credentials = format!(
+                    "{}:{}",
+                    user.as_value(ast::Context::Text),
+                    pass.as_value(ast::Context::Text)
+                );
+
+                tags.extend([
+                    Stmt::Let(
+                        format!("{name}_credentials").into(),
+                        credentials.as_str().into(),
+                    ),
+                    Stmt::Let(
+                        format!("{name}_encoder").into(),
+                        Expr::static_method("java.util.Base64", "getEncoder", vec![]),
+                    ),
+                    Stmt::Let(
+                        format!("{name}_auth").into(),
+                        Expr::call(
+                            format!("{name}_encoder.encodeToString"),
+                            vec![Expr::call(
+                                format!("{name}_credentials.getBytes"),
+                                vec!["UTF-8".into()],
+                            )],
+                        ),
+                    ),
+                    Stmt::Let(
+                        format!("{name}_authorization").into(),
+                        format!("Basic ${{{name}_auth}}").as_str().into(),
+                    ),
+                    set_request_property(
+                        name,
+                        "Authorization",
+                        Expr::Ident(format!("{name}_authorization").into()),
+                    ),
+                ]);
+            }
+            "auth_bearer" => {
+                let Some(token) = args.pop() else {
+                    unreachable!("`Parser::check_directive` gives `auth_bearer` a token")
+                };
+
+                if let Expr::Value {
+                    value: Value::Str(token),
+                    ..
+                } = token
+                {
+                    tags.push(set_request_property(
+                        name,
+                        "Authorization",
+                        format!("Bearer {token}").as_str(),
+                    ));
+                } else {
+                    tags.extend([
+                        Stmt::Let(
+                            format!("{name}_authorization").into(),
+                            format!("Bearer {}", token.as_value(ast::Context::Text))
+                                .as_str()
+                                .into(),
+                        ),
+                        set_request_property(
+                            name,
+                            "Authorization",
+                            Expr::Ident(format!("{name}_authorization").into()),
+                        ),
+                    ]);
+                }
+            }
+            "json" => {
+                tags.extend(content_type("application/json"));
+
+                tags.push(output_writer(name));
+
+                // a dict is built for this request, a name is an object built
+                // before, e.g. with `let payload = {...}`.
+                let payload = match args.pop() {
+                    Some(Expr::Dict(dict)) => {
+                        let payload = format!("{name}_payload");
+                        create_json_tags(dict, &payload, &mut tags);
+                        payload
+                    }
+                    Some(Expr::Ident(Name::Ident(payload))) => payload.to_string(),
+                    _ => unreachable!(
+                        "`Parser::check_directive` only lets `json` take a dict or a name"
+                    ),
+                };
+
+                tags.push(Stmt::Expr {
+                    expr: Expr::call(
+                        format!("{payload}.write"),
+                        vec![Expr::Ident(format!("{name}_w").into())],
+                    ),
+                });
+                tags.push(Stmt::Expr {
+                    expr: Expr::call(format!("{name}_w.flush"), vec![]),
+                });
+            }
+            "form" => {
+                let Some(Expr::Dict(dict)) = args.pop() else {
+                    unreachable!("`Parser::check_directive` only lets `form` take a dict")
+                };
+
+                tags.extend(content_type("application/x-www-form-urlencoded"));
+
+                let form = encode_params(name, "form", dict, &mut tags);
+                write_body(name, form.as_str().into(), &mut tags);
+            }
+            "status" => capture_status = true,
+            "save_to" => {
+                let Some(path) = args.pop() else {
+                    unreachable!("`Parser::check_directive` gives `save_to` a path")
+                };
+
+                save_to = Some(path);
+            }
+            "retry" => {
+                let (Some(delay), Some(attempts)) = (args.pop(), args.pop()) else {
+                    unreachable!("`Parser::check_directive` gives `retry` the attempts and a delay")
+                };
+
+                retry = Some((attempts, delay));
+            }
+            "capture_headers" => {
+                let Some(Expr::Dict(headers)) = args.pop() else {
+                    unreachable!(
+                        "`Parser::check_directive` only lets `capture_headers` take a dict"
+                    )
+                };
+
+                captured_headers.extend(headers);
+            }
+            "on_error" => {
+                let Some(Expr::Func { params, body }) = args.pop() else {
+                    unreachable!("`on_error` is parsed with `Parser::handler_from`")
+                };
+
+                capture_status = true;
+                on_error = Some((params, body));
+            }
+            "body" | "xml" => {
+                let Some(payload) = args.pop() else {
+                    unreachable!("`Parser::check_directive` and `http_xml` give `{func}` a payload")
+                };
+
+                tags.extend(content_type(if func.as_str() == "xml" {
+                    "application/xml"
+                } else {
+                    "text/plain"
+                }));
+
+                write_body(name, payload, &mut tags);
+            }
+            _ => unreachable!("`Parser::check_directive` rejects what isn't in `HTTP_DIRECTIVES`"),
+        }
+    }
+
+    tags.push(Stmt::Expr {
+        expr: Expr::call(format!("{name}.connect"), vec![]),
+    });
+
+    if capture_status {
+        tags.push(Stmt::Let(
+            format!("{name}_status").into(),
+            Expr::call(format!("{name}.getResponseCode"), vec![]),
+        ));
+    }
+
+    if let Some((attempts, delay)) = retry {
+        let request = tags.split_off(request_start);
+        tags.extend(with_retry(name, request, attempts, delay));
+    }
+
+    if let Some(path) = save_to {
+        save_response(name, path, &mut tags);
+    }
+
+    tags.extend(captured_headers.into_iter().map(|(var, header)| {
+        Stmt::Let(
+            var.into(),
+            Expr::call(format!("{name}.getHeaderField"), vec![header]),
+        )
+    }));
+
+    if let Some((params, body)) = on_error {
+        let status = Expr::Ident(format!("{name}_status").into());
+        let mut handler = vec![];
+        let mut params = params.into_iter();
+
+        if let Some(param) = params.next() {
+            handler.push(Stmt::Let(param, status.clone()));
+        }
+
+        if let Some(param) = params.next() {
+            read_stream(
+                Expr::call(format!("{name}.getErrorStream"), vec![]),
+                &format!("{name}_error"),
+                &mut handler,
+            );
+            handler.push(Stmt::Let(
+                param,
+                Expr::call(format!("{name}_error_sb.toString"), vec![]),
+            ));
+        }
+
+        handler.extend(body);
+
+        tags.push(Stmt::If {
+            test: Expr::infix(status, InfixOp::Gte, 400.into()),
+            body: handler,
+            alt: None,
+        });
+    }
+
+    Node::Stmt(Stmt::Block(tags))
+}
+
+fn json(name: &Ident, expr: &Ident) -> Node {
+    let mut tags = vec![];
+    read_stream(
+        Expr::call(format!("{expr}.getInputStream"), vec![]),
+        name.as_str(),
+        &mut tags,
+    );
+
+    // JSONTokener yields either a JSONObject or a JSONArray depending on the response.
+    tags.extend([
+        Stmt::Let(
+            format!("{name}_tokener").into(),
+            Expr::Instance {
+                class: "org.json.JSONTokener".into(),
+                args: vec![Expr::call(format!("{name}_sb.toString"), vec![])],
+            },
+        ),
+        Stmt::Let(
+            name.clone(),
+            Expr::call(format!("{name}_tokener.nextValue"), vec![]),
+        ),
+    ]);
+
+    Node::Stmt(Stmt::Block(tags))
+}
+
+fn xog_login(name: Ident, endpoint: Expr, username: &Expr, password: &Expr) -> Node {
+    let response: Ident = format!("{name}_login").into();
+    let endpoint_name: Ident = format!("{name}_endpoint").into();
+
+    let body = xog_element(
+        "Login",
+        &[
+            ("Username", username.as_value(ast::Context::Text)),
+            ("Password", password.as_value(ast::Context::Text)),
+        ],
+    );
+
+    Node::Stmt(Stmt::Block(vec![
+        // keep the endpoint around so `xog_logout!` can reach it
+        Stmt::Let(endpoint_name.clone(), endpoint),
+        Stmt::Let(
+            response.clone(),
+            Expr::Soap {
+                endpoint: Box::new(Expr::Ident(Name::Ident(endpoint_name))),
+                header: None,
+                body: Some(body),
+                on_fault: None,
+            },
+        ),
+        Stmt::Let(
+            name,
+            Expr::XPath {
+                source: response,
+                path: "//xog:SessionID/text()".into(),
+            },
+        ),
+    ]))
+}
+
+fn xog_logout(session: &Ident) -> Node {
+    Node::Stmt(Stmt::Let(
+        format!("{session}_logout").into(),
+        xog_request(session, xog_element("Logout", &[])),
+    ))
+}
+
+fn create_json_tags(map: Dict, bind_to: &str, tags: &mut Vec<Stmt>) {
+    tags.push(Stmt::Let(
+        bind_to.into(),
+        Expr::Instance {
+            class: "org.json.JSONObject".into(),
+            args: vec![],
+        },
+    ));
+
+    // nested objects are built first and bound to `{bind_to}_{key}`.
+    for (k, mut v) in map {
+        if let Expr::Dict(inner) = v {
+            let nested = json_var(bind_to, &k);
+            create_json_tags(inner, &nested, tags);
+            v = Expr::Ident(nested.into());
+        }
+
+        tags.push(Stmt::Expr {
+            expr: Expr::call(format!("{bind_to}.put"), vec![k.into(), v]),
+        });
+    }
+}
+
+/// Returns `true` if `stmt` is a call to the `directive` inside an `http!` body.
+pub(super) fn is_directive(stmt: &Stmt, directive: &str) -> bool {
+    matches!(
+        stmt,
+        Stmt::Expr {
+            expr: Expr::Call(Call {
+                name: Name::Ident(func),
+                ..
+            }),
+        } if func.as_str() == directive
+    )
+}
+
+fn set_request_property<K: Into<Expr>, V: Into<Expr>>(name: &Ident, key: K, value: V) -> Stmt {
+    Stmt::Expr {
+        expr: Expr::call(
+            format!("{name}.setRequestProperty"),
+            vec![key.into(), value.into()],
+        ),
+    }
+}
+
+/// Binds `{name}_w` to a writer over the connection's output stream.
+fn output_writer(name: &Ident) -> Stmt {
+    Stmt::Let(
+        format!("{name}_w").into(),
+        Expr::Instance {
+            class: "java.io.OutputStreamWriter".into(),
+            args: vec![Expr::call(format!("{name}.getOutputStream"), vec![])],
+        },
+    )
+}
+
+/// Wraps `request` in a loop that runs it up to `attempts` times, sleeping
+/// `delay * attempt` milliseconds after each failed attempt but the last one.
+/// The last attempt isn't caught, so its error stops the script as it would
+/// without retries.
+fn with_retry(name: &Ident, mut request: Vec<Stmt>, attempts: Expr, delay: Expr) -> Vec<Stmt> {
+    let attempt = Expr::Ident(format!("{name}_attempt").into());
+    let done = Expr::Ident(format!("{name}_done").into());
+    let pending = Expr::infix(done, InfixOp::Eq, 0.into());
+
+    request.push(Stmt::Let(format!("{name}_done").into(), 1.into()));
+
+    vec![
+        Stmt::Let(format!("{name}_attempt").into(), 0.into()),
+        Stmt::Let(format!("{name}_done").into(), 0.into()),
+        Stmt::While {
+            test: Expr::infix(
+                pending.clone(),
+                InfixOp::And,
+                Expr::infix(attempt.clone(), InfixOp::Lt, attempts.clone()),
+            ),
+            body: vec![
+                Stmt::Let(
+                    format!("{name}_attempt").into(),
+                    Expr::infix(attempt.clone(), InfixOp::Add, 1.into()),
+                ),
+                Stmt::If {
+                    test: Expr::infix(attempt.clone(), InfixOp::Lt, attempts),
+                    body: vec![
+                        Stmt::Catch {
+                            name: format!("{name}_exception").into(),
+                            body: request.clone(),
+                        },
+                        Stmt::If {
+                            test: pending,
+                            body: vec![Stmt::Let(
+                                format!("{name}_sleep").into(),
+                                Expr::static_method(
+                                    "java.lang.Thread",
+                                    "sleep",
+                                    vec![Expr::infix(delay, InfixOp::Mul, attempt)],
+                                ),
+                            )],
+                            alt: None,
+                        },
+                    ],
+                    alt: Some(request),
+                },
+            ],
+        },
+    ]
+}
+
+/// Copies the response body of `name` into the file at `path`.
+fn save_response(name: &Ident, path: Expr, tags: &mut Vec<Stmt>) {
+    let input = format!("{name}_in");
+    let output = format!("{name}_out");
+    let buffer = Expr::Ident(format!("{name}_buffer").into());
+    let read = Expr::call(format!("{input}.read"), vec![buffer.clone()]);
+
+    tags.extend([
+        Stmt::Let(
+            input.as_str().into(),
+            Expr::call(format!("{name}.getInputStream"), vec![]),
+        ),
+        Stmt::Let(
+            output.as_str().into(),
+            Expr::Instance {
+                class: "java.io.FileOutputStream".into(),
+                args: vec![path],
+            },
+        ),
+        Stmt::Let(
+            format!("{name}_byte").into(),
+            Expr::static_field("java.lang.Byte", "TYPE"),
+        ),
+        Stmt::Let(
+            format!("{name}_buffer").into(),
+            Expr::static_method(
+                "java.lang.reflect.Array",
+                "newInstance",
+                vec![Expr::Ident(format!("{name}_byte").into()), 8192.into()],
+            ),
+        ),
+        Stmt::Let(format!("{name}_read").into(), read.clone()),
+        Stmt::While {
+            test: Expr::infix(
+                Expr::Ident(format!("{name}_read").into()),
+                InfixOp::Gte,
+                0.into(),
+            ),
+            body: vec![
+                Stmt::Expr {
+                    expr: Expr::call(
+                        format!("{output}.write"),
+                        vec![buffer, 0.into(), Expr::Ident(format!("{name}_read").into())],
+                    ),
+                },
+                Stmt::Let(format!("{name}_read").into(), read),
+            ],
+        },
+        Stmt::Expr {
+            expr: Expr::call(format!("{output}.close"), vec![]),
+        },
+        Stmt::Expr {
+            expr: Expr::call(format!("{input}.close"), vec![]),
+        },
+    ]);
+}
+
+/// Reads `stream` line by line into a `{prefix}_sb` string builder.
+fn read_stream(stream: Expr, prefix: &str, tags: &mut Vec<Stmt>) {
+    let line = Expr::Ident(format!("{prefix}_line").into());
+    let read_line = Expr::call(format!("{prefix}_buf_reader.readLine"), vec![]);
+
+    buffered_reader("java.io.InputStreamReader", stream, prefix, tags);
+    tags.extend([
+        Stmt::Let(
+            format!("{prefix}_sb").into(),
+            Expr::Instance {
+                class: "java.lang.StringBuilder".into(),
+                args: vec![],
+            },
+        ),
+        Stmt::Let(format!("{prefix}_line").into(), read_line.clone()),
+        Stmt::While {
+            test: Expr::infix(line.clone(), InfixOp::Neq, Value::Nothing.into()),
+            body: vec![
+                Stmt::Expr {
+                    expr: Expr::call(format!("{prefix}_sb.append"), vec![line]),
+                },
+                Stmt::Let(format!("{prefix}_line").into(), read_line),
+            ],
+        },
+    ]);
+}
+
+/// Wraps a new `reader` over `source` in a `{prefix}_buf_reader`.
+pub(super) fn buffered_reader(reader: &str, source: Expr, prefix: &str, tags: &mut Vec<Stmt>) {
+    tags.extend([
+        Stmt::Let(
+            format!("{prefix}_reader").into(),
+            Expr::Instance {
+                class: reader.into(),
+                args: vec![source],
+            },
+        ),
+        Stmt::Let(
+            format!("{prefix}_buf_reader").into(),
+            Expr::Instance {
+                class: "java.io.BufferedReader".into(),
+                args: vec![Expr::Ident(format!("{prefix}_reader").into())],
+            },
+        ),
+    ]);
+}
+
+/// Writes `payload` as the request body through `{name}_w`.
+fn write_body(name: &Ident, payload: Expr, tags: &mut Vec<Stmt>) {
+    tags.extend([
+        Stmt::Let(format!("{name}_body").into(), payload),
+        output_writer(name),
+        Stmt::Expr {
+            expr: Expr::call(
+                format!("{name}_w.write"),
+                vec![Expr::Ident(format!("{name}_body").into())],
+            ),
+        },
+        Stmt::Expr {
+            expr: Expr::call(format!("{name}_w.flush"), vec![]),
+        },
+    ]);
+}
+
+/// Appends `params` as the query string of `url`. Whether a url that isn't a literal
+/// already has a query is only known at runtime, it's checked then, binding the url to
+/// `{name}_url` unless it's a variable.
+fn url_with_query(name: &Ident, url: Expr, params: Dict, tags: &mut Vec<Stmt>) -> Expr {
+    let query = encode_params(name, "query", params, tags);
+    if query.is_empty() {
+        return url;
+    }
+
+    let (url, separator) = match url {
+        Expr::Value {
+            value: Value::Str(url),
+            ..
+        } if !is_interpolated(&url) => {
+            let separator = if url.contains('?') { "&" } else { "?" };
+            (url.to_string(), separator.to_string())
+        }
+        url => {
+            let url = if let Expr::Ident(_) = url {
+                url
+            } else {
+                let var = format!("{name}_url");
+                tags.push(Stmt::Let(var.clone().into(), url));
+                Expr::Ident(Name::Ident(var.into()))
+            };
+            let separator = format!(
+                "${{{}.contains(\"?\") ? \"&\" : \"?\"}}",
+                url.as_value(ast::Context::Expr)
+            );
+            (url.as_value(ast::Context::Text).into_owned(), separator)
+        }
+    };
+
+    Expr::from(format!("{url}{separator}{query}").as_str())
+}
+
+/// Url-encodes `params` into a `key=value&...` string.
+///
+/// Literals are encoded right away, any other expression is encoded at runtime
+/// through `java.net.URLEncoder` into a `{name}_{prefix}_{key}` variable.
+fn encode_params(name: &Ident, prefix: &str, params: Dict, tags: &mut Vec<Stmt>) -> String {
+    let mut pairs = Vec::with_capacity(params.len());
+
+    for (key, value) in params {
+        let value = match value {
+            Expr::Value {
+                value: Value::Nothing,
+                ..
+            } => continue,
+            // literal text has its `${` escaped for GEL, which isn't part of
+            // what gets sent
+            Expr::Value {
+                value: Value::Str(value),
+                ..
+            } if !is_interpolated(&value) => url_encode(&value.replace("$${", "${")),
+            Expr::Value {
+                value: Value::Int(n),
+                ..
+            } => n.to_string(),
+            Expr::Value {
+                value: Value::Bool(b),
+                ..
+            } => b.to_string(),
+            value => {
+                let var = Name::Ident(
+                    format!(
+                        "{name}_{prefix}_{}",
+                        key.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+                    )
+                    .into(),
+                );
+
+                tags.push(Stmt::Let(
+                    var.to_string().into(),
+                    Expr::static_method(
+                        "java.net.URLEncoder",
+                        "encode",
+                        vec![value, "UTF-8".into()],
+                    ),
+                ));
+
+                var.as_value(ast::Context::Text).into_owned()
+            }
+        };
+
+        pairs.push(format!("{}={value}", url_encode(&key)));
+    }
+
+    pairs.join("&")
+}
+
+/// Whether GEL fills in parts of `text`, as it does for format strings.
+fn is_interpolated(text: &str) -> bool {
+    text.replace("$${", "").contains("${")
+}
+
+/// The variable a nested JSON object at `key` of `parent` is bound to.
+fn json_var(parent: &str, key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    format!("{parent}_{key}")
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986.
+fn url_encode(value: &str) -> String {
+    let mut buff = String::with_capacity(value.len());
+
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                buff.push(char::from(byte));
+            }
+            byte => {
+                let _ = write!(buff, "%{byte:02X}");
+            }
+        }
+    }
+
+    buff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::create_json_tags;
+    use crate::{
+        compile_str,
+        gelatin::ast::{Call, Context, Dict, Expr, Stmt},
+        CompileOptions,
+    };
+
+    /// `entries` as a dict, in their order.
+    fn dict<const N: usize>(entries: [(&str, Expr); N]) -> Dict {
+        let mut dict = Dict::new();
+        for (key, value) in entries {
+            dict.insert(key.into(), value);
+        }
+        dict
+    }
+
+    /// The tags building `map` as a JSON object bound to `bind_to`, a line each.
+    fn json_tags(map: Dict, bind_to: &str) -> Vec<String> {
+        let mut tags = vec![];
+        create_json_tags(map, bind_to, &mut tags);
+
+        tags.iter()
+            .map(|tag| match tag {
+                Stmt::Let(name, Expr::Instance { class, .. }) => format!("{name} = new {class}"),
+                Stmt::Expr {
+                    expr: Expr::Call(Call { name, args }),
+                } => {
+                    let args: Vec<_> = args.iter().map(|arg| arg.as_value(Context::Expr)).collect();
+                    format!("{name}({})", args.join(", "))
+                }
+                tag => panic!("{tag:?} doesn't build a JSON object"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn json_objects_put_their_entries_in_order() {
+        let map = dict([("code", "PR001".into()), ("count", 3.into())]);
+
+        assert_eq!(
+            json_tags(map, "project"),
+            [
+                "project = new org.json.JSONObject",
+                "project.put(\"code\", \"PR001\")",
+                "project.put(\"count\", 3)",
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_json_objects_are_built_before_their_parent_puts_them() {
+        let finish = dict([("planned", "2024-06-30".into())]);
+        let dates = dict([
+            ("start", "2024-01-01".into()),
+            ("finish", Expr::Dict(finish)),
+        ]);
+        let map = dict([
+            ("code", "PR001".into()),
+            ("dates", Expr::Dict(dates)),
+            ("owner id", Expr::Dict(dict([("id", 5.into())]))),
+        ]);
+
+        assert_eq!(
+            json_tags(map, "payload"),
+            [
+                "payload = new org.json.JSONObject",
+                "payload.put(\"code\", \"PR001\")",
+                "payload_dates = new org.json.JSONObject",
+                "payload_dates.put(\"start\", \"2024-01-01\")",
+                "payload_dates_finish = new org.json.JSONObject",
+                "payload_dates_finish.put(\"planned\", \"2024-06-30\")",
+                "payload_dates.put(\"finish\", payload_dates_finish)",
+                "payload.put(\"dates\", payload_dates)",
+                "payload_owner_id = new org.json.JSONObject",
+                "payload_owner_id.put(\"id\", 5)",
+                "payload.put(\"owner id\", payload_owner_id)",
+            ]
+        );
+    }
+
+    #[test]
+    fn requests_write_the_object_their_entries_are_put_on() {
+        let xml = compile_str(
+            "let _r = http! POST \"https://example.com\" do\n  json {\"a\": {\"b\": 1}}\nend\n",
+            &CompileOptions::default(),
+        )
+        .unwrap_or_else(|err| panic!("{err:?}"));
+
+        for tag in [
+            "<core:new className=\"org.json.JSONObject\" var=\"_r_payload\" />",
+            "<core:new className=\"org.json.JSONObject\" var=\"_r_payload_a\" />",
+            "${_r_payload.put(&quot;a&quot;, _r_payload_a)}",
+            "${_r_payload.write(_r_w)}",
+        ] {
+            assert!(xml.contains(tag), "{xml} doesn't have {tag}");
+        }
+    }
+
+    /// The url the request of `source` is made to, as it's written in the script.
+    fn request_url(source: &str) -> String {
+        let xml = compile_str(source, &CompileOptions::default()).unwrap_or_else(|err| {
+            panic!("{err:?}");
+        });
+        let (_, url) = xml
+            .split_once("<core:new className=\"java.net.URL\" var=\"remoteURL\"><core:arg value=\"")
+            .unwrap_or_else(|| panic!("{xml} makes no request"));
+        url[..url.find('"').expect("a closed attribute")].to_string()
+    }
+
+    #[test]
+    fn requests_reuse_the_objects_bound_before() {
+        let source = "let payload = {\"code\": \"PR001\"}\nlet _a = http! POST \"https://example.com/a\" do\n  json payload\nend\nlet _b = http! POST \"https://example.com/b\" do\n  json payload\nend\n";
+        let xml = compile_str(source, &CompileOptions::default()).unwrap_or_else(|err| {
+            panic!("{err:?}");
+        });
+
+        assert_eq!(xml.matches("className=\"org.json.JSONObject\"").count(), 1);
+        for write in ["${payload.write(_a_w)}", "${payload.write(_b_w)}"] {
+            assert!(xml.contains(write), "{xml} doesn't have {write}");
+        }
+    }
+
+    #[test]
+    fn queries_are_appended_to_literal_urls() {
+        let request =
+            |url| format!("let _r = http! GET \"{url}\" do\n  query {{\"limit\": 100}}\nend\n");
+
+        assert_eq!(
+            request_url(&request("https://example.com/api")),
+            "https://example.com/api?limit=100"
+        );
+        assert_eq!(
+            request_url(&request("https://example.com/api?page=2")),
+            "https://example.com/api?page=2&amp;limit=100"
+        );
+    }
+
+    #[test]
+    fn queries_check_the_urls_of_variables_at_runtime() {
+        let source = "let base = \"https://example.com/api?page=2\"\nlet _r = http! GET base do\n  query {\"limit\": 100}\nend\n";
+
+        assert_eq!(
+            request_url(source),
+            "${base}${base.contains(&quot;?&quot;) ? &quot;&amp;&quot; : &quot;?&quot;}limit=100"
+        );
+    }
+}
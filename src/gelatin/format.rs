@@ -0,0 +1,533 @@
+use std::ops::Range;
+
+use pest::{iterators::Pair, Parser as _};
+
+use super::{syntax_error, Gelatin, Rule};
+
+const INDENT: &str = "  ";
+
+/// Rules written as they are in the source, embedded sql and xml included.
+const VERBATIM: [Rule; 27] = [
+    Rule::normal_string,
+    Rule::fmt_string,
+    Rule::number,
+    Rule::bool,
+    Rule::null,
+    Rule::unit,
+    Rule::ident,
+    Rule::alias_ident,
+    Rule::access_ident,
+    Rule::xml_body,
+    Rule::query_body,
+    Rule::log_level,
+    Rule::param_type,
+    Rule::datasource,
+    Rule::http_verb,
+    Rule::and,
+    Rule::or,
+    Rule::eq,
+    Rule::neq,
+    Rule::lt,
+    Rule::lte,
+    Rule::gt,
+    Rule::gte,
+    Rule::plus,
+    Rule::sub,
+    Rule::mul,
+    Rule::div,
+];
+
+/// Writes the script `source` in the canonical style: two spaces of indentation per
+/// block, one statement per line, single spaces between tokens and at most one blank line
+/// between statements. Comments are kept, sql and xml bodies are left as written.
+///
+/// # Errors
+/// Returns `Err` if `source` isn't a valid script.
+pub fn format(file_name: &str, source: &str) -> miette::Result<String> {
+    let pairs = Gelatin::parse(Rule::program, source)
+        .map_err(|err| syntax_error(file_name, source, &err))?;
+
+    let mut leaves = vec![];
+    for pair in pairs.clone() {
+        collect_leaves(pair, &mut leaves);
+    }
+
+    let mut formatter = Formatter {
+        source,
+        out: String::with_capacity(source.len()),
+        line: String::new(),
+        space: true,
+        indent: 0,
+        comments: comments(source, &leaves),
+        next_comment: 0,
+        last: 0,
+        block_start: true,
+    };
+
+    for pair in pairs.filter(|pair| pair.as_rule() == Rule::stmt) {
+        formatter.line(pair);
+    }
+    formatter.flush_comments(source.len());
+    formatter.end_line();
+
+    Ok(formatter.out)
+}
+
+/// Pushes the spans of the tokens of `pair` to `leaves`, in source order.
+fn collect_leaves(pair: Pair<Rule>, leaves: &mut Vec<Range<usize>>) {
+    // the rest only holds keywords besides its inner pairs
+    if VERBATIM.contains(&pair.as_rule()) {
+        let span = pair.as_span();
+        leaves.push(span.start()..span.end());
+        return;
+    }
+
+    for inner in pair.into_inner() {
+        collect_leaves(inner, leaves);
+    }
+}
+
+/// The `(* ... *)` comments of `source`, which can only be in between its tokens.
+fn comments(source: &str, leaves: &[Range<usize>]) -> Vec<Range<usize>> {
+    let starts = std::iter::once(0).chain(leaves.iter().map(|leaf| leaf.end));
+    let ends = leaves
+        .iter()
+        .map(|leaf| leaf.start)
+        .chain(std::iter::once(source.len()));
+
+    let mut comments = vec![];
+    for (mut start, end) in starts.zip(ends) {
+        while let Some(open) = source[start..end].find("(*") {
+            let open = start + open;
+            let Some(close) = source[open + 2..end].find("*)") else {
+                break;
+            };
+            let close = open + 2 + close + 2;
+            comments.push(open..close);
+            start = close;
+        }
+    }
+    comments
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    out: String,
+    /// The line being written, with its indentation.
+    line: String,
+    /// Whether the next token is separated from the previous one by a space.
+    space: bool,
+    indent: usize,
+    comments: Vec<Range<usize>>,
+    /// The first comment not written yet.
+    next_comment: usize,
+    /// Where the last token written ends in the source.
+    last: usize,
+    /// Whether nothing was written in the current block yet, so no blank line goes before
+    /// its first line.
+    block_start: bool,
+}
+
+impl Formatter<'_> {
+    /// Writes the statement or directive `pair` on its own line.
+    fn line(&mut self, pair: Pair<Rule>) {
+        let start = pair.as_span().start();
+        self.flush_comments(start);
+        if self.line.is_empty() {
+            self.separate(start);
+        }
+
+        if pair.as_rule() == Rule::stmt {
+            self.node(pair.into_inner().next().expect("statements aren't empty"));
+        } else {
+            self.node(pair);
+        }
+        self.end_line();
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn node(&mut self, pair: Pair<Rule>) {
+        let span = pair.as_span();
+        let rule = pair.as_rule();
+
+        if VERBATIM.contains(&rule) {
+            self.leaf(&pair);
+            return;
+        }
+
+        match rule {
+            Rule::dotted_access => {
+                let text = pair
+                    .clone()
+                    .into_inner()
+                    .map(|part| part.as_str())
+                    .collect::<Vec<_>>()
+                    .join(".");
+                self.token(span.start(), &text);
+                self.last = span.end();
+            }
+            Rule::range => {
+                let text = pair
+                    .clone()
+                    .into_inner()
+                    .map(|bound| bound.as_str())
+                    .collect::<Vec<_>>()
+                    .join("..");
+                self.token(span.start(), &text);
+                self.last = span.end();
+            }
+            Rule::dict => self.dict(pair),
+            Rule::body => self.body(pair),
+            Rule::r#if => self.if_stmt(pair),
+            Rule::http_body | Rule::soap_body => {
+                self.keyword_at(span.start(), "do");
+                self.open_block();
+                for directive in pair.into_inner() {
+                    self.line(directive);
+                }
+                self.close_block(span.end());
+            }
+            Rule::lete | Rule::alias | Rule::namespace => {
+                self.keyword_at(span.start(), keyword(rule));
+                let mut inner = pair.into_inner();
+                self.node(inner.next().expect("it binds a name"));
+                self.keyword("=");
+                self.nodes(inner);
+            }
+            Rule::letfn | Rule::template => {
+                self.keyword_at(span.start(), keyword(rule));
+                for inner in pair.into_inner() {
+                    if matches!(inner.as_rule(), Rule::body | Rule::xml_body) {
+                        self.keyword("=");
+                    }
+                    self.node(inner);
+                }
+            }
+            Rule::param => {
+                self.keyword_at(span.start(), "param");
+                for inner in pair.into_inner() {
+                    if inner.as_rule() == Rule::param_type {
+                        self.glue(":");
+                    }
+                    self.node(inner);
+                }
+            }
+            Rule::use_static => {
+                self.keyword_at(span.start(), "use static");
+                let mut inner = pair.into_inner();
+                self.node(inner.next().expect("the fields are of a class"));
+                self.glue(".{");
+                self.space = false;
+                for (i, field) in inner.enumerate() {
+                    if i > 0 {
+                        self.glue(",");
+                    }
+                    self.node(field);
+                }
+                self.glue("}");
+            }
+            Rule::kwarg => {
+                let mut inner = pair.into_inner();
+                self.node(inner.next().expect("kwargs have a name"));
+                self.keyword("=");
+                self.nodes(inner);
+            }
+            Rule::r#for => {
+                self.keyword_at(span.start(), "for");
+                let mut inner = pair.into_inner();
+                self.node(inner.next().expect("loops have a variable"));
+                self.keyword("in");
+                self.nodes(inner);
+            }
+            Rule::java_class => {
+                self.keyword("class");
+                self.nodes(pair.into_inner());
+            }
+            Rule::query_arg | Rule::invoke_arg => {
+                let mut inner = pair.into_inner();
+                self.node(inner.next().expect("args have a value"));
+                if let Some(cast) = inner.next() {
+                    self.keyword("as");
+                    self.node(cast);
+                }
+            }
+            Rule::expr
+            | Rule::call
+            | Rule::call_args
+            | Rule::params
+            | Rule::query_args
+            | Rule::invoke_args
+            | Rule::static_class
+            | Rule::stmt => self.nodes(pair.into_inner()),
+            rule => {
+                self.keyword_at(span.start(), keyword(rule));
+                self.nodes(pair.into_inner());
+            }
+        }
+    }
+
+    fn nodes<'i>(&mut self, pairs: impl Iterator<Item = Pair<'i, Rule>>) {
+        for pair in pairs {
+            self.node(pair);
+        }
+    }
+
+    /// Writes `do`, the statements of the block a level deeper and `end`.
+    fn body(&mut self, pair: Pair<Rule>) {
+        let span = pair.as_span();
+        self.keyword_at(span.start(), "do");
+        self.open_block();
+
+        for inner in pair.into_inner() {
+            if inner.as_rule() == Rule::catch {
+                let start = inner.as_span().start();
+                self.flush_comments(start);
+                self.indent -= 1;
+                self.keyword_at(start, "catch");
+                let mut inner = inner.into_inner();
+                self.node(inner.next().expect("catch binds the error"));
+                self.open_block();
+                for stmt in inner {
+                    self.line(stmt);
+                }
+            } else {
+                self.line(inner);
+            }
+        }
+
+        self.close_block(span.end());
+    }
+
+    fn if_stmt(&mut self, pair: Pair<Rule>) {
+        let span = pair.as_span();
+        self.keyword_at(span.start(), "if");
+        let mut inner = pair.into_inner();
+        self.node(inner.next().expect("ifs have a condition"));
+        self.keyword("then");
+        self.open_block();
+
+        for inner in inner {
+            if inner.as_rule() == Rule::else_body {
+                let start = inner.as_span().start();
+                self.flush_comments(start);
+                self.indent -= 1;
+                self.keyword_at(start, "else");
+                self.open_block();
+                for stmt in inner.into_inner() {
+                    self.line(stmt);
+                }
+            } else {
+                self.line(inner);
+            }
+        }
+
+        self.close_block(span.end());
+    }
+
+    /// Writes the dict `pair` on one line, or one entry per line if it spans several lines
+    /// in the source.
+    fn dict(&mut self, pair: Pair<Rule>) {
+        let span = pair.as_span();
+        let multiline = pair.as_str().contains('\n');
+        self.keyword_at(span.start(), "{");
+
+        if multiline {
+            self.open_block();
+        } else {
+            self.space = false;
+        }
+
+        for (i, entry) in pair.into_inner().enumerate() {
+            if multiline {
+                let start = entry.as_span().start();
+                self.flush_comments(start);
+                if self.line.is_empty() {
+                    self.separate(start);
+                }
+            } else if i > 0 {
+                self.glue(",");
+            }
+
+            let mut entry = entry.into_inner();
+            self.node(entry.next().expect("entries have a key"));
+            self.glue(":");
+            self.nodes(entry);
+
+            if multiline {
+                self.glue(",");
+                self.end_line();
+            }
+        }
+
+        let end = span.end() - "}".len();
+        self.flush_comments(end);
+        if multiline {
+            self.indent -= 1;
+        } else {
+            self.space = false;
+        }
+        self.keyword_at(end, "}");
+    }
+
+    /// Ends the line opening a block and goes a level deeper.
+    fn open_block(&mut self) {
+        self.end_line();
+        self.indent += 1;
+        self.block_start = true;
+    }
+
+    /// Writes the `end` of the block ending at `end`, a level up.
+    fn close_block(&mut self, end: usize) {
+        let end = end - "end".len();
+        self.flush_comments(end);
+        self.end_line();
+        self.indent -= 1;
+        self.keyword_at(end, "end");
+    }
+
+    /// Writes the token `pair` as it is in the source.
+    fn leaf(&mut self, pair: &Pair<Rule>) {
+        let span = pair.as_span();
+        self.token(span.start(), span.as_str());
+        self.last = span.end();
+    }
+
+    /// Writes `keyword`, which is at `start` in the source.
+    fn keyword_at(&mut self, start: usize, keyword: &str) {
+        self.token(start, keyword);
+        self.last = start + keyword.len();
+    }
+
+    /// Writes `keyword`, whose position in the source isn't known.
+    fn keyword(&mut self, keyword: &str) {
+        self.push(keyword);
+    }
+
+    /// Writes `text` right after the previous token.
+    fn glue(&mut self, text: &str) {
+        self.space = false;
+        self.push(text);
+    }
+
+    /// Writes the comments before `start`, then `text`.
+    fn token(&mut self, start: usize, text: &str) {
+        self.flush_comments(start);
+        self.push(text);
+    }
+
+    fn push(&mut self, text: &str) {
+        if self.line.is_empty() {
+            self.line.push_str(&INDENT.repeat(self.indent));
+        } else if self.space {
+            self.line.push(' ');
+        }
+        self.line.push_str(text);
+        self.space = true;
+    }
+
+    /// Writes the comments that come before `pos`, those on a line of their own stay on
+    /// one.
+    fn flush_comments(&mut self, pos: usize) {
+        while let Some(comment) = self
+            .comments
+            .get(self.next_comment)
+            .filter(|comment| comment.start < pos)
+            .cloned()
+        {
+            self.next_comment += 1;
+
+            let own_line = self.line.is_empty();
+            if own_line {
+                self.separate(comment.start);
+            }
+            self.push(&self.source[comment.clone()]);
+            self.last = comment.end;
+
+            let line_ends = self.source[comment.end..]
+                .split('\n')
+                .next()
+                .is_some_and(|rest| rest.trim().is_empty());
+            if own_line && line_ends {
+                self.end_line();
+            }
+        }
+    }
+
+    /// Writes a blank line before a line starting at `start` if there was at least one in
+    /// the source.
+    fn separate(&mut self, start: usize) {
+        let blank = self
+            .source
+            .get(self.last..start)
+            .is_some_and(|gap| gap.matches('\n').count() > 1);
+        if blank && !self.block_start && !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.block_start = false;
+    }
+
+    /// Finishes the current line, comments after its last token in the source stay on it.
+    fn end_line(&mut self) {
+        while let Some(comment) = self
+            .comments
+            .get(self.next_comment)
+            .filter(|comment| {
+                self.source
+                    .get(self.last..comment.start)
+                    .is_none_or(|gap| !gap.contains('\n'))
+            })
+            .cloned()
+        {
+            self.next_comment += 1;
+            self.push(&self.source[comment.clone()]);
+            self.last = comment.end;
+        }
+
+        if !self.line.trim().is_empty() {
+            self.out.push_str(self.line.trim_end());
+            self.out.push('\n');
+        }
+        self.line.clear();
+        self.space = true;
+    }
+}
+
+/// The keyword a construct starts with.
+fn keyword(rule: Rule) -> &'static str {
+    match rule {
+        Rule::lete | Rule::letfn => "let",
+        Rule::template => "template",
+        Rule::alias => "alias",
+        Rule::namespace => "namespace",
+        Rule::new_class => "new!",
+        Rule::r#static => "static!",
+        Rule::invoke => "invoke!",
+        Rule::http => "http!",
+        Rule::json_get => "json_get!",
+        Rule::json => "json!",
+        Rule::xog_login => "xog_login!",
+        Rule::xog_logout => "xog_logout!",
+        Rule::xog_read => "xog_read!",
+        Rule::xog_write => "xog_write!",
+        Rule::xpath => "xpath!",
+        Rule::read_lines => "read_lines!",
+        Rule::csv => "csv!",
+        Rule::query => "query!",
+        Rule::soap => "soap!",
+        Rule::log => "log!",
+        Rule::write_file => "write_file!",
+        Rule::out => "out!",
+        Rule::set_props => "set_props!",
+        Rule::whitespace => "whitespace!",
+        Rule::transaction => "transaction!",
+        Rule::comment => "comment!",
+        Rule::raw_xml => "xml!",
+        Rule::soap_message_body => "body",
+        Rule::soap_message_header => "header",
+        Rule::soap_on_fault => "on_fault",
+        Rule::http_xml => "xml",
+        Rule::http_on_error => "on_error",
+        Rule::xog_from | Rule::query_file | Rule::xml_file => "from",
+        rule => unreachable!("got rule {rule:?}"),
+    }
+}
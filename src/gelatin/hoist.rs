@@ -0,0 +1,405 @@
+//! Hoisting of the EL expressions a loop evaluates several times with the same value, into
+//! a `let` before the loop so Clarity evaluates them once.
+//!
+//! This runs over the expanded script. The method calls of `${}` expressions and of calls
+//! are hoisted when they're written more than once in the body of a loop and read none of
+//! the names the loop sets. The calls are assumed to have no effects, so this is opt-in.
+
+use std::{collections::HashMap, ops::Range, sync::Arc};
+
+use super::ast::{xml_vars, Call, Context, Expr, Ident, Name, Node, Span, Spanned, Stmt, Value};
+
+/// An expression moved out of a loop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hoisting {
+    /// The top-level statement holding the loop.
+    pub at: Span,
+    /// The name the value of the expression is bound to before the loop.
+    pub name: String,
+    /// The expression, without the `${}`.
+    pub expr: String,
+    /// How many times the body of the loop had it.
+    pub count: usize,
+}
+
+/// Where an EL expression of a loop is.
+enum Site<'a> {
+    /// Text that may hold `${}` expressions.
+    Text(&'a mut Arc<str>),
+    /// A method call evaluated as an EL expression, such as an argument of another one.
+    Call(&'a mut Expr),
+}
+
+/// Hoists the repeated expressions of the loops in the top-level `nodes`, inner loops first.
+pub fn hoist(nodes: &mut Vec<Spanned<Node>>) -> Vec<Hoisting> {
+    let mut hoistings = vec![];
+
+    let mut i = 0;
+    while i < nodes.len() {
+        let span = nodes[i].span;
+        if let Node::Stmt(stmt) = &mut nodes[i].node {
+            for body in bodies(stmt) {
+                hoist_in(body, span, &mut hoistings);
+            }
+
+            let lets = hoist_loop(stmt, span, &mut hoistings);
+            let count = lets.len();
+            nodes.splice(
+                i..i,
+                lets.into_iter().map(|stmt| Spanned {
+                    node: Node::Stmt(stmt),
+                    span,
+                }),
+            );
+            i += count;
+        }
+        i += 1;
+    }
+
+    hoistings
+}
+
+/// Hoists the expressions of the loops in `body`, `at` is the top-level statement holding
+/// it.
+fn hoist_in(body: &mut Vec<Stmt>, at: Span, hoistings: &mut Vec<Hoisting>) {
+    let mut i = 0;
+    while i < body.len() {
+        for inner in bodies(&mut body[i]) {
+            hoist_in(inner, at, hoistings);
+        }
+
+        let lets = hoist_loop(&mut body[i], at, hoistings);
+        let count = lets.len();
+        body.splice(i..i, lets);
+        i += count + 1;
+    }
+}
+
+/// Replaces the repeated expressions of `stmt`, if it's a loop, returning the `let`s binding
+/// them.
+fn hoist_loop(stmt: &mut Stmt, at: Span, hoistings: &mut Vec<Hoisting>) -> Vec<Stmt> {
+    let mut bound = vec![];
+    let (mut condition, body) = match stmt {
+        Stmt::ForEach { var, body, .. } => {
+            bound.push(var.to_string());
+            (None, body)
+        }
+        Stmt::While { test, body } => (Some(test), body),
+        _ => return vec![],
+    };
+    for stmt in body.iter() {
+        binds(stmt, &mut bound);
+    }
+
+    // the candidates by their first appearance, so the names are stable
+    let mut counts: Vec<(String, usize)> = vec![];
+    let mut count = |expr: &str| {
+        if !is_invariant(expr, &bound) {
+            return;
+        }
+        match counts.iter_mut().find(|(seen, _)| seen == expr) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((expr.to_string(), 1)),
+        }
+    };
+    visit_loop(condition.as_deref_mut(), body, &mut |site| {
+        match site {
+            Site::Text(text) => {
+                for range in expressions(text) {
+                    count(&text[range]);
+                }
+            }
+            Site::Call(expr) => count(&expr.as_value(Context::Expr)),
+        }
+        false
+    });
+
+    let names: HashMap<String, String> = counts
+        .iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(expr, _)| {
+            let name = format!("__hoisted{}", hoistings.len());
+            hoistings.push(Hoisting {
+                at,
+                name: name.clone(),
+                expr: expr.clone(),
+                count: 0,
+            });
+            (expr.clone(), name)
+        })
+        .collect();
+    if names.is_empty() {
+        return vec![];
+    }
+
+    let first = hoistings.len() - names.len();
+    let mut replaced = |expr: &str| {
+        let name = names.get(expr)?;
+        let hoisting = hoistings[first..].iter_mut().find(|h| h.name == *name)?;
+        hoisting.count += 1;
+        Some(name.clone())
+    };
+    visit_loop(condition, body, &mut |site| match site {
+        Site::Text(text) => {
+            let mut rewritten = String::with_capacity(text.len());
+            let mut end = 0;
+            for range in expressions(text) {
+                if let Some(name) = replaced(&text[range.clone()]) {
+                    rewritten.push_str(&text[end..range.start]);
+                    rewritten.push_str(&name);
+                    end = range.end;
+                }
+            }
+            if end > 0 {
+                rewritten.push_str(&text[end..]);
+                *text = rewritten.into();
+            }
+            false
+        }
+        Site::Call(expr) => {
+            let name = replaced(&expr.as_value(Context::Expr));
+            name.is_some_and(|name| {
+                *expr = Expr::Ident(Name::Ident(name.into()));
+                true
+            })
+        }
+    });
+
+    // an expression only in hoisted calls isn't evaluated by the loop anymore
+    hoistings.retain(|hoisting| hoisting.count > 0);
+    hoistings[first..]
+        .iter()
+        .map(|hoisting| {
+            Stmt::Let(
+                Ident::from(hoisting.name.as_str()),
+                Expr::from(Value::Str(format!("${{{}}}", hoisting.expr).into())),
+            )
+        })
+        .collect()
+}
+
+/// Whether `expr` is a method call whose value doesn't depend on the `bound` names.
+fn is_invariant(expr: &str, bound: &[String]) -> bool {
+    expr.contains('(')
+        && !expr
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .any(|word| bound.iter().any(|name| name == word))
+}
+
+/// The ranges of the expressions of the `${}`s of `text`, without the `${}`. The ones with
+/// unbalanced parentheses or braces inside, which can't be told apart from the text, are
+/// left out.
+fn expressions(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut offset = 0;
+
+    while let Some(start) = text[offset..].find("${") {
+        let start = offset + start + 2;
+        let Some(len) = text[start..].find('}') else {
+            break;
+        };
+        let end = start + len;
+        offset = end + 1;
+
+        // `$${` is a literal `${`
+        let escaped = text[..start - 2].ends_with('$');
+        let expr = &text[start..end];
+        if !escaped && !expr.contains('{') && expr.matches('(').count() == expr.matches(')').count()
+        {
+            ranges.push(start..end);
+        }
+    }
+
+    ranges
+}
+
+/// The names set by `stmt` and the statements it holds, and the objects of the methods it
+/// calls, which may change them.
+fn binds(stmt: &Stmt, bound: &mut Vec<String>) {
+    match stmt {
+        Stmt::Let(name, _)
+        | Stmt::Catch { name, .. }
+        | Stmt::ForEach { var: name, .. }
+        | Stmt::Param { name, .. } => {
+            bound.push(name.to_string());
+        }
+        Stmt::SetProperties { object: name, .. }
+        | Stmt::Expr {
+            expr: Expr::Call(Call { name, .. }) | Expr::Invoke { object: name, .. },
+        } => bound.push(root(name).to_string()),
+        Stmt::Xml(events) => bound.extend(xml_vars(events).map(ToString::to_string)),
+        _ => {}
+    }
+
+    match stmt {
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::ForEach { body, .. }
+        | Stmt::While { body, .. }
+        | Stmt::WriteFile { body, .. }
+        | Stmt::Transaction { body, .. }
+        | Stmt::If {
+            body, alt: None, ..
+        } => {
+            for stmt in body {
+                binds(stmt, bound);
+            }
+        }
+        Stmt::If {
+            body,
+            alt: Some(alt),
+            ..
+        } => {
+            for stmt in body.iter().chain(alt) {
+                binds(stmt, bound);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn root(name: &Name) -> &Ident {
+    match name {
+        Name::Ident(ident) => ident,
+        Name::Dotted { parent, .. } => root(parent),
+    }
+}
+
+/// The bodies of the statements `stmt` holds, which don't include the ones of functions.
+fn bodies(stmt: &mut Stmt) -> Vec<&mut Vec<Stmt>> {
+    match stmt {
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::ForEach { body, .. }
+        | Stmt::While { body, .. }
+        | Stmt::WriteFile { body, .. }
+        | Stmt::Transaction { body, .. }
+        | Stmt::If {
+            body, alt: None, ..
+        } => vec![body],
+        Stmt::If {
+            body,
+            alt: Some(alt),
+            ..
+        } => vec![body, alt],
+        _ => vec![],
+    }
+}
+
+/// Visits the expressions the loop evaluates on each iteration, its `test` if it has one.
+fn visit_loop(test: Option<&mut Expr>, body: &mut [Stmt], f: &mut dyn FnMut(Site<'_>) -> bool) {
+    if let Some(test) = test {
+        visit_text(test, f);
+    }
+    for stmt in body {
+        visit_stmt(stmt, f);
+    }
+}
+
+fn visit_stmt(stmt: &mut Stmt, f: &mut dyn FnMut(Site<'_>) -> bool) {
+    match stmt {
+        Stmt::Let(_, Expr::Func { .. })
+        | Stmt::Alias { .. }
+        | Stmt::Namespace { .. }
+        | Stmt::Comment(_)
+        | Stmt::Template { .. }
+        | Stmt::Param { .. }
+        | Stmt::Xml(_) => {}
+        // the call itself is run for its effects
+        Stmt::Expr {
+            expr: Expr::Call(Call { args, .. }),
+        } => {
+            for arg in args {
+                visit_expr(arg, f);
+            }
+        }
+        Stmt::Let(_, expr) | Stmt::Expr { expr } | Stmt::Out { value: expr } => {
+            visit_text(expr, f);
+        }
+        Stmt::Log { message, .. } => {
+            f(Site::Text(message));
+        }
+        Stmt::SetProperties { props, .. } => {
+            for value in props.values_mut() {
+                visit_text(value, f);
+            }
+        }
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::Transaction { body, .. } => visit_loop(None, body, f),
+        Stmt::ForEach {
+            items: test, body, ..
+        }
+        | Stmt::While { test, body }
+        | Stmt::WriteFile {
+            path: test, body, ..
+        }
+        | Stmt::If {
+            test,
+            body,
+            alt: None,
+        } => visit_loop(Some(test), body, f),
+        Stmt::If {
+            test,
+            body,
+            alt: Some(alt),
+        } => {
+            visit_loop(Some(test), body, f);
+            visit_loop(None, alt, f);
+        }
+    }
+}
+
+/// Visits `expr`, written as the text of an attribute.
+fn visit_text(expr: &mut Expr, f: &mut dyn FnMut(Site<'_>) -> bool) {
+    match expr {
+        Expr::Value {
+            value: Value::Str(text),
+            ..
+        } => {
+            f(Site::Text(text));
+        }
+        Expr::Instance { args, .. } | Expr::Static(Call { args, .. }) => {
+            for arg in args {
+                visit_text(arg, f);
+            }
+        }
+        Expr::Invoke { args, .. } => {
+            for arg in args {
+                visit_text(&mut arg.value, f);
+            }
+        }
+        _ => visit_expr(expr, f),
+    }
+}
+
+/// Visits `expr`, written inside a `${}`, where strings are literals.
+fn visit_expr(expr: &mut Expr, f: &mut dyn FnMut(Site<'_>) -> bool) {
+    let is_method = matches!(
+        expr,
+        Expr::Call(Call {
+            name: Name::Dotted { .. },
+            ..
+        })
+    );
+    if is_method && expr.is_inline() && f(Site::Call(expr)) {
+        return;
+    }
+
+    match expr {
+        Expr::Call(Call { args, .. }) => {
+            for arg in args {
+                visit_expr(arg, f);
+            }
+        }
+        Expr::Infix { lhs, rhs, .. } => {
+            visit_expr(lhs, f);
+            visit_expr(rhs, f);
+        }
+        Expr::Named { value, .. } => visit_expr(value, f),
+        _ => {}
+    }
+}
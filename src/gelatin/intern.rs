@@ -0,0 +1,58 @@
+//! The names of idents, interned so that every occurrence of a name shares a single
+//! allocation: the parser, the expanders lowering macros into `{name}_status` and the like,
+//! and the transpiler all get their names from here.
+//!
+//! The names are kept per thread, so parallel builds don't wait on each other, and only for
+//! as long as a build holds a [`Scope`]. Once the last of them is dropped they're freed, a
+//! long running process such as the REPL or an editor building a script on every change
+//! doesn't keep the names of every script it ever built. Outside of a build each name gets
+//! an allocation of its own.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    sync::Arc,
+};
+
+thread_local! {
+    static NAMES: RefCell<HashSet<Arc<str>>> = RefCell::default();
+    /// How many [`Scope`]s are alive, builds nest when one parses another to import it.
+    static SCOPES: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Shares the names interned on this thread until it's dropped.
+#[must_use = "the names are only shared while the scope is held"]
+pub struct Scope(());
+
+impl Scope {
+    /// Starts sharing the names, or keeps sharing them in a nested build.
+    pub fn enter() -> Self {
+        SCOPES.set(SCOPES.get() + 1);
+        Self(())
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        SCOPES.set(SCOPES.get() - 1);
+        if SCOPES.get() == 0 {
+            NAMES.take();
+        }
+    }
+}
+
+/// The shared allocation of `name`, made the first time it's interned in the [`Scope`].
+pub fn intern(name: &str) -> Arc<str> {
+    if SCOPES.get() == 0 {
+        return Arc::from(name);
+    }
+
+    NAMES.with_borrow_mut(|names| {
+        if let Some(interned) = names.get(name) {
+            return Arc::clone(interned);
+        }
+        let interned: Arc<str> = Arc::from(name);
+        names.insert(Arc::clone(&interned));
+        interned
+    })
+}
@@ -1,40 +1,268 @@
 pub mod ast;
+#[cfg(feature = "fs")]
+pub mod attributes;
+pub mod dce;
+#[cfg(feature = "fs")]
+pub mod doc;
 mod env;
-
+pub mod expand;
+pub mod format;
+pub mod hoist;
+pub mod intern;
+pub mod secrets;
+pub mod types;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
 use std::fmt::Write;
-use std::sync::Arc;
-use std::{collections::HashMap, fmt::Debug};
-
-use crate::errors::Error;
-use ast::{Datasource, Expr, HttpVerb, Ident, Name, Node, QueryType, Stmt, Value};
-use env::Env;
-use lazy_static::lazy_static;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+
+use crate::dialect::dialect_from_name;
+use crate::errors::{Error, Warning};
+use ast::{
+    Datasource, Dict, Expr, HttpVerb, Ident, Name, Node, PathSegment, QueryType, Spanned, SqlParam,
+    Stmt, TypedArg, Value,
+};
+use env::{Env, Kind};
 use miette::{NamedSource, SourceOffset, SourceSpan};
 use pest::iterators::Pairs;
 use pest::pratt_parser::{Op, PrattParser};
 use pest::Parser as PestParser;
 use pest_derive::Parser as PestParser;
 use sqlparser::ast::Statement;
-use sqlparser::dialect::Dialect;
+use sqlparser::dialect::{Dialect, GenericDialect};
 use sqlparser::{ast::Query, parser::ParserError};
 use xml::common::Position;
 
-use self::ast::{Call, InfixOp, LogLevel};
+use self::ast::{Call, InfixOp, LogLevel, ParamType};
 
 #[derive(PestParser)]
 #[grammar = "gelatin/gel-lang.pest"]
 struct Gelatin;
 
-pub struct Parser<'a, D: Dialect> {
-    env: Env<Expr>,
+pub struct Parser<'a> {
+    env: Env<Bound>,
+    /// Namespaces in scope of the embedded xml.
+    namespaces: xml::namespace::Namespace,
     file_name: &'a str,
     source: &'a str,
+    /// A copy of `source` shared by the diagnostics pointing into it.
+    shared_source: Arc<str>,
     #[allow(clippy::struct_field_names)]
-    sql_parser: D,
+    sql_parser: Box<dyn Dialect>,
+    warnings: Vec<Warning>,
+    /// Bindings to warn about if they are never read.
+    tracked: Vec<Tracked>,
+    /// Selects without a row limit, to warn about if a loop around them updates rows.
+    unbounded: Vec<ast::Span>,
+    /// The datasource of the `transaction!` being parsed.
+    transaction: Option<Datasource>,
+    /// The rows of the `for`s being parsed, innermost last.
+    rows: Vec<Row>,
+    /// Where the selects bound with `let` were written, by name.
+    selects: HashMap<String, ast::Span>,
+    /// The names of the parameters declared with `param`.
+    params: HashSet<String>,
+    /// Whether the name being parsed is called, its last attribute is then a method.
+    callee: bool,
+    /// Whether the name being parsed is of a class, as the ones of `new!` and `static!`.
+    class: bool,
+    /// The templates defined so far, by name.
+    templates: HashMap<String, Template>,
+    /// The files read so far, such as the sql of queries and the scripts of templates.
+    imports: Vec<std::path::PathBuf>,
+    #[cfg(feature = "fs")]
+    usage: Usage,
+}
+
+/// The functions and templates a script defines and imports, with whether it calls them,
+/// to find the ones a whole project never uses.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    pub functions: Vec<Defined>,
+    /// The templates the script defines itself.
+    pub templates: Vec<Defined>,
+    /// The files of templates it imports.
+    pub imports: Vec<Import>,
+}
+
+/// A function or template, and whether it's called.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone)]
+pub struct Defined {
+    pub name: Ident,
+    called: Arc<AtomicBool>,
+}
+
+#[cfg(feature = "fs")]
+impl Defined {
+    #[must_use]
+    pub fn is_called(&self) -> bool {
+        self.called.load(Ordering::Relaxed)
+    }
+}
+
+/// A file of templates a script imports with `template from`.
+#[cfg(feature = "fs")]
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub path: std::path::PathBuf,
+    /// Where it's imported.
+    pub at: ast::Span,
+    /// The templates it defines.
+    pub templates: Vec<Defined>,
+    /// The files it imports itself.
+    pub imports: Vec<Self>,
+}
+
+#[cfg(feature = "fs")]
+impl Import {
+    /// Whether any of its templates, or the ones of the files it imports, is called.
+    #[must_use]
+    pub fn is_used(&self) -> bool {
+        self.templates.iter().any(Defined::is_called) || self.imports.iter().any(Self::is_used)
+    }
+}
+
+/// A template of raw GEL, see [`Stmt::Template`].
+#[derive(Clone)]
+struct Template {
+    params: Vec<Ident>,
+    /// The text of its body, split where the arguments go.
+    parts: Vec<Part>,
+    /// Namespaces in scope of its body, it can be imported from another script.
+    namespaces: xml::namespace::Namespace,
+    /// Set when it's called, shared with the [`Defined`] of its [`Usage`].
+    called: Arc<AtomicBool>,
+}
+
+#[derive(Clone)]
+enum Part {
+    Text(String),
+    /// The argument given for the param at this index.
+    Param(usize),
+}
+
+impl Template {
+    /// The xml of the body with `args` in place of the params.
+    fn render(&self, args: &[String]) -> String {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.as_str(),
+                Part::Param(i) => args[*i].as_str(),
+            })
+            .collect()
+    }
+}
+
+/// The variable of a `for`, with the columns of the select it iterates over.
+struct Row {
+    var: String,
+    /// `None` if the `for` isn't over a select or its columns can't be told.
+    columns: Option<Vec<String>>,
+    query: ast::Span,
+}
+
+/// What the checks need of the value of a binding, kept in place of the value so binding a
+/// large expression doesn't copy it.
+#[derive(Debug, Clone)]
+enum Bound {
+    /// A value the checks don't look into.
+    Opaque,
+    /// A literal other than a string or unit, which can't name a header.
+    Literal,
+    /// A select, with its columns if they can be told and whether it has no row limit.
+    Select {
+        columns: Option<Vec<String>>,
+        unbounded: bool,
+    },
+    /// The class of an `alias`.
+    Class(Box<Expr>),
+}
+
+impl Bound {
+    fn of(expr: &Expr) -> Self {
+        match expr {
+            Expr::Value { value, .. } if !matches!(value, Value::Str(_) | Value::Unit) => {
+                Self::Literal
+            }
+            Expr::Query {
+                r#type: QueryType::SELECT,
+                query,
+                ..
+            } => Self::Select {
+                columns: select_columns(query),
+                unbounded: is_unbounded_select(expr),
+            },
+            _ => Self::Opaque,
+        }
+    }
+}
+
+struct Tracked {
+    name: Ident,
+    alias: bool,
+    read: Arc<AtomicBool>,
 }
 
-lazy_static! {
-    static ref PRATT: PrattParser<Rule> = PrattParser::new()
+/// The `java.sql.Types` a query param can be cast to with `as`.
+const SQL_TYPES: [&str; 25] = [
+    "ARRAY",
+    "BIGINT",
+    "BINARY",
+    "BIT",
+    "BLOB",
+    "BOOLEAN",
+    "CHAR",
+    "CLOB",
+    "DATE",
+    "DECIMAL",
+    "DOUBLE",
+    "FLOAT",
+    "INTEGER",
+    "LONGVARCHAR",
+    "NCHAR",
+    "NCLOB",
+    "NUMERIC",
+    "NVARCHAR",
+    "REAL",
+    "SMALLINT",
+    "TIME",
+    "TIMESTAMP",
+    "TINYINT",
+    "VARBINARY",
+    "VARCHAR",
+];
+
+/// What can go in an `http!` body.
+const HTTP_DIRECTIVES: [&str; 14] = [
+    "timeout",
+    "headers",
+    "query",
+    "json",
+    "form",
+    "body",
+    "xml",
+    "auth_basic",
+    "auth_bearer",
+    "status",
+    "on_error",
+    "retry",
+    "save_to",
+    "capture_headers",
+];
+
+pub const XOG_NS: &str = "http://www.niku.com/xog";
+pub const SOAPENV_NS: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+
+static PRATT: LazyLock<PrattParser<Rule>> = LazyLock::new(|| {
+    PrattParser::new()
+        .op(Op::infix(Rule::or, pest::pratt_parser::Assoc::Left))
+        .op(Op::infix(Rule::and, pest::pratt_parser::Assoc::Left))
         .op(Op::infix(Rule::eq, pest::pratt_parser::Assoc::Right)
             | Op::infix(Rule::neq, pest::pratt_parser::Assoc::Right)
             | Op::infix(Rule::gt, pest::pratt_parser::Assoc::Right)
@@ -44,997 +272,3270 @@ lazy_static! {
         .op(Op::infix(Rule::plus, pest::pratt_parser::Assoc::Left)
             | Op::infix(Rule::sub, pest::pratt_parser::Assoc::Left))
         .op(Op::infix(Rule::mul, pest::pratt_parser::Assoc::Left)
-            | Op::infix(Rule::div, pest::pratt_parser::Assoc::Left));
-}
+            | Op::infix(Rule::div, pest::pratt_parser::Assoc::Left))
+});
+
+impl<'a> Parser<'a> {
+    /// A parser for queries in `dialect`, which can be picked at runtime.
+    pub fn new(file_name: &'a str, source: &'a str, dialect: Box<dyn Dialect>) -> Self {
+        let mut namespaces = xml::namespace::Namespace::empty();
+        namespaces.put("soapenv", SOAPENV_NS);
+        namespaces.put("xog", XOG_NS);
 
-impl<'a, D: Dialect> Parser<'a, D> {
-    pub fn new_with_dialect(file_name: &'a str, source: &'a str, dialect: D) -> Self {
         Self {
             env: Env::new(),
+            namespaces,
             file_name,
             source,
+            shared_source: source.into(),
             sql_parser: dialect,
+            warnings: Vec::new(),
+            tracked: Vec::new(),
+            unbounded: Vec::new(),
+            transaction: None,
+            rows: Vec::new(),
+            selects: HashMap::new(),
+            params: HashSet::new(),
+            callee: false,
+            class: false,
+            templates: HashMap::new(),
+            imports: Vec::new(),
+            #[cfg(feature = "fs")]
+            usage: Usage::default(),
         }
     }
 
-    pub fn parse(&mut self) -> miette::Result<Vec<Node>> {
-        let pairs: miette::Result<_> = Gelatin::parse(Rule::program, self.source).map_err(|e| {
-            let line_col = match e.line_col {
-                pest::error::LineColLocation::Pos((line, col)) => {
-                    SourceOffset::from_location(self.source, line, col)
-                }
-                pest::error::LineColLocation::Span((l1, c1), (_l2, _c2)) => {
-                    SourceOffset::from_location(self.source, l1, c1)
-                }
-            };
+    /// The script, as the source code of a diagnostic.
+    fn named_source(&self) -> NamedSource<Arc<str>> {
+        NamedSource::new(self.file_name, Arc::clone(&self.shared_source))
+    }
 
-            match e.variant {
-                pest::error::ErrorVariant::ParsingError {
-                    positives,
-                    negatives: _,
-                } => {
-                    let expected = positives
-                        .iter()
-                        .map(|r| format!("{r:?}"))
-                        .collect::<Vec<String>>()
-                        .join(", ");
-
-                    Error::Syntax {
-                        source_code: NamedSource::new(self.file_name, self.source.to_string()),
-                        at: SourceSpan::new(line_col, 1),
-                        expected: Some(format!("expected {expected}")),
-                    }
-                    .into()
-                }
-                pest::error::ErrorVariant::CustomError { message: _ } => todo!(),
+    /// The files the script reads when it's parsed, relative to the working directory, in
+    /// the order they're read. The ones the files it imports read are included.
+    #[cfg(feature = "fs")]
+    #[must_use]
+    pub fn imports(&self) -> &[std::path::PathBuf] {
+        &self.imports
+    }
+
+    /// The functions and templates the script defines and imports.
+    #[cfg(feature = "fs")]
+    #[must_use]
+    pub const fn usage(&self) -> &Usage {
+        &self.usage
+    }
+
+    /// Takes the warnings found by the last call to [`Self::parse`].
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    fn warn_shadowing(&mut self, name: &Ident) {
+        if self.env.is_bound(&name.to_string()) {
+            self.warnings.push(Warning::Shadowing {
+                name: name.to_string(),
+                at: name.span().into(),
+            });
+        }
+    }
+
+    /// Opens a scope for the names bound until [`Self::close_scope`].
+    fn open_scope(&mut self) {
+        self.env = std::mem::take(&mut self.env).scoped();
+    }
+
+    fn close_scope(&mut self) {
+        self.env = std::mem::take(&mut self.env).unscoped();
+    }
+
+    /// Binds `name` as `kind`, warning at the end of its scope if it's never read. Returns the
+    /// flag that's set once it's read.
+    fn bind_tracked(&mut self, name: &Ident, bound: Bound, kind: Kind) -> Arc<AtomicBool> {
+        let (read, _) = self.env.bind_tracked(name.to_string(), bound, kind);
+        self.tracked.push(Tracked {
+            name: name.clone(),
+            alias: matches!(kind, Kind::Alias | Kind::Class),
+            read: Arc::clone(&read),
+        });
+        read
+    }
+
+    fn warn_unused(&mut self) {
+        for Tracked { name, alias, read } in self.tracked.drain(..) {
+            if read.load(Ordering::Relaxed) || name.to_string().starts_with('_') {
+                continue;
             }
+
+            let (name, at) = (name.to_string(), name.span().into());
+            self.warnings.push(if alias {
+                Warning::UnusedAlias { name, at }
+            } else {
+                Warning::UnusedVariable { name, at }
+            });
+        }
+    }
+
+    /// Warns about `query`, `span` is the sql it was parsed from or where it was read from.
+    fn lint_query(&mut self, query: &Statement, span: pest::Span) {
+        for (message, keywords) in sql_lints(query) {
+            self.warnings.push(Warning::SqlLint {
+                message,
+                at: sql_span(span, keywords).into(),
+            });
+        }
+
+        let message = match query {
+            Statement::Update {
+                selection: None, ..
+            } => "this update has no `where` clause and changes every row",
+            Statement::Delete {
+                selection: None, ..
+            } => "this delete has no `where` clause and removes every row",
+            _ => return,
+        };
+
+        self.warnings.push(Warning::SuspiciousSql {
+            message: message.to_string(),
+            at: ast::Span::from(span).into(),
+        });
+    }
+
+    /// The row `var` of a `for` over `items`, written at `at`.
+    fn row(&self, var: &Ident, items: &Expr, at: ast::Span) -> Row {
+        let (columns, query_at) = match items {
+            Expr::Ident(Name::Ident(name)) => (
+                match self.env.resolve(name.as_str()) {
+                    Some(Bound::Select { columns, .. }) => columns.clone(),
+                    _ => None,
+                },
+                self.selects.get(name.as_str()).copied().unwrap_or(at),
+            ),
+            Expr::Query {
+                r#type: QueryType::SELECT,
+                query,
+                ..
+            } => (select_columns(query), at),
+            _ => (None, at),
+        };
+
+        Row {
+            var: var.to_string(),
+            columns,
+            query: query_at,
+        }
+    }
+
+    /// Warns if `row` is the row of a select without the column `column`.
+    fn check_column(&mut self, row: &Ident, column: &Ident) {
+        let Some(Row {
+            columns: Some(columns),
+            query,
+            ..
+        }) = self.rows.iter().rev().find(|r| r.var == row.as_str())
+        else {
+            return;
+        };
+        // the rows of a result are maps that ignore the case of the column names
+        if columns
+            .iter()
+            .any(|c| c.eq_ignore_ascii_case(column.as_str()))
+        {
+            return;
+        }
+
+        let columns = columns
+            .iter()
+            .map(|c| format!("`{c}`"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.warnings.push(Warning::UnknownColumn {
+            column: column.to_string(),
+            columns,
+            at: column.span().into(),
+            query: (*query).into(),
         });
-        let pairs = pairs?;
+    }
+
+    pub fn parse(&mut self) -> miette::Result<Vec<Spanned<Node>>> {
+        let _names = intern::Scope::enter();
+        let pairs = Gelatin::parse(Rule::program, self.source)
+            .map_err(|err| syntax_error(self.file_name, self.source, &err))?;
 
         let mut ast = vec![];
+        let mut errors = vec![];
 
         for pair in pairs {
-            match pair.as_rule() {
+            let span = span_of(&pair);
+            let node = match pair.as_rule() {
                 Rule::stmt => {
-                    let stmt = self.stmt_from(pair.into_inner().next().unwrap())?;
-                    let stmt = Self::macro_expand_stmt(stmt);
-                    ast.push(stmt);
-                }
-                Rule::expression => {
-                    let expr = self.expr_from(pair.into_inner())?;
-                    let expr = Self::macro_expand_expr("_", expr);
-                    ast.push(expr);
+                    // the statement binds in a scope of its own, so it can be dropped if
+                    // the statement fails without copying everything bound before it
+                    self.open_scope();
+                    let env = self.env.clone();
+                    let defined = Self::defined_name(&pair);
+                    let stmt = self.stmt_from(pair.into_inner().next().unwrap());
+
+                    match stmt {
+                        Ok(stmt) => {
+                            drop(env);
+                            self.env = std::mem::take(&mut self.env).merged();
+                            Ok(Node::Stmt(stmt))
+                        }
+                        Err(err) => {
+                            // Whatever the statement would have bound is still bound, so the
+                            // statements using it don't report it as unbound.
+                            self.env = env.unscoped();
+                            if let Some(name) = defined {
+                                self.env.bind(name, Bound::Opaque);
+                            }
+                            Err(err)
+                        }
+                    }
                 }
+                Rule::expression => self.expr_from(pair.into_inner()).map(Node::Expr),
                 Rule::EOI => break,
                 rule => unreachable!("got rule {rule:?}"),
+            };
+
+            match node {
+                Ok(node) => ast.push(Spanned { node, span }),
+                Err(err) => errors.push(err),
             }
         }
 
-        Ok(ast)
+        self.warn_unused();
+
+        match errors.len() {
+            0 => Ok(ast),
+            1 => Err(errors.remove(0)),
+            count => Err(Error::Multiple {
+                count,
+                errors: errors
+                    .into_iter()
+                    .map(|err| {
+                        err.downcast::<Error>().unwrap_or_else(|err| Error::Value {
+                            message: err.to_string(),
+                        })
+                    })
+                    .collect(),
+            }
+            .into()),
+        }
+    }
+
+    /// The name a top-level statement binds, if any.
+    fn defined_name(pair: &pest::iterators::Pair<Rule>) -> Option<String> {
+        let inner = pair.clone().into_inner().next()?;
+        match inner.as_rule() {
+            Rule::lete | Rule::letfn | Rule::alias => inner
+                .into_inner()
+                .next()
+                .map(|ident| ident.as_str().to_string()),
+            _ => None,
+        }
     }
-    #[allow(clippy::too_many_lines)]
     fn expr_from(&mut self, pair: Pairs<Rule>) -> miette::Result<Expr> {
-        PRATT
+        let (file_name, source) = (self.file_name, Arc::clone(&self.shared_source));
+        let expr = PRATT
             .map_primary(|pair| {
-                match pair.as_rule() {
-                    Rule::unit => Ok(Expr::Value(Value::Unit)),
-                    Rule::bool => Ok(Expr::Value(Value::Bool(pair.as_str() == "true"))),
-                    Rule::null => Ok(Expr::Value(Value::Nothing)),
-                    Rule::number => Ok(Expr::Value(Value::Int(
-                        pair.as_str().trim().parse().unwrap(),
-                    ))),
-                    Rule::normal_string => Ok(Expr::Value(pair.into_inner().as_str().into())),
-                    Rule::access_ident => {
-                        if self.env.resolve(pair.as_str()).is_none() {
-                            let (line, col) = pair.line_col();
-                            return Err(Error::UnboundName {
-                                source_code: pair.get_input().to_string(),
-                                at: SourceSpan::new(
-                                    SourceOffset::from_location(pair.get_input(), line, col),
-                                    pair.as_str().len(),
-                                ),
-                            }
-                            .into());
-                        }
-
-                        Ok(Expr::Ident(Name::Ident(Ident::from(pair.as_str()))))
+                let at = span_of(&pair);
+                // a nested expression keeps the span of what's inside it, as its pair can
+                // take the whitespace after it
+                let expr = self.primary(pair)?.with_span(at);
+                let at = match &expr {
+                    Expr::Value { span, .. } | Expr::Infix { span, .. } => *span,
+                    _ => at,
+                };
+                Ok((expr, at))
+            })
+            .map_prefix(|op, _| {
+                Err(invalid(
+                    NamedSource::new(file_name, Arc::clone(&source)),
+                    op.as_span(),
+                    "unsupported prefix operator",
+                ))
+            })
+            .map_infix(|lhs, op, rhs| {
+                let op = match op.as_rule() {
+                    Rule::plus => InfixOp::Add,
+                    Rule::sub => InfixOp::Sub,
+                    Rule::div => InfixOp::Div,
+                    Rule::mul => InfixOp::Mul,
+                    Rule::eq => InfixOp::Eq,
+                    Rule::neq => InfixOp::Neq,
+                    Rule::lt => InfixOp::Lt,
+                    Rule::gt => InfixOp::Gt,
+                    Rule::lte => InfixOp::Lte,
+                    Rule::gte => InfixOp::Gte,
+                    Rule::and => InfixOp::And,
+                    Rule::or => InfixOp::Or,
+                    _ => {
+                        return Err(invalid(
+                            NamedSource::new(file_name, Arc::clone(&source)),
+                            op.as_span(),
+                            "unsupported operator",
+                        ))
                     }
-                    Rule::ident => Ok(Expr::Ident(Name::Ident(Ident::from(pair.as_str())))),
-                    Rule::dotted_access => {
-                        let mut dpair = pair.clone().into_inner();
-                        let parentp = dpair.next().unwrap();
-                        let Expr::Ident(parent) = self.expr_from(Pairs::single(parentp.clone()))?
-                        else {
-                            unreachable!()
-                        };
-
-                        // If it's an ident, we can resolve it as it should be defined.
-                        if let Name::Ident(ref parent) = parent {
-                            if self.env.resolve(parent.as_str()).is_none() {
-                                let (line, col) = parentp.line_col();
-                                return Err(Error::UnboundName {
-                                    source_code: pair.get_input().to_string(),
-                                    at: SourceSpan::new(
-                                        SourceOffset::from_location(pair.get_input(), line, col),
-                                        parentp.as_str().len(),
-                                    ),
-                                }
-                                .into());
-                            }
-                        }
+                };
+                let (lhs, lhs_at) = lhs?;
+                let (rhs, rhs_at) = rhs?;
+                let at = lhs_at.to(rhs_at);
 
-                        let mut attrs = Vec::with_capacity(dpair.len());
+                Ok((Expr::infix(lhs, op, rhs).with_span(at), at))
+            })
+            .parse(pair);
+        expr.map(|(expr, _)| expr)
+    }
 
-                        for attr in dpair {
-                            let Expr::Ident(attr @ Name::Ident(_)) =
-                                self.expr_from(Pairs::single(attr))?
-                            else {
-                                unreachable!()
-                            };
-                            attrs.push(attr);
-                        }
+    /// Parses the operand `pair` of an expression.
+    #[allow(clippy::too_many_lines)]
+    fn primary(&mut self, pair: pest::iterators::Pair<Rule>) -> miette::Result<Expr> {
+        match pair.as_rule() {
+            Rule::unit => Ok(Expr::from(Value::Unit)),
+            Rule::bool => Ok(Expr::from(Value::Bool(pair.as_str() == "true"))),
+            Rule::null => Ok(Expr::from(Value::Nothing)),
+            Rule::number => Ok(Expr::from(Value::Int(self.int(&pair)?))),
+            Rule::normal_string => Ok(Expr::from(Value::literal(pair.into_inner().as_str()))),
+            Rule::access_ident => {
+                self.check_kind(&pair, false, false)?;
+                if self.env.resolve(pair.as_str()).is_none() {
+                    let (line, col) = pair.line_col();
+                    return Err(Error::UnboundName {
+                        source_code: self.named_source(),
+                        at: SourceSpan::new(
+                            SourceOffset::from_location(pair.get_input(), line, col),
+                            pair.as_str().len(),
+                        ),
+                    }
+                    .into());
+                }
 
-                        Ok(Expr::Ident(Name::Dotted {
+                Ok(Expr::Ident(Name::Ident(span_ident(&pair))))
+            }
+            Rule::ident => {
+                // callees and the names taken by some macros aren't checked, but
+                // they still count as reads.
+                let _ = self.env.resolve(pair.as_str());
+                Ok(Expr::Ident(Name::Ident(span_ident(&pair))))
+            }
+            Rule::dotted_access => {
+                let callee = std::mem::take(&mut self.callee);
+                let class = std::mem::take(&mut self.class);
+                let mut dpair = pair.clone().into_inner();
+                let parentp = dpair.next().unwrap();
+
+                // the qualified name of a class, as in `new! java.io.File path`, its
+                // last part is the member for `static!` as the one of an alias is
+                if class && !self.env.is_bound(parentp.as_str()) {
+                    let mut parent = Name::Ident(span_ident(&parentp));
+                    let mut attrs: Vec<Name> =
+                        dpair.map(|attr| Name::Ident(span_ident(&attr))).collect();
+                    let member = attrs.pop().expect("dotted names have an attribute");
+                    if !attrs.is_empty() {
+                        parent = Name::Dotted {
                             parent: Box::new(parent),
                             attrs,
-                        }))
-                    }
-                    Rule::soap => {
-                        let mut pair = pair.into_inner();
-                        let Expr::Value(Value::Str(endpoint)) =
-                            self.expr_from(Pairs::single(pair.next().unwrap()))?
-                        else {
-                            unreachable!()
                         };
+                    }
 
-                        let body = pair.next().expect("HEADER").into_inner();
-                        let mut soap_header = None;
-                        let mut soap_body = None;
-
-                        for pair in body {
-                            let (line, _) = pair.line_col();
-
-                            match pair.as_rule() {
-                                Rule::soap_message_header => {
-                                    let mut buff = String::new();
-                                    let pair = pair.into_inner().next().unwrap();
-
-                                    for arg in pair.into_inner() {
-                                        match arg.as_rule() {
-                                            Rule::fmt => {
-                                                let fmt = self.expr_from(arg.into_inner())?;
-                                                let _ = write!(
-                                                    buff,
-                                                    "{}",
-                                                    fmt.as_value(ast::Context::Text)
-                                                );
-                                            }
-                                            Rule::xml_str => {
-                                                let arg = arg.as_str();
-
-                                                let _ = buff.write_str(arg);
-                                            }
-                                            _ => unreachable!("{:?}", arg.as_rule()),
-                                        }
-                                    }
-
-                                    if buff.is_empty() {
-                                        continue;
-                                    }
+                    return Ok(Expr::Ident(Name::Dotted {
+                        parent: Box::new(parent),
+                        attrs: vec![member],
+                    }));
+                }
 
-                                    let _ = soap_header.insert(self.parse_xml(&buff, line)?);
-                                }
-                                Rule::soap_message_body => {
-                                    let mut buff = String::new();
-                                    let pair = pair.into_inner().next().unwrap();
-
-                                    for arg in pair.into_inner() {
-                                        match arg.as_rule() {
-                                            Rule::fmt => {
-                                                let fmt = self.expr_from(arg.into_inner())?;
-                                                let _ = write!(
-                                                    buff,
-                                                    "{}",
-                                                    fmt.as_value(ast::Context::Text)
-                                                );
-                                            }
-                                            Rule::xml_str => {
-                                                let arg = arg.as_str();
-
-                                                let _ = buff.write_str(arg);
-                                            }
-                                            _ => unreachable!("{:?}", arg.as_rule()),
-                                        }
-                                    }
+                if parentp.as_rule() == Rule::ident {
+                    self.check_kind(&parentp, class, false)?;
+                }
 
-                                    if buff.is_empty() {
-                                        continue;
-                                    }
+                // an alias may resolve to something that's not a name.
+                self.class = class;
+                let parent = self.expr_from(Pairs::single(parentp.clone()));
+                self.class = false;
+                let Expr::Ident(parent) = parent? else {
+                    return Err(self.invalid(
+                        parentp.as_span(),
+                        "only names and aliases of classes have fields",
+                    ));
+                };
 
-                                    let _ = soap_body.insert(self.parse_xml(&buff, line)?);
-                                }
-                                _ => unreachable!(),
-                            }
+                // If it's an ident, we can resolve it as it should be defined.
+                if let Name::Ident(ref parent) = parent {
+                    if self.env.resolve(parent.as_str()).is_none() {
+                        let (line, col) = parentp.line_col();
+                        return Err(Error::UnboundName {
+                            source_code: self.named_source(),
+                            at: SourceSpan::new(
+                                SourceOffset::from_location(pair.get_input(), line, col),
+                                parentp.as_str().len(),
+                            ),
                         }
-
-                        Ok(Expr::Soap {
-                            endpoint,
-                            header: soap_header,
-                            body: soap_body,
-                        })
+                        .into());
                     }
-                    Rule::java_class => {
-                        let mut dpair = pair.clone().into_inner();
-                        let parent = Ident::from(dpair.next().unwrap().as_str());
+                }
 
-                        let mut attrs = Vec::with_capacity(dpair.len());
+                let mut attrs = Vec::with_capacity(dpair.len());
 
-                        for attr in dpair {
-                            let attr = Name::Ident(Ident::from(attr.as_str()));
-                            attrs.push(attr);
-                        }
+                for attr in dpair {
+                    let Expr::Ident(attr @ Name::Ident(_)) = self.expr_from(Pairs::single(attr))?
+                    else {
+                        unreachable!()
+                    };
+                    attrs.push(attr);
+                }
 
-                        Ok(Expr::Ident(Name::Dotted {
-                            parent: Box::new(Name::Ident(parent)),
-                            attrs,
-                        }))
+                // the attribute of a method call is the method
+                if let (Name::Ident(parent), Some(Name::Ident(column))) = (&parent, attrs.first()) {
+                    if !callee || attrs.len() > 1 {
+                        self.check_column(parent, column);
                     }
-                    Rule::http => {
-                        let mut qpair = pair.clone().into_inner();
-                        let verb: HttpVerb = qpair.next().unwrap().as_str().try_into()?;
+                }
 
-                        let url = self.expr_from(Pairs::single(qpair.next().unwrap()))?;
+                Ok(Expr::Ident(Name::Dotted {
+                    parent: Box::new(parent),
+                    attrs,
+                }))
+            }
+            Rule::soap => {
+                let mut pair = pair.into_inner();
+                let endpoint = self.expr_from(Pairs::single(pair.next().unwrap()))?;
+
+                let body = pair.next().expect("HEADER").into_inner();
+                let mut soap_header = None;
+                let mut soap_body = None;
+                let mut on_fault = None;
+
+                for pair in body {
+                    let rule = pair.as_rule();
+                    if rule == Rule::soap_on_fault {
+                        on_fault = Some(Box::new(self.handler_from(pair.into_inner())?));
+                        continue;
+                    }
 
-                        let Stmt::Block(body) = self.stmt_from(qpair.next().unwrap())? else {
-                            unreachable!()
-                        };
+                    let Some(message) = self.soap_message(pair.into_inner().next().unwrap())?
+                    else {
+                        continue;
+                    };
 
-                        Ok(Expr::Http {
-                            verb,
-                            url: Box::new(url),
-                            body,
-                        })
+                    match rule {
+                        Rule::soap_message_header => {
+                            let _ = soap_header.insert(message);
+                        }
+                        Rule::soap_message_body => {
+                            let _ = soap_body.insert(message);
+                        }
+                        _ => unreachable!(),
                     }
-                    Rule::fmt_string => {
-                        let pair = pair.into_inner();
+                }
+
+                Ok(Expr::Soap {
+                    endpoint: Box::new(endpoint),
+                    header: soap_header,
+                    body: soap_body,
+                    on_fault,
+                })
+            }
+            Rule::java_class => {
+                let mut dpair = pair.clone().into_inner();
+                let parent = span_ident(&dpair.next().unwrap());
 
-                        let mut buff = String::new();
+                let mut attrs = Vec::with_capacity(dpair.len());
 
-                        for arg in pair {
-                            match arg.as_rule() {
-                                Rule::fmt => {
-                                    let fmt = self.expr_from(arg.into_inner())?;
-                                    let _ =
-                                        buff.write_str(fmt.as_value(ast::Context::Text).as_ref());
-                                }
-                                Rule::character => {
-                                    let _ = buff.write_str(arg.as_str());
-                                }
-                                _ => unreachable!(),
-                            }
-                        }
+                for attr in dpair {
+                    let attr = Name::Ident(span_ident(&attr));
+                    attrs.push(attr);
+                }
 
-                        Ok(Expr::Value(buff.as_str().into()))
-                    }
-                    Rule::range => {
-                        let mut pair = pair.into_inner();
-                        let start = pair.next().unwrap();
-                        let end = pair.next().unwrap();
-
-                        Ok(Expr::Range {
-                            start: start.as_str().parse().unwrap(),
-                            end: end.as_str().parse().unwrap(),
-                            step: 1,
-                        })
-                    }
-                    Rule::alias_ident => {
-                        let alias = pair.as_str();
-                        if let Some(expr) = self.env.resolve(alias) {
-                            return Ok(expr.clone());
-                        }
+                Ok(Expr::Ident(Name::Dotted {
+                    parent: Box::new(Name::Ident(parent)),
+                    attrs,
+                }))
+            }
+            Rule::http => {
+                let mut qpair = pair.clone().into_inner();
+                let verb: HttpVerb = qpair.next().unwrap().as_str().try_into()?;
+
+                let url = self.expr_from(Pairs::single(qpair.next().unwrap()))?;
+
+                let mut body: Vec<Stmt> = Vec::new();
+                for directive in qpair.next().unwrap().into_inner() {
+                    let span = directive.as_span();
+                    let directive = match directive.as_rule() {
+                        Rule::http_xml => {
+                            let (line, _) = directive.line_col();
+                            let xml = self.xml_from(directive.into_inner().next().unwrap())?;
+                            // only validate it, it's written to the connection as is.
+                            self.parse_xml(&xml, line)?;
 
-                        Err(Error::UnboundAlias {
-                            source_code: pair.get_input().to_string(),
-                            at: SourceSpan::new(
-                                SourceOffset::from_location(
-                                    pair.get_input(),
-                                    pair.line_col().0,
-                                    pair.line_col().1,
-                                ),
-                                alias.len(),
+                            Stmt::Expr {
+                                expr: Expr::call("xml", vec![xml.trim().into()]),
+                            }
+                        }
+                        Rule::http_on_error => Stmt::Expr {
+                            expr: Expr::call(
+                                "on_error",
+                                vec![self.handler_from(directive.into_inner())?],
                             ),
+                        },
+                        // it takes no arguments, so it's written bare
+                        _ if directive.as_str() == "status" => Stmt::Expr {
+                            expr: Expr::call("status", vec![]),
+                        },
+                        _ => {
+                            let stmt = self.stmt_from(directive)?;
+                            self.check_directive(span, &stmt)?;
+                            stmt
                         }
-                        .into())
+                    };
+                    if let Some(message) = repeated_directive(&body, &directive) {
+                        return Err(self.invalid(span, &message));
                     }
-                    Rule::json => {
-                        let mut pair = pair.into_inner();
-                        if let Expr::Ident(Name::Ident(conn_obj)) =
-                            self.expr_from(Pairs::single(pair.next().unwrap()))?
-                        {
-                            Ok(Expr::Json { expr: conn_obj })
-                        } else {
-                            unreachable!()
+                    body.push(directive);
+                }
+
+                Ok(Expr::Http {
+                    verb,
+                    url: Box::new(url),
+                    body,
+                })
+            }
+            Rule::fmt_string => {
+                let pair = pair.into_inner();
+
+                let mut buff = String::new();
+                // the text since the last interpolation
+                let mut text = String::new();
+
+                for arg in pair {
+                    match arg.as_rule() {
+                        Rule::fmt => {
+                            let fmt = self.interpolated(arg)?;
+                            let fmt = fmt.as_value(ast::Context::Text);
+                            buff.push_str(&text.replace("${", "$${"));
+                            // Jelly reads `$$` as a `$`, so a `$` before the interpolation
+                            // doesn't escape it
+                            if text.ends_with('$') && fmt.starts_with(['$', '{']) {
+                                buff.push('$');
+                            }
+                            buff.push_str(&fmt);
+                            text.clear();
                         }
+                        Rule::character => text.push_str(arg.as_str()),
+                        _ => unreachable!(),
                     }
+                }
+                buff.push_str(&text.replace("${", "$${"));
 
-                    Rule::dict => {
-                        let pair = pair.into_inner();
-
-                        let mut dict = HashMap::new();
+                Ok(Expr::from(buff.as_str()))
+            }
+            Rule::range => {
+                let mut pair = pair.into_inner();
+                let start = pair.next().unwrap();
+                let end = pair.next().unwrap();
 
-                        for kv in pair {
-                            let mut kv = kv.into_inner();
+                Ok(Expr::Range {
+                    start: self.int(&start)?,
+                    end: self.int(&end)?,
+                    step: 1,
+                })
+            }
+            Rule::alias_ident => {
+                let class = std::mem::take(&mut self.class);
+                self.check_kind(&pair, class, self.callee)?;
+                let alias = pair.as_str();
+                match self.env.resolve(alias) {
+                    Some(Bound::Class(cls)) => return Ok(cls.as_ref().clone()),
+                    Some(_) => return Ok(Expr::Ident(Name::Ident(span_ident(&pair)))),
+                    None => {}
+                }
 
-                            let Expr::Value(Value::Str(key)) =
-                                self.expr_from(Pairs::single(kv.next().unwrap()))?
-                            else {
-                                unreachable!()
-                            };
+                Err(Error::UnboundAlias {
+                    source_code: self.named_source(),
+                    at: SourceSpan::new(
+                        SourceOffset::from_location(
+                            pair.get_input(),
+                            pair.line_col().0,
+                            pair.line_col().1,
+                        ),
+                        alias.len(),
+                    ),
+                }
+                .into())
+            }
+            Rule::kwarg => {
+                let mut pair = pair.into_inner();
+                let name = span_ident(&pair.next().unwrap());
+                let value = self.expr_from(Pairs::single(pair.next().unwrap()))?;
 
-                            let value = self.expr_from(kv.next().unwrap().into_inner())?;
-                            dict.insert(key, value);
-                        }
+                Ok(Expr::Named {
+                    name,
+                    value: Box::new(value),
+                })
+            }
+            Rule::json => {
+                let mut pair = pair.into_inner();
+                if let Expr::Ident(Name::Ident(conn_obj)) =
+                    self.expr_from(Pairs::single(pair.next().unwrap()))?
+                {
+                    Ok(Expr::Json { expr: conn_obj })
+                } else {
+                    unreachable!()
+                }
+            }
 
-                        Ok(Expr::Dict(dict))
-                    }
-                    Rule::query => {
-                        let mut qpair = pair.clone().into_inner();
-                        let datasource: Datasource = qpair.next().unwrap().as_str().try_into()?;
-
-                        let body_pair = qpair.next().unwrap();
-
-                        let body = body_pair.clone().into_inner().as_str();
-
-                        let parser = sqlparser::parser::Parser::new(&self.sql_parser);
-
-                        let ((query, param_len), query_type) =
-                            self.try_parse_query(parser, body, &pair, &body_pair)?;
-
-                        let params = match qpair.next() {
-                            Some(qpair) => {
-                                let (line, col) = qpair.line_col();
-
-                                let params = qpair
-                                    .into_inner()
-                                    .map(|expr| self.expr_from(Pairs::single(expr)))
-                                    .collect::<miette::Result<Vec<Expr>>>()?;
-
-                                if param_len != params.len() {
-                                    return Err(Error::SqlParamErr {
-                                        source_code: NamedSource::new(
-                                            self.file_name,
-                                            self.source.to_string(),
-                                        ),
-                                        at: SourceSpan::new(
-                                            SourceOffset::from_location(self.source, line, col),
-                                            col,
-                                        ),
-                                        err: format!(
-                                            "expected {param_len} parameters but got {}",
-                                            params.len()
-                                        ),
-                                    }
-                                    .into());
-                                }
+            Rule::xog_login => {
+                let mut args = Vec::with_capacity(3);
+                for arg in pair.into_inner() {
+                    args.push(Box::new(self.expr_from(Pairs::single(arg))?));
+                }
 
-                                params
-                            }
-                            None => vec![],
-                        };
+                let [endpoint, username, password] =
+                    args.try_into().expect("the grammar ensures 3 arguments");
 
-                        Ok(Expr::Query {
-                            datasource,
-                            query,
-                            params,
-                            r#type: query_type,
-                        })
-                    }
-                    Rule::new_class => {
-                        let pair = pair.into_inner();
-                        let (callable, args) = self.parse_callable(pair)?;
+                Ok(Expr::XogLogin {
+                    endpoint,
+                    username,
+                    password,
+                })
+            }
+            Rule::xog_logout => Ok(Expr::XogLogout {
+                session: self.session_from(pair.into_inner().next().unwrap())?,
+            }),
+            Rule::xog_read => {
+                let mut pair = pair.into_inner();
+                let session = self.session_from(pair.next().unwrap())?;
+                let object_type = pair.next().unwrap().into_inner().as_str();
 
-                        Ok(Expr::Instance {
-                            class: callable,
-                            args,
-                        })
+                let (filters, line) = match pair.next() {
+                    Some(filters) => {
+                        let (line, _) = filters.line_col();
+                        (self.xml_from(filters)?, line)
                     }
-                    Rule::r#static => {
-                        let mut pair = pair.into_inner();
-                        let Expr::Ident(callable) =
-                            self.expr_from(Pairs::single(pair.next().unwrap()))?
-                        else {
-                            unreachable!()
-                        };
+                    None => (String::new(), 0),
+                };
 
-                        let mut args = Vec::new();
+                let xml = format!(
+                    "{}<Query>\n{filters}\n</Query></NikuDataBus>",
+                    nikudatabus_start("read", object_type)
+                );
+                let body = self.parse_xml(&xml, line.saturating_sub(1))?;
 
-                        if let Some(args_pair) = pair.next() {
-                            let args_pair = args_pair.into_inner();
+                Ok(xog_request(&session, body))
+            }
+            Rule::xog_write => {
+                let mut pair = pair.into_inner();
+                let session = self.session_from(pair.next().unwrap())?;
+                let source = pair.next().unwrap();
+
+                let body = if source.as_rule() == Rule::xog_from {
+                    // the output of a `xog_read!` can be written back as is
+                    let from = self.session_from(source.into_inner().next().unwrap())?;
+                    let xml = format!(
+                        "<gel:include xmlns:gel=\"jelly:com.niku.union.gel.GELTagLibrary\" \
+                         select=\"${from}//NikuDataBus\"/>"
+                    );
+                    self.parse_xml(&xml, 0)?
+                } else {
+                    let object_type = source.into_inner().as_str();
+                    let content = pair.next().unwrap();
+                    let (line, _) = content.line_col();
+
+                    let xml = format!(
+                        "{}\n{}\n</NikuDataBus>",
+                        nikudatabus_start("write", object_type),
+                        self.xml_from(content)?
+                    );
+                    self.parse_xml(&xml, line.saturating_sub(1))?
+                };
 
-                            for arg in args_pair.map(|arg| self.expr_from(Pairs::single(arg))) {
-                                let arg = arg?;
-                                if matches!(arg, Expr::Value(Value::Unit)) {
-                                    continue;
-                                }
+                Ok(xog_request(&session, body))
+            }
+            Rule::read_lines => {
+                let path = self.expr_from(pair.into_inner())?;
 
-                                args.push(arg);
-                            }
-                            Ok(Expr::static_invoke(callable, args))
-                        } else {
-                            Ok(Expr::get_static(callable))
+                Ok(Expr::ReadLines {
+                    path: Box::new(path),
+                })
+            }
+            Rule::csv => {
+                let mut pair = pair.into_inner();
+                let path = self.expr_from(Pairs::single(pair.next().unwrap()))?;
+
+                let mut delimiter = Expr::from(",");
+                let mut headers = false;
+                for kwarg in pair {
+                    let span = kwarg.as_span();
+                    match self.expr_from(Pairs::single(kwarg))? {
+                        Expr::Named { name, value } if name.as_str() == "delimiter" => {
+                            delimiter = *value;
+                        }
+                        Expr::Named { name, value }
+                            if name.as_str() == "headers"
+                                && matches!(
+                                    *value,
+                                    Expr::Value {
+                                        value: Value::Bool(_),
+                                        ..
+                                    }
+                                ) =>
+                        {
+                            headers = matches!(
+                                *value,
+                                Expr::Value {
+                                    value: Value::Bool(true),
+                                    ..
+                                }
+                            );
+                        }
+                        _ => {
+                            return Err(self.unexpected_kwarg(
+                                span,
+                                "`delimiter = \";\"` or `headers = true|false`",
+                            ));
                         }
                     }
-                    Rule::call => {
-                        let pair = pair.into_inner();
-                        let (callable, args) = self.parse_callable(pair)?;
-
-                        Ok(Expr::call(callable, args))
-                    }
-                    Rule::expression | Rule::expr | Rule::value => {
-                        self.expr_from(pair.into_inner())
-                    }
-                    rule => unreachable!("not an expr rule: {:?}", rule),
                 }
-            })
-            .map_prefix(|op, rhs| todo!("{op:?} {rhs:?}"))
-            .map_infix(|lhs, op, rhs| {
-                let op = match op.as_rule() {
-                    Rule::plus => InfixOp::Add,
-                    Rule::sub => InfixOp::Sub,
-                    Rule::div => InfixOp::Div,
-                    Rule::mul => InfixOp::Mul,
-                    Rule::eq => InfixOp::Eq,
-                    Rule::neq => InfixOp::Neq,
-                    Rule::lt => InfixOp::Lt,
-                    Rule::gt => InfixOp::Gt,
-                    Rule::lte => InfixOp::Lte,
-                    Rule::gte => InfixOp::Gte,
-                    _ => todo!(),
-                };
-                let lhs = lhs?;
-                let rhs = rhs?;
-
-                Ok(Expr::infix(lhs, op, rhs))
-            })
-            .parse(pair)
-    }
-
-    fn parse_xml(&self, xml: &str, line: usize) -> miette::Result<Vec<xml::reader::XmlEvent>> {
-        let reader = xml::reader::ParserConfig::new()
-            .trim_whitespace(true)
-            .create_reader(xml.as_bytes());
-
-        reader
-            .into_iter()
-            .map(|event| {
-                event.map_err(|err| {
-                    let xml::common::TextPosition { row, column } = err.position();
 
-                    Error::XmlSyntax {
-                        source_code: NamedSource::new(self.file_name, self.source.to_string()),
-                        at: SourceSpan::new(
-                            SourceOffset::from_location(
-                                self.source,
-                                line + usize::try_from(row).expect("a valid usize"),
-                                usize::try_from(column).expect("a valid usize"),
-                            ),
-                            1,
-                        ),
-                        err: err.msg().to_string(),
-                    }
-                    .into()
+                Ok(Expr::Csv {
+                    path: Box::new(path),
+                    delimiter: Box::new(delimiter),
+                    headers,
                 })
-            })
-            .skip(1)
-            .collect::<miette::Result<Vec<xml::reader::XmlEvent>>>()
-    }
-
-    #[allow(clippy::too_many_lines)]
-    fn stmt_from(&mut self, pair: pest::iterators::Pair<Rule>) -> miette::Result<Stmt> {
-        match pair.as_rule() {
-            Rule::expression | Rule::expr => {
-                let expr = self.expr_from(pair.into_inner())?;
-
-                Ok(Stmt::Expr { expr })
             }
-            Rule::catch => {
+            Rule::xpath => {
                 let mut pair = pair.into_inner();
-                let Expr::Ident(Name::Ident(name)) =
+                let Expr::Ident(Name::Ident(source)) =
                     self.expr_from(Pairs::single(pair.next().unwrap()))?
                 else {
                     unreachable!()
                 };
+                let path = pair.next().unwrap().into_inner().as_str().into();
 
-                let scoped = self.env.scoped();
-                let mut old = std::mem::replace(&mut self.env, scoped);
-                self.env.bind(name.to_string(), Expr::Value(Value::Unit));
-
-                let body = pair
-                    .map(|stmt| self.stmt_from(stmt))
-                    .collect::<miette::Result<_>>()?;
+                Ok(Expr::XPath { source, path })
+            }
+            Rule::json_get => {
+                let mut pair = pair.into_inner();
+                let Expr::Ident(root) = self.expr_from(Pairs::single(pair.next().unwrap()))? else {
+                    unreachable!()
+                };
 
-                std::mem::swap(&mut old, &mut self.env);
+                let path_pair = pair.next().unwrap();
+                let Some(path) = json_path(path_pair.clone().into_inner().as_str()) else {
+                    let (line, col) = path_pair.line_col();
+                    return Err(Error::Syntax {
+                        source_code: self.named_source(),
+                        at: SourceSpan::new(
+                            SourceOffset::from_location(path_pair.get_input(), line, col),
+                            path_pair.as_str().len(),
+                        ),
+                        expected: Some("expected a path like \"data.items[0].name\"".to_string()),
+                    }
+                    .into());
+                };
 
-                Ok(Stmt::Catch { name, body })
+                Ok(Expr::JsonPath { root, path })
             }
-            Rule::body => {
-                let mut stmts = Vec::new();
-                for pair in pair.into_inner() {
-                    let stmt = match self.stmt_from(pair)? {
-                        Stmt::Catch { name, body } => {
-                            let ident = Expr::Ident(name.as_str().into());
 
-                            return Ok(Stmt::Block(vec![
-                                Stmt::Catch { name, body: stmts },
-                                Stmt::If {
-                                    test: Expr::infix(ident, InfixOp::Neq, Value::Nothing.into()),
-                                    body,
-                                    alt: None,
-                                },
-                            ]));
-                        }
-                        stmt => stmt,
-                    };
-                    stmts.push(stmt);
+            Rule::dict => {
+                let mut dict = Dict::new();
+                for (key, value, _) in self.dict_entries(pair)? {
+                    dict.insert(key, value);
                 }
 
-                Ok(Stmt::Block(stmts))
+                Ok(Expr::Dict(dict))
             }
-            Rule::r#let | Rule::stmt => self.stmt_from(pair.into_inner().next().unwrap()),
-            Rule::r#letfn => {
-                let mut pair = pair.into_inner();
-                let Expr::Ident(Name::Ident(name)) =
-                    self.expr_from(Pairs::single(pair.next().unwrap()))?
-                else {
-                    unreachable!()
-                };
-
-                let params_pair = pair.next().expect("function params").into_inner();
-                let mut params = Vec::with_capacity(params_pair.len());
-
-                let old = self.env.clone();
-                self.env = old.scoped();
+            Rule::query => {
+                let mut qpair = pair.clone().into_inner();
+                let datasource: Datasource = qpair.next().unwrap().as_str().try_into()?;
+                if let Some(transaction) = &self.transaction {
+                    if *transaction != datasource {
+                        return Err(self.invalid(
+                            pair.as_span(),
+                            &format!(
+                                "a `{datasource}` query can't run in a `{transaction}` \
+                                 transaction"
+                            ),
+                        ));
+                    }
+                }
 
-                for arg in params_pair {
-                    let Expr::Ident(Name::Ident(ident)) = self.expr_from(Pairs::single(arg))?
+                // a `dialect = "..."` overrides the one the script is parsed with,
+                // `limit` and `offset` page the rows of a select.
+                let expected = "`dialect = \"oracle\"`, `limit = 500` or `offset = 1000`";
+                let (mut dialect, mut limit, mut offset) = (None, None, None);
+                let mut body_pair = qpair.next().unwrap();
+                while body_pair.as_rule() == Rule::kwarg {
+                    let span = body_pair.as_span();
+                    let Expr::Named { name, value } = self.expr_from(Pairs::single(body_pair))?
                     else {
                         unreachable!()
                     };
-                    self.env.bind(ident.to_string(), Expr::Value(Value::Unit));
 
-                    params.push(ident);
+                    match (name.as_str(), *value) {
+                        (
+                            "dialect",
+                            Expr::Value {
+                                value: Value::Str(name_of),
+                                ..
+                            },
+                        ) => {
+                            dialect = Some(dialect_from_name(&name_of).ok_or_else(|| {
+                                self.invalid(span, &format!("unknown sql dialect `{name_of}`"))
+                            })?);
+                        }
+                        ("limit", value) if value.is_inline() => {
+                            limit = Some(Box::new(value));
+                        }
+                        ("offset", value) if value.is_inline() => {
+                            offset = Some(Box::new(value));
+                        }
+                        _ => return Err(self.unexpected_kwarg(span, expected)),
+                    }
+                    body_pair = qpair.next().unwrap();
                 }
 
-                let body = pair
-                    .next()
-                    .expect("function body")
-                    .into_inner()
-                    .map(|stmt| self.stmt_from(stmt))
-                    .collect::<miette::Result<Vec<Stmt>>>()?;
-
-                self.env = old;
+                let file = if body_pair.as_rule() == Rule::query_file {
+                    let path_pair = body_pair.clone().into_inner().next().unwrap();
+                    Some(self.read_relative(&path_pair)?)
+                } else {
+                    None
+                };
+                let dialect = dialect.as_deref().unwrap_or(&*self.sql_parser);
+
+                let queries = if let Some((full_path, sql)) = file {
+                    self.try_parse_query_file(dialect, &full_path, &sql)?
+                } else {
+                    let body = body_pair.clone().into_inner();
+                    let start = body
+                        .clone()
+                        .next()
+                        .map_or_else(|| body_pair.as_span().end(), |sql| sql.as_span().start());
+                    self.try_parse_query(dialect, body.as_str(), start, &body_pair)?
+                };
+                for query in &queries {
+                    self.lint_query(&query.stmt, body_pair.as_span());
+                }
 
-                Ok(Stmt::Let(name, Expr::Func { params, body }))
-            }
-            Rule::r#lete => {
-                let mut pair = pair.into_inner();
-                let ident = pair.next().unwrap();
-                let ident = Ident::from(ident.as_str());
+                let (span, mut params) = match qpair.next() {
+                    Some(qpair) => (
+                        qpair.as_span(),
+                        qpair
+                            .into_inner()
+                            .map(|arg| self.sql_param(arg))
+                            .collect::<miette::Result<Vec<SqlParam>>>()?,
+                    ),
+                    None => (body_pair.as_span(), vec![]),
+                };
 
-                let expr = self.expr_from(Pairs::single(pair.next().unwrap()))?;
+                let param_len: usize = queries.iter().map(|query| query.params).sum();
+                if param_len != params.len() {
+                    return Err(Error::SqlParamErr {
+                        source_code: self.named_source(),
+                        at: ast::Span::from(span).into(),
+                        err: format!("expected {param_len} parameters but got {}", params.len()),
+                    }
+                    .into());
+                }
 
-                self.env.bind(ident.to_string(), expr.clone());
+                if (limit.is_some() || offset.is_some())
+                    && queries
+                        .iter()
+                        .any(|query| query.r#type != QueryType::SELECT)
+                {
+                    return Err(self.invalid(
+                        pair.as_span(),
+                        "`limit` and `offset` only apply to a select",
+                    ));
+                }
 
-                Ok(Stmt::Let(ident, expr))
+                let mut queries = queries
+                    .into_iter()
+                    .map(|query| Expr::Query {
+                        datasource: datasource.clone(),
+                        query: query.stmt,
+                        text: query.text,
+                        params: params.drain(..query.params).collect(),
+                        r#type: query.r#type,
+                        max_rows: limit.clone(),
+                        start_row: offset.clone(),
+                    })
+                    .collect::<Vec<_>>();
+
+                if queries.len() == 1 {
+                    let query = queries.pop().unwrap();
+                    if is_unbounded_select(&query) {
+                        self.unbounded.push(span_of(&pair));
+                    }
+                    return Ok(query);
+                }
+                Ok(Expr::Batch(queries))
             }
-            Rule::alias => {
+            Rule::new_class => {
+                let pair = pair.into_inner();
+                self.class = true;
+                let (callable, args) = self.parse_callable(pair)?;
+
+                Ok(Expr::Instance {
+                    class: callable,
+                    args,
+                })
+            }
+            Rule::r#static => {
                 let mut pair = pair.into_inner();
-                let next = pair.next().unwrap();
-                let Expr::Ident(Name::Ident(alias)) = self.expr_from(Pairs::single(next))? else {
+                self.class = true;
+                let Expr::Ident(callable) = self.expr_from(Pairs::single(pair.next().unwrap()))?
+                else {
                     unreachable!()
                 };
 
-                let cls = self.expr_from(pair)?;
+                let mut args = Vec::new();
 
-                let _ = self.env.bind(alias.to_string(), cls.clone());
+                if let Some(args_pair) = pair.next() {
+                    let args_pair = args_pair.into_inner();
 
-                Ok(Stmt::Alias { alias, cls })
+                    for arg in args_pair.map(|arg| self.expr_from(Pairs::single(arg))) {
+                        let arg = arg?;
+                        if matches!(
+                            arg,
+                            Expr::Value {
+                                value: Value::Unit,
+                                ..
+                            }
+                        ) {
+                            continue;
+                        }
+
+                        args.push(arg);
+                    }
+                    Ok(Expr::static_invoke(callable, args))
+                } else {
+                    Ok(Expr::get_static(callable))
+                }
             }
-            Rule::log => {
+            Rule::invoke => {
                 let mut pair = pair.into_inner();
-
-                let level = match pair.next().unwrap().as_str() {
-                    "INFO" => LogLevel::Info,
-                    "DEBUG" => LogLevel::Debug,
-                    "WARN" => LogLevel::Warn,
-                    "ERROR" => LogLevel::Error,
-                    _ => unreachable!(),
+                self.callee = true;
+                let callee = self.expr_from(Pairs::single(pair.next().unwrap()));
+                self.callee = false;
+                let Expr::Ident(Name::Dotted { parent, mut attrs }) = callee? else {
+                    unreachable!()
                 };
-                let Expr::Value(Value::Str(message)) =
-                    self.expr_from(Pairs::single(pair.next().unwrap()))?
-                else {
+                let Some(Name::Ident(method)) = attrs.pop() else {
                     unreachable!()
                 };
+                let object = if attrs.is_empty() {
+                    *parent
+                } else {
+                    Name::Dotted { parent, attrs }
+                };
 
-                Ok(Stmt::Log { level, message })
+                let mut args = Vec::new();
+                for arg in pair.next().unwrap().into_inner() {
+                    let mut arg = arg.into_inner();
+                    let value = self.expr_from(Pairs::single(arg.next().unwrap()))?;
+                    if matches!(
+                        value,
+                        Expr::Value {
+                            value: Value::Unit,
+                            ..
+                        }
+                    ) {
+                        continue;
+                    }
+
+                    let r#type = arg.next().map(|ty| ty.into_inner().as_str().into());
+                    args.push(TypedArg { value, r#type });
+                }
+
+                Ok(Expr::Invoke {
+                    object,
+                    method,
+                    args,
+                })
             }
-            Rule::r#if => {
-                let mut pair = pair.into_inner();
+            Rule::call => {
+                let span = pair.as_span();
+                let pair = pair.into_inner();
+
+                let mut inner = pair.clone();
+                let builtin = inner.next().unwrap().as_str();
+                if matches!(builtin, "defined" | "exists") && !self.env.is_bound(builtin) {
+                    return self.presence(builtin, inner, span);
+                }
+                if builtin == "secret" && !self.env.is_bound(builtin) {
+                    return self.secret(inner, span);
+                }
 
-                let expr = self.expr_from(Pairs::single(pair.next().unwrap()))?;
-                let conseq = pair
-                    .next()
-                    .unwrap()
-                    .into_inner()
-                    .map(|stmt| self.stmt_from(stmt))
-                    .collect::<miette::Result<_>>()?;
+                let (callable, args) = self.parse_callable(pair)?;
+                if self.template(&callable.to_string()).is_some() {
+                    return Err(self.invalid(
+                        span,
+                        "templates are written as tags, so they can only be called on their own",
+                    ));
+                }
 
-                let mut alt: Option<Vec<Stmt>> = None;
-                if let Some(pair) = pair.next() {
-                    alt = Some(
-                        pair.into_inner()
-                            .map(|stmt| self.stmt_from(stmt))
-                            .collect::<miette::Result<Vec<_>>>()?,
-                    );
+                // `expr "${...}"` evaluates the `${}`s of a string, unless `expr` is
+                // a function of the script
+                if callable.to_string() == "expr" && !self.env.is_bound("expr") {
+                    let [Expr::Value {
+                        value: Value::Str(text),
+                        ..
+                    }] = args.as_slice()
+                    else {
+                        return Err(self.invalid(
+                            span,
+                            "`expr` takes a single string, as in `expr \"${row.id}\"`",
+                        ));
+                    };
+                    return Ok(Expr::from(Value::Str(text.replace("$${", "${").into())));
                 }
 
-                Ok(Stmt::If {
-                    test: expr,
-                    body: conseq,
-                    alt,
-                })
+                Ok(Expr::call(callable, args))
             }
-            Rule::r#for => {
-                let mut pair = pair.into_inner();
+            Rule::expression | Rule::expr | Rule::value => self.expr_from(pair.into_inner()),
+            rule => unreachable!("not an expr rule: {:?}", rule),
+        }
+    }
 
-                let Expr::Ident(Name::Ident(var)) =
-                    self.expr_from(Pairs::single(pair.next().unwrap()))?
-                else {
-                    unreachable!()
-                };
-                let old = self.env.clone();
-                self.env = old.scoped();
-                self.env.bind(var.to_string(), Expr::Value(Value::Unit));
+    /// Parses the expression of a `{}` in a string.
+    fn interpolated(&mut self, pair: pest::iterators::Pair<Rule>) -> miette::Result<Expr> {
+        let span = pair.as_span();
+        let expr = self.expr_from(pair.into_inner())?;
+        if !expr.is_inline() {
+            return Err(self.invalid(
+                span,
+                "this can't be interpolated, bind it to a name with `let` first",
+            ));
+        }
 
-                let expr = self.expr_from(Pairs::single(pair.next().unwrap()))?;
+        Ok(expr)
+    }
+
+    /// The keys and values of the `dict` pair, with where each value was written.
+    fn dict_entries<'i>(
+        &mut self,
+        pair: pest::iterators::Pair<'i, Rule>,
+    ) -> miette::Result<Vec<(Arc<str>, Expr, pest::Span<'i>)>> {
+        let mut entries = vec![];
+        for kv in pair.into_inner() {
+            let mut kv = kv.into_inner();
+
+            let Expr::Value {
+                value: Value::Str(key),
+                ..
+            } = self.expr_from(Pairs::single(kv.next().unwrap()))?
+            else {
+                unreachable!("`string`s are parsed as `Value::Str`s")
+            };
+
+            let value = kv.next().unwrap();
+            let span = value.as_span();
+            entries.push((key, self.expr_from(value.into_inner())?, span));
+        }
+        Ok(entries)
+    }
+
+    /// Whether `header` is the name of a header or a name that can be bound to one.
+    fn is_header_name(&self, header: &Expr) -> bool {
+        match header {
+            Expr::Value { value, .. } => matches!(value, Value::Str(_)),
+            Expr::Ident(Name::Ident(name)) => {
+                !matches!(self.env.resolve(name.as_str()), Some(Bound::Literal))
+            }
+            _ => false,
+        }
+    }
+
+    /// Checks the arguments of a directive in an `http!` body.
+    fn check_directive(&self, span: pest::Span, stmt: &Stmt) -> miette::Result<()> {
+        let Stmt::Expr {
+            expr:
+                Expr::Call(Call {
+                    name: Name::Ident(func),
+                    args,
+                }),
+        } = stmt
+        else {
+            return Err(self.invalid(span, "expected a directive, as in `timeout 5000`"));
+        };
 
-                let mut body = Vec::with_capacity(pair.len());
+        let func = func.as_str();
+        let named = |arg: &Expr| matches!(arg, Expr::Named { .. });
+        let arity = |n: usize, expected: &str| {
+            (args.len() != n).then(|| format!("`{func}` expects {expected}"))
+        };
 
-                for stmt in pair {
-                    let stmt = self.stmt_from(stmt)?;
-                    body.push(stmt);
+        // the directives taking a dict write its entries one by one
+        let takes_dict = matches!(
+            func,
+            "json" | "headers" | "form" | "query" | "capture_headers"
+        );
+        let message = if args.iter().any(|arg| match arg {
+            Expr::Named { value, .. } => !value.is_inline(),
+            Expr::Dict(dict) if takes_dict => !entries_inline(dict),
+            arg => !arg.is_inline(),
+        }) {
+            Some("directives only take values, bind the others to a name with `let` first".into())
+        } else if func != "timeout" && args.iter().any(named) {
+            Some(format!("`{func}` doesn't take named arguments"))
+        } else {
+            match func {
+                "timeout" => args
+                    .iter()
+                    .any(|arg| {
+                        matches!(arg, Expr::Named { name, .. }
+                            if !matches!(name.as_str(), "connect" | "read"))
+                    })
+                    .then(|| "`timeout` only takes `connect` and `read`".to_string())
+                    .or_else(|| {
+                        args.is_empty()
+                            .then(|| "`timeout` expects the milliseconds to wait".to_string())
+                    }),
+                "json" => (!matches!(
+                    args.as_slice(),
+                    [Expr::Dict(_) | Expr::Ident(Name::Ident(_))]
+                ))
+                .then(|| "`json` expects a dict or the name of one".to_string()),
+                // their values are url-encoded one by one
+                "form" | "query" => match args.as_slice() {
+                    [Expr::Dict(params)] => params
+                        .iter()
+                        .find(|(_, value)| matches!(value, Expr::Dict(_)))
+                        .map(|(key, _)| {
+                            format!("the `{key}` of `{func}` is url-encoded, so it has to be a value or a name, not a dict")
+                        }),
+                    _ => Some(format!("`{func}` expects a dict")),
+                },
+                "headers" => match args.as_slice() {
+                    [Expr::Dict(dict)] => dict
+                        .iter()
+                        .find(|(_, value)| matches!(value, Expr::Dict(_)))
+                        .map(|(key, _)| {
+                            format!("the `{key}` of `{func}` has to be a value or a name, not a dict")
+                        }),
+                    _ => Some(format!("`{func}` expects a dict")),
+                },
+                // the names are bound to the headers they're given
+                "capture_headers" => match args.as_slice() {
+                    [Expr::Dict(dict)] => dict
+                        .iter()
+                        .find(|(_, header)| !self.is_header_name(header))
+                        .map(|(key, _)| {
+                            format!("`{key}` has to be given the name of a header, as in `\"{key}\": \"Location\"`")
+                        }),
+                    _ => Some(format!("`{func}` expects a dict")),
+                },
+                "auth_basic" => arity(2, "a user and a password"),
+                "auth_bearer" => arity(1, "a token"),
+                "save_to" => arity(1, "a path"),
+                "retry" => arity(2, "the attempts and a delay"),
+                "body" | "xml" => arity(1, "a payload"),
+                "status" => arity(0, "no arguments"),
+                "on_error" => {
+                    Some("`on_error` expects a handler, as in `on_error status do ... end`".into())
+                }
+                _ => {
+                    return Err(Error::UnknownDirective {
+                        source_code: self.named_source(),
+                        at: SourceSpan::new(span.start().into(), func.len()),
+                        directive: func.to_string(),
+                        supported: format!(
+                            "supported directives are {}",
+                            HTTP_DIRECTIVES.join(", ")
+                        ),
+                    }
+                    .into())
                 }
+            }
+        };
 
-                self.env = old;
+        message.map_or(Ok(()), |message| Err(self.invalid(span, &message)))
+    }
 
-                Ok(Stmt::ForEach {
-                    var,
-                    items: expr,
-                    body,
-                })
+    /// Renders an `xml_body` pair into a string, interpolating its `{}` expressions.
+    fn xml_from(&mut self, pair: pest::iterators::Pair<Rule>) -> miette::Result<String> {
+        let mut buff = String::new();
+
+        for arg in pair.into_inner() {
+            match arg.as_rule() {
+                Rule::fmt => {
+                    let fmt = self.interpolated(arg)?;
+                    let _ = write!(buff, "{}", fmt.as_value(ast::Context::Text));
+                }
+                Rule::xml_str => {
+                    let _ = buff.write_str(arg.as_str());
+                }
+                _ => unreachable!("{:?}", arg.as_rule()),
             }
-            rule => unreachable!("not an stmt rule: {:?}", rule),
         }
+
+        Ok(buff)
     }
 
-    fn parse_callable(
-        &mut self,
-        mut pair: pest::iterators::Pairs<Rule>,
-    ) -> miette::Result<(Name, Vec<Expr>)> {
-        let Expr::Ident(callable) = self.expr_from(Pairs::single(pair.next().unwrap()))? else {
+    /// Parses the integer literal `pair`, which can be hex with `0x`, binary with `0b` and
+    /// have `_`s between its digits.
+    fn int(&self, pair: &pest::iterators::Pair<Rule>) -> miette::Result<i64> {
+        let literal = pair.as_str().trim();
+        let digits = literal.replace('_', "");
+        let (digits, radix) = match digits.get(..2) {
+            Some("0x" | "0X") => (&digits[2..], 16),
+            Some("0b" | "0B") => (&digits[2..], 2),
+            _ => (digits.as_str(), 10),
+        };
+
+        i64::from_str_radix(digits, radix).map_err(|_| {
+            self.invalid(
+                pair.as_span(),
+                &format!("`{literal}` is too large, integers go up to {}", i64::MAX),
+            )
+        })
+    }
+
+    fn invalid(&self, span: pest::Span, message: &str) -> miette::Report {
+        invalid(self.named_source(), span, message)
+    }
+
+    fn unexpected_kwarg(&self, span: pest::Span, expected: &str) -> miette::Report {
+        Error::Syntax {
+            source_code: self.named_source(),
+            at: SourceSpan::new(span.start().into(), span.as_str().len()),
+            expected: Some(format!("expected {expected}")),
+        }
+        .into()
+    }
+
+    /// Resolves the session ident given to a `xog_*!` call.
+    fn session_from(&mut self, pair: pest::iterators::Pair<Rule>) -> miette::Result<Ident> {
+        let Expr::Ident(Name::Ident(session)) = self.expr_from(Pairs::single(pair))? else {
             unreachable!()
         };
 
-        let mut args = Vec::new();
+        Ok(session)
+    }
+
+    /// Parses the params and body of an error handler into an [`Expr::Func`], the params
+    /// are bound while parsing the body.
+    fn handler_from(&mut self, pairs: Pairs<Rule>) -> miette::Result<Expr> {
+        self.open_scope();
+
+        let mut params = Vec::with_capacity(2);
+        let mut handler = None;
+        for pair in pairs {
+            if pair.as_rule() == Rule::body {
+                handler = Some(pair);
+                break;
+            }
+
+            let param = span_ident(&pair);
+            self.bind_tracked(&param, Bound::Opaque, Kind::Variable);
+            params.push(param);
+        }
+
+        let Stmt::Block(body) = self.stmt_from(handler.expect("handler body"))? else {
+            unreachable!()
+        };
+
+        self.close_scope();
+
+        Ok(Expr::Func { params, body })
+    }
+
+    /// Parses the inline or external xml of a soap `header`/`body`, `None` if it is empty.
+    fn soap_message(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> miette::Result<Option<Vec<xml::reader::XmlEvent>>> {
+        if pair.as_rule() == Rule::xml_body {
+            let (line, _) = pair.line_col();
+            let buff = self.xml_from(pair)?;
+
+            if buff.is_empty() {
+                return Ok(None);
+            }
+
+            return self.parse_xml(&buff, line).map(Some);
+        }
+
+        let path_pair = pair.into_inner().next().unwrap();
+        let (full_path, xml) = self.read_relative(&path_pair)?;
+
+        let events = self
+            .xml_events(&xml)
+            .map_err(|(row, column, err)| Error::XmlSyntax {
+                at: SourceSpan::new(SourceOffset::from_location(&xml, row + 1, column + 1), 1),
+                source_code: NamedSource::new(full_path.display().to_string(), xml.as_str().into()),
+                err,
+            })?;
+
+        Ok(Some(events))
+    }
+
+    /// Reads the xml of a template or `xml!` written at `line`, with `namespaces` in scope.
+    fn fragment(
+        &self,
+        xml: &str,
+        namespaces: &xml::namespace::Namespace,
+        line: usize,
+    ) -> miette::Result<Vec<xml::reader::XmlEvent>> {
+        crate::transpiler::read_fragment(xml, namespaces).map_err(|(row, column, err)| {
+            Error::XmlSyntax {
+                source_code: self.named_source(),
+                at: SourceSpan::new(
+                    SourceOffset::from_location(self.source, line + row, column),
+                    1,
+                ),
+                err,
+            }
+            .into()
+        })
+    }
+
+    /// Parses the templates of the script at the path of `file`, which can only define
+    /// templates and namespaces. They're parsed as if they were written in place of the
+    /// import, in scope of what's bound before it.
+    fn import_templates(&mut self, file: &pest::iterators::Pair<Rule>) -> miette::Result<Stmt> {
+        let path_pair = file.clone().into_inner().next().unwrap();
+        let (full_path, source) = self.read_relative(&path_pair)?;
+        let file_name = full_path.display().to_string();
+
+        let mut parser = Parser::new(&file_name, &source, Box::new(GenericDialect {}));
+        parser.env = self.env.clone();
+        parser.namespaces = self.namespaces.clone();
+        let nodes = parser.parse()?;
+
+        let mut templates = vec![];
+        for Spanned { node, span } in nodes {
+            match node {
+                Node::Stmt(template @ Stmt::Template { .. }) => templates.push(template),
+                Node::Stmt(Stmt::Namespace { .. }) => {}
+                _ => {
+                    return Err(Error::Invalid {
+                        source_code: parser.named_source(),
+                        at: span.into(),
+                        message: "only templates and namespaces can be imported".to_string(),
+                    }
+                    .into())
+                }
+            }
+        }
+        self.templates.extend(parser.templates);
+        self.imports.extend(parser.imports);
+
+        #[cfg(feature = "fs")]
+        self.usage.imports.push(Import {
+            path: full_path,
+            at: span_of(file),
+            templates: parser.usage.templates,
+            imports: parser.usage.imports,
+        });
+
+        Ok(Stmt::Block(templates))
+    }
+
+    /// Writes `pair` as the tags of the template it calls, `None` if it isn't the call of
+    /// a template.
+    fn template_call(
+        &mut self,
+        pair: &pest::iterators::Pair<Rule>,
+    ) -> miette::Result<Option<Stmt>> {
+        let mut inner = pair.clone().into_inner();
+        let (Some(call), None) = (inner.next(), inner.next()) else {
+            return Ok(None);
+        };
+        if call.as_rule() != Rule::call {
+            return Ok(None);
+        }
+
+        let span = call.as_span();
+        let call = call.into_inner();
+        let Some(template) = call.peek().and_then(|name| self.template(name.as_str())) else {
+            return Ok(None);
+        };
+        let template = template.clone();
+        template.called.store(true, Ordering::Relaxed);
+        let (name, args) = self.parse_callable(call)?;
+
+        if args.len() != template.params.len() {
+            let params: Vec<String> = template.params.iter().map(|p| format!("`{p}`")).collect();
+            let takes = match params.as_slice() {
+                [] => format!("no arguments, as in `{name} ()`"),
+                [param] => format!("1 argument, {param}"),
+                params => format!("{} arguments, {}", params.len(), params.join(", ")),
+            };
+            return Err(self.invalid(span, &format!("`{name}` takes {takes}")));
+        }
+
+        let mut values = vec![];
+        for arg in &args {
+            if !arg.is_inline() || matches!(arg, Expr::Named { .. }) {
+                return Err(self.invalid(
+                    span,
+                    "the arguments of a template are written in its tags, so they have to be values or names",
+                ));
+            }
+            values.push(escape_xml(&arg.as_value(ast::Context::Text)));
+        }
+
+        let (line, _) = pair.line_col();
+        let events = self.fragment(&template.render(&values), &template.namespaces, line)?;
+        self.bind_xml_vars(&events);
+
+        Ok(Some(Stmt::Xml(events)))
+    }
+
+    /// Binds the names the `var` attributes of the tags of `events` set.
+    fn bind_xml_vars(&mut self, events: &[xml::reader::XmlEvent]) {
+        for var in ast::xml_vars(events) {
+            self.env.bind(var.to_string(), Bound::Opaque);
+        }
+    }
+
+    /// The template `name` refers to, unless the script binds it to something else.
+    fn template(&self, name: &str) -> Option<&Template> {
+        self.templates
+            .get(name)
+            .filter(|_| !self.env.is_bound(name))
+    }
+
+    fn parse_xml(&self, xml: &str, line: usize) -> miette::Result<Vec<xml::reader::XmlEvent>> {
+        self.xml_events(xml).map_err(|(row, column, err)| {
+            Error::XmlSyntax {
+                source_code: self.named_source(),
+                at: SourceSpan::new(
+                    SourceOffset::from_location(self.source, line + row, column),
+                    1,
+                ),
+                err,
+            }
+            .into()
+        })
+    }
+
+    /// Reads `xml` into events with the declared namespaces in scope.
+    ///
+    /// The xml is wrapped in an element declaring them, which is then skipped along with
+    /// the document events. Errors are returned as the row, column and message.
+    fn xml_events(&self, xml: &str) -> Result<Vec<xml::reader::XmlEvent>, (usize, usize, String)> {
+        let mut wrapper = String::from("<gelatin");
+        for (prefix, uri) in &self.namespaces {
+            let _ = write!(wrapper, " xmlns:{prefix}=\"{uri}\"");
+        }
+        wrapper.push('>');
+
+        // the xml declaration is only allowed at the start, blank it to keep positions.
+        let xml = xml
+            .find("?>")
+            .filter(|_| xml.trim_start().starts_with("<?xml"))
+            .map_or(Cow::Borrowed(xml), |end| {
+                Cow::Owned(format!("{}{}", " ".repeat(end + 2), &xml[end + 2..]))
+            });
+
+        let source = format!("{wrapper}{xml}</gelatin>");
+        let mut events = xml::reader::ParserConfig::new()
+            .trim_whitespace(true)
+            .create_reader(source.as_bytes())
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| {
+                let xml::common::TextPosition { row, column } = err.position();
+                let row = usize::try_from(row).expect("a valid usize");
+                let mut column = usize::try_from(column).expect("a valid usize");
+                if row == 0 {
+                    column = column.saturating_sub(wrapper.len());
+                }
+
+                (row, column, err.msg().to_string())
+            })?;
+
+        // StartDocument and the wrapper, then their closing events
+        events.truncate(events.len() - 2);
+        events.drain(..2);
+
+        Ok(events)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn stmt_from(&mut self, pair: pest::iterators::Pair<Rule>) -> miette::Result<Stmt> {
+        match pair.as_rule() {
+            Rule::expression | Rule::expr => {
+                if let Some(stmt) = self.template_call(&pair)? {
+                    return Ok(stmt);
+                }
+                let expr = self.expr_from(pair.into_inner())?;
+
+                Ok(Stmt::Expr { expr })
+            }
+            Rule::raw_xml => {
+                let body = pair.into_inner().next().unwrap();
+                let (line, _) = body.line_col();
+                let xml = self.xml_from(body)?;
+
+                let events = self.fragment(&xml, &self.namespaces, line)?;
+                self.bind_xml_vars(&events);
+                Ok(Stmt::Xml(events))
+            }
+            Rule::template => {
+                let mut pair = pair.into_inner();
+                let first = pair.next().unwrap();
+                if first.as_rule() == Rule::xml_file {
+                    return self.import_templates(&first);
+                }
+
+                let name = span_ident(&first);
+                let mut params = vec![];
+                let mut body = None;
+                for pair in pair {
+                    match pair.as_rule() {
+                        Rule::params => params.extend(pair.into_inner().map(|p| span_ident(&p))),
+                        _ => body = Some(pair),
+                    }
+                }
+                let body = body.expect("templates have a body");
+                let (line, _) = body.line_col();
+
+                let mut parts = vec![];
+                for part in body.into_inner() {
+                    let text = match part.as_rule() {
+                        Rule::fmt => {
+                            let written = part.as_str();
+                            let inner = written[1..written.len() - 1].trim();
+                            if let Some(i) = params.iter().position(|param| param.as_str() == inner)
+                            {
+                                parts.push(Part::Param(i));
+                                continue;
+                            }
+                            self.interpolated(part)?
+                                .as_value(ast::Context::Text)
+                                .into_owned()
+                        }
+                        _ => part.as_str().to_string(),
+                    };
+                    match parts.last_mut() {
+                        Some(Part::Text(last)) => last.push_str(&text),
+                        _ => parts.push(Part::Text(text)),
+                    }
+                }
+
+                let called = Arc::new(AtomicBool::new(false));
+                #[cfg(feature = "fs")]
+                self.usage.templates.push(Defined {
+                    name: name.clone(),
+                    called: Arc::clone(&called),
+                });
+                let template = Template {
+                    params: params.clone(),
+                    parts,
+                    namespaces: self.namespaces.clone(),
+                    called,
+                };
+                // the body has to be xml whatever the arguments are
+                let blanks = vec![String::new(); params.len()];
+                self.fragment(&template.render(&blanks), &template.namespaces, line)?;
+                self.templates.insert(name.to_string(), template);
+
+                Ok(Stmt::Template { name, params })
+            }
+            Rule::catch => {
+                let mut pair = pair.into_inner();
+                let Expr::Ident(Name::Ident(name)) =
+                    self.expr_from(Pairs::single(pair.next().unwrap()))?
+                else {
+                    unreachable!()
+                };
+
+                self.open_scope();
+                self.env.bind(name.to_string(), Bound::Opaque);
+
+                let body = pair
+                    .map(|stmt| self.stmt_from(stmt))
+                    .collect::<miette::Result<_>>()?;
+
+                self.close_scope();
+
+                Ok(Stmt::Catch { name, body })
+            }
+            Rule::body => {
+                let mut stmts = Vec::new();
+                for pair in pair.into_inner() {
+                    let stmt = match self.stmt_from(pair)? {
+                        Stmt::Catch { name, body } => {
+                            let ident = Expr::Ident(name.as_str().into());
+
+                            return Ok(Stmt::Block(vec![
+                                Stmt::Catch { name, body: stmts },
+                                Stmt::If {
+                                    test: Expr::infix(ident, InfixOp::Neq, Value::Nothing.into()),
+                                    body,
+                                    alt: None,
+                                },
+                            ]));
+                        }
+                        stmt => stmt,
+                    };
+                    stmts.push(stmt);
+                }
+
+                Ok(Stmt::Block(stmts))
+            }
+            Rule::r#let | Rule::stmt => self.stmt_from(pair.into_inner().next().unwrap()),
+            Rule::r#letfn => {
+                let mut pair = pair.into_inner();
+                let Expr::Ident(Name::Ident(name)) =
+                    self.expr_from(Pairs::single(pair.next().unwrap()))?
+                else {
+                    unreachable!()
+                };
+
+                self.warn_shadowing(&name);
+                #[cfg_attr(not(feature = "fs"), allow(unused_variables))]
+                let (called, _) =
+                    self.env
+                        .bind_tracked(name.to_string(), Bound::Opaque, Kind::Function);
+                #[cfg(feature = "fs")]
+                self.usage.functions.push(Defined {
+                    name: name.clone(),
+                    called,
+                });
+
+                let params_pair = pair.next().expect("function params").into_inner();
+                let mut params = Vec::with_capacity(params_pair.len());
+
+                self.open_scope();
+
+                for arg in params_pair {
+                    let Expr::Ident(Name::Ident(ident)) = self.expr_from(Pairs::single(arg))?
+                    else {
+                        unreachable!()
+                    };
+                    self.bind_tracked(&ident, Bound::Opaque, Kind::Variable);
+
+                    params.push(ident);
+                }
+
+                let body = pair
+                    .next()
+                    .expect("function body")
+                    .into_inner()
+                    .map(|stmt| self.stmt_from(stmt))
+                    .collect::<miette::Result<Vec<Stmt>>>()?;
+
+                self.close_scope();
+
+                Ok(Stmt::Let(name, Expr::Func { params, body }))
+            }
+            Rule::r#lete => {
+                let mut pair = pair.into_inner();
+                let ident = pair.next().unwrap();
+                let ident = span_ident(&ident);
+
+                let value = pair.next().unwrap();
+                let at = span_of(&value);
+                let expr = self.expr_from(Pairs::single(value))?;
+
+                self.warn_shadowing(&ident);
+                let read = self.bind_tracked(&ident, Bound::of(&expr), Kind::Variable);
+                if matches!(
+                    expr,
+                    Expr::Query {
+                        r#type: QueryType::SELECT,
+                        ..
+                    }
+                ) {
+                    self.selects.insert(ident.to_string(), at);
+                } else {
+                    self.selects.remove(ident.as_str());
+                }
+
+                // reading what a request binds reads the request
+                if let Expr::Http { body, .. } = &expr {
+                    if body.iter().any(|stmt| {
+                        expand::is_directive(stmt, "status")
+                            || expand::is_directive(stmt, "on_error")
+                    }) {
+                        self.env
+                            .bind_derived(format!("{ident}_status"), Bound::Opaque, &read);
+                    }
+
+                    // captured headers are bound as plain variables.
+                    for directive in body {
+                        if let Stmt::Expr {
+                            expr: Expr::Call(Call { args, .. }),
+                        } = directive
+                        {
+                            if let (true, Some(Expr::Dict(headers))) = (
+                                expand::is_directive(directive, "capture_headers"),
+                                args.last(),
+                            ) {
+                                for var in headers.keys() {
+                                    self.env.bind_derived(var.to_string(), Bound::Opaque, &read);
+                                }
+                            }
+                        }
+                    }
+                }
+
+                Ok(Stmt::Let(ident, expr))
+            }
+            Rule::alias => {
+                let mut pair = pair.into_inner();
+                let next = pair.next().unwrap();
+                let Expr::Ident(Name::Ident(alias)) = self.expr_from(Pairs::single(next))? else {
+                    unreachable!()
+                };
+
+                let cls = self.expr_from(pair)?;
+
+                self.bind_tracked(&alias, Bound::Class(Box::new(cls.clone())), Kind::Class);
+
+                Ok(Stmt::Alias { alias, cls })
+            }
+            Rule::use_static => {
+                let mut pair = pair.into_inner();
+                let class = pair.next().unwrap().into_inner().next().unwrap();
+                let class = if class.as_rule() == Rule::alias_ident {
+                    self.class = true;
+                    let Expr::Ident(class) = self.expr_from(Pairs::single(class.clone()))? else {
+                        return Err(self.invalid(class.as_span(), "expected the alias of a class"));
+                    };
+                    class.to_string()
+                } else {
+                    class.as_str().to_string()
+                };
+
+                // each field is read once into a variable of its name, bound as a constant
+                // so it's reported as an unused alias since it can't be renamed with an
+                // underscore
+                let mut fields = vec![];
+                for field in pair {
+                    let field = span_ident(&field);
+                    self.warn_shadowing(&field);
+                    self.bind_tracked(&field, Bound::Opaque, Kind::Alias);
+                    fields.push(Stmt::Let(
+                        field.clone(),
+                        Expr::static_field(&class, field.as_str()),
+                    ));
+                }
+
+                Ok(Stmt::Block(fields))
+            }
+            Rule::namespace => {
+                let mut pair = pair.into_inner();
+                let prefix = span_ident(&pair.next().unwrap());
+                let uri: Arc<str> = pair.next().unwrap().into_inner().as_str().into();
+
+                self.namespaces.put(prefix.as_str(), uri.as_ref());
+
+                Ok(Stmt::Namespace { prefix, uri })
+            }
+            Rule::log => {
+                let mut pair = pair.into_inner();
+
+                let level = match pair.next().unwrap().as_str() {
+                    "INFO" => LogLevel::Info,
+                    "DEBUG" => LogLevel::Debug,
+                    "WARN" => LogLevel::Warn,
+                    "ERROR" => LogLevel::Error,
+                    _ => unreachable!(),
+                };
+                let Expr::Value {
+                    value: Value::Str(message),
+                    ..
+                } = self.expr_from(Pairs::single(pair.next().unwrap()))?
+                else {
+                    unreachable!()
+                };
+
+                Ok(Stmt::Log { level, message })
+            }
+            Rule::r#if => {
+                let mut pair = pair.into_inner();
+
+                let expr = self.expr_from(Pairs::single(pair.next().unwrap()))?;
+                let conseq = pair
+                    .next()
+                    .unwrap()
+                    .into_inner()
+                    .map(|stmt| self.stmt_from(stmt))
+                    .collect::<miette::Result<_>>()?;
+
+                let alt = match pair.next() {
+                    Some(pair) => Some(
+                        pair.into_inner()
+                            .map(|stmt| self.stmt_from(stmt))
+                            .collect::<miette::Result<Vec<_>>>()?,
+                    ),
+                    None => None,
+                };
+
+                Ok(Stmt::If {
+                    test: expr,
+                    body: conseq,
+                    alt,
+                })
+            }
+            Rule::write_file => {
+                let mut pair = pair.into_inner();
+                let path = self.expr_from(Pairs::single(pair.next().unwrap()))?;
+
+                let mut escape_xml = true;
+                let mut body = vec![];
+                for pair in pair {
+                    if pair.as_rule() == Rule::body {
+                        let Stmt::Block(stmts) = self.stmt_from(pair)? else {
+                            unreachable!()
+                        };
+                        body = stmts;
+                        continue;
+                    }
+
+                    let span = pair.as_span();
+                    match self.expr_from(Pairs::single(pair))? {
+                        Expr::Named { name, value }
+                            if name.as_str() == "escape_xml"
+                                && matches!(
+                                    *value,
+                                    Expr::Value {
+                                        value: Value::Bool(_),
+                                        ..
+                                    }
+                                ) =>
+                        {
+                            escape_xml = matches!(
+                                *value,
+                                Expr::Value {
+                                    value: Value::Bool(true),
+                                    ..
+                                }
+                            );
+                        }
+                        _ => return Err(self.unexpected_kwarg(span, "`escape_xml = true|false`")),
+                    }
+                }
+
+                Ok(Stmt::WriteFile {
+                    path,
+                    escape_xml,
+                    body,
+                })
+            }
+            Rule::param => {
+                let mut pair = pair.into_inner();
+                let name = span_ident(&pair.next().unwrap());
+                let mut r#type = None;
+                let mut description = None;
+                for pair in pair {
+                    match pair.as_rule() {
+                        Rule::param_type => {
+                            r#type = Some(match pair.as_str() {
+                                "string" => ParamType::String,
+                                "int" => ParamType::Int,
+                                "float" => ParamType::Float,
+                                "bool" => ParamType::Bool,
+                                "date" => ParamType::Date,
+                                _ => unreachable!(),
+                            });
+                        }
+                        _ => description = Some(pair.into_inner().as_str().into()),
+                    }
+                }
+
+                self.warn_shadowing(&name);
+                self.bind_tracked(&name, Bound::Opaque, Kind::Variable);
+                self.params.insert(name.to_string());
+
+                Ok(Stmt::Param {
+                    name,
+                    r#type,
+                    description,
+                })
+            }
+            Rule::comment => {
+                let text = pair.into_inner().next().unwrap().into_inner().as_str();
+
+                Ok(Stmt::Comment(text.into()))
+            }
+            Rule::whitespace => {
+                let Stmt::Block(body) = self.stmt_from(pair.into_inner().next().unwrap())? else {
+                    unreachable!()
+                };
+
+                Ok(Stmt::Whitespace(body))
+            }
+            Rule::transaction => {
+                let span = pair.as_span();
+                let mut pair = pair.into_inner();
+                let datasource: Datasource = pair.next().unwrap().as_str().try_into()?;
+
+                if self.transaction.is_some() {
+                    return Err(self.invalid(span, "transactions can't be nested"));
+                }
+
+                self.transaction = Some(datasource.clone());
+                let body = self.stmt_from(pair.next().unwrap());
+                self.transaction = None;
+
+                let Stmt::Block(body) = body? else {
+                    unreachable!()
+                };
+
+                Ok(Stmt::Transaction { datasource, body })
+            }
+            Rule::set_props => {
+                let mut pair = pair.into_inner();
+                let Expr::Ident(object) = self.expr_from(Pairs::single(pair.next().unwrap()))?
+                else {
+                    unreachable!("`access_ident`s and `dotted_access`es are parsed as names")
+                };
+
+                // each property is written as an attribute of the tag
+                let mut props = Dict::new();
+                for (key, value, span) in self.dict_entries(pair.next().unwrap())? {
+                    if !value.is_inline() {
+                        return Err(self.invalid(
+                            span,
+                            &format!("the `{key}` property has to be a value or a name, bind it to a name with `let` first"),
+                        ));
+                    }
+                    props.insert(key, value);
+                }
+
+                Ok(Stmt::SetProperties { object, props })
+            }
+            Rule::out => {
+                let value = self.expr_from(pair.into_inner())?;
+
+                Ok(Stmt::Out { value })
+            }
+            Rule::r#for => {
+                let mut pair = pair.into_inner();
+
+                let Expr::Ident(Name::Ident(var)) =
+                    self.expr_from(Pairs::single(pair.next().unwrap()))?
+                else {
+                    unreachable!()
+                };
+                self.open_scope();
+                self.env.bind(var.to_string(), Bound::Opaque);
+
+                let unbounded = self.unbounded.len();
+                let items = pair.next().unwrap();
+                let at = span_of(&items);
+                let expr = self.expr_from(Pairs::single(items))?;
+
+                let row = self.row(&var, &expr, at);
+                self.rows.push(row);
+                let body = pair
+                    .map(|stmt| self.stmt_from(stmt))
+                    .collect::<miette::Result<Vec<Stmt>>>();
+                self.rows.pop();
+                let body = body?;
+
+                self.close_scope();
+
+                // a select without a row limit that feeds updates can run away on big tables
+                if body.iter().any(updates_rows) {
+                    let selects = self.unbounded.drain(unbounded..);
+                    self.warnings
+                        .extend(selects.map(|at| Warning::UnboundedQuery { at: at.into() }));
+
+                    // or be bound before the loop, as in `let rows = query! ...`
+                    if let Expr::Ident(Name::Ident(name)) = &expr {
+                        if let (
+                            Some(Bound::Select {
+                                unbounded: true, ..
+                            }),
+                            Some(&at),
+                        ) = (
+                            self.env.resolve(name.as_str()),
+                            self.selects.get(name.as_str()),
+                        ) {
+                            self.warnings
+                                .push(Warning::UnboundedQuery { at: at.into() });
+                        }
+                    }
+                }
+
+                match expr {
+                    Expr::ReadLines { path } => return Ok(Self::read_lines(&var, *path, body)),
+                    Expr::Csv {
+                        path,
+                        delimiter,
+                        headers,
+                    } => return Ok(Self::read_csv(&var, *path, *delimiter, headers, body)),
+                    // the items are written as an attribute, so a macro is bound first
+                    Expr::Range { .. } | Expr::Dict(_) => {}
+                    ref items if !items.is_inline() => {
+                        let name = Ident::from(format!("{var}_items"));
+                        return Ok(Stmt::Block(vec![
+                            Stmt::Let(name.clone(), expr),
+                            Stmt::ForEach {
+                                var,
+                                items: Expr::Ident(Name::Ident(name)),
+                                body,
+                            },
+                        ]));
+                    }
+                    _ => {}
+                }
+
+                Ok(Stmt::ForEach {
+                    var,
+                    items: expr,
+                    body,
+                })
+            }
+            rule => unreachable!("not an stmt rule: {:?}", rule),
+        }
+    }
+
+    /// `defined name` tests that `name` isn't null, and `exists name` that it isn't empty
+    /// either. `name` is usually an optional parameter of the process, so it doesn't have to
+    /// be bound, and it's bound from then on.
+    fn presence(
+        &mut self,
+        builtin: &str,
+        mut args: Pairs<Rule>,
+        span: pest::Span,
+    ) -> miette::Result<Expr> {
+        let args: Vec<_> = args
+            .next()
+            .map(|args| args.into_inner().collect())
+            .unwrap_or_default();
+        let [arg] = args.as_slice() else {
+            return Err(self.invalid(
+                span,
+                &format!("`{builtin}` takes a single name, as in `{builtin} param`"),
+            ));
+        };
+        if arg.as_rule() != Rule::access_ident {
+            return Err(self.invalid(
+                arg.as_span(),
+                &format!("`{builtin}` takes a name, as in `{builtin} param`"),
+            ));
+        }
+
+        let name = span_ident(arg);
+        if self.env.resolve(name.as_str()).is_none() {
+            self.env.bind(name.to_string(), Bound::Opaque);
+        }
+
+        let value = Expr::Ident(Name::Ident(name));
+        Ok(if builtin == "defined" {
+            Expr::infix(value, InfixOp::Neq, Value::Nothing.into())
+        } else {
+            Expr::infix(
+                Expr::call("empty", vec![value]),
+                InfixOp::Eq,
+                Value::Bool(false).into(),
+            )
+        })
+    }
+
+    /// `secret "NAME"` reads the parameter `NAME` of the process if the script declares it
+    /// with `param`, or else the system property `NAME`, so the value isn't written in the
+    /// script. Like `static!`, reading a system property is only written in a `let`.
+    fn secret(&mut self, mut args: Pairs<Rule>, span: pest::Span) -> miette::Result<Expr> {
+        const USAGE: &str = "`secret` takes the name of a parameter or a system property, as in `secret \"XOG_PASSWORD\"`";
+
+        let args: Vec<_> = args
+            .next()
+            .map(|args| args.into_inner().collect())
+            .unwrap_or_default();
+        let [arg] = args.as_slice() else {
+            return Err(self.invalid(span, USAGE));
+        };
+        let Expr::Value {
+            value: Value::Str(name),
+            ..
+        } = self.expr_from(Pairs::single(arg.clone()))?
+        else {
+            return Err(self.invalid(arg.as_span(), USAGE));
+        };
+        if name.is_empty() || name.contains("${") {
+            return Err(self.invalid(arg.as_span(), USAGE));
+        }
+
+        if self.params.contains(&*name) && self.env.resolve(&name).is_some() {
+            return Ok(Expr::Ident(Name::Ident(Ident::from(&*name))));
+        }
+        Ok(Expr::static_method(
+            "java.lang.System",
+            "getProperty",
+            vec![Expr::from(Value::Str(name))],
+        ))
+    }
+
+    /// Checks that the name `pair` can be used as a class if `class`, or else as a value or
+    /// as the function it calls if `called`, given what it's bound as.
+    fn check_kind(
+        &self,
+        pair: &pest::iterators::Pair<Rule>,
+        class: bool,
+        called: bool,
+    ) -> miette::Result<()> {
+        let name = pair.as_str();
+        let Some(kind) = self.env.kind(name) else {
+            return Ok(());
+        };
+
+        let message = match (kind, class) {
+            (Kind::Variable | Kind::Alias | Kind::Function, true) => {
+                format!("`{name}` is a {kind}, not a class")
+            }
+            (Kind::Class, false) => format!(
+                "`{name}` is a class, its methods and fields are used with `static!` or `new!`"
+            ),
+            (Kind::Function, false) if !called => {
+                format!("`{name}` is a function, so it can only be called, as in `{name} ()`")
+            }
+            _ => return Ok(()),
+        };
+        Err(self.invalid(pair.as_span(), &message))
+    }
+
+    fn parse_callable(
+        &mut self,
+        mut pair: pest::iterators::Pairs<Rule>,
+    ) -> miette::Result<(Name, Vec<Expr>)> {
+        self.callee = true;
+        let callable = self.expr_from(Pairs::single(pair.next().unwrap()));
+        self.callee = false;
+        let Expr::Ident(callable) = callable? else {
+            unreachable!()
+        };
+
+        let mut args = Vec::new();
+
+        if let Some(args_pair) = pair.next() {
+            let args_pair = args_pair.into_inner();
+
+            for arg in args_pair.map(|arg| self.expr_from(Pairs::single(arg))) {
+                let arg = arg?;
+                if matches!(
+                    arg,
+                    Expr::Value {
+                        value: Value::Unit,
+                        ..
+                    }
+                ) {
+                    continue;
+                }
+
+                args.push(arg);
+            }
+        }
+
+        Ok((callable, args))
+    }
+
+    /// Runs `body` for every line of the file at `path`, bound to `var`.
+    fn read_lines(var: &Ident, path: Expr, body: Vec<Stmt>) -> Stmt {
+        let read_line = Expr::call(format!("{var}_buf_reader.readLine"), vec![]);
+
+        let mut tags = vec![];
+        expand::buffered_reader("java.io.FileReader", path, var.as_str(), &mut tags);
+
+        let mut body = body;
+        body.push(Stmt::Let(var.clone(), read_line.clone()));
+
+        tags.extend([
+            Stmt::Let(var.clone(), read_line),
+            Stmt::While {
+                test: Expr::infix(
+                    Expr::Ident(Name::Ident(var.clone())),
+                    InfixOp::Neq,
+                    Value::Nothing.into(),
+                ),
+                body,
+            },
+            Stmt::Expr {
+                expr: Expr::call(format!("{var}_buf_reader.close"), vec![]),
+            },
+        ]);
+
+        Stmt::Block(tags)
+    }
+
+    /// Runs `body` for every row of the csv file at `path`, bound to `var`. Rows are
+    /// string arrays, or maps of the trimmed columns if the file has `headers`.
+    fn read_csv(var: &Ident, path: Expr, delimiter: Expr, headers: bool, body: Vec<Stmt>) -> Stmt {
+        let ident = |suffix: &str| Expr::Ident(format!("{var}_{suffix}").into());
+        let read_line = Expr::call(format!("{var}_buf_reader.readLine"), vec![]);
+        let split = |line: &str| {
+            Expr::call(
+                format!("{var}_{line}.split"),
+                vec![ident("delimiter"), Value::Int(-1).into()],
+            )
+        };
+
+        // the delimiter is taken literally rather than as a regex
+        let mut tags = vec![Stmt::Let(
+            format!("{var}_delimiter").into(),
+            Expr::static_method("java.util.regex.Pattern", "quote", vec![delimiter]),
+        )];
+        expand::buffered_reader("java.io.FileReader", path, var.as_str(), &mut tags);
+
+        if headers {
+            tags.extend([
+                Stmt::Let(format!("{var}_header").into(), read_line.clone()),
+                Stmt::Let(format!("{var}_headers").into(), split("header")),
+            ]);
+        }
+        tags.push(Stmt::Let(format!("{var}_line").into(), read_line.clone()));
+
+        let mut row = if headers {
+            vec![
+                Stmt::Let(format!("{var}_values").into(), split("line")),
+                Stmt::Let(
+                    var.clone(),
+                    Expr::Instance {
+                        class: "java.util.HashMap".into(),
+                        args: vec![],
+                    },
+                ),
+                Stmt::Let(format!("{var}_i").into(), 0.into()),
+                Stmt::ForEach {
+                    var: format!("{var}_column").into(),
+                    items: ident("headers"),
+                    body: vec![
+                        // short rows leave the missing columns out
+                        Stmt::If {
+                            test: Expr::infix(
+                                ident("i"),
+                                InfixOp::Lt,
+                                Expr::Ident(format!("{var}_values.length").into()),
+                            ),
+                            body: vec![
+                                Stmt::Let(
+                                    format!("{var}_value").into(),
+                                    Expr::static_method(
+                                        "java.lang.reflect.Array",
+                                        "get",
+                                        vec![ident("values"), ident("i")],
+                                    ),
+                                ),
+                                Stmt::Expr {
+                                    expr: Expr::call(
+                                        format!("{var}.put"),
+                                        vec![
+                                            Expr::call(format!("{var}_column.trim"), vec![]),
+                                            Expr::call(format!("{var}_value.trim"), vec![]),
+                                        ],
+                                    ),
+                                },
+                            ],
+                            alt: None,
+                        },
+                        Stmt::Let(
+                            format!("{var}_i").into(),
+                            Expr::infix(ident("i"), InfixOp::Add, 1.into()),
+                        ),
+                    ],
+                },
+            ]
+        } else {
+            vec![Stmt::Let(var.clone(), split("line"))]
+        };
+        row.extend(body);
+        row.push(Stmt::Let(format!("{var}_line").into(), read_line));
+
+        tags.extend([
+            Stmt::While {
+                test: Expr::infix(ident("line"), InfixOp::Neq, Value::Nothing.into()),
+                body: row,
+            },
+            Stmt::Expr {
+                expr: Expr::call(format!("{var}_buf_reader.close"), vec![]),
+            },
+        ]);
+
+        Stmt::Block(tags)
+    }
+
+    /// Parses a query param and the sql type it is cast to, if any.
+    fn sql_param(&mut self, pair: pest::iterators::Pair<Rule>) -> miette::Result<SqlParam> {
+        let mut pair = pair.into_inner();
+        let value = self.expr_from(Pairs::single(pair.next().unwrap()))?;
+
+        let Some(type_pair) = pair.next() else {
+            return Ok(SqlParam {
+                value,
+                r#type: None,
+            });
+        };
+
+        let r#type = type_pair.clone().into_inner().as_str().to_ascii_uppercase();
+        if !SQL_TYPES.contains(&r#type.as_str()) {
+            return Err(Error::Syntax {
+                source_code: self.named_source(),
+                at: ast::Span::from(type_pair.as_span()).into(),
+                expected: Some(format!("expected one of {}", SQL_TYPES.join(", "))),
+            }
+            .into());
+        }
+
+        Ok(SqlParam {
+            value,
+            r#type: Some(r#type.into()),
+        })
+    }
+
+    /// An sql error spanning the whole query body.
+    fn sql_error(&self, body_pair: &pest::iterators::Pair<Rule>, err: String) -> miette::Report {
+        Error::SqlSyntax {
+            source_code: self.named_source(),
+            at: ast::Span::from(body_pair.as_span()).into(),
+            err,
+        }
+        .into()
+    }
+
+    /// Parses the `sql` of a query block, which starts at the byte offset `start` of the
+    /// script, errors point at their token in the script.
+    fn try_parse_query(
+        &self,
+        dialect: &dyn Dialect,
+        sql: &str,
+        start: usize,
+        body_pair: &pest::iterators::Pair<Rule>,
+    ) -> miette::Result<Vec<AnalyzedSql>> {
+        self.analyze_interpolated(dialect, sql)
+            .map_err(|SqlError { location, message }| {
+                let Some(location) = location else {
+                    return self.sql_error(body_pair, message);
+                };
+
+                Error::SqlSyntax {
+                    source_code: self.named_source(),
+                    at: token_span(sql, start, location),
+                    err: message,
+                }
+                .into()
+            })
+    }
+
+    /// Analyzes the `sql` of a `query! ... from "file.sql"` read from `full_path`, errors
+    /// point into that file.
+    fn try_parse_query_file(
+        &self,
+        dialect: &dyn Dialect,
+        full_path: &std::path::Path,
+        sql: &str,
+    ) -> miette::Result<Vec<AnalyzedSql>> {
+        self.analyze_interpolated(dialect, sql)
+            .map_err(|SqlError { location, message }| {
+                let at = location.map_or_else(
+                    || SourceSpan::new(0.into(), sql.len()),
+                    |location| token_span(sql, 0, location),
+                );
+
+                Error::SqlSyntax {
+                    source_code: NamedSource::new(full_path.display().to_string(), sql.into()),
+                    at,
+                    err: message,
+                }
+                .into()
+            })
+    }
+
+    /// Analyzes `sql` with its `${name}`s naming tables or columns, values have to be passed
+    /// as params instead so they can't inject sql.
+    fn analyze_interpolated(
+        &self,
+        dialect: &dyn Dialect,
+        sql: &str,
+    ) -> Result<Vec<AnalyzedSql>, SqlError> {
+        let interpolations = sql_interpolations(sql);
+        if interpolations.is_empty() {
+            return analyze_query(dialect, sql, sql);
+        }
+
+        let error_at = |offset: usize, message: String| SqlError {
+            location: Some(location_of(sql, offset)),
+            message,
+        };
+
+        let mut substituted = String::with_capacity(sql.len());
+        let mut last = 0;
+        for (offset, name) in &interpolations {
+            if self.env.resolve(name).is_none() {
+                return Err(error_at(*offset, format!("`{name}` is unbound")));
+            }
+            substituted.push_str(&sql[last..*offset]);
+            substituted.push_str(&interpolation_marker(name));
+            last = offset + name.len() + 3;
+        }
+        substituted.push_str(&sql[last..]);
+
+        let mut queries = analyze_query(dialect, &substituted, sql)?;
+        for AnalyzedSql { stmt, .. } in &mut queries {
+            for_each_sql_name(stmt, &mut |ident| {
+                if let Some((_, name)) = interpolations
+                    .iter()
+                    .find(|(_, name)| ident.value == interpolation_marker(name))
+                {
+                    ident.value = format!("${{{name}}}");
+                }
+            });
+
+            // whatever is left is used as a value
+            let stmt = stmt.to_string();
+            if let Some((offset, name)) = interpolations
+                .iter()
+                .find(|(_, name)| stmt.contains(&interpolation_marker(name)))
+            {
+                return Err(error_at(
+                    *offset,
+                    format!(
+                        "`${{{name}}}` can only name a table or a column, pass values as `?` \
+                         params"
+                    ),
+                ));
+            }
+        }
+
+        Ok(queries)
+    }
+
+    /// Reads the file a path string points to, relative to the script being parsed.
+    fn read_relative(
+        &mut self,
+        path_pair: &pest::iterators::Pair<Rule>,
+    ) -> miette::Result<(std::path::PathBuf, String)> {
+        let path = path_pair.clone().into_inner().as_str();
+        let full_path = std::path::Path::new(self.file_name)
+            .parent()
+            .unwrap_or_else(|| std::path::Path::new(""))
+            .join(path);
+
+        #[cfg(feature = "fs")]
+        let contents = std::fs::read_to_string(&full_path);
+        #[cfg(not(feature = "fs"))]
+        let contents: std::io::Result<String> = Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "gelatin was built without the `fs` feature",
+        ));
+        let contents = contents.map_err(|err| {
+            let (line, col) = path_pair.line_col();
+
+            Error::Read {
+                source_code: self.named_source(),
+                at: SourceSpan::new(
+                    SourceOffset::from_location(self.source, line, col),
+                    path_pair.as_str().len(),
+                ),
+                path: full_path.display().to_string(),
+                err: err.to_string(),
+            }
+        })?;
+
+        if !self.imports.contains(&full_path) {
+            self.imports.push(full_path.clone());
+        }
+        Ok((full_path, contents))
+    }
+}
+
+/// Why `directive` can't follow the ones in `body`, if it repeats one of them.
+fn repeated_directive(body: &[Stmt], directive: &Stmt) -> Option<String> {
+    const PAYLOADS: [&str; 4] = ["json", "form", "body", "xml"];
+
+    let Stmt::Expr {
+        expr: Expr::Call(Call {
+            name: Name::Ident(func),
+            ..
+        }),
+    } = directive
+    else {
+        return None;
+    };
+    let func = func.as_str();
+
+    // the headers are merged
+    if matches!(func, "headers" | "capture_headers") {
+        return None;
+    }
+    if PAYLOADS.contains(&func) {
+        return PAYLOADS
+            .iter()
+            .find(|payload| body.iter().any(|stmt| expand::is_directive(stmt, payload)))
+            .map(|payload| {
+                format!("the request already sends `{payload}`, it can only send one payload")
+            });
+    }
+    body.iter()
+        .any(|stmt| expand::is_directive(stmt, func))
+        .then(|| format!("`{func}` is already given to this request"))
+}
+
+/// Whether every value of `dict`, and of the dicts nested in it, can be written inline.
+fn entries_inline(dict: &Dict) -> bool {
+    dict.values().all(|value| match value {
+        Expr::Dict(dict) => entries_inline(dict),
+        value => value.is_inline(),
+    })
+}
+
+/// Escapes `text` to be written in the xml of a template.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Finds the style issues of `query`, along with the keywords where each of them starts.
+fn sql_lints(query: &Statement) -> Vec<(String, &'static [&'static str])> {
+    let mut lints = Vec::new();
+
+    if let Statement::Query(query) = query {
+        if let sqlparser::ast::SetExpr::Select(select) = query.body.as_ref() {
+            if select.projection.iter().any(|item| {
+                matches!(
+                    item,
+                    sqlparser::ast::SelectItem::Wildcard(_)
+                        | sqlparser::ast::SelectItem::QualifiedWildcard(..)
+                )
+            }) {
+                lints.push((
+                    "`select *` breaks when columns are added or reordered, list them instead"
+                        .to_string(),
+                    &["*"][..],
+                ));
+            }
+
+            if select.from.len() > 1 && select.selection.is_none() {
+                lints.push((
+                    format!(
+                        "`{}` and `{}` are joined without a condition, pairing every row of \
+                         both",
+                        select.from[0].relation, select.from[1].relation
+                    ),
+                    &["from"][..],
+                ));
+            }
+
+            for join in select.from.iter().flat_map(|table| &table.joins) {
+                if matches!(
+                    join.join_operator,
+                    sqlparser::ast::JoinOperator::CrossJoin
+                        | sqlparser::ast::JoinOperator::Inner(sqlparser::ast::JoinConstraint::None)
+                ) {
+                    lints.push((
+                        format!(
+                            "`{}` is joined without a condition, pairing it with every row",
+                            join.relation
+                        ),
+                        &["join"][..],
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut ordered = false;
+    for_each_subquery(query, &mut |subquery| {
+        // ordering only changes which rows a limited subquery returns
+        ordered |=
+            !subquery.order_by.is_empty() && subquery.limit.is_none() && subquery.fetch.is_none();
+    });
+    if ordered {
+        lints.push((
+            "the `order by` of a subquery is ignored, order the outer query instead".to_string(),
+            &["order", "by"][..],
+        ));
+    }
+
+    lints
+}
+
+/// Calls `f` with each subquery in the `from`, joins and `where` of `stmt`.
+fn for_each_subquery(stmt: &Statement, f: &mut impl FnMut(&Query)) {
+    fn visit_body(body: &sqlparser::ast::SetExpr, f: &mut impl FnMut(&Query)) {
+        match body {
+            sqlparser::ast::SetExpr::Select(select) => {
+                for table in &select.from {
+                    let joins = table.joins.iter().map(|join| &join.relation);
+                    for relation in std::iter::once(&table.relation).chain(joins) {
+                        if let sqlparser::ast::TableFactor::Derived { subquery, .. } = relation {
+                            f(subquery);
+                            visit_body(&subquery.body, f);
+                        }
+                    }
+                }
+                if let Some(selection) = &select.selection {
+                    visit_expr(selection, f);
+                }
+            }
+            sqlparser::ast::SetExpr::Query(query) => visit_body(&query.body, f),
+            sqlparser::ast::SetExpr::SetOperation { left, right, .. } => {
+                visit_body(left, f);
+                visit_body(right, f);
+            }
+            _ => {}
+        }
+    }
+
+    fn visit_expr(expr: &sqlparser::ast::Expr, f: &mut impl FnMut(&Query)) {
+        match expr {
+            sqlparser::ast::Expr::Subquery(subquery)
+            | sqlparser::ast::Expr::InSubquery { subquery, .. }
+            | sqlparser::ast::Expr::Exists { subquery, .. } => {
+                f(subquery);
+                visit_body(&subquery.body, f);
+            }
+            sqlparser::ast::Expr::BinaryOp { left, right, .. } => {
+                visit_expr(left, f);
+                visit_expr(right, f);
+            }
+            sqlparser::ast::Expr::UnaryOp { expr, .. } | sqlparser::ast::Expr::Nested(expr) => {
+                visit_expr(expr, f);
+            }
+            _ => {}
+        }
+    }
+
+    match stmt {
+        Statement::Query(query) => visit_body(&query.body, f),
+        Statement::Update {
+            selection: Some(selection),
+            ..
+        }
+        | Statement::Delete {
+            selection: Some(selection),
+            ..
+        } => visit_expr(selection, f),
+        Statement::Insert {
+            source: Some(source),
+            ..
+        } => visit_body(&source.body, f),
+        _ => {}
+    }
+}
+
+/// The span of the first `keywords` in the sql at `span`, or all of it if they aren't
+/// there, as when the sql is read from a file.
+fn sql_span(span: pest::Span, keywords: &[&str]) -> ast::Span {
+    let sql = span.as_str().to_ascii_lowercase();
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    for (start, _) in sql.match_indices(keywords[0]) {
+        let mut end = start + keywords[0].len();
+        let alone = keywords[0].starts_with(|c: char| !is_word(c))
+            || !(sql[..start].ends_with(is_word) || sql[end..].starts_with(is_word));
+        if !alone {
+            continue;
+        }
+
+        let rest = keywords[1..].iter().try_for_each(|keyword| {
+            let skipped = sql[end..].len() - sql[end..].trim_start().len();
+            if skipped == 0 || !sql[end + skipped..].starts_with(keyword) {
+                return None;
+            }
+            end += skipped + keyword.len();
+            Some(())
+        });
+
+        if rest.is_some() {
+            let (start, end) = (span.start() + start, span.start() + end);
+            return ast::Span::from(pest::Span::new(span.get_input(), start, end).unwrap());
+        }
+    }
+
+    ast::Span::from(span)
+}
+
+/// The names of the columns `query` selects, if they can be told without running it.
+fn select_columns(query: &Statement) -> Option<Vec<String>> {
+    let Statement::Query(query) = query else {
+        return None;
+    };
+    let sqlparser::ast::SetExpr::Select(select) = query.body.as_ref() else {
+        return None;
+    };
+
+    select
+        .projection
+        .iter()
+        .map(|item| match item {
+            sqlparser::ast::SelectItem::UnnamedExpr(sqlparser::ast::Expr::Identifier(ident))
+            | sqlparser::ast::SelectItem::ExprWithAlias { alias: ident, .. } => {
+                Some(ident.value.clone())
+            }
+            sqlparser::ast::SelectItem::UnnamedExpr(sqlparser::ast::Expr::CompoundIdentifier(
+                idents,
+            )) => idents.last().map(|ident| ident.value.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether `expr` is a select without a `limit` or a sql `LIMIT`/`FETCH`.
+fn is_unbounded_select(expr: &Expr) -> bool {
+    let Expr::Query {
+        r#type: QueryType::SELECT,
+        query: Statement::Query(query),
+        max_rows: None,
+        ..
+    } = expr
+    else {
+        return false;
+    };
+
+    query.limit.is_none() && query.fetch.is_none()
+}
+
+/// Whether running `stmt` can insert, update or delete rows.
+fn updates_rows(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Let(_, expr) | Stmt::Expr { expr } => match expr {
+            Expr::Query { r#type, .. } => *r#type != QueryType::SELECT,
+            Expr::Batch(_) => true,
+            _ => false,
+        },
+        Stmt::Block(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::ForEach { body, .. }
+        | Stmt::While { body, .. }
+        | Stmt::WriteFile { body, .. }
+        | Stmt::Whitespace(body)
+        | Stmt::Transaction { body, .. } => body.iter().any(updates_rows),
+        Stmt::If { body, alt, .. } => body.iter().chain(alt.iter().flatten()).any(updates_rows),
+        _ => false,
+    }
+}
+
+/// Finds the `${name}`s in `sql`, along with their offsets.
+fn sql_interpolations(sql: &str) -> Vec<(usize, &str)> {
+    let mut interpolations = Vec::new();
+
+    for (offset, _) in sql.match_indices("${") {
+        let rest = &sql[offset + 2..];
+        let Some(end) = rest.find('}') else {
+            continue;
+        };
+        let name = &rest[..end];
+        if name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            interpolations.push((offset, name));
+        }
+    }
+
+    interpolations
+}
+
+/// The identifier standing for `${name}` while the sql is parsed, it is as long as
+/// `${name}` so the locations of errors don't move.
+fn interpolation_marker(name: &str) -> String {
+    format!("__{name}_")
+}
+
+/// The 1-based line and column of `offset` in `sql`.
+fn location_of(sql: &str, offset: usize) -> sqlparser::tokenizer::Location {
+    let before = &sql[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+
+    sqlparser::tokenizer::Location {
+        line: line as u64,
+        column: column as u64,
+    }
+}
+
+/// Calls `f` with each identifier of `stmt` that names a table or a column: the tables
+/// it reads or writes and the plain columns it selects, orders, groups, inserts or sets.
+fn for_each_sql_name(stmt: &mut Statement, f: &mut impl FnMut(&mut sqlparser::ast::Ident)) {
+    fn visit_tables(
+        tables: &mut [sqlparser::ast::TableWithJoins],
+        f: &mut impl FnMut(&mut sqlparser::ast::Ident),
+    ) {
+        for table in tables {
+            let joins = table.joins.iter_mut().map(|join| &mut join.relation);
+            for relation in std::iter::once(&mut table.relation).chain(joins) {
+                match relation {
+                    sqlparser::ast::TableFactor::Table { name, .. } => {
+                        name.0.iter_mut().for_each(&mut *f);
+                    }
+                    sqlparser::ast::TableFactor::Derived { subquery, .. } => {
+                        visit_query(subquery, f);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn visit_column(
+        expr: &mut sqlparser::ast::Expr,
+        f: &mut impl FnMut(&mut sqlparser::ast::Ident),
+    ) {
+        match expr {
+            sqlparser::ast::Expr::Identifier(ident) => f(ident),
+            sqlparser::ast::Expr::CompoundIdentifier(idents) => idents.iter_mut().for_each(f),
+            _ => {}
+        }
+    }
+
+    fn visit_query(query: &mut Query, f: &mut impl FnMut(&mut sqlparser::ast::Ident)) {
+        visit_body(&mut query.body, f);
+        for order_by in &mut query.order_by {
+            visit_column(&mut order_by.expr, f);
+        }
+    }
+
+    fn visit_body(
+        body: &mut sqlparser::ast::SetExpr,
+        f: &mut impl FnMut(&mut sqlparser::ast::Ident),
+    ) {
+        match body {
+            sqlparser::ast::SetExpr::Select(select) => {
+                for item in &mut select.projection {
+                    match item {
+                        sqlparser::ast::SelectItem::UnnamedExpr(expr)
+                        | sqlparser::ast::SelectItem::ExprWithAlias { expr, .. } => {
+                            visit_column(expr, f);
+                        }
+                        _ => {}
+                    }
+                }
+                visit_tables(&mut select.from, f);
+                if let sqlparser::ast::GroupByExpr::Expressions(exprs) = &mut select.group_by {
+                    for expr in exprs {
+                        visit_column(expr, f);
+                    }
+                }
+            }
+            sqlparser::ast::SetExpr::Query(query) => visit_query(query, f),
+            sqlparser::ast::SetExpr::SetOperation { left, right, .. } => {
+                visit_body(left, f);
+                visit_body(right, f);
+            }
+            _ => {}
+        }
+    }
+
+    match stmt {
+        Statement::Query(query) => visit_query(query, f),
+        Statement::Insert {
+            table_name,
+            columns,
+            source,
+            ..
+        } => {
+            table_name.0.iter_mut().for_each(&mut *f);
+            columns.iter_mut().for_each(&mut *f);
+            if let Some(source) = source {
+                visit_query(source, f);
+            }
+        }
+        Statement::Update {
+            table, assignments, ..
+        } => {
+            visit_tables(std::slice::from_mut(table), f);
+            for assignment in assignments {
+                assignment.id.iter_mut().for_each(&mut *f);
+            }
+        }
+        Statement::Delete { tables, from, .. } => {
+            for name in tables {
+                name.0.iter_mut().for_each(&mut *f);
+            }
+            match from {
+                sqlparser::ast::FromTable::WithFromKeyword(from)
+                | sqlparser::ast::FromTable::WithoutKeyword(from) => visit_tables(from, f),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// An error found while analyzing the sql of a query, `location` is the token it
+/// happened at when it is known.
+struct SqlError {
+    location: Option<sqlparser::tokenizer::Location>,
+    message: String,
+}
 
-        if let Some(args_pair) = pair.next() {
-            let args_pair = args_pair.into_inner();
+impl From<String> for SqlError {
+    fn from(message: String) -> Self {
+        Self {
+            location: None,
+            message,
+        }
+    }
+}
 
-            for arg in args_pair.map(|arg| self.expr_from(Pairs::single(arg))) {
-                let arg = arg?;
-                if matches!(arg, Expr::Value(Value::Unit)) {
-                    continue;
-                }
+/// A statement of a query block.
+struct AnalyzedSql {
+    stmt: Statement,
+    /// How many `?` placeholders it has.
+    params: usize,
+    r#type: QueryType,
+    /// The sql it was parsed from.
+    text: Arc<str>,
+}
 
-                args.push(arg);
-            }
+/// Parses the statements in `sql`, counting their placeholders and telling their type.
+///
+/// Several statements run as a batch, so they all have to be `insert`/`update`/`delete`s.
+/// Their text is taken from `written`, which `sql` may have replaced parts of with text as
+/// long.
+fn analyze_query(
+    dialect: &dyn Dialect,
+    sql: &str,
+    written: &str,
+) -> Result<Vec<AnalyzedSql>, SqlError> {
+    let mut parser = match sqlparser::parser::Parser::new(dialect).try_with_sql(sql) {
+        Ok(p) => p,
+        Err(ParserError::TokenizerError(err) | ParserError::ParserError(err)) => {
+            return Err(err.into());
+        }
+        Err(ParserError::RecursionLimitExceeded) => {
+            return Err(String::from("the query is nested too deeply").into());
         }
+    };
+
+    let stmts = parser.parse_statements().map_err(|e| {
+        let tok = parser.next_token();
+
+        let err = e.to_string();
+        let err = err.split_once(": ").map_or(err.as_str(), |(_, err)| err);
+        SqlError {
+            // the end of the input has no location
+            location: (tok.location.line > 0).then_some(tok.location),
+            // the location sqlparser adds is within the query, the label shows where it is
+            message: err
+                .rsplit_once(" at Line: ")
+                .map_or(err, |(err, _)| err)
+                .to_string(),
+        }
+    })?;
 
-        Ok((callable, args))
+    if stmts.is_empty() {
+        return Err(String::from("expected a select/insert/update/delete statement").into());
     }
 
-    #[allow(clippy::too_many_lines)]
-    fn macro_expand_expr<I: Into<Ident>>(name: I, expr: Expr) -> Node {
-        match expr {
-            Expr::Http {
-                verb,
-                box url,
-                body,
-            } => {
-                let name: Ident = name.into();
-
-                let mut tags = vec![
-                    Stmt::Let(
-                        "remoteURL".into(),
-                        Expr::Instance {
-                            class: "java.net.URL".into(),
-                            args: vec![url],
-                        },
-                    ),
-                    Stmt::Let(name.clone(), Expr::call("remoteURL.openConnection", vec![])),
-                    Stmt::Expr {
-                        expr: Expr::call(
-                            format!("{name}.setRequestMethod"),
-                            vec![verb.as_str().into()],
-                        ),
-                    },
-                    Stmt::Expr {
-                        expr: Expr::call(format!("{name}.setDoOutput"), vec![true.into()]),
-                    },
-                ];
-
-                if !matches!(verb, HttpVerb::GET) {
-                    tags.push(Stmt::Expr {
-                        expr: Expr::call(format!("{name}.setDoInput"), vec![true.into()]),
-                    });
+    let batch = stmts.len() > 1;
+    let texts = statement_spans(dialect, sql)
+        .filter(|spans| spans.len() == stmts.len())
+        .map_or_else(
+            || vec![written.trim(); stmts.len()],
+            |spans| spans.into_iter().map(|span| written[span].trim()).collect(),
+        );
+
+    stmts
+        .into_iter()
+        .zip(texts)
+        .map(|(stmt, text)| {
+            let (ty, len) = match stmt {
+                sqlparser::ast::Statement::Query(ref query) => {
+                    let Some(ty) = query_type(query) else {
+                        return Err(String::from(
+                            "expected a select/insert/update/delete statement",
+                        )
+                        .into());
+                    };
+                    (ty, query_params(query, 0)?)
                 }
-
-                for expr in body {
-                    let Stmt::Expr {
-                        expr:
-                            Expr::Call(Call {
-                                name: Name::Ident(func),
-                                mut args,
-                            }),
-                    } = expr
-                    else {
-                        unreachable!()
+                sqlparser::ast::Statement::Insert { ref source, .. } => {
+                    let len = match source {
+                        Some(source) => query_params(source, 0)?,
+                        None => 0,
                     };
+                    (QueryType::INSERT, len)
+                }
+                sqlparser::ast::Statement::Update { .. } => {
+                    (QueryType::UPDATE, statement_params(&stmt)?)
+                }
+                sqlparser::ast::Statement::Delete { .. } => {
+                    (QueryType::DELETE, statement_params(&stmt)?)
+                }
+                _ => {
+                    return Err(
+                        String::from("expected a select/insert/update/delete statement").into(),
+                    );
+                }
+            };
 
-                    match func.as_str() {
-                        "timeout" => {
-                            let Some(timeout) = args.pop() else {
-                                unreachable!()
-                            };
+            if batch && ty == QueryType::SELECT {
+                return Err(String::from(
+                    "a select can't run in a batch, move it to its own `query!`",
+                )
+                .into());
+            }
 
-                            tags.push(Stmt::Expr {
-                                expr: Expr::call(
-                                    format!("{name}.setConnectTimeout"),
-                                    vec![timeout.clone()],
-                                ),
-                            });
-                            tags.push(Stmt::Expr {
-                                expr: Expr::call(
-                                    format!("{name}.setReadTimeout"),
-                                    vec![timeout.clone()],
-                                ),
-                            });
-                        }
-                        "headers" => {
-                            let Some(Expr::Dict(dict)) = args.pop() else {
-                                unreachable!("kek")
-                            };
-
-                            tags.extend(dict.into_iter().map(|(k, v)| Stmt::Expr {
-                                expr: Expr::call(
-                                    format!("{name}.setRequestProperty"),
-                                    vec![k.into(), v],
-                                ),
-                            }));
-                        }
-                        "json" => {
-                            let Some(Expr::Dict(dict)) = args.pop() else {
-                                unreachable!("kek")
-                            };
-                            tags.push(Stmt::Expr {
-                                expr: Expr::call(
-                                    format!("{name}.setRequestProperty"),
-                                    vec!["content-type".into(), "application/json".into()],
-                                ),
-                            });
+            Ok(AnalyzedSql {
+                stmt,
+                params: len,
+                r#type: ty,
+                text: text.into(),
+            })
+        })
+        .collect()
+}
 
-                            tags.push(Stmt::Let(
-                                format!("{name}_w").into(),
-                                Expr::Instance {
-                                    class: "java.io.OutputStreamWriter".into(),
-                                    args: vec![Expr::call(
-                                        format!("{name}.getOutputStream"),
-                                        vec![],
-                                    )],
-                                },
-                            ));
+/// The span of the character at `location` in `sql`, which starts at the byte offset
+/// `start` of its source.
+fn token_span(sql: &str, start: usize, location: sqlparser::tokenizer::Location) -> SourceSpan {
+    let offset = offset_of(sql, location);
+    let len = sql[offset..].chars().next().map_or(0, char::len_utf8);
 
-                            Self::create_json_tags(
-                                dict,
-                                format!("{name}_payload").as_str(),
-                                &mut tags,
-                            );
+    SourceSpan::new((start + offset).into(), len)
+}
 
-                            tags.push(Stmt::Expr {
-                                expr: Expr::call(
-                                    format!("{name}_payload.write"),
-                                    vec![Expr::Ident(format!("{name}_w").into())],
-                                ),
-                            });
-                            tags.push(Stmt::Expr {
-                                expr: Expr::call(format!("{name}_w.flush"), vec![]),
-                            });
-                        }
-                        _ => unreachable!(),
-                    }
-                }
+/// Splits `sql` into where each statement is, `None` if it can't be tokenized.
+fn statement_spans(dialect: &dyn Dialect, sql: &str) -> Option<Vec<std::ops::Range<usize>>> {
+    let tokens = sqlparser::tokenizer::Tokenizer::new(dialect, sql)
+        .tokenize_with_location()
+        .ok()?;
+
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for token in tokens {
+        if token.token == sqlparser::tokenizer::Token::SemiColon {
+            let end = offset_of(sql, token.location);
+            spans.push(start..end);
+            start = end + 1;
+        }
+    }
+    spans.push(start..sql.len());
 
-                tags.push(Stmt::Expr {
-                    expr: Expr::call(format!("{name}.connect"), vec![]),
-                });
+    spans.retain(|span| !sql[span.clone()].trim().is_empty());
+    Some(spans)
+}
 
-                Node::Stmt(Stmt::Block(tags))
-            }
-            Expr::Json { expr } => {
-                let tags = vec![
-                    Stmt::Let(
-                        "input_stream".into(),
-                        Expr::call(format!("{expr}.getInputStream"), vec![]),
-                    ),
-                    Stmt::Let(
-                        "input_reader".into(),
-                        Expr::Instance {
-                            class: "java.io.InputStreamReader".into(),
-                            args: vec![Expr::Ident("input_stream".into())],
-                        },
-                    ),
-                    Stmt::Let(
-                        "buf_reader".into(),
-                        Expr::Instance {
-                            class: "java.io.BufferedReader".into(),
-                            args: vec![Expr::Ident("input_reader".into())],
-                        },
-                    ),
-                    Stmt::Let(
-                        "sb".into(),
-                        Expr::Instance {
-                            class: "java.lang.StringBuilder".into(),
-                            args: vec![],
-                        },
-                    ),
-                    Stmt::Let("line".into(), Expr::call("buf_reader.readLine", vec![])),
-                    Stmt::While {
-                        test: Expr::Infix {
-                            lhs: Box::new(Expr::Ident("line".into())),
-                            op: InfixOp::Neq,
-                            rhs: Box::new(Expr::Value(Value::Nothing)),
-                        },
-                        body: vec![
-                            Stmt::Expr {
-                                expr: Expr::call("sb.append", vec![Expr::Ident("line".into())]),
-                            },
-                            Stmt::Let("line".into(), Expr::call("buf_reader.readLine", vec![])),
-                        ],
-                    },
-                    Stmt::Let(
-                        name.into(),
-                        Expr::Instance {
-                            class: "org.json.JSONObject".into(),
-                            args: vec![Expr::call("sb.toString", vec![])],
-                        },
-                    ),
-                ];
+/// The byte offset of the 1-based `location` in `sql`.
+fn offset_of(sql: &str, location: sqlparser::tokenizer::Location) -> usize {
+    let line_start = sql
+        .split_inclusive('\n')
+        .take(usize::try_from(location.line).expect("a valid usize") - 1)
+        .map(str::len)
+        .sum::<usize>();
+
+    sql[line_start..]
+        .char_indices()
+        .nth(usize::try_from(location.column).expect("a valid usize") - 1)
+        .map_or(sql.len(), |(offset, _)| line_start + offset)
+}
 
-                Node::Stmt(Stmt::Block(tags))
-            }
-            Expr::Dict(dict) => {
-                let name: Ident = name.into();
-                let mut tags = vec![];
-                Self::create_json_tags(dict, name.as_str(), &mut tags);
+/// Opens the `NikuDataBus` envelope of a XOG `action` over `object_type`.
+fn nikudatabus_start(action: &str, object_type: &str) -> String {
+    let schema = if action == "read" {
+        "nikuxog_read.xsd".to_string()
+    } else {
+        format!("nikuxog_{object_type}.xsd")
+    };
+
+    format!(
+        "<NikuDataBus xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xsi:noNamespaceSchemaLocation=\"../xsd/{schema}\">\
+         <Header action=\"{action}\" externalSource=\"NIKU\" objectType=\"{object_type}\" \
+         version=\"8.0\"/>"
+    )
+}
 
-                Node::Stmt(Stmt::Block(tags))
-            }
-            expr => Node::Expr(expr),
-        }
+/// The tree pest parses `source` into, a pair per line as `rule line:col`, followed by the
+/// text of the pairs holding no others.
+///
+/// # Errors
+/// Returns `Err` if `source` isn't a valid script.
+#[cfg(feature = "fs")]
+pub fn tokens(file_name: &str, source: &str) -> miette::Result<String> {
+    let pairs = Gelatin::parse(Rule::program, source)
+        .map_err(|err| syntax_error(file_name, source, &err))?;
+
+    let mut buff = String::new();
+    for pair in pairs {
+        write_pair(&mut buff, &pair, 0);
     }
+    Ok(buff)
+}
 
-    fn macro_expand_stmt(stmt: Stmt) -> Node {
-        match stmt {
-            Stmt::Let(name, expr @ (Expr::Dict(_) | Expr::Json { .. } | Expr::Http { .. })) => {
-                Self::macro_expand_expr(name, expr)
-            }
-            stmt => Node::Stmt(stmt),
-        }
+/// Whether `source` only fails to parse because it ends too early, like a block missing its
+/// `end`, so more lines can complete it.
+#[cfg(feature = "fs")]
+pub fn is_incomplete(source: &str) -> bool {
+    Gelatin::parse(Rule::program, source).is_err_and(|err| {
+        let at = match err.location {
+            pest::error::InputLocation::Pos(at) | pest::error::InputLocation::Span((at, _)) => at,
+        };
+        source[at..].trim().is_empty()
+    })
+}
+
+#[cfg(feature = "fs")]
+fn write_pair(buff: &mut String, pair: &pest::iterators::Pair<Rule>, depth: usize) {
+    let (line, col) = pair.line_col();
+    let _ = write!(
+        buff,
+        "{}{:?} {line}:{col}",
+        "  ".repeat(depth),
+        pair.as_rule()
+    );
+
+    let mut inner = pair.clone().into_inner().peekable();
+    if inner.peek().is_none() {
+        let _ = write!(buff, " {:?}", pair.as_str());
     }
+    buff.push('\n');
 
-    fn create_json_tags(map: HashMap<Arc<str>, Expr>, bind_to: &str, tags: &mut Vec<Stmt>) {
-        tags.push(Stmt::Let(
-            bind_to.into(),
-            Expr::Instance {
-                class: "org.json.JSONObject".into(),
-                args: vec![],
-            },
-        ));
+    for inner in inner {
+        write_pair(buff, &inner, depth + 1);
+    }
+}
 
-        for (k, mut v) in map {
-            if let Expr::Dict(inner) = v {
-                Self::create_json_tags(inner, &format!("{bind_to}_{k}"), tags);
-                v = Expr::Ident(k.clone().into());
-            }
+/// The diagnostic of the pest error `err` parsing `source`.
+fn syntax_error(file_name: &str, source: &str, err: &pest::error::Error<Rule>) -> miette::Report {
+    let line_col = match err.line_col {
+        pest::error::LineColLocation::Pos((line, col))
+        | pest::error::LineColLocation::Span((line, col), _) => {
+            SourceOffset::from_location(source, line, col)
+        }
+    };
 
-            tags.push(Stmt::Expr {
-                expr: Expr::call(format!("{bind_to}_payload.put"), vec![k.into(), v]),
-            });
+    let expected = match &err.variant {
+        pest::error::ErrorVariant::ParsingError {
+            positives,
+            negatives: _,
+        } => {
+            let expected = positives
+                .iter()
+                .map(|r| format!("{r:?}"))
+                .collect::<Vec<String>>()
+                .join(", ");
+            format!("expected {expected}")
         }
+        pest::error::ErrorVariant::CustomError { message } => message.clone(),
+    };
+
+    Error::Syntax {
+        source_code: NamedSource::new(file_name, source.into()),
+        at: SourceSpan::new(line_col, 1),
+        expected: Some(expected),
     }
+    .into()
+}
 
-    fn try_parse_query(
-        &self,
-        parser: sqlparser::parser::Parser,
-        sql: &str,
-        pair: &pest::iterators::Pair<Rule>,
-        body_pair: &pest::iterators::Pair<Rule>,
-    ) -> miette::Result<((Statement, usize), QueryType)> {
-        let mut parser = match parser.try_with_sql(sql) {
-            Ok(p) => p,
-            Err(e) => match e {
-                ParserError::TokenizerError(_) => todo!(),
-                ParserError::RecursionLimitExceeded => todo!(),
-                ParserError::ParserError(_) => unreachable!(),
+/// Calls XOG with `body` on behalf of the `session` created by `xog_login!`.
+fn xog_request(session: &Ident, body: Vec<xml::reader::XmlEvent>) -> Expr {
+    let header = xog_element(
+        "Auth",
+        &[("SessionID", Cow::Owned(format!("${{{session}}}")))],
+    );
+
+    Expr::Soap {
+        endpoint: Box::new(Expr::Ident(format!("{session}_endpoint").into())),
+        header: Some(header),
+        body: Some(body),
+        on_fault: None,
+    }
+}
+
+/// Builds a `<xog:{name}>` element holding a `<xog:{child}>{text}</xog:{child}>` per child.
+fn xog_element(name: &str, children: &[(&str, Cow<'_, str>)]) -> Vec<xml::reader::XmlEvent> {
+    use xml::{name::OwnedName, namespace::Namespace, reader::XmlEvent};
+
+    let xog_name = |local_name: &str| OwnedName {
+        local_name: local_name.to_string(),
+        namespace: Some(XOG_NS.to_string()),
+        prefix: Some("xog".to_string()),
+    };
+    let mut namespace = Namespace::empty();
+    namespace.put("xog", XOG_NS);
+
+    let mut events = vec![XmlEvent::StartElement {
+        name: xog_name(name),
+        attributes: vec![],
+        namespace: namespace.clone(),
+    }];
+
+    for (child, text) in children {
+        events.extend([
+            XmlEvent::StartElement {
+                name: xog_name(child),
+                attributes: vec![],
+                namespace: namespace.clone(),
             },
-        };
+            XmlEvent::Characters(text.to_string()),
+            XmlEvent::EndElement {
+                name: xog_name(child),
+            },
+        ]);
+    }
 
-        let stmts: miette::Result<_> = parser.parse_statements().map_err(|e| {
-            let tok = parser.next_token();
+    events.push(XmlEvent::EndElement {
+        name: xog_name(name),
+    });
+    events
+}
 
-            let (line, _) = pair.line_col();
+/// Splits a `json_get!` path such as `data.items[0].name` into its segments.
+fn json_path(path: &str) -> Option<Vec<PathSegment>> {
+    let mut segments = vec![];
 
-            Error::SqlSyntax {
-                source_code: NamedSource::new(self.file_name, self.source.to_string()),
-                at: SourceSpan::new(
-                    SourceOffset::from_location(
-                        self.source,
-                        usize::try_from(tok.location.line + (line as u64) - 1)
-                            .expect("a valid usize"),
-                        usize::try_from(tok.location.column).expect("a valid usize"),
-                    ),
-                    1,
-                ),
-                err: e.to_string().split_once(": ").unwrap().1.to_string(),
-            }
-            .into()
-        });
-        let mut stmts = stmts?;
+    for (i, part) in path.split('.').enumerate() {
+        let (key, mut indexes) = part.find('[').map_or((part, ""), |at| part.split_at(at));
 
-        assert!(stmts.len() == 1, "expected only one statement in sql query");
+        // only the first part may start with an index, e.g. `[0].name`
+        if key.is_empty() && (i > 0 || indexes.is_empty()) {
+            return None;
+        }
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key.into()));
+        }
 
-        let stmt = stmts.pop().unwrap();
+        while let Some(rest) = indexes.strip_prefix('[') {
+            let (idx, rest) = rest.split_once(']')?;
+            segments.push(PathSegment::Index(idx.parse().ok()?));
+            indexes = rest;
+        }
 
-        let (ty, len) = match stmt {
-            sqlparser::ast::Statement::Query(ref query) => {
-                (query_type(query), query_params(query, 0))
-            }
-            sqlparser::ast::Statement::Insert { .. } => (QueryType::INSERT, 0),
-            sqlparser::ast::Statement::Update { .. } => (QueryType::UPDATE, 0),
-            sqlparser::ast::Statement::Delete { .. } => (QueryType::DELETE, 0),
-            _ => {
-                let input = pair.get_input();
-                return Err(Error::Syntax {
-                    source_code: NamedSource::new(self.file_name, input.to_string()),
-                    at: SourceSpan::new(
-                        SourceOffset::from_location(
-                            body_pair.get_input(),
-                            pair.line_col().0,
-                            pair.line_col().1,
-                        ),
-                        sql.len() + 13, // move it to the }
-                    ),
-                    expected: Some("expected a select/insert/update/delete statement".to_string()),
-                }
-                .into());
-            }
-        };
+        if !indexes.is_empty() {
+            return None;
+        }
+    }
+
+    Some(segments)
+}
+
+fn invalid(source_code: NamedSource<Arc<str>>, span: pest::Span, message: &str) -> miette::Report {
+    Error::Invalid {
+        source_code,
+        at: ast::Span::from(span).into(),
+        message: message.to_string(),
+    }
+    .into()
+}
 
-        Ok(((stmt, len), ty))
+/// The span of `pair`. Its line is looked up in the lines the parse indexed, converting its
+/// `pest::Span` would count the lines before it.
+fn span_of(pair: &pest::iterators::Pair<Rule>) -> ast::Span {
+    let (line, col) = pair.line_col();
+    let span = pair.as_span();
+    ast::Span {
+        offset: span.start(),
+        len: span.end() - span.start(),
+        line,
+        col,
     }
 }
 
-fn add_if_placeholder(expr: &sqlparser::ast::Expr, count: &mut usize) {
+/// Creates an ident spanning the `pair` it was parsed from.
+fn span_ident(pair: &pest::iterators::Pair<Rule>) -> Ident {
+    Ident::from(pair.as_str()).with_span(span_of(pair))
+}
+
+/// Counts the `?` placeholders in `expr`, `Err` names the query they can't be counted in.
+#[allow(clippy::too_many_lines)]
+fn add_if_placeholder(expr: &sqlparser::ast::Expr, count: &mut usize) -> Result<(), String> {
     match expr {
         sqlparser::ast::Expr::Value(sqlparser::ast::Value::Placeholder(_)) => {
             *count += 1;
@@ -1045,8 +3546,8 @@ fn add_if_placeholder(expr: &sqlparser::ast::Expr, count: &mut usize) {
         | sqlparser::ast::Expr::AllOp { left, right, .. }
         | sqlparser::ast::Expr::IsDistinctFrom(left, right)
         | sqlparser::ast::Expr::IsNotDistinctFrom(left, right) => {
-            add_if_placeholder(left, count);
-            add_if_placeholder(right, count);
+            add_if_placeholder(left, count)?;
+            add_if_placeholder(right, count)?;
         }
         sqlparser::ast::Expr::IsFalse(expr)
         | sqlparser::ast::Expr::IsNotFalse(expr)
@@ -1056,100 +3557,555 @@ fn add_if_placeholder(expr: &sqlparser::ast::Expr, count: &mut usize) {
         | sqlparser::ast::Expr::IsNotNull(expr)
         | sqlparser::ast::Expr::IsUnknown(expr)
         | sqlparser::ast::Expr::IsNotUnknown(expr)
-        | sqlparser::ast::Expr::UnaryOp { expr, .. } => add_if_placeholder(expr, count),
+        | sqlparser::ast::Expr::UnaryOp { expr, .. }
+        | sqlparser::ast::Expr::CompositeAccess { expr, .. }
+        | sqlparser::ast::Expr::Convert { expr, .. }
+        | sqlparser::ast::Expr::Cast { expr, .. }
+        | sqlparser::ast::Expr::TryCast { expr, .. }
+        | sqlparser::ast::Expr::SafeCast { expr, .. }
+        | sqlparser::ast::Expr::AtTimeZone {
+            timestamp: expr, ..
+        }
+        | sqlparser::ast::Expr::Extract { expr, .. }
+        | sqlparser::ast::Expr::Ceil { expr, .. }
+        | sqlparser::ast::Expr::Floor { expr, .. }
+        | sqlparser::ast::Expr::Collate { expr, .. }
+        | sqlparser::ast::Expr::Named { expr, .. }
+        | sqlparser::ast::Expr::Nested(expr)
+        | sqlparser::ast::Expr::Interval(sqlparser::ast::Interval { value: expr, .. }) => {
+            add_if_placeholder(expr, count)?;
+        }
         sqlparser::ast::Expr::InList { expr, list, .. } => {
-            add_if_placeholder(expr, count);
-            list.iter()
-                .map(|e| add_if_placeholder(e, count))
-                .for_each(drop);
+            add_if_placeholder(expr, count)?;
+            for e in list {
+                add_if_placeholder(e, count)?;
+            }
         }
         sqlparser::ast::Expr::InSubquery { expr, subquery, .. } => {
-            add_if_placeholder(expr, count);
+            add_if_placeholder(expr, count)?;
 
-            *count = query_params(subquery, *count);
+            *count = query_params(subquery, *count)?;
         }
         sqlparser::ast::Expr::InUnnest {
             expr, array_expr, ..
         } => {
-            add_if_placeholder(expr, count);
-            add_if_placeholder(array_expr, count);
+            add_if_placeholder(expr, count)?;
+            add_if_placeholder(array_expr, count)?;
         }
         sqlparser::ast::Expr::Between {
             expr, low, high, ..
         } => {
-            add_if_placeholder(expr, count);
-            add_if_placeholder(low, count);
-            add_if_placeholder(high, count);
+            add_if_placeholder(expr, count)?;
+            add_if_placeholder(low, count)?;
+            add_if_placeholder(high, count)?;
         }
         sqlparser::ast::Expr::Like { expr, pattern, .. }
         | sqlparser::ast::Expr::ILike { expr, pattern, .. }
         | sqlparser::ast::Expr::SimilarTo { expr, pattern, .. }
         | sqlparser::ast::Expr::RLike { expr, pattern, .. } => {
-            add_if_placeholder(expr, count);
-            add_if_placeholder(pattern, count);
+            add_if_placeholder(expr, count)?;
+            add_if_placeholder(pattern, count)?;
+        }
+        sqlparser::ast::Expr::Position { expr, r#in } => {
+            add_if_placeholder(expr, count)?;
+            add_if_placeholder(r#in, count)?;
+        }
+        sqlparser::ast::Expr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+            ..
+        } => {
+            add_if_placeholder(expr, count)?;
+            if let Some(from) = substring_from {
+                add_if_placeholder(from, count)?;
+            }
+            if let Some(r#for) = substring_for {
+                add_if_placeholder(r#for, count)?;
+            }
+        }
+        sqlparser::ast::Expr::Trim {
+            expr,
+            trim_what,
+            trim_characters,
+            ..
+        } => {
+            // `trim_what` is written before `expr`: TRIM(BOTH ? FROM col)
+            if let Some(what) = trim_what {
+                add_if_placeholder(what, count)?;
+            }
+            add_if_placeholder(expr, count)?;
+            for e in trim_characters.iter().flatten() {
+                add_if_placeholder(e, count)?;
+            }
+        }
+        sqlparser::ast::Expr::Overlay {
+            expr,
+            overlay_what,
+            overlay_from,
+            overlay_for,
+        } => {
+            add_if_placeholder(expr, count)?;
+            add_if_placeholder(overlay_what, count)?;
+            add_if_placeholder(overlay_from, count)?;
+            if let Some(r#for) = overlay_for {
+                add_if_placeholder(r#for, count)?;
+            }
+        }
+        sqlparser::ast::Expr::MapAccess { column, keys } => {
+            add_if_placeholder(column, count)?;
+            for key in keys {
+                add_if_placeholder(key, count)?;
+            }
+        }
+        sqlparser::ast::Expr::ArrayIndex { obj, indexes } => {
+            add_if_placeholder(obj, count)?;
+            for index in indexes {
+                add_if_placeholder(index, count)?;
+            }
         }
         sqlparser::ast::Expr::AggregateExpressionWithFilter { expr, filter } => {
-            add_if_placeholder(expr, count);
-            add_if_placeholder(filter, count);
+            add_if_placeholder(expr, count)?;
+            add_if_placeholder(filter, count)?;
+        }
+        sqlparser::ast::Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            if let Some(operand) = operand {
+                add_if_placeholder(operand, count)?;
+            }
+            for (condition, result) in conditions.iter().zip(results) {
+                add_if_placeholder(condition, count)?;
+                add_if_placeholder(result, count)?;
+            }
+            if let Some(else_result) = else_result {
+                add_if_placeholder(else_result, count)?;
+            }
+        }
+        sqlparser::ast::Expr::Function(function) => {
+            for arg in &function.args {
+                if let sqlparser::ast::FunctionArg::Named {
+                    arg: sqlparser::ast::FunctionArgExpr::Expr(arg),
+                    ..
+                }
+                | sqlparser::ast::FunctionArg::Unnamed(
+                    sqlparser::ast::FunctionArgExpr::Expr(arg),
+                ) = arg
+                {
+                    add_if_placeholder(arg, count)?;
+                }
+            }
+            for order_by in &function.order_by {
+                add_if_placeholder(&order_by.expr, count)?;
+            }
+            if let Some(filter) = &function.filter {
+                add_if_placeholder(filter, count)?;
+            }
+            if let Some(sqlparser::ast::WindowType::WindowSpec(window)) = &function.over {
+                for e in &window.partition_by {
+                    add_if_placeholder(e, count)?;
+                }
+                for order_by in &window.order_by {
+                    add_if_placeholder(&order_by.expr, count)?;
+                }
+            }
+        }
+        sqlparser::ast::Expr::ListAgg(agg) => {
+            add_if_placeholder(&agg.expr, count)?;
+            if let Some(separator) = &agg.separator {
+                add_if_placeholder(separator, count)?;
+            }
+            if let Some(sqlparser::ast::ListAggOnOverflow::Truncate {
+                filler: Some(filler),
+                ..
+            }) = &agg.on_overflow
+            {
+                add_if_placeholder(filler, count)?;
+            }
+            for order_by in &agg.within_group {
+                add_if_placeholder(&order_by.expr, count)?;
+            }
+        }
+        sqlparser::ast::Expr::ArrayAgg(agg) => {
+            add_if_placeholder(&agg.expr, count)?;
+            for order_by in agg.order_by.iter().flatten() {
+                add_if_placeholder(&order_by.expr, count)?;
+            }
+            if let Some(limit) = &agg.limit {
+                add_if_placeholder(limit, count)?;
+            }
+        }
+        sqlparser::ast::Expr::GroupingSets(sets)
+        | sqlparser::ast::Expr::Cube(sets)
+        | sqlparser::ast::Expr::Rollup(sets) => {
+            for e in sets.iter().flatten() {
+                add_if_placeholder(e, count)?;
+            }
+        }
+        sqlparser::ast::Expr::Tuple(exprs)
+        | sqlparser::ast::Expr::Struct { values: exprs, .. }
+        | sqlparser::ast::Expr::Array(sqlparser::ast::Array { elem: exprs, .. }) => {
+            for e in exprs {
+                add_if_placeholder(e, count)?;
+            }
         }
-        sqlparser::ast::Expr::Case { .. } => todo!(),
         sqlparser::ast::Expr::Exists { subquery, .. } => {
-            *count = query_params(subquery, *count);
-        }
-        sqlparser::ast::Expr::Subquery(qry) => {
-            *count = query_params(qry, *count);
-        }
-        sqlparser::ast::Expr::OuterJoin(join) => add_if_placeholder(join, count),
-        sqlparser::ast::Expr::Array(sqlparser::ast::Array { elem, .. }) => {
-            for el in elem {
-                add_if_placeholder(el, count);
-            }
-        }
-        sqlparser::ast::Expr::Tuple(_) => todo!(),
-        sqlparser::ast::Expr::ArrayIndex { .. } => todo!(),
-        sqlparser::ast::Expr::Interval(_) => todo!(),
-        sqlparser::ast::Expr::Convert { .. } => todo!(),
-        sqlparser::ast::Expr::Cast { .. } => todo!(),
-        sqlparser::ast::Expr::TryCast { .. } => todo!(),
-        sqlparser::ast::Expr::SafeCast { .. } => todo!(),
-        sqlparser::ast::Expr::AtTimeZone { .. } => todo!(),
-        sqlparser::ast::Expr::Extract { .. } => todo!(),
-        sqlparser::ast::Expr::Ceil { .. } => todo!(),
-        sqlparser::ast::Expr::Floor { .. } => todo!(),
-        sqlparser::ast::Expr::Position { .. } => todo!(),
-        sqlparser::ast::Expr::Substring { .. } => todo!(),
-        sqlparser::ast::Expr::Trim { .. } => todo!(),
-        sqlparser::ast::Expr::Collate { .. } => todo!(),
-        sqlparser::ast::Expr::Nested(_) => todo!(),
-        sqlparser::ast::Expr::Function(_) => todo!(),
+            *count = query_params(subquery, *count)?;
+        }
+        sqlparser::ast::Expr::Subquery(qry) | sqlparser::ast::Expr::ArraySubquery(qry) => {
+            *count = query_params(qry, *count)?;
+        }
+        sqlparser::ast::Expr::OuterJoin(join) => add_if_placeholder(join, count)?,
 
         _ => (),
     }
+
+    Ok(())
+}
+
+/// Counts the `?` placeholders of an update or a delete.
+fn statement_params(stmt: &Statement) -> Result<usize, String> {
+    let mut len = 0;
+    match stmt {
+        Statement::Update {
+            table,
+            assignments,
+            from,
+            selection,
+            ..
+        } => {
+            len = table_params(table, len)?;
+            for assignment in assignments {
+                add_if_placeholder(&assignment.value, &mut len)?;
+            }
+            if let Some(from) = from {
+                len = table_params(from, len)?;
+            }
+            if let Some(selection) = selection {
+                add_if_placeholder(selection, &mut len)?;
+            }
+        }
+        Statement::Delete {
+            from,
+            using,
+            selection,
+            ..
+        } => {
+            let (sqlparser::ast::FromTable::WithFromKeyword(from)
+            | sqlparser::ast::FromTable::WithoutKeyword(from)) = from;
+            for table in from.iter().chain(using.iter().flatten()) {
+                len = table_params(table, len)?;
+            }
+            if let Some(selection) = selection {
+                add_if_placeholder(selection, &mut len)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(len)
+}
+
+/// Counts the `?` placeholders of `query` on top of the `other` ones before it.
+fn query_params(query: &Query, mut other: usize) -> Result<usize, String> {
+    for cte in query.with.iter().flat_map(|with| &with.cte_tables) {
+        other = query_params(&cte.query, other)?;
+    }
+    other = set_expr_params(&query.body, other)?;
+
+    let order_by = query.order_by.iter().map(|order_by| &order_by.expr);
+    let limits = query
+        .limit
+        .iter()
+        .chain(&query.limit_by)
+        .chain(query.offset.as_ref().map(|offset| &offset.value))
+        .chain(
+            query
+                .fetch
+                .as_ref()
+                .and_then(|fetch| fetch.quantity.as_ref()),
+        );
+    for expr in order_by.chain(limits) {
+        add_if_placeholder(expr, &mut other)?;
+    }
+    Ok(other)
+}
+
+/// Counts the `?` placeholders of `table` and of the tables joined to it, subqueries and
+/// join constraints included.
+fn table_params(table: &sqlparser::ast::TableWithJoins, mut other: usize) -> Result<usize, String> {
+    use sqlparser::ast::{JoinConstraint, JoinOperator, TableFactor};
+
+    let joins = table
+        .joins
+        .iter()
+        .map(|join| (&join.relation, Some(&join.join_operator)));
+    for (relation, operator) in std::iter::once((&table.relation, None)).chain(joins) {
+        other = match relation {
+            TableFactor::Derived { subquery, .. } => query_params(subquery, other)?,
+            TableFactor::NestedJoin {
+                table_with_joins, ..
+            } => table_params(table_with_joins, other)?,
+            _ => other,
+        };
+
+        if let Some(
+            JoinOperator::Inner(JoinConstraint::On(on))
+            | JoinOperator::LeftOuter(JoinConstraint::On(on))
+            | JoinOperator::RightOuter(JoinConstraint::On(on))
+            | JoinOperator::FullOuter(JoinConstraint::On(on))
+            | JoinOperator::LeftSemi(JoinConstraint::On(on))
+            | JoinOperator::RightSemi(JoinConstraint::On(on))
+            | JoinOperator::LeftAnti(JoinConstraint::On(on))
+            | JoinOperator::RightAnti(JoinConstraint::On(on)),
+        ) = operator
+        {
+            add_if_placeholder(on, &mut other)?;
+        }
+    }
+    Ok(other)
 }
 
-fn query_params(query: &Query, mut other: usize) -> usize {
-    match query.body.as_ref() {
+fn set_expr_params(body: &sqlparser::ast::SetExpr, mut other: usize) -> Result<usize, String> {
+    match body {
         sqlparser::ast::SetExpr::Select(ref select) => {
-            if let Some(selection) = &select.selection {
-                add_if_placeholder(selection, &mut other);
+            for item in &select.projection {
+                if let sqlparser::ast::SelectItem::UnnamedExpr(expr)
+                | sqlparser::ast::SelectItem::ExprWithAlias { expr, .. } = item
+                {
+                    add_if_placeholder(expr, &mut other)?;
+                }
+            }
+            for table in &select.from {
+                other = table_params(table, other)?;
+            }
+
+            let group_by = match &select.group_by {
+                sqlparser::ast::GroupByExpr::Expressions(exprs) => exprs.as_slice(),
+                sqlparser::ast::GroupByExpr::All => &[],
+            };
+            for expr in select
+                .selection
+                .iter()
+                .chain(group_by)
+                .chain(&select.having)
+            {
+                add_if_placeholder(expr, &mut other)?;
             }
-            other
+            Ok(other)
         }
         sqlparser::ast::SetExpr::Query(qry) => query_params(qry, other),
-        sqlparser::ast::SetExpr::SetOperation { .. } => todo!(),
-        sqlparser::ast::SetExpr::Values(_) => todo!(),
-        sqlparser::ast::SetExpr::Insert(_) => todo!(),
-        sqlparser::ast::SetExpr::Update(_) => todo!(),
-        sqlparser::ast::SetExpr::Table(_) => todo!(),
+        sqlparser::ast::SetExpr::SetOperation { left, right, .. } => {
+            let other = set_expr_params(left, other)?;
+            set_expr_params(right, other)
+        }
+        sqlparser::ast::SetExpr::Values(values) => {
+            for e in values.rows.iter().flatten() {
+                add_if_placeholder(e, &mut other)?;
+            }
+            Ok(other)
+        }
+        sqlparser::ast::SetExpr::Table(_) => Ok(other),
+        body @ (sqlparser::ast::SetExpr::Insert(_) | sqlparser::ast::SetExpr::Update(_)) => {
+            Err(format!("placeholders can't be counted inside `{body}` yet"))
+        }
     }
 }
 
-fn query_type(query: &Query) -> QueryType {
-    match *query.body {
-        sqlparser::ast::SetExpr::Select(_) => QueryType::SELECT,
-        sqlparser::ast::SetExpr::Insert(_) => QueryType::INSERT,
-        sqlparser::ast::SetExpr::Update(_) => QueryType::UPDATE,
+fn query_type(query: &Query) -> Option<QueryType> {
+    set_expr_type(&query.body)
+}
+
+fn set_expr_type(body: &sqlparser::ast::SetExpr) -> Option<QueryType> {
+    match body {
+        sqlparser::ast::SetExpr::Select(_)
+        | sqlparser::ast::SetExpr::Values(_)
+        | sqlparser::ast::SetExpr::Table(_) => Some(QueryType::SELECT),
+        sqlparser::ast::SetExpr::Insert(_) => Some(QueryType::INSERT),
+        sqlparser::ast::SetExpr::Update(_) => Some(QueryType::UPDATE),
         sqlparser::ast::SetExpr::Query(ref query) => query_type(query),
-        _ => todo!("make this return a result"),
+        // both sides of a UNION/EXCEPT/INTERSECT must return the same kind of rows
+        sqlparser::ast::SetExpr::SetOperation { left, right, .. } => {
+            let ty = set_expr_type(left)?;
+            (set_expr_type(right)? == ty).then_some(ty)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Diagnostic;
+
+    use super::expand::Expanders;
+    use crate::{compile_str, CompileOptions, SqlDialect};
+
+    /// Where the sql error of `source` points, as the text from there to the end of `source`.
+    fn sql_error_at(source: &str) -> &str {
+        let err = compile_str(source, &CompileOptions::default()).expect_err("the sql is invalid");
+        let label = err
+            .labels()
+            .and_then(|mut labels| labels.next())
+            .unwrap_or_else(|| panic!("{err:?} has no label"));
+
+        &source[label.offset()..]
+    }
+
+    #[test]
+    fn sql_errors_point_at_their_token() {
+        // `oops` is where each statement should have ended
+        let queries = [
+            "let _r = query! niku do select id from srm_projects p oops end",
+            "let _r = query! niku do\nselect id from srm_projects p oops\nend",
+            "let _r = query! niku do\n  select id from srm_projects p oops\nend",
+            "let _r = query! niku do\n\n\n        select id from srm_projects p oops\nend",
+            "let _r = query! niku do\n  select id\n\n    from srm_projects\n\t\t where id = 1 oops\nend",
+            "let x = 1\nlet _r = query! niku do\n  select id from srm_projects where id = ${x} oops\nend",
+            "if true then\n  for i in 1..2 do\n    query! niku do\n\n      update srm_projects\n         set name = 'a' oops\n    end\n  end\nend",
+        ];
+
+        for source in queries {
+            let at = sql_error_at(source);
+            assert!(at.starts_with("oops"), "{source} errors at {at:?}");
+        }
+    }
+
+    #[test]
+    fn sql_errors_point_at_their_token_at_any_depth() {
+        let blocks = [
+            ("if true then", "end"),
+            ("for i in 1..2 do", "end"),
+            ("do", "end"),
+            ("let f x = do", "end"),
+        ];
+
+        let indent = |level: usize| "  ".repeat(level);
+        for depth in 0..=blocks.len() {
+            let mut lines = vec![];
+            for (level, (open, _)) in blocks[..depth].iter().enumerate() {
+                lines.push(format!("{}{open}", indent(level)));
+            }
+            lines.extend([
+                format!("{}query! niku do", indent(depth)),
+                String::new(),
+                format!("{}select id", indent(depth + 1)),
+                format!("{}  from srm_projects p oops", indent(depth + 1)),
+                format!("{}end", indent(depth)),
+            ]);
+            for (level, (_, close)) in blocks[..depth].iter().enumerate().rev() {
+                lines.push(format!("{}{close}", indent(level)));
+            }
+            let source = lines.join("\n") + "\n";
+
+            let at = sql_error_at(&source);
+            assert!(
+                at.starts_with("oops"),
+                "at depth {depth}, {source} errors at {at:?}"
+            );
+        }
+    }
+
+    /// What building a script running `sql` with `args` says about its parameters, if it
+    /// doesn't build.
+    fn param_error(sql: &str, args: &str) -> Option<String> {
+        let source = format!("let _rows = query! niku do\n  {sql}\nend {args}\n");
+        let err = compile_str(&source, &CompileOptions::default()).err()?;
+        Some(
+            err.help()
+                .map_or_else(|| err.to_string(), |help| help.to_string()),
+        )
+    }
+
+    #[test]
+    fn counts_placeholders_outside_the_where_clause() {
+        let queries = [
+            "select substr(name, ?, 2) from srm_projects",
+            "select trim(? from name) from srm_projects",
+            "select cast(? as varchar(10)) from dual",
+            "select p.id from srm_projects p join srm_resources r on r.id = p.manager_id and r.is_active = ?",
+            "select id from (select id from srm_projects where is_active = ?) p",
+            "select manager_id from srm_projects group by manager_id having count(*) > ?",
+            "select id from srm_projects order by ?",
+            "select id from srm_projects offset ? rows",
+            "with active as (select id from srm_projects where is_active = ?) select id from active",
+            "update srm_projects set name = (select name from srm_resources where id = ?)",
+            "delete from srm_projects where id in (select id from odf_ca_project where obs = ?)",
+        ];
+
+        for sql in queries {
+            assert_eq!(param_error(sql, "1"), None, "{sql}");
+        }
+    }
+
+    #[test]
+    fn counts_the_placeholders_of_every_clause() {
+        let sql = "with a as (select id from srm_projects where code = ?) \
+                   select substr(name, ?, 2) from a join srm_projects p on p.id = a.id and p.x = ? \
+                   where p.y = ? group by name having count(*) > ? order by ?";
+
+        assert_eq!(param_error(sql, "1 2 3 4 5 6"), None);
+        assert_eq!(
+            param_error(sql, "1").as_deref(),
+            Some("expected 6 parameters but got 1")
+        );
+    }
+
+    /// What the warnings of `source` underline.
+    fn warned_at(source: &str) -> Vec<&str> {
+        let (_, warnings) = crate::parse(
+            "<input>",
+            source,
+            SqlDialect::default(),
+            &Expanders::default(),
+        )
+        .expect("the script parses");
+        warnings
+            .iter()
+            .filter_map(|warning| warning.labels()?.next())
+            .map(|label| &source[label.offset()..label.offset() + label.len()])
+            .collect()
+    }
+
+    #[test]
+    fn warnings_point_at_their_expression() {
+        let source = "for i in 1..3 do\n  if i > 1 then\n    let _x = 1 - \"a\"\n  end\nend\n";
+        assert_eq!(warned_at(source), ["1 - \"a\""]);
+
+        let source = "for i in 1..3 do\n  if 2 then\n    log! INFO f\"{i}\"\n  end\nend\n";
+        assert_eq!(warned_at(source), ["2"]);
+    }
+
+    #[test]
+    fn f_strings_escape_the_dollars_of_their_text() {
+        let cases = [
+            // Jelly reads `$$` as a `$`, in an attribute it's kept apart from the `${`
+            (
+                "log! INFO f\"Total: ${total}\"",
+                "message=\"Total: $$${total}\"",
+            ),
+            ("log! INFO f\"${total}\"", "message=\"$$${total}\""),
+            (
+                "print f\"Total: ${total}\"",
+                "print(&quot;Total: $${total}&quot;)",
+            ),
+            // a `${` that isn't an interpolation is text
+            (
+                "log! INFO f\"a ${ b {total}\"",
+                "message=\"a $${ b ${total}\"",
+            ),
+            (
+                "print f\"a ${ b {total}\"",
+                "print(&quot;a ${ b ${total}&quot;)",
+            ),
+            ("log! INFO f\"{total}$\"", "message=\"${total}$\""),
+        ];
+
+        for (stmt, expected) in cases {
+            let source = format!("let total = 1\n{stmt}\n");
+            let xml = compile_str(&source, &CompileOptions::default())
+                .unwrap_or_else(|err| panic!("{err:?}"));
+            assert!(xml.contains(expected), "{stmt} builds into {xml}");
+        }
     }
 }
@@ -0,0 +1,315 @@
+//! Detection of the passwords and tokens written as literals in the constructs that send
+//! them elsewhere: soap and http requests, XOG logins, and the tags of the email and FTP
+//! libraries. A literal is reported if what it's passed as is named like a secret, such as
+//! a `password` attribute or the token of `auth_bearer`, or if it looks random enough to be
+//! a key.
+//!
+//! This runs over the parsed script, before its macros are expanded. A `let` of a literal
+//! is followed to the requests it's passed to, the warnings then point at the `let`.
+
+use std::{collections::HashMap, sync::Arc};
+
+use xml::reader::XmlEvent;
+
+use super::ast::{Call, Expr, Name, Node, Span, Spanned, Stmt, Value};
+use crate::errors::Warning;
+
+/// The words of the names of secrets, matched anywhere in a name regardless of case.
+const KEYWORDS: [&str; 10] = [
+    "password",
+    "passwd",
+    "pwd",
+    "secret",
+    "token",
+    "apikey",
+    "api_key",
+    "api-key",
+    "credential",
+    "authorization",
+];
+
+/// The namespaces of the tags whose attributes and text are checked.
+const LIBRARIES: [&str; 2] = ["jelly:email", "jelly:com.niku.union.gel.FTPTagLibrary"];
+
+/// How few characters a literal can have and still look random.
+const MIN_RANDOM_LENGTH: usize = 20;
+
+/// How many bits of entropy per character a literal has to have to look random.
+const MIN_ENTROPY: f64 = 3.5;
+
+/// Checks the top-level `nodes` for secrets written as literals.
+#[must_use]
+pub fn check(nodes: &[Spanned<Node>]) -> Vec<Warning> {
+    let mut checker = Checker::default();
+    for Spanned { node, span } in nodes {
+        checker.at = *span;
+        match node {
+            Node::Stmt(stmt) => checker.stmt(stmt),
+            Node::Expr(expr) => checker.expr(expr),
+        }
+    }
+    checker.warnings
+}
+
+#[derive(Default)]
+struct Checker {
+    warnings: Vec<Warning>,
+    /// The top-level statement being checked, the warnings point at it when the literal
+    /// has no span of its own.
+    at: Span,
+    /// The literals the names were last bound to, with where they were written.
+    literals: HashMap<String, (Arc<str>, Span)>,
+}
+
+impl Checker {
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let(
+                name,
+                expr @ Expr::Value {
+                    value: Value::Str(text),
+                    ..
+                },
+            ) => {
+                let at = expr.span().unwrap_or(self.at);
+                self.literals
+                    .insert(name.to_string(), (Arc::clone(text), at));
+            }
+            Stmt::Let(name, expr) => {
+                self.literals.remove(name.as_str());
+                self.expr(expr);
+            }
+            Stmt::Expr { expr } => self.expr(expr),
+            Stmt::Block(body)
+            | Stmt::Whitespace(body)
+            | Stmt::Catch { body, .. }
+            | Stmt::ForEach { body, .. }
+            | Stmt::While { body, .. }
+            | Stmt::WriteFile { body, .. }
+            | Stmt::Transaction { body, .. } => self.body(body),
+            Stmt::If { body, alt, .. } => {
+                self.body(body);
+                if let Some(alt) = alt {
+                    self.body(alt);
+                }
+            }
+            Stmt::Xml(events) => self.xml(events, false),
+            _ => {}
+        }
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            self.stmt(stmt);
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Func { body, .. } => self.body(body),
+            Expr::XogLogin { password, .. } => {
+                self.named("the password of `xog_login!`", password);
+            }
+            Expr::Soap {
+                header,
+                body,
+                on_fault,
+                ..
+            } => {
+                for events in header.iter().chain(body) {
+                    self.xml(events, true);
+                }
+                if let Some(on_fault) = on_fault {
+                    self.expr(on_fault);
+                }
+            }
+            Expr::Http { url, body, .. } => {
+                self.url(url);
+                for stmt in body {
+                    self.directive(stmt);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Checks a directive of an `http!` body.
+    fn directive(&mut self, stmt: &Stmt) {
+        let Stmt::Expr {
+            expr:
+                Expr::Call(Call {
+                    name: Name::Ident(directive),
+                    args,
+                }),
+        } = stmt
+        else {
+            return self.stmt(stmt);
+        };
+
+        match (directive.as_str(), args.as_slice()) {
+            ("auth_basic", [_, password]) => {
+                self.named("the password of `auth_basic`", password);
+            }
+            ("auth_bearer", [token]) => self.named("the token of `auth_bearer`", token),
+            ("headers" | "query" | "form" | "json", [Expr::Dict(dict)]) => {
+                for (key, value) in dict.iter() {
+                    let what = format!("the `{key}` of `{directive}`");
+                    if is_secret_name(key) {
+                        self.named(&what, value);
+                    } else {
+                        self.unnamed(&what, value);
+                    }
+                }
+            }
+            ("on_error", [Expr::Func { body, .. }]) => self.body(body),
+            _ => {}
+        }
+    }
+
+    /// Checks the query of the `url` of a request.
+    fn url(&mut self, url: &Expr) {
+        let Some((url, at)) = self.literal(url) else {
+            return;
+        };
+        let Some((_, query)) = url.split_once('?') else {
+            return;
+        };
+
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            if is_secret_name(key) && !value.is_empty() {
+                self.warn(format!("the `{key}` of the url"), at);
+            }
+        }
+    }
+
+    /// Checks the tags of `events`, all of them if `all`, or else the ones of [`LIBRARIES`].
+    fn xml(&mut self, events: &[XmlEvent], all: bool) {
+        let mut open = vec![];
+        for event in events {
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let checked = all
+                        || name
+                            .namespace
+                            .as_deref()
+                            .is_some_and(|uri| LIBRARIES.contains(&uri));
+                    open.push(checked.then(|| name.local_name.clone()));
+                    if !checked {
+                        continue;
+                    }
+
+                    for attribute in attributes {
+                        let what = format!(
+                            "the `{}` attribute of `<{}>`",
+                            attribute.name.local_name, name.local_name
+                        );
+                        let value = &attribute.value;
+                        if is_literal(value)
+                            && (is_secret_name(&attribute.name.local_name) && !value.is_empty()
+                                || looks_random(value))
+                        {
+                            self.warn(what, self.at);
+                        }
+                    }
+                }
+                XmlEvent::EndElement { .. } => {
+                    open.pop();
+                }
+                XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                    let Some(Some(tag)) = open.last() else {
+                        continue;
+                    };
+                    let text = text.trim();
+                    if is_literal(text)
+                        && (is_secret_name(tag) && !text.is_empty() || looks_random(text))
+                    {
+                        self.warn(format!("the text of `<{tag}>`"), self.at);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Reports `expr` if it's a literal, as it's passed as a secret.
+    fn named(&mut self, what: &str, expr: &Expr) {
+        if let Some((text, at)) = self.literal(expr) {
+            if !text.is_empty() {
+                self.warn(what.to_string(), at);
+            }
+        }
+    }
+
+    /// Reports `expr` if it's a literal that looks like a secret.
+    fn unnamed(&mut self, what: &str, expr: &Expr) {
+        if let Some((text, at)) = self.literal(expr) {
+            let credentials = ["Basic ", "Bearer "]
+                .iter()
+                .find_map(|scheme| text.strip_prefix(scheme));
+            if credentials.is_some_and(|credentials| !credentials.is_empty()) || looks_random(&text)
+            {
+                self.warn(what.to_string(), at);
+            }
+        }
+    }
+
+    /// The literal `expr` is, or is the name of, with where it was written.
+    fn literal(&self, expr: &Expr) -> Option<(Arc<str>, Span)> {
+        let (text, at) = match expr {
+            Expr::Value {
+                value: Value::Str(text),
+                ..
+            } => (Arc::clone(text), expr.span().unwrap_or(self.at)),
+            Expr::Ident(Name::Ident(name)) => self.literals.get(name.as_str()).cloned()?,
+            _ => return None,
+        };
+        is_literal(&text).then_some((text, at))
+    }
+
+    fn warn(&mut self, what: String, at: Span) {
+        self.warnings.push(Warning::HardcodedSecret {
+            what,
+            at: at.into(),
+        });
+    }
+}
+
+/// Whether `text` doesn't read any variables, the ones that do are left alone.
+fn is_literal(text: &str) -> bool {
+    !text.contains("${")
+}
+
+fn is_secret_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    KEYWORDS.iter().any(|keyword| name.contains(keyword))
+}
+
+/// Whether `text` is a long word of letters and digits, random enough to be a key.
+#[allow(clippy::cast_precision_loss)]
+fn looks_random(text: &str) -> bool {
+    let length = text.chars().count();
+    if length < MIN_RANDOM_LENGTH
+        || !text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+/=_-.".contains(c))
+        || !text.chars().any(|c| c.is_ascii_digit())
+        || !text.chars().any(|c| c.is_ascii_alphabetic())
+    {
+        return false;
+    }
+
+    let mut counts = HashMap::new();
+    for c in text.chars() {
+        *counts.entry(c).or_insert(0_usize) += 1;
+    }
+    let entropy: f64 = counts
+        .values()
+        .map(|&count| {
+            let p = count as f64 / length as f64;
+            -p * p.log2()
+        })
+        .sum();
+    entropy >= MIN_ENTROPY
+}
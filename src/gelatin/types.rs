@@ -0,0 +1,615 @@
+//! Inference of the types of the expressions of a script, to report the ones used where
+//! they can't work, such as a string compared with an int, before Clarity runs the script.
+//!
+//! Types are only known from literals, queries and a few java builtins. Everything else is
+//! [`Type::Unknown`], which fits anywhere, so only the mistakes that are certain are
+//! reported.
+
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use super::ast::{
+    xml_vars, Call, Expr, InfixOp, Name, Node, ParamType, QueryType, Span, Spanned, Stmt, Value,
+};
+use crate::errors::Warning;
+
+/// The type of the value of an expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Null,
+    Dict,
+    List,
+    /// An instance of the java class of that name.
+    Object(Arc<str>),
+    /// The rows bound by a select.
+    QueryResult,
+    /// A value whose type can't be told.
+    Unknown,
+}
+
+impl Type {
+    const fn is_numeric(&self) -> bool {
+        matches!(self, Self::Int | Self::Float)
+    }
+
+    /// Whether the type is one of gelatin's own, the methods of objects can return anything.
+    const fn is_known(&self) -> bool {
+        !matches!(self, Self::Object(_) | Self::Unknown)
+    }
+
+    /// Whether a `for` can't iterate over a value of the type.
+    const fn is_scalar(&self) -> bool {
+        matches!(self, Self::Int | Self::Float | Self::Bool | Self::Null)
+    }
+
+    /// Whether a value of the type can stand for a string in an attribute.
+    const fn is_textual(&self) -> bool {
+        !matches!(
+            self,
+            Self::Dict | Self::List | Self::QueryResult | Self::Null
+        )
+    }
+
+    /// The type of the numbers `self` and `other` make.
+    const fn widen(&self, other: &Self) -> Self {
+        if matches!(self, Self::Float) || matches!(other, Self::Float) {
+            Self::Float
+        } else {
+            Self::Int
+        }
+    }
+}
+
+/// Checks the types of the top-level `nodes`, returning a warning for each mismatch.
+#[must_use]
+pub fn check(nodes: &[Spanned<Node>]) -> Vec<Warning> {
+    let mut checker = Checker::default();
+    for Spanned { node, span } in nodes {
+        checker.at = *span;
+        match node {
+            Node::Stmt(stmt) => checker.stmt(stmt),
+            Node::Expr(expr) => {
+                checker.type_of(expr);
+            }
+        }
+    }
+    checker.warnings
+}
+
+#[derive(Default)]
+struct Checker {
+    /// The type of each name bound so far.
+    types: HashMap<String, Type>,
+    warnings: Vec<Warning>,
+    /// The top-level statement being checked, the warnings point at it when the expression
+    /// has no span of its own.
+    at: Span,
+}
+
+impl Checker {
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Let(name, Expr::Func { params, body }) => {
+                let mut names: Vec<_> = params.iter().map(ToString::to_string).collect();
+                for stmt in body {
+                    assigned(stmt, &mut names);
+                }
+                let outer = self.save(names);
+                for param in params {
+                    self.types.insert(param.to_string(), Type::Unknown);
+                }
+                self.body(body);
+                self.restore(outer);
+                self.types.insert(name.to_string(), Type::Unknown);
+            }
+            Stmt::Let(name, expr) => {
+                let ty = self.type_of(expr);
+                self.types.insert(name.to_string(), ty);
+            }
+            Stmt::Expr { expr } | Stmt::Out { value: expr } => {
+                self.type_of(expr);
+            }
+            Stmt::SetProperties { props, .. } => {
+                for value in props.values() {
+                    self.type_of(value);
+                }
+            }
+            Stmt::Block(body) | Stmt::Whitespace(body) | Stmt::Transaction { body, .. } => {
+                self.body(body);
+            }
+            Stmt::Catch { name, body } => {
+                self.types.insert(name.to_string(), Type::Unknown);
+                self.body(body);
+            }
+            Stmt::WriteFile { path, body, .. } => {
+                self.expect_text(path, "the path of a file");
+                self.body(body);
+            }
+            Stmt::ForEach { var, items, body } => {
+                let items_ty = self.type_of(items);
+                if items_ty.is_scalar() {
+                    self.mismatch(
+                        items,
+                        format!("iterating over {items_ty}, which isn't a list"),
+                    );
+                }
+
+                let item = match (items, items_ty) {
+                    (Expr::Range { .. }, _) => Type::Int,
+                    (_, Type::Str) => Type::Str,
+                    _ => Type::Unknown,
+                };
+                self.in_loop(stmt, body, |checker| {
+                    checker.types.insert(var.to_string(), item);
+                });
+            }
+            Stmt::While { test, body } => self.in_loop(stmt, body, |checker| {
+                checker.test(test);
+            }),
+            Stmt::If { test, body, alt } => {
+                self.test(test);
+
+                let mut names = vec![];
+                assigned(stmt, &mut names);
+                let before = self.save(names);
+                self.body(body);
+                let then = self.restore(before);
+                if let Some(alt) = alt {
+                    self.body(alt);
+                }
+                self.join(then);
+            }
+            Stmt::Param { name, r#type, .. } => {
+                let ty = match r#type {
+                    Some(ParamType::String) => Type::Str,
+                    Some(ParamType::Int) => Type::Int,
+                    Some(ParamType::Float) => Type::Float,
+                    Some(ParamType::Bool) => Type::Bool,
+                    Some(ParamType::Date) => Type::Object("java.util.Date".into()),
+                    None => Type::Unknown,
+                };
+                self.types.insert(name.to_string(), ty);
+            }
+            Stmt::Xml(events) => {
+                for var in xml_vars(events) {
+                    self.types.insert(var.to_string(), Type::Unknown);
+                }
+            }
+            Stmt::Alias { .. }
+            | Stmt::Namespace { .. }
+            | Stmt::Log { .. }
+            | Stmt::Comment(_)
+            | Stmt::Template { .. } => {}
+        }
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            self.stmt(stmt);
+        }
+    }
+
+    /// Checks the `body` of the loop `stmt` after `enter`, the names it sets may hold the
+    /// values of the previous iteration, and after the loop the ones from before it.
+    fn in_loop(&mut self, stmt: &Stmt, body: &[Stmt], enter: impl FnOnce(&mut Self)) {
+        let mut names = vec![];
+        assigned(stmt, &mut names);
+        let before = self.save(names.clone());
+        for name in names {
+            self.types.insert(name, Type::Unknown);
+        }
+
+        enter(self);
+        self.body(body);
+        self.join(before);
+    }
+
+    /// The types of `names`, the only ones the statements setting them change. Saving them
+    /// rather than all the types keeps checking linear in the names a script has.
+    fn save(&self, mut names: Vec<String>) -> Vec<(String, Option<Type>)> {
+        names.sort_unstable();
+        names.dedup();
+        names
+            .into_iter()
+            .map(|name| {
+                let ty = self.types.get(&name).cloned();
+                (name, ty)
+            })
+            .collect()
+    }
+
+    /// Puts back the `saved` types, returning the ones they replace.
+    fn restore(&mut self, saved: Vec<(String, Option<Type>)>) -> Vec<(String, Option<Type>)> {
+        saved
+            .into_iter()
+            .map(|(name, ty)| {
+                let replaced = match ty {
+                    Some(ty) => self.types.insert(name.clone(), ty),
+                    None => self.types.remove(&name),
+                };
+                (name, replaced)
+            })
+            .collect()
+    }
+
+    /// Forgets the types of the names that are different in `other`, which may be the ones
+    /// set instead.
+    fn join(&mut self, other: Vec<(String, Option<Type>)>) {
+        for (name, other) in other {
+            let Some(other) = other else {
+                continue;
+            };
+            match self.types.get_mut(&name) {
+                Some(ty) if *ty != other => *ty = Type::Unknown,
+                Some(_) => {}
+                None => {
+                    self.types.insert(name, other);
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn type_of(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Value {
+                value: Value::Int(_),
+                ..
+            } => Type::Int,
+            Expr::Value {
+                value: Value::Bool(_),
+                ..
+            } => Type::Bool,
+            Expr::Value {
+                value: Value::Nothing,
+                ..
+            } => Type::Null,
+            // a string that is a single EL expression has the value of the expression
+            Expr::Value {
+                value: Value::Str(text),
+                ..
+            } if is_expression(text) => Type::Unknown,
+            Expr::Value {
+                value: Value::Str(_),
+                ..
+            }
+            | Expr::XPath { .. } => Type::Str,
+            Expr::Ident(Name::Ident(ident)) => self
+                .types
+                .get(ident.as_str())
+                .cloned()
+                .unwrap_or(Type::Unknown),
+            Expr::Infix { lhs, op, rhs, .. } => {
+                let lhs_ty = self.type_of(lhs);
+                let rhs_ty = self.type_of(rhs);
+                self.infix(expr, &lhs_ty, op, &rhs_ty)
+            }
+            Expr::Call(Call { name, args }) => {
+                self.types_of(args);
+                match name {
+                    Name::Dotted { attrs, .. } => attrs
+                        .last()
+                        .map_or(Type::Unknown, |method| method_type(&method.to_string())),
+                    Name::Ident(_) => Type::Unknown,
+                }
+            }
+            Expr::Static(Call { name, args }) => {
+                self.types_of(args);
+                static_type(name, static_method_type)
+            }
+            Expr::StaticField(name) => static_type(name, static_field_type),
+            Expr::Instance { class, args } => {
+                self.types_of(args);
+                instance_type(&class.to_string())
+            }
+            Expr::Invoke { method, args, .. } => {
+                for arg in args {
+                    self.type_of(&arg.value);
+                }
+                method_type(method.as_str())
+            }
+            Expr::Range { .. } => Type::List,
+            Expr::Named { value, .. } => self.type_of(value),
+            Expr::Dict(dict) => {
+                for value in dict.values() {
+                    self.type_of(value);
+                }
+                Type::Dict
+            }
+            Expr::Query {
+                r#type,
+                params,
+                max_rows,
+                start_row,
+                ..
+            } => {
+                for param in params {
+                    self.type_of(&param.value);
+                }
+                for expr in max_rows.iter().chain(start_row) {
+                    self.type_of(expr);
+                }
+                if *r#type == QueryType::SELECT {
+                    Type::QueryResult
+                } else {
+                    Type::Int
+                }
+            }
+            Expr::Http { url, body, .. } => {
+                self.expect_text(url, "a url");
+                self.body(body);
+                Type::Unknown
+            }
+            Expr::XogLogin {
+                endpoint,
+                username,
+                password,
+            } => {
+                self.expect_text(endpoint, "the endpoint of XOG");
+                self.expect_text(username, "a username");
+                self.expect_text(password, "a password");
+                Type::Str
+            }
+            Expr::ReadLines { path } => {
+                self.expect_text(path, "the path of a file");
+                Type::List
+            }
+            Expr::Csv {
+                path, delimiter, ..
+            } => {
+                self.expect_text(path, "the path of a file");
+                self.expect_text(delimiter, "a delimiter");
+                Type::List
+            }
+            Expr::Value {
+                value: Value::Unit, ..
+            }
+            | Expr::Ident(Name::Dotted { .. })
+            | Expr::Func { .. }
+            | Expr::Alias(_)
+            | Expr::Batch(_)
+            | Expr::Json { .. }
+            | Expr::JsonPath { .. }
+            | Expr::XogLogout { .. }
+            | Expr::Soap { .. } => Type::Unknown,
+        }
+    }
+
+    fn types_of(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            self.type_of(expr);
+        }
+    }
+
+    /// The type of `lhs op rhs`, `expr`.
+    fn infix(&mut self, expr: &Expr, lhs: &Type, op: &InfixOp, rhs: &Type) -> Type {
+        let known = lhs.is_known() && rhs.is_known();
+        match op {
+            InfixOp::Eq | InfixOp::Neq => {
+                let comparable = lhs == rhs
+                    || (lhs.is_numeric() && rhs.is_numeric())
+                    || matches!(lhs, Type::Null)
+                    || matches!(rhs, Type::Null);
+                if known && !comparable {
+                    self.mismatch(expr, format!("comparing {lhs} with {rhs}"));
+                }
+                Type::Bool
+            }
+            InfixOp::Lt | InfixOp::Gt | InfixOp::Lte | InfixOp::Gte => {
+                let ordered = (lhs.is_numeric() && rhs.is_numeric())
+                    || (matches!(lhs, Type::Str) && matches!(rhs, Type::Str));
+                if known && !ordered {
+                    self.mismatch(expr, format!("ordering {lhs} and {rhs}"));
+                }
+                Type::Bool
+            }
+            InfixOp::And | InfixOp::Or => Type::Bool,
+            InfixOp::Add if matches!(lhs, Type::Str) || matches!(rhs, Type::Str) => {
+                if known && !(lhs.is_textual() && rhs.is_textual()) {
+                    self.mismatch(expr, format!("adding {lhs} and {rhs}"));
+                }
+                Type::Str
+            }
+            InfixOp::Add | InfixOp::Sub | InfixOp::Mul | InfixOp::Div => {
+                if lhs.is_numeric() && rhs.is_numeric() {
+                    return lhs.widen(rhs);
+                }
+                let operand = [lhs, rhs]
+                    .into_iter()
+                    .find(|ty| ty.is_known() && !ty.is_numeric());
+                if let Some(operand) = operand {
+                    self.mismatch(expr, format!("`{op}` on {operand}, which isn't a number"));
+                }
+                Type::Unknown
+            }
+        }
+    }
+
+    /// Reports the condition `test` and the operands of its `and`s and `or`s that aren't
+    /// bools.
+    fn test(&mut self, test: &Expr) {
+        if let Expr::Infix {
+            lhs,
+            op: InfixOp::And | InfixOp::Or,
+            rhs,
+            ..
+        } = test
+        {
+            self.test(lhs);
+            self.test(rhs);
+            return;
+        }
+
+        let ty = self.type_of(test);
+        if !ty.is_known() || matches!(ty, Type::Bool) {
+            return;
+        }
+
+        let subject = match test {
+            Expr::Ident(name) => name.to_string(),
+            _ => "value".to_string(),
+        };
+        let fix = match ty {
+            Type::QueryResult => format!("{subject}.rowCount > 0"),
+            Type::List => format!("{subject}.size () > 0"),
+            Type::Int | Type::Float => format!("{subject} != 0"),
+            _ => format!("{subject} != null"),
+        };
+        let span = test.span();
+        let whole = match test {
+            Expr::Ident(name @ Name::Ident(_)) if span.is_some() => Some(name.to_string()),
+            _ => None,
+        };
+        self.warnings.push(Warning::NonBooleanTest {
+            ty: ty.to_string(),
+            fix,
+            at: span.unwrap_or(self.at).into(),
+            test: whole,
+        });
+    }
+
+    /// Reports `expr` if it's a value that can't be written as the string `what` is.
+    fn expect_text(&mut self, expr: &Expr, what: &str) {
+        let ty = self.type_of(expr);
+        if !ty.is_textual() {
+            self.mismatch(expr, format!("{what} has to be a string, not {ty}"));
+        }
+    }
+
+    fn mismatch(&mut self, expr: &Expr, message: String) {
+        let at = expr.span().unwrap_or(self.at);
+        self.warnings.push(Warning::TypeMismatch {
+            message,
+            at: at.into(),
+        });
+    }
+}
+
+/// The names the `let`s, `catch`es, `for`s, parameters and tags of `stmt` set.
+fn assigned(stmt: &Stmt, names: &mut Vec<String>) {
+    match stmt {
+        Stmt::Let(name, _) | Stmt::Param { name, .. } => names.push(name.to_string()),
+        Stmt::Xml(events) => names.extend(xml_vars(events).map(ToString::to_string)),
+        Stmt::Catch { name, body }
+        | Stmt::ForEach {
+            var: name, body, ..
+        } => {
+            names.push(name.to_string());
+            for stmt in body {
+                assigned(stmt, names);
+            }
+        }
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::While { body, .. }
+        | Stmt::WriteFile { body, .. }
+        | Stmt::Transaction { body, .. } => {
+            for stmt in body {
+                assigned(stmt, names);
+            }
+        }
+        Stmt::If { body, alt, .. } => {
+            for stmt in body.iter().chain(alt.iter().flatten()) {
+                assigned(stmt, names);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether `text` is a single `${}` EL expression.
+fn is_expression(text: &str) -> bool {
+    text.strip_prefix("${")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .is_some_and(|inner| !inner.contains('}'))
+}
+
+/// The type of the member of `name`, `Class.member`, of java's own classes.
+fn static_type(name: &Name, member_type: fn(&str, &str) -> Type) -> Type {
+    match name {
+        Name::Dotted { parent, attrs } => attrs.last().map_or(Type::Unknown, |member| {
+            member_type(java_class(&parent.to_string()), &member.to_string())
+        }),
+        Name::Ident(_) => Type::Unknown,
+    }
+}
+
+/// The name of `class` without the package, if it's one of `java.lang` or `java.util`.
+fn java_class(class: &str) -> &str {
+    class
+        .strip_prefix("java.lang.")
+        .or_else(|| class.strip_prefix("java.util."))
+        .unwrap_or(class)
+}
+
+fn static_method_type(class: &str, method: &str) -> Type {
+    match (class, method) {
+        ("Integer", "parseInt" | "valueOf")
+        | ("Long", "parseLong" | "valueOf")
+        | ("Math", "round")
+        | ("System", "currentTimeMillis" | "nanoTime") => Type::Int,
+        ("Double", "parseDouble" | "valueOf")
+        | ("Float", "parseFloat" | "valueOf")
+        | ("Math", "sqrt" | "pow" | "random" | "floor" | "ceil") => Type::Float,
+        ("Boolean", "parseBoolean" | "valueOf") => Type::Bool,
+        ("String", "valueOf" | "format" | "join") | ("System", "getProperty" | "getenv") => {
+            Type::Str
+        }
+        ("Arrays", "asList") | ("Collections", "emptyList") => Type::List,
+        ("Collections", "emptyMap") => Type::Dict,
+        ("Calendar", "getInstance") => Type::Object("java.util.Calendar".into()),
+        _ => Type::Unknown,
+    }
+}
+
+fn static_field_type(class: &str, field: &str) -> Type {
+    match (class, field) {
+        ("Integer" | "Long", "MAX_VALUE" | "MIN_VALUE") => Type::Int,
+        ("Math", "PI" | "E") => Type::Float,
+        ("Boolean", "TRUE" | "FALSE") => Type::Bool,
+        _ => Type::Unknown,
+    }
+}
+
+fn instance_type(class: &str) -> Type {
+    match java_class(class) {
+        "HashMap" | "LinkedHashMap" | "TreeMap" | "Hashtable" => Type::Dict,
+        "ArrayList" | "LinkedList" | "Vector" | "HashSet" | "LinkedHashSet" | "TreeSet" => {
+            Type::List
+        }
+        "String" => Type::Str,
+        _ => Type::Object(class.into()),
+    }
+}
+
+/// The type the method `method` of the common java classes returns.
+fn method_type(method: &str) -> Type {
+    match method {
+        "size" | "length" | "indexOf" | "lastIndexOf" | "compareTo" | "hashCode" => Type::Int,
+        "toString" | "trim" | "toUpperCase" | "toLowerCase" | "substring" | "concat"
+        | "replace" | "replaceAll" => Type::Str,
+        "equals" | "equalsIgnoreCase" | "isEmpty" | "contains" | "containsKey" | "startsWith"
+        | "endsWith" | "matches" => Type::Bool,
+        _ => Type::Unknown,
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int => write!(f, "an int"),
+            Self::Float => write!(f, "a float"),
+            Self::Bool => write!(f, "a bool"),
+            Self::Str => write!(f, "a string"),
+            Self::Null => write!(f, "null"),
+            Self::Dict => write!(f, "a dict"),
+            Self::List => write!(f, "a list"),
+            Self::Object(class) => write!(f, "a `{class}`"),
+            Self::QueryResult => write!(f, "the rows of a query"),
+            Self::Unknown => write!(f, "a value"),
+        }
+    }
+}
@@ -1,89 +1,305 @@
-#![feature(iter_intersperse, box_patterns)]
 #![warn(clippy::pedantic, clippy::nursery)]
+#[cfg(feature = "fs")]
+mod cli;
+#[cfg(feature = "fs")]
+mod config;
+#[cfg(feature = "fs")]
+mod deploy;
+mod dialect;
+mod diff;
 mod errors;
 mod gelatin;
+#[cfg(feature = "fs")]
+pub mod testing;
 mod transpiler;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-use clap::{Parser as ClapParser, ValueEnum};
-use gelatin::{ast::Node, Parser};
-use miette::IntoDiagnostic;
-use sqlparser::dialect::{GenericDialect, MsSqlDialect, PostgreSqlDialect};
-use std::{io, path::PathBuf};
-use transpiler::Transpiler;
+use clap::ValueEnum;
+#[cfg(feature = "fs")]
+pub use cli::{
+    Args, Build, CheckArgs, Command, DeployArgs, DiffArgs, DocArgs, DocFormat, Emit, ErrorFormat,
+    FixArgs, FmtArgs, GraphArgs, GraphFormat, LintArgs, Lints, ReplArgs, Runtime, Wrap,
+};
+#[cfg(feature = "fs")]
+pub use config::LintConfig;
+#[cfg(feature = "fs")]
+pub use deploy::{deploy, Endpoint, XogOutput};
+use dialect::OracleDialect;
+pub use diff::{diff, Change, Difference};
+pub use errors::{Fix, Level, Warning, WarningCode};
+pub use gelatin::{
+    ast,
+    expand::{Expander, Expanders},
+};
+use gelatin::{
+    ast::{Node, Spanned},
+    dce, hoist, intern, secrets, types, Parser,
+};
+use miette::{IntoDiagnostic, NamedSource};
+#[cfg(feature = "fs")]
+use miette::{SourceOffset, SourceSpan};
+use sqlparser::dialect::{Dialect, GenericDialect, MsSqlDialect, MySqlDialect, PostgreSqlDialect};
+use std::io;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use transpiler::{layout::split_attributes, Backend, Transpiler};
+pub use transpiler::{ClassPolicy, Snippet};
 
-#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub enum SqlDialect {
     /// A generic SQL dialect.
+    #[default]
     Generic,
     /// Postgres SQL dialect.
     Pg,
     /// SQL Server dialect.
     Mssql,
+    /// Oracle dialect, with `(+)` outer joins.
+    Oracle,
+    /// `MySQL` and `MariaDB` dialect.
+    Mysql,
 }
 
-#[derive(Debug, ClapParser)]
-#[command(version, about)]
-pub struct Args {
-    file: PathBuf,
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SqlFormat {
+    /// The parsed query written back on one line.
+    #[default]
+    Compact,
+    /// The parsed query with each clause on its own line.
+    Pretty,
+    /// The query as written in the script.
+    Verbatim,
+}
 
-    /// Where to output the GEL script to.
-    #[arg(short, long)]
-    output: Option<PathBuf>,
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Target {
+    /// A GEL script for Clarity.
+    #[default]
+    Gel,
+    /// A plain Apache Jelly script, queries run on the `javax.sql.DataSource` the runner
+    /// binds to `niku` or `datawarehouse`.
+    Jelly,
+}
 
-    /// Whether to prettify the output or not.
-    #[arg(short, long)]
+/// The Clarity a GEL script runs on, which may not allow all of the tags it's written with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Platform {
+    /// Clarity `SaaS`, whose scripts can't reach the file system and can only use the classes
+    /// of some packages.
+    Saas,
+    /// An on-premise Clarity, of a version if it's known.
+    OnPrem(Option<Version>),
+}
+
+/// A version of Clarity, such as `16.1.2`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// `\n`.
+    #[default]
+    Lf,
+    /// `\r\n`.
+    Crlf,
+}
+
+/// How the xml of a script is laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    /// Whether to write a tag per line, indented.
     pub prettify: bool,
+    /// What indents a level of tags, two spaces by default.
+    pub indent: String,
+    pub line_ending: LineEnding,
+    /// Whether to write each attribute of tags with several on its own line, a level
+    /// deeper than the tag.
+    pub attrs_per_line: bool,
+    /// Whether to write CDATA sections as escaped text.
+    pub escape_cdata: bool,
+}
 
+/// How the transpiler writes its output.
+#[derive(Debug, Clone, Default)]
+pub struct TranspileOptions<'a> {
+    pub layout: Layout,
+    /// The source file to credit in a header comment.
+    pub provenance: Option<&'a str>,
+    /// The source file to point at before each top-level tag.
+    pub source_map: Option<&'a str>,
+    /// `(prefix, uri)` namespaces to declare on the script besides the ones it uses.
+    pub namespaces: &'a [(String, String)],
+    pub sql_format: SqlFormat,
+    pub target: Target,
+    /// The Clarity the script runs on, whose restrictions it's checked against.
+    pub platform: Option<Platform>,
+    /// The Java classes the script is allowed to use.
+    pub classes: Option<&'a ClassPolicy>,
+    /// The code of the process to write the script as the custom script step of.
+    pub process: Option<&'a str>,
+    /// The tags written at the start of the script.
+    pub prologue: Option<&'a Snippet>,
+    /// The tags written at the end of the script.
+    pub epilogue: Option<&'a Snippet>,
+}
+
+/// How [`compile_str`] and [`compile_file`] build a script.
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct CompileOptions {
     /// SQL dialect to parse queries.
-    #[arg(short, long, default_value_t = SqlDialect::Generic)]
     pub dialect: SqlDialect,
+    pub layout: Layout,
+    /// `(prefix, uri)` namespaces to declare on the script besides the ones it uses.
+    pub namespaces: Vec<(String, String)>,
+    /// Whether to write a comment with the script, gelatin version and time of generation
+    /// at the top of the output.
+    pub provenance: bool,
+    /// Whether to write a comment pointing at the source line before each top-level tag.
+    pub source_map: bool,
+    pub sql_format: SqlFormat,
+    /// The kind of script to generate.
+    pub target: Target,
+    /// The Clarity the script runs on, whose restrictions it's checked against.
+    pub platform: Option<Platform>,
+    /// The Java classes the script is allowed to use.
+    pub classes: Option<ClassPolicy>,
+    /// The code of the process to write the script as the custom script step of, the
+    /// output is then a process XOG document.
+    pub process: Option<String>,
+    /// Lowers constructs of the script besides the built-in ones.
+    pub expanders: Expanders,
+    /// Whether to remove the `let`s of names that are never read and the blocks without
+    /// statements.
+    pub eliminate_dead_code: bool,
+    /// Whether to bind the method calls written several times in the body of a loop before
+    /// it, see [`Args::hoist_repeated_expressions`].
+    pub hoist_repeated_expressions: bool,
+    /// The tags written at the start of the script, inside its root tag.
+    pub prologue: Option<Snippet>,
+    /// The tags written at the end of the script, inside its root tag.
+    pub epilogue: Option<Snippet>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            prettify: false,
+            indent: "  ".to_string(),
+            line_ending: LineEnding::default(),
+            attrs_per_line: false,
+            escape_cdata: false,
+        }
+    }
 }
 
-impl Args {
-    /// # Panics
-    /// This method may panic if the file is not a valid utf-8 string.
+impl LineEnding {
     #[must_use]
-    pub fn file_name(&self) -> &str {
-        self.file.to_str().expect("a valid file name")
-    }
-
-    /// # Errors
-    /// Returns `Err` if the output file cannot be opened.
-    pub fn writer(&self) -> io::Result<io::BufWriter<Box<dyn io::Write>>> {
-        if let Some(ref output) = self.output {
-            let output = std::fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(output)?;
-            Ok(io::BufWriter::new(Box::new(output)))
-        } else {
-            let stdout = std::io::stdout();
-            let stdout = stdout.lock();
-            Ok(io::BufWriter::new(Box::new(stdout)))
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
         }
     }
+}
+
+impl std::fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
 
-    /// # Errors
-    /// Returns `Err` if the input file cannot be read.
-    pub fn read_file_to_string(&self) -> io::Result<String> {
-        std::fs::read_to_string(&self.file)
+impl std::str::FromStr for Platform {
+    type Err = String;
+
+    /// Reads `saas`, `onprem` or `onprem:<version>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "saas" => Ok(Self::Saas),
+            None if s == "onprem" => Ok(Self::OnPrem(None)),
+            Some(("onprem", version)) => version.parse().map(|version| Self::OnPrem(Some(version))),
+            _ => Err(format!(
+                "`{s}` isn't a platform, use `saas`, `onprem` or `onprem:<version>`, as in `onprem:16.1`"
+            )),
+        }
     }
+}
 
-    /// # Errors
-    /// Returns `Err` if the parsing fails.
-    pub fn to_parser(self) -> miette::Result<Vec<Node>> {
-        let source = self.read_file_to_string().into_diagnostic()?;
-        match self.dialect {
-            SqlDialect::Generic => {
-                Parser::new_with_dialect(self.file_name(), &source, GenericDialect {}).parse()
-            }
-            SqlDialect::Pg => {
-                Parser::new_with_dialect(self.file_name(), &source, PostgreSqlDialect {}).parse()
-            }
-            SqlDialect::Mssql => {
-                Parser::new_with_dialect(self.file_name(), &source, MsSqlDialect {}).parse()
-            }
+impl std::fmt::Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Saas => write!(f, "saas"),
+            Self::OnPrem(None) => write!(f, "onprem"),
+            Self::OnPrem(Some(version)) => write!(f, "onprem:{version}"),
+        }
+    }
+}
+
+impl std::str::FromStr for Version {
+    type Err = String;
+
+    /// Reads a version of up to three numbers, the missing ones are 0.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("`{s}` isn't a version of Clarity, such as `16.1` or `16.1.2`");
+
+        let mut parts = [0; 3];
+        let numbers: Vec<&str> = s.split('.').collect();
+        if numbers.len() > parts.len() {
+            return Err(invalid());
+        }
+        for (part, number) in parts.iter_mut().zip(numbers) {
+            *part = number.parse().map_err(|_| invalid())?;
+        }
+
+        let [major, minor, patch] = parts;
+        Ok(Self {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl std::fmt::Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl std::fmt::Display for SqlFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.to_possible_value()
+            .expect("no values are skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+impl SqlDialect {
+    fn to_dialect(self) -> Box<dyn Dialect> {
+        match self {
+            Self::Generic => Box::new(GenericDialect {}),
+            Self::Pg => Box::new(PostgreSqlDialect {}),
+            Self::Mssql => Box::new(MsSqlDialect {}),
+            Self::Oracle => Box::new(OracleDialect),
+            Self::Mysql => Box::new(MySqlDialect {}),
         }
     }
 }
@@ -97,13 +313,324 @@ impl std::fmt::Display for SqlDialect {
     }
 }
 
+#[cfg(feature = "fs")]
+/// The `.gel` scripts under `dir`, sorted by path.
+fn scripts_in(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut scripts = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            scripts.extend(scripts_in(&path)?);
+        } else if path.extension().is_some_and(|extension| extension == "gel") {
+            scripts.push(path);
+        }
+    }
+
+    scripts.sort();
+    Ok(scripts)
+}
+
+#[cfg(feature = "fs")]
+/// Reads the script at `path`.
+fn read_source(path: &Path) -> miette::Result<String> {
+    let bytes = std::fs::read(path).into_diagnostic()?;
+    decode(&path.to_string_lossy(), bytes)
+}
+
+#[cfg(feature = "fs")]
+/// Reads the snippet at `path`, a gelatin script whose queries are in `dialect` if it's a
+/// `.gel` file and an xml fragment otherwise. Its warnings are not reported.
+///
+/// # Errors
+/// Returns `Err` if the file can't be read or parsed.
+pub fn read_snippet(path: &Path, dialect: SqlDialect) -> miette::Result<Snippet> {
+    let source = read_source(path)?;
+    let file_name = path.to_string_lossy();
+
+    if path.extension().is_some_and(|extension| extension == "gel") {
+        let (nodes, _) = parse(&file_name, &source, dialect, &Expanders::default())?;
+        return Ok(Snippet::Script(nodes));
+    }
+
+    Snippet::from_xml(&source).map_err(|(row, column, err)| {
+        errors::Error::XmlSyntax {
+            at: SourceSpan::new(SourceOffset::from_location(&source, row + 1, column + 1), 1),
+            source_code: NamedSource::new(&file_name, source.as_str().into()),
+            err,
+        }
+        .into()
+    })
+}
+
+#[cfg(feature = "fs")]
+/// Reads the class policy at `path`, allowing the `overrides` whatever it says.
+///
+/// # Errors
+/// Returns `Err` if the file can't be read, or one of its lines isn't an entry.
+pub fn read_class_policy(path: &Path, overrides: &[String]) -> miette::Result<ClassPolicy> {
+    let source = read_source(path)?;
+
+    let policy = ClassPolicy::parse(&source).map_err(|(line, err)| {
+        let at = SourceOffset::from_location(&source, line + 1, 1);
+        let len = source.lines().nth(line).map_or(1, str::len);
+        errors::Error::ClassPolicy {
+            at: SourceSpan::new(at, len),
+            source_code: NamedSource::new(path.to_string_lossy(), source.as_str().into()),
+            err,
+        }
+    })?;
+    Ok(policy.with_overrides(overrides.to_vec()))
+}
+
+#[cfg(feature = "fs")]
+/// The script `bytes` of the file `file_name`, which have to be utf-8.
+fn decode(file_name: &str, bytes: Vec<u8>) -> miette::Result<String> {
+    String::from_utf8(bytes).map_err(|err| {
+        let utf8 = err.utf8_error();
+        let at = SourceSpan::new(utf8.valid_up_to().into(), utf8.error_len().unwrap_or(1));
+        let source = String::from_utf8_lossy(err.as_bytes()).into_owned();
+
+        errors::Error::InvalidUtf8 {
+            source_code: NamedSource::new(file_name, source.into()),
+            at,
+            offset: utf8.valid_up_to(),
+        }
+        .into()
+    })
+}
+
+/// Parses the script `source`, whose queries are in `dialect`, and lowers its constructs
+/// with `expanders`.
+///
+/// Each phase runs in a `tracing` span named after it, `parse`, `analysis` and
+/// `expansion`, and the ones changing the script log how many statements it has after them
+/// at the debug level.
+fn parse(
+    file_name: &str,
+    source: &str,
+    dialect: SqlDialect,
+    expanders: &Expanders,
+) -> miette::Result<(Vec<Spanned<Node>>, Vec<Warning>)> {
+    let _names = intern::Scope::enter();
+    let mut parser = Parser::new(file_name, source, dialect.to_dialect());
+    let nodes = tracing::info_span!("parse").in_scope(|| {
+        let nodes = parser.parse()?;
+        log_statements(&nodes);
+        miette::Result::<_>::Ok(nodes)
+    })?;
+
+    let warnings = tracing::info_span!("analysis").in_scope(|| {
+        let mut warnings = parser.take_warnings();
+        warnings.extend(types::check(&nodes));
+        warnings.extend(secrets::check(&nodes));
+        warnings
+    });
+
+    let nodes = tracing::info_span!("expansion").in_scope(|| {
+        let nodes = expanders
+            .expand_all(nodes)
+            .map_err(|err| err.with_source_code(NamedSource::new(file_name, source.to_string())))?;
+        log_statements(&nodes);
+        miette::Result::<_>::Ok(nodes)
+    })?;
+
+    Ok((nodes, warnings))
+}
+
+/// Logs how many statements the phase being run left the script with.
+fn log_statements(nodes: &[Spanned<Node>]) {
+    tracing::debug!(statements = ast::statements(nodes));
+}
+
+/// Builds the GEL script of the gelatin `source`, warnings are not reported. Paths in the
+/// script are relative to the working directory.
+///
+/// # Errors
+/// Returns `Err` if `source` can't be parsed or transpiled.
+pub fn compile_str(source: &str, options: &CompileOptions) -> miette::Result<String> {
+    compile("<input>", source, options)
+}
+
+#[cfg(feature = "fs")]
+/// Builds the GEL script of the gelatin file at `path`, warnings are not reported.
+///
+/// # Errors
+/// Returns `Err` if the file can't be read, parsed or transpiled.
+pub fn compile_file<P: AsRef<Path>>(path: P, options: &CompileOptions) -> miette::Result<String> {
+    let path = path.as_ref();
+    let source = read_source(path)?;
+    compile(&path.to_string_lossy(), &source, options)
+}
+
+fn compile(file_name: &str, source: &str, options: &CompileOptions) -> miette::Result<String> {
+    let _names = intern::Scope::enter();
+    let _build = tracing::info_span!("build", file = file_name).entered();
+    let (mut nodes, _) = parse(file_name, source, options.dialect, &options.expanders)?;
+    if options.eliminate_dead_code || options.hoist_repeated_expressions {
+        let _optimization = tracing::info_span!("optimization").entered();
+        if options.eliminate_dead_code {
+            dce::eliminate(&mut nodes);
+        }
+        if options.hoist_repeated_expressions {
+            hoist::hoist(&mut nodes);
+        }
+        log_statements(&nodes);
+    }
+
+    let transpile_options = TranspileOptions {
+        layout: options.layout.clone(),
+        provenance: options.provenance.then_some(file_name),
+        source_map: options.source_map.then_some(file_name),
+        namespaces: &options.namespaces,
+        sql_format: options.sql_format,
+        target: options.target,
+        platform: options.platform,
+        classes: options.classes.as_ref(),
+        process: options.process.as_deref(),
+        prologue: options.prologue.as_ref(),
+        epilogue: options.epilogue.as_ref(),
+    };
+    let mut xml = Vec::new();
+    tracing::info_span!("emission")
+        .in_scope(|| transpile(nodes, &mut xml, &transpile_options))
+        .map_err(|err| err.with_source_code(NamedSource::new(file_name, source.to_string())))?;
+    String::from_utf8(xml).into_diagnostic()
+}
+
+/// Writes the gelatin `source` in the canonical style, keeping its comments.
+///
+/// # Errors
+/// Returns `Err` if `source` can't be parsed.
+pub fn format_str(source: &str) -> miette::Result<String> {
+    gelatin::format::format("<input>", source)
+}
+
+#[cfg(feature = "fs")]
+/// Rewrites the gelatin file at `path` in the canonical style, unless `check` is set.
+/// Returns whether the file wasn't formatted.
+///
+/// # Errors
+/// Returns `Err` if the file can't be read, parsed or written.
+pub fn format_file<P: AsRef<Path>>(path: P, check: bool) -> miette::Result<bool> {
+    let path = path.as_ref();
+    let source = read_source(path)?;
+    let formatted = gelatin::format::format(&path.to_string_lossy(), &source)?;
+
+    if formatted == source {
+        return Ok(false);
+    }
+    if !check {
+        std::fs::write(path, formatted).into_diagnostic()?;
+    }
+    Ok(true)
+}
+
+#[cfg(feature = "fs")]
+/// Applies the fixes of the warnings of the script at `path` and formats it, unless
+/// `check`, returning how many fixes it has. A script that builds is only rewritten if it
+/// still builds once fixed.
+///
+/// # Errors
+/// Returns `Err` if the file can't be read, parsed, formatted or written, or if the fixes
+/// would break it.
+pub fn fix_file<P: AsRef<Path>>(
+    path: P,
+    dialect: SqlDialect,
+    check: bool,
+) -> miette::Result<usize> {
+    let path = path.as_ref();
+    let file_name = path.to_string_lossy();
+    let source = read_source(path)?;
+    let (_, warnings) = parse(&file_name, &source, dialect, &Expanders::default())?;
+
+    // the warnings of imported files point at them, so their text doesn't match
+    let mut fixes: Vec<Fix> = warnings
+        .iter()
+        .filter_map(Warning::fix)
+        .filter(|fix| {
+            let at = fix.at.offset()..fix.at.offset() + fix.at.len();
+            source.get(at) == Some(fix.original.as_str())
+        })
+        .collect();
+    fixes.sort_by_key(|fix| fix.at.offset());
+    fixes.dedup_by_key(|fix| fix.at.offset());
+
+    if fixes.is_empty() || check {
+        return Ok(fixes.len());
+    }
+
+    let mut rewritten = source.clone();
+    for fix in fixes.iter().rev() {
+        let at = fix.at.offset()..fix.at.offset() + fix.at.len();
+        rewritten.replace_range(at, &fix.replacement);
+    }
+    let formatted = gelatin::format::format(&file_name, &rewritten)?;
+
+    let options = CompileOptions {
+        dialect,
+        ..CompileOptions::default()
+    };
+    if let Err(err) = compile(&file_name, &formatted, &options) {
+        if compile(&file_name, &source, &options).is_ok() {
+            return Err(errors::Error::BrokenFix {
+                path: file_name.into_owned(),
+                err: err.to_string(),
+            }
+            .into());
+        }
+    }
+    std::fs::write(path, formatted).into_diagnostic()?;
+    Ok(fixes.len())
+}
+
 /// # Errors
-/// Returns `Err` if the write to `sink` fails.
-pub fn transpile<W>(input: Vec<Node>, sink: W, prettify: bool) -> xml::writer::Result<()>
+/// Returns `Err` if the write to `sink` fails or `input` can't be transpiled.
+pub fn transpile<W>(
+    input: Vec<Spanned<Node>>,
+    mut sink: W,
+    options: &TranspileOptions,
+) -> miette::Result<()>
 where
     W: io::Write,
 {
-    let mut t = Transpiler::new(sink, prettify);
+    if !options.layout.attrs_per_line {
+        return Ok(transpiler(sink, options).transpile(input)?);
+    }
 
-    t.transpile(input)
+    // the attributes are split once the script is written
+    let mut t = transpiler(Vec::new(), options);
+    t.transpile(input)?;
+    let xml = String::from_utf8(t.into_inner()).into_diagnostic()?;
+    sink.write_all(split_attributes(&xml, &options.layout).as_bytes())
+        .into_diagnostic()
+}
+
+/// A transpiler writing to `sink` as `options` say.
+fn transpiler<W: io::Write>(sink: W, options: &TranspileOptions) -> Transpiler<W> {
+    let mut t = Transpiler::new(sink, &options.layout)
+        .with_sql_format(options.sql_format)
+        .with_target(options.target)
+        .with_namespaces(options.namespaces.to_vec());
+    if let Some(platform) = options.platform {
+        t = t.with_platform(platform);
+    }
+    if let Some(classes) = options.classes {
+        t = t.with_class_policy(classes.clone());
+    }
+    if let Some(source) = options.provenance {
+        t = t.with_provenance(source);
+    }
+    if let Some(source) = options.source_map {
+        t = t.with_source_map(source);
+    }
+    if let Some(code) = options.process {
+        t = t.with_process(code);
+    }
+    if let Some(prologue) = options.prologue {
+        t = t.with_prologue(prologue.clone());
+    }
+    if let Some(epilogue) = options.epilogue {
+        t = t.with_epilogue(epilogue.clone());
+    }
+    t
 }
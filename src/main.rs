@@ -1,18 +1,35 @@
 use clap::Parser;
-use gel_lang::{transpile, Args};
-use miette::IntoDiagnostic;
+use gel_lang::{Args, Command, ErrorFormat};
+use miette::JSONReportHandler;
 
 fn main() -> miette::Result<()> {
     let args = Args::parse();
 
-    let mut writer = args.writer().into_diagnostic()?;
+    if args.error_format == ErrorFormat::Json {
+        miette::set_hook(Box::new(|_| Box::new(JSONReportHandler::new())))?;
 
-    let prettify = args.prettify;
-    let nodes = args.to_parser()?;
-    transpile(nodes, &mut writer, prettify).into_diagnostic()?;
-    // for tag in tags {
-    //     write!(writer, "{tag}").into_diagnostic()?;
-    // }
+        // returning the error would prefix it with `Error: `, which isn't json.
+        if let Err(err) = run(&args) {
+            eprintln!("{err:?}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    Ok(())
+    run(&args)
+}
+
+fn run(args: &Args) -> miette::Result<()> {
+    match args.command {
+        Some(Command::Deploy(ref deploy_args)) => deploy_args.run(),
+        Some(Command::Fmt(ref fmt_args)) => fmt_args.run(),
+        Some(Command::Fix(ref fix_args)) => fix_args.run(),
+        Some(Command::Check(ref check_args)) => check_args.run(),
+        Some(Command::Lint(ref lint_args)) => lint_args.run(),
+        Some(Command::Diff(ref diff_args)) => diff_args.run(),
+        Some(Command::Repl(ref repl_args)) => repl_args.run(),
+        Some(Command::Doc(ref doc_args)) => doc_args.run(),
+        Some(Command::Graph(ref graph_args)) => graph_args.run(),
+        None => args.run(),
+    }
 }
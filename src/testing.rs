@@ -0,0 +1,211 @@
+//! Helpers for snapshot tests of the scripts gelatin builds, and the large scripts it's
+//! benchmarked with.
+//!
+//! A golden directory holds `.gel` scripts, each next to the `.xml` it's expected to build,
+//! the output of [`snapshot`] for it:
+//!
+//! ```no_run
+//! use gel_lang::{testing, CompileOptions};
+//!
+//! let bless = std::env::var_os("GELATIN_BLESS").is_some();
+//! let mismatches = testing::run_golden("scripts", &CompileOptions::default(), bless)?;
+//! for mismatch in &mismatches {
+//!     eprintln!("{mismatch}");
+//! }
+//! assert!(mismatches.is_empty());
+//! # Ok::<(), miette::Report>(())
+//! ```
+
+use std::{
+    borrow::Cow,
+    fmt::{self, Write as _},
+    path::{Path, PathBuf},
+};
+
+use miette::IntoDiagnostic;
+use xml::{attribute::Attribute, reader::XmlEvent, writer, EmitterConfig, ParserConfig};
+
+use crate::{compile_file, compile_str, diff, scripts_in, CompileOptions};
+
+/// A script of a golden directory that doesn't build its golden file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub script: PathBuf,
+    /// Why, such as where the output differs from the golden file.
+    pub reason: String,
+}
+
+/// Builds the gelatin `source` with `options` and [`normalize`]s it.
+///
+/// # Errors
+/// Returns `Err` if `source` can't be parsed or transpiled.
+pub fn snapshot(source: &str, options: &CompileOptions) -> miette::Result<String> {
+    let script = compile_str(source, options)?;
+    normalize(&script).map_err(|err| miette::miette!("the script isn't xml: {err}"))
+}
+
+/// Writes `document` indented, with the attributes of each element sorted, comments dropped
+/// and the runs of whitespace in text collapsed, so documents meaning the same are written
+/// the same.
+///
+/// # Errors
+/// Returns `Err` with the reason if `document` isn't well-formed xml.
+pub fn normalize(document: &str) -> Result<String, String> {
+    let reader = ParserConfig::new()
+        .trim_whitespace(true)
+        .cdata_to_characters(true)
+        .ignore_comments(true)
+        .coalesce_characters(true)
+        .create_reader(document.as_bytes());
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .write_document_declaration(false)
+        .create_writer(Vec::new());
+
+    for event in reader {
+        let result = match event.map_err(|err| err.to_string())? {
+            XmlEvent::StartElement {
+                name,
+                mut attributes,
+                namespace,
+            } => {
+                attributes.sort_by(|a, b| {
+                    (&a.name.namespace, &a.name.local_name)
+                        .cmp(&(&b.name.namespace, &b.name.local_name))
+                });
+                let attributes: Vec<Attribute> =
+                    attributes.iter().map(|attr| attr.borrow()).collect();
+
+                writer.write(writer::XmlEvent::StartElement {
+                    name: name.borrow(),
+                    attributes: Cow::Owned(attributes),
+                    namespace: Cow::Borrowed(&namespace),
+                })
+            }
+            XmlEvent::EndElement { .. } => writer.write(writer::XmlEvent::end_element()),
+            XmlEvent::Characters(text) => {
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                writer.write(writer::XmlEvent::characters(&text))
+            }
+            _ => continue,
+        };
+        result.map_err(|err| err.to_string())?;
+    }
+
+    let mut output = String::from_utf8(writer.into_inner()).map_err(|err| err.to_string())?;
+    output.push('\n');
+    Ok(output)
+}
+
+/// Builds every `.gel` script under `dir` with `options`, comparing their [`snapshot`]s
+/// with the `.xml` files next to them. With `bless`, the golden files that are missing or
+/// differ are written instead.
+///
+/// # Errors
+/// Returns `Err` if `dir` or a golden file can't be read, or a golden file can't be written.
+pub fn run_golden<P: AsRef<Path>>(
+    dir: P,
+    options: &CompileOptions,
+    bless: bool,
+) -> miette::Result<Vec<Mismatch>> {
+    let mut mismatches = vec![];
+
+    for script in scripts_in(dir.as_ref()).into_diagnostic()? {
+        let golden = script.with_extension("xml");
+        let mismatch = |reason: String| Mismatch {
+            script: script.clone(),
+            reason,
+        };
+
+        let actual = match compile_file(&script, options)
+            .and_then(|output| normalize(&output).map_err(|err| miette::miette!("{err}")))
+        {
+            Ok(actual) => actual,
+            Err(err) => {
+                mismatches.push(mismatch(format!("it can't be built: {err:?}")));
+                continue;
+            }
+        };
+
+        if !golden.exists() {
+            if bless {
+                std::fs::write(&golden, actual).into_diagnostic()?;
+            } else {
+                mismatches.push(mismatch(format!("{} is missing", golden.display())));
+            }
+            continue;
+        }
+
+        let expected = std::fs::read_to_string(&golden).into_diagnostic()?;
+        let differences = match diff(&expected, &actual) {
+            Ok(differences) => differences,
+            Err(err) => {
+                mismatches.push(mismatch(format!("{}: {err}", golden.display())));
+                continue;
+            }
+        };
+
+        if differences.is_empty() {
+            continue;
+        }
+        if bless {
+            std::fs::write(&golden, actual).into_diagnostic()?;
+        } else {
+            let differences: Vec<String> = differences.iter().map(ToString::to_string).collect();
+            mismatches.push(mismatch(format!(
+                "{} is the old script, the one built the new one\n{}",
+                golden.display(),
+                differences.join("\n")
+            )));
+        }
+    }
+
+    Ok(mismatches)
+}
+
+/// A dict nested `depth` times, with a few keys at each level.
+#[must_use]
+pub fn nested_dicts(depth: usize) -> String {
+    let mut dict = "1".to_string();
+    for level in 0..depth {
+        dict = format!(r#"{{"a{level}": {dict}, "b": "text", "c": {level}}}"#);
+    }
+    format!("let d = {dict}\nlog! INFO f\"{{d}}\"\n")
+}
+
+/// `statements` lets, conditions and loops.
+#[must_use]
+pub fn long_script(statements: usize) -> String {
+    let mut source = String::new();
+    for i in 0..statements / 4 {
+        let _ = writeln!(source, "let x{i} = {i}");
+        let _ = writeln!(
+            source,
+            "if x{i} == 1 then\n  log! INFO f\"one {{x{i}}}\"\nend"
+        );
+        let _ = writeln!(
+            source,
+            "for j{i} in 1..3 do\n  log! INFO f\"{{j{i}}}\"\nend"
+        );
+        let _ = writeln!(source, "log! INFO f\"{{x{i}}}\"");
+    }
+    source
+}
+
+/// A query of `columns` columns and conditions.
+#[must_use]
+pub fn huge_sql(columns: usize) -> String {
+    let columns_list: Vec<_> = (0..columns).map(|i| format!("c{i}")).collect();
+    let conditions: Vec<_> = (0..columns).map(|i| format!("c{i} = {i}")).collect();
+    format!(
+        "query! niku do select {} from t where {} end\n",
+        columns_list.join(", "),
+        conditions.join(" and ")
+    )
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.script.display(), self.reason)
+    }
+}
@@ -0,0 +1,125 @@
+/// The Java classes scripts are allowed to create and call, read from a file of entries
+/// such as
+///
+/// ```text
+/// # anything of java.util but its concurrency utilities
+/// allow java.util
+/// deny java.util.concurrent
+/// ```
+///
+/// An entry is a class or a package, which covers the classes under it. The most specific
+/// entry covering a class decides whether it's allowed, a class none of them cover is only
+/// allowed if there are no `allow` entries.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassPolicy {
+    entries: Vec<(Access, String)>,
+    /// The classes and packages allowed whatever the entries say.
+    overrides: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Allow,
+    Deny,
+}
+
+impl ClassPolicy {
+    /// Reads the entries of `source`, one per line. Empty lines and the ones starting with
+    /// `#` are skipped.
+    ///
+    /// # Errors
+    /// Returns the index of the first line that isn't an entry, with what's wrong with it.
+    pub fn parse(source: &str) -> Result<Self, (usize, String)> {
+        let mut entries = vec![];
+
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut words = line.split_whitespace();
+            let access =
+                match words.next() {
+                    Some("allow") => Access::Allow,
+                    Some("deny") => Access::Deny,
+                    _ => return Err((
+                        index,
+                        "expected `allow` or `deny` and a class or package, as in `deny java.io`"
+                            .to_string(),
+                    )),
+                };
+            let (Some(name), None) = (words.next(), words.next()) else {
+                return Err((index, "expected a single class or package".to_string()));
+            };
+            if !is_class_name(name) {
+                return Err((
+                    index,
+                    format!("`{name}` isn't a class or package, such as `java.util.ArrayList`"),
+                ));
+            }
+
+            entries.push((access, name.to_string()));
+        }
+
+        Ok(Self {
+            entries,
+            overrides: vec![],
+        })
+    }
+
+    /// Allows the `classes`, and the classes of the packages among them, whatever the
+    /// entries say.
+    #[must_use]
+    pub fn with_overrides(mut self, classes: Vec<String>) -> Self {
+        self.overrides = classes;
+        self
+    }
+
+    /// Why a script can't use `class`, if the policy doesn't allow it.
+    pub(crate) fn denial(&self, class: &str) -> Option<String> {
+        if self.overrides.iter().any(|name| covers(name, class)) {
+            return None;
+        }
+
+        let entry = self
+            .entries
+            .iter()
+            .filter(|(_, name)| covers(name, class))
+            .max_by_key(|(_, name)| name.len());
+        match entry {
+            Some((Access::Deny, name)) if name == class => {
+                Some(format!("the class policy denies `{class}`"))
+            }
+            Some((Access::Deny, name)) => Some(format!(
+                "the class policy denies `{class}`, as it denies the classes of `{name}`"
+            )),
+            None if self
+                .entries
+                .iter()
+                .any(|(access, _)| *access == Access::Allow) =>
+            {
+                Some(format!(
+                    "the class policy only allows the classes it lists, `{class}` isn't one of them"
+                ))
+            }
+            Some((Access::Allow, _)) | None => None,
+        }
+    }
+}
+
+/// Whether the class or package `name` is `class` or a package holding it.
+pub(super) fn covers(name: &str, class: &str) -> bool {
+    class
+        .strip_prefix(name)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+fn is_class_name(name: &str) -> bool {
+    name.split('.').all(|part| {
+        part.chars().next().is_some_and(|c| !c.is_ascii_digit())
+            && part
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+    })
+}
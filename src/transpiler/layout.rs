@@ -0,0 +1,108 @@
+use crate::Layout;
+
+/// Markup copied as is, `(open, close)`. CDATA and comments can hold anything, tags
+/// included.
+const VERBATIM: [(&str, &str); 4] = [
+    ("<![CDATA[", "]]>"),
+    ("<!--", "-->"),
+    ("<?", "?>"),
+    ("</", ">"),
+];
+
+/// Splits the start tags of the indented `xml` with several attributes, so each attribute
+/// goes on its own line a level deeper than the line of the tag.
+pub fn split_attributes(xml: &str, layout: &Layout) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let verbatim = VERBATIM
+            .iter()
+            .find(|(open, _)| rest.starts_with(open))
+            .map(|(open, close)| {
+                rest[open.len()..]
+                    .find(close)
+                    .map_or(rest.len(), |at| open.len() + at + close.len())
+            });
+        if let Some(end) = verbatim {
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = tag_end(rest);
+        let tag = &rest[..end];
+        rest = &rest[end..];
+
+        let inner = tag[1..].trim_end_matches('>');
+        let body = inner.strip_suffix('/').map_or(inner, str::trim_end);
+        let close = &tag[1 + body.len()..];
+
+        let mut parts = words(body);
+        if parts.len() <= 2 {
+            out.push_str(tag);
+            continue;
+        }
+
+        let line_start = out.rfind('\n').map_or(0, |at| at + 1);
+        let indent: String = out[line_start..]
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .collect();
+
+        out.push('<');
+        out.push_str(parts.remove(0));
+        for attribute in parts {
+            out.push_str(layout.line_ending.as_str());
+            out.push_str(&indent);
+            out.push_str(&layout.indent);
+            out.push_str(attribute);
+        }
+        out.push_str(close);
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// The length of the start tag `xml` starts with, up to its `>` out of attribute values.
+fn tag_end(xml: &str) -> usize {
+    let mut quoted = false;
+    for (at, c) in xml.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '>' if !quoted => return at + 1,
+            _ => {}
+        }
+    }
+    xml.len()
+}
+
+/// The name and attributes of a start tag, split on the whitespace out of attribute values.
+fn words(body: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut quoted = false;
+    let mut start = None;
+
+    for (at, c) in body.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if let Some(start) = start.take() {
+                    words.push(&body[start..at]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(at);
+    }
+
+    if let Some(start) = start {
+        words.push(&body[start..]);
+    }
+    words
+}
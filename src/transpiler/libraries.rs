@@ -0,0 +1,187 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    gelatin::{
+        ast::{Expr, Node, Stmt},
+        SOAPENV_NS, XOG_NS,
+    },
+    Target,
+};
+
+/// A tag library, or xml namespace, the generated script may use.
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash, Clone, Copy)]
+#[allow(dead_code)]
+pub enum Libraries {
+    Core,
+    Gel,
+    Log,
+    Sql,
+    Email,
+    File,
+    Ftp,
+    Soap,
+    SoapEnv,
+    Xml,
+    Xog,
+}
+
+impl Libraries {
+    pub const ALL: [Self; 11] = [
+        Self::Core,
+        Self::Gel,
+        Self::Log,
+        Self::Sql,
+        Self::Email,
+        Self::File,
+        Self::Ftp,
+        Self::Soap,
+        Self::SoapEnv,
+        Self::Xml,
+        Self::Xog,
+    ];
+
+    pub const fn prefix(self) -> &'static str {
+        match self {
+            Self::Core => "core",
+            Self::Gel => "gel",
+            Self::Log => "log",
+            Self::Sql => "sql",
+            Self::Email => "email",
+            Self::File => "file",
+            Self::Ftp => "ftp",
+            Self::Soap => "soap",
+            Self::SoapEnv => "soapenv",
+            Self::Xml => "xml",
+            Self::Xog => "xog",
+        }
+    }
+
+    pub const fn uri(self) -> &'static str {
+        match self {
+            Self::Core => "jelly:core",
+            Self::Gel => "jelly:com.niku.union.gel.GELTagLibrary",
+            Self::Log => "jelly:log",
+            Self::Sql => "jelly:sql",
+            Self::Email => "jelly:email",
+            Self::File => "jelly:com.niku.union.gel.FileTagLibrary",
+            Self::Ftp => "jelly:com.niku.union.gel.FTPTagLibrary",
+            Self::Soap => "jelly:com.niku.union.gel.SOAPTagLibrary",
+            Self::SoapEnv => SOAPENV_NS,
+            Self::Xml => "jelly:xml",
+            Self::Xog => XOG_NS,
+        }
+    }
+
+    /// Returns the libraries whose tags `nodes` transpile to for `target`. The library of
+    /// the root tag, `gel` for a `gel:script` and `core` for a `core:jelly`, is always used.
+    pub fn used_by<'a>(
+        nodes: impl IntoIterator<Item = &'a Node>,
+        target: Target,
+    ) -> BTreeSet<Self> {
+        let mut used = BTreeSet::from([match target {
+            Target::Gel => Self::Gel,
+            Target::Jelly => Self::Core,
+        }]);
+
+        for node in nodes {
+            match node {
+                Node::Stmt(stmt) => Self::collect(stmt, target, &mut used),
+                Node::Expr(expr) => Self::collect_expr(expr, target, &mut used),
+            }
+        }
+
+        used
+    }
+
+    /// The libraries of the queries of `target`, `gel` sets their datasource.
+    const fn sql(target: Target) -> &'static [Self] {
+        match target {
+            Target::Gel => &[Self::Gel, Self::Sql],
+            Target::Jelly => &[Self::Sql],
+        }
+    }
+
+    fn collect(stmt: &Stmt, target: Target, used: &mut BTreeSet<Self>) {
+        match stmt {
+            Stmt::Block(body) => {
+                for stmt in body {
+                    Self::collect(stmt, target, used);
+                }
+            }
+            Stmt::Catch { body, .. }
+            | Stmt::ForEach { body, .. }
+            | Stmt::While { body, .. }
+            | Stmt::WriteFile { body, .. }
+            | Stmt::Whitespace(body) => {
+                used.insert(Self::Core);
+
+                for stmt in body {
+                    Self::collect(stmt, target, used);
+                }
+            }
+            Stmt::Transaction { body, .. } => {
+                used.extend(Self::sql(target));
+
+                for stmt in body {
+                    Self::collect(stmt, target, used);
+                }
+            }
+            Stmt::If { body, alt, .. } => {
+                used.insert(Self::Core);
+
+                for stmt in body.iter().chain(alt.iter().flatten()) {
+                    Self::collect(stmt, target, used);
+                }
+            }
+            Stmt::Let(_, expr) | Stmt::Expr { expr } => Self::collect_expr(expr, target, used),
+            Stmt::SetProperties { .. } => {
+                used.insert(Self::Core);
+            }
+            Stmt::Log { .. } => {
+                used.insert(match target {
+                    Target::Gel => Self::Gel,
+                    Target::Jelly => Self::Log,
+                });
+            }
+            // plain jelly writes the text as is, and has no parameters
+            Stmt::Out { .. } | Stmt::Param { .. } => {
+                if target == Target::Gel {
+                    used.insert(Self::Gel);
+                }
+            }
+            Stmt::Xml(events) => {
+                for event in events {
+                    if let xml::reader::XmlEvent::StartElement { name, .. } = event {
+                        let uri = name.namespace.as_deref();
+                        used.extend(Self::ALL.into_iter().filter(|l| Some(l.uri()) == uri));
+                    }
+                }
+            }
+            Stmt::Alias { .. }
+            | Stmt::Namespace { .. }
+            | Stmt::Comment(_)
+            | Stmt::Template { .. } => {}
+        }
+    }
+
+    fn collect_expr(expr: &Expr, target: Target, used: &mut BTreeSet<Self>) {
+        match expr {
+            Expr::Query { .. } | Expr::Batch(_) => {
+                used.extend(Self::sql(target));
+            }
+            // soapenv and xog are declared on the envelope and gel:set themselves
+            Expr::Soap { .. } => {
+                used.insert(Self::Soap);
+            }
+            Expr::XPath { .. } => {
+                used.insert(match target {
+                    Target::Gel => Self::Gel,
+                    Target::Jelly => Self::Xml,
+                });
+            }
+            _ => {
+                used.insert(Self::Core);
+            }
+        }
+    }
+}
@@ -1,33 +1,78 @@
+mod classes;
+pub mod layout;
+mod libraries;
+mod restrictions;
+mod snippet;
+mod sql;
 pub mod tags;
 
-use std::{borrow::Borrow, collections::HashMap, io};
+use std::{
+    borrow::{Borrow, Cow},
+    collections::HashMap,
+    io,
+};
 
 use xml::{writer::XmlEvent, EventWriter};
 
 use crate::{
-    gelatin::ast::{Call, Context, Expr, Ident, Name, Node, QueryType, Stmt},
-    transpiler::tags::{Soap, SoapEnv},
+    gelatin::{
+        ast::{
+            Call, Context, Expr, Ident, Name, Node, QueryType, Span, Spanned, SqlParam, Stmt,
+            TypedArg, Value,
+        },
+        SOAPENV_NS, XOG_NS,
+    },
+    transpiler::tags::{Log, Soap, SoapEnv, Xml},
+    Layout, Platform, SqlFormat, Target,
 };
 
+use crate::errors::TranspileError as Error;
+
+pub use self::classes::ClassPolicy;
+use self::libraries::Libraries;
+use self::restrictions::Restrictions;
+pub use self::snippet::read_fragment;
+pub use self::snippet::Snippet;
 use self::tags::{Core, Gel, Sql};
 
-#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
-#[allow(dead_code)]
-enum Libraries {
-    Core,
-    Gel,
-    Sql,
-    Email,
-    File,
-    Ftp,
-    Soap,
-    SoapEnv,
-    Xog,
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Writes a parsed script in an output format.
+pub trait Backend {
+    /// Writes the script made of the top-level `nodes`.
+    fn transpile(&mut self, nodes: Vec<Spanned<Node>>) -> Result<()>;
+
+    /// Writes the output of a single `node` of the script.
+    fn emit(&mut self, node: Node) -> Result<()>;
 }
 
 pub struct Transpiler<W> {
     env: HashMap<String, Expr>,
     writer: EventWriter<W>,
+    /// The source file to credit in a header comment.
+    provenance: Option<String>,
+    /// The source file to point at before each top-level node.
+    source_map: Option<String>,
+    /// The span of the top-level node being transpiled.
+    current: Span,
+    /// Whether the queries being transpiled run in a `sql:transaction`, which already
+    /// holds their connection.
+    in_transaction: bool,
+    sql_format: SqlFormat,
+    /// The runner the tags are written for.
+    target: Target,
+    /// The Clarity the script runs on, whose restrictions it's checked against.
+    platform: Option<Platform>,
+    /// The Java classes the script is allowed to use.
+    classes: Option<ClassPolicy>,
+    /// `(prefix, uri)` namespaces declared on the script besides the ones it uses.
+    namespaces: Vec<(String, String)>,
+    /// The code of the process the script is written as the custom script step of.
+    process: Option<String>,
+    /// The tags written before the ones of the script, inside its root tag.
+    prologue: Option<Snippet>,
+    /// The tags written after the ones of the script, inside its root tag.
+    epilogue: Option<Snippet>,
 }
 
 macro_rules! close {
@@ -43,51 +88,383 @@ macro_rules! auto_close {
 }
 
 impl<W: io::Write> Transpiler<W> {
-    pub fn new(sink: W, prettify: bool) -> Self {
+    /// A transpiler writing the xml laid out as `layout` says, save for its attributes
+    /// per line, which [`layout::split_attributes`] splits once the script is written.
+    pub fn new(sink: W, layout: &Layout) -> Self {
+        Self::with_config(
+            sink,
+            xml::EmitterConfig::default()
+                .perform_indent(layout.prettify)
+                .indent_string(layout.indent.clone())
+                .line_separator(layout.line_ending.as_str())
+                .cdata_to_characters(layout.escape_cdata),
+        )
+    }
+
+    /// A transpiler writing the tags of top-level nodes on their own with
+    /// [`Self::write_fragment`], rather than whole scripts.
+    #[cfg(feature = "fs")]
+    pub fn fragments(sink: W) -> Self {
+        Self::with_config(
+            sink,
+            xml::EmitterConfig::default()
+                .perform_indent(true)
+                .write_document_declaration(false),
+        )
+    }
+
+    fn with_config(sink: W, config: xml::EmitterConfig) -> Self {
         Self {
             env: HashMap::new(),
-            writer: EventWriter::new_with_config(
-                sink,
-                xml::EmitterConfig::default().perform_indent(prettify),
-            ),
+            writer: EventWriter::new_with_config(sink, config),
+            provenance: None,
+            source_map: None,
+            current: Span::default(),
+            in_transaction: false,
+            sql_format: SqlFormat::default(),
+            target: Target::default(),
+            platform: None,
+            classes: None,
+            namespaces: Vec::new(),
+            process: None,
+            prologue: None,
+            epilogue: None,
         }
     }
 
-    pub fn transpile<I>(&mut self, it: I) -> xml::writer::Result<()>
-    where
-        I: IntoIterator<Item = Node>,
-    {
+    /// Writes the tags of the top-level `node`, without a script around them.
+    ///
+    /// # Errors
+    /// Returns `Err` if the write to the sink fails or `node` can't be transpiled.
+    #[cfg(feature = "fs")]
+    pub fn write_fragment(&mut self, Spanned { node, span }: Spanned<Node>) -> Result<()> {
+        self.current = span;
+        self.emit(node)
+    }
+
+    /// Writes the script for the `target` runner.
+    #[must_use]
+    pub const fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Rejects the constructs `platform` doesn't run.
+    #[must_use]
+    pub const fn with_platform(mut self, platform: Platform) -> Self {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Rejects the classes `policy` doesn't allow.
+    #[must_use]
+    pub fn with_class_policy(mut self, policy: ClassPolicy) -> Self {
+        self.classes = Some(policy);
+        self
+    }
+
+    /// Declares the `(prefix, uri)` `namespaces` on the script.
+    #[must_use]
+    pub fn with_namespaces(mut self, namespaces: Vec<(String, String)>) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Writes the script as the custom script step of a process XOG document, with `code`
+    /// as the code of the process.
+    #[must_use]
+    pub fn with_process(mut self, code: &str) -> Self {
+        self.process = Some(code.to_string());
+        self
+    }
+
+    /// Writes the tags of `snippet` at the start of the script, inside its root tag.
+    #[must_use]
+    pub fn with_prologue(mut self, snippet: Snippet) -> Self {
+        self.prologue = Some(snippet);
+        self
+    }
+
+    /// Writes the tags of `snippet` at the end of the script, inside its root tag.
+    #[must_use]
+    pub fn with_epilogue(mut self, snippet: Snippet) -> Self {
+        self.epilogue = Some(snippet);
+        self
+    }
+
+    /// The sink the script was written to.
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner()
+    }
+
+    /// Writes the sql of queries as `format` says.
+    #[must_use]
+    pub const fn with_sql_format(mut self, format: SqlFormat) -> Self {
+        self.sql_format = format;
+        self
+    }
+
+    /// Writes a `src: {source}:{line}` comment before the tags of every top-level node.
+    #[must_use]
+    pub fn with_source_map(mut self, source: &str) -> Self {
+        self.source_map = Some(source.to_string());
+        self
+    }
+
+    /// Writes a comment naming `source`, the gelatin version and the time of generation
+    /// before the script.
+    #[must_use]
+    pub fn with_provenance(mut self, source: &str) -> Self {
+        self.provenance = Some(source.to_string());
+        self
+    }
+
+    fn write_script(&mut self, nodes: Vec<Spanned<Node>>) -> Result<()> {
+        if let Some(ref source) = self.provenance {
+            let comment = comment_text(&format!(
+                "generated by gelatin {} from {source} at {}",
+                env!("CARGO_PKG_VERSION"),
+                utc_timestamp(std::time::SystemTime::now())
+            ));
+            // the declaration has to come first
+            self.writer.write(XmlEvent::StartDocument {
+                version: xml::common::XmlVersion::Version10,
+                encoding: Some("utf-8"),
+                standalone: None,
+            })?;
+            self.writer.write(XmlEvent::comment(&comment))?;
+        }
+
+        if self.platform.is_some() && self.target == Target::Jelly {
+            return Err(self.error(
+                Span::default(),
+                "plain jelly scripts don't run on Clarity, so they have no platform",
+            ));
+        }
+
+        if let Some(code) = self.process.clone() {
+            if self.target == Target::Jelly {
+                return Err(self.error(
+                    Span::default(),
+                    "processes run GEL scripts, a plain jelly script can't be wrapped in one",
+                ));
+            }
+            self.open_process(&code)?;
+        }
+
         //       <gel:script xmlns:core="jelly:core"
         // xmlns:gel="">
+        let mut script = XmlEvent::start_element(match self.target {
+            Target::Gel => Gel::Script.as_str(),
+            Target::Jelly => Core::Jelly.as_str(),
+        });
+        let prologue = self.prologue.take();
+        let epilogue = self.epilogue.take();
+        let snippets = prologue.iter().chain(&epilogue);
+        let all_nodes = || {
+            let snippet_nodes = snippets.clone().flat_map(Snippet::nodes);
+            snippet_nodes.chain(&nodes).map(|n| &n.node)
+        };
+
+        for library in Libraries::used_by(all_nodes(), self.target) {
+            script = script.ns(library.prefix(), library.uri());
+        }
+        for (prefix, uri) in snippets.clone().flat_map(Snippet::namespaces) {
+            script = script.ns(prefix, uri);
+        }
+
+        for (prefix, uri) in &self.namespaces {
+            script = script.ns(prefix.as_str(), uri.as_str());
+        }
+
+        // namespaces are declared at the top level only
+        for node in all_nodes() {
+            if let Node::Stmt(Stmt::Namespace { prefix, uri }) = node {
+                script = script.ns(prefix.as_str(), uri.as_ref());
+            }
+        }
+
+        self.writer.write(script)?;
+        self.document_params(&nodes)?;
+
+        if let Some(prologue) = prologue {
+            self.write_snippet(prologue)?;
+        }
+
+        for Spanned { node, span } in nodes {
+            self.current = span;
+
+            let emits_tags = !matches!(
+                node,
+                Node::Stmt(Stmt::Alias { .. } | Stmt::Namespace { .. })
+            );
+
+            if let Some(source) = self.source_map.as_ref().filter(|_| emits_tags) {
+                let comment = comment_text(&format!("src: {source}:{}", span.line));
+                self.writer.write(XmlEvent::comment(&comment))?;
+            }
+
+            self.emit(node)?;
+        }
+
+        if let Some(epilogue) = epilogue {
+            self.write_snippet(epilogue)?;
+        }
+
+        close!(self.writer);
+
+        if self.process.is_some() {
+            self.close_process()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the tags of `snippet`, without source map comments as it isn't part of the
+    /// script.
+    fn write_snippet(&mut self, snippet: Snippet) -> Result<()> {
+        match snippet {
+            Snippet::Xml(events) => {
+                for event in events
+                    .iter()
+                    .filter_map(xml::reader::XmlEvent::as_writer_event)
+                {
+                    self.writer.write(event)?;
+                }
+            }
+            Snippet::Script(nodes) => {
+                for Spanned { node, span } in nodes {
+                    self.current = span;
+                    self.emit(node)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens a process XOG document with the code `code`, up to the custom script action of
+    /// its start step.
+    fn open_process(&mut self, code: &str) -> Result<()> {
+        self.writer.write(
+            XmlEvent::start_element("NikuDataBus")
+                .ns("xsi", "http://www.w3.org/2001/XMLSchema-instance")
+                .attr(
+                    "xsi:noNamespaceSchemaLocation",
+                    "../xsd/nikuxog_process.xsd",
+                ),
+        )?;
+        auto_close!(
+            XmlEvent::start_element("Header")
+                .attr("action", "write")
+                .attr("externalSource", "NIKU")
+                .attr("objectType", "process")
+                .attr("version", "13.0"),
+            self.writer
+        );
+        self.writer.write(XmlEvent::start_element("Processes"))?;
+        self.writer.write(
+            XmlEvent::start_element("Process")
+                .attr("code", code)
+                .attr("source", "niku"),
+        )?;
+        self.describe(code)?;
+        self.writer.write(
+            XmlEvent::start_element("ProcessVersion")
+                .attr("version", "1")
+                .attr("userStatus", "ACTIVE"),
+        )?;
+        self.writer.write(XmlEvent::start_element("Steps"))?;
+        self.writer.write(
+            XmlEvent::start_element("Step")
+                .attr("code", "start")
+                .attr("name", "Start"),
+        )?;
+        self.describe("Start")?;
+        self.writer.write(XmlEvent::start_element("Actions"))?;
+        self.writer.write(
+            XmlEvent::start_element("CustomScript")
+                .attr("code", "script")
+                .attr("name", "Script"),
+        )?;
+        self.describe("Script")?;
+        Ok(())
+    }
+
+    /// Closes the document opened by [`Self::open_process`], the start step goes on to a
+    /// finish step.
+    fn close_process(&mut self) -> Result<()> {
+        // CustomScript and Actions
+        close!(self.writer);
+        close!(self.writer);
+        self.writer
+            .write(XmlEvent::start_element("PostConditions"))?;
+        auto_close!(
+            XmlEvent::start_element("Transition").attr("stepCode", "finish"),
+            self.writer
+        );
+        close!(self.writer);
+        // the start step
+        close!(self.writer);
+
         self.writer.write(
-            XmlEvent::start_element(Gel::Script)
-                .ns("gel", "jelly:com.niku.union.gel.GELTagLibrary")
-                .ns("core", "jelly:core")
-                .ns("sql", "jelly:sql"),
+            XmlEvent::start_element("Step")
+                .attr("code", "finish")
+                .attr("name", "Finish"),
         )?;
+        self.describe("Finish")?;
+        close!(self.writer);
 
-        for node in it {
-            self.as_tags(node)?;
+        // Steps, ProcessVersion, Process, Processes and NikuDataBus
+        for _ in 0..5 {
+            close!(self.writer);
         }
+        Ok(())
+    }
 
+    /// Writes the english `Descriptions` of a process XOG element named `name`.
+    fn describe(&mut self, name: &str) -> Result<()> {
+        self.writer.write(XmlEvent::start_element("Descriptions"))?;
+        auto_close!(
+            XmlEvent::start_element("Description")
+                .attr("languageCode", "en")
+                .attr("name", name),
+            self.writer
+        );
         close!(self.writer);
         Ok(())
     }
 
-    pub fn as_tags(&mut self, node: Node) -> xml::writer::Result<()> {
-        match node {
-            Node::Stmt(stmt) => self.transpile_node(stmt),
-            Node::Expr(expr) => self.transpile_node(Stmt::Expr { expr }),
+    /// Creates an error at `span`, or at the current top-level node if `span` is unknown.
+    fn error(&self, span: Span, message: &str) -> Error {
+        let span = if span == Span::default() {
+            self.current
+        } else {
+            span
+        };
+
+        Error::Unsupported {
+            at: span.into(),
+            message: message.to_string(),
         }
     }
 
-    pub fn transpile_node(&mut self, stmt: Stmt) -> xml::writer::Result<()> {
+    /// Renders `expr` as the value of an attribute, or errors if it has to be bound with a
+    /// `let` first.
+    fn attribute<'e>(&self, span: Span, expr: &'e Expr) -> Result<Cow<'e, str>> {
+        if !expr.is_inline() {
+            return Err(self.error(span, "this expression has to be bound to a name with `let`"));
+        }
+
+        Ok(expr.as_value(Context::Text))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub fn transpile_node(&mut self, stmt: Stmt) -> Result<()> {
         match stmt {
             Stmt::Expr {
-                expr: expr @ Expr::Query { .. },
+                expr: expr @ (Expr::Query { .. } | Expr::Batch(_)),
             } => self.transpile_node(Stmt::Let("_".into(), expr)),
             Stmt::While { test, body } => {
-                let val = test.as_value(Context::Text);
+                let val = self.attribute(Span::default(), &test)?;
 
                 self.writer
                     .write(XmlEvent::start_element(Core::While).attr("test", val.borrow()))?;
@@ -97,8 +474,16 @@ impl<W: io::Write> Transpiler<W> {
                 close!(self.writer);
                 Ok(())
             }
+            Stmt::Expr {
+                expr:
+                    Expr::Invoke {
+                        object,
+                        method,
+                        args,
+                    },
+            } => self.invoke(None, &object, &method, args),
             Stmt::Expr { expr } => {
-                let value = expr.as_value(Context::Text);
+                let value = self.attribute(Span::default(), &expr)?;
                 auto_close!(
                     XmlEvent::start_element(Core::Expr).attr("value", &value),
                     self.writer
@@ -115,7 +500,127 @@ impl<W: io::Write> Transpiler<W> {
                 self.env.insert(ident.to_string(), cls);
                 Ok(())
             }
+            Stmt::Namespace { .. } | Stmt::Template { .. } => Ok(()),
+            Stmt::WriteFile {
+                path,
+                escape_xml,
+                body,
+            } => {
+                let path = self.attribute(Span::default(), &path)?;
+                self.writer.write(
+                    XmlEvent::start_element(Core::File)
+                        .attr("name", &path)
+                        .attr("omitXmlDeclaration", "true")
+                        .attr("escapeText", if escape_xml { "true" } else { "false" }),
+                )?;
+
+                self.transpile_vec(body)?;
+
+                close!(self.writer);
+                Ok(())
+            }
+            Stmt::Xml(events) => {
+                for event in events
+                    .iter()
+                    .filter_map(xml::reader::XmlEvent::as_writer_event)
+                {
+                    self.writer.write(event)?;
+                }
+                Ok(())
+            }
+            Stmt::Comment(text) => {
+                self.writer.write(XmlEvent::comment(&comment_text(&text)))?;
+                Ok(())
+            }
+            Stmt::Param { name, .. } => {
+                if self.target == Target::Jelly {
+                    return Err(self.error(
+                        name.span(),
+                        "parameters are set by the process running a GEL script, plain jelly doesn't have them",
+                    ));
+                }
+
+                auto_close!(
+                    XmlEvent::start_element(Gel::Parameter).attr("var", name.as_str()),
+                    self.writer
+                );
+                Ok(())
+            }
+            Stmt::Whitespace(body) => {
+                self.writer
+                    .write(XmlEvent::start_element(Core::Whitespace))?;
+
+                self.transpile_vec(body)?;
+
+                close!(self.writer);
+                Ok(())
+            }
+            Stmt::Transaction { datasource, body } => {
+                let datasource = datasource.to_string();
+                match self.target {
+                    Target::Gel => {
+                        auto_close!(
+                            XmlEvent::start_element(Gel::SetDatasource).attr("dbId", &datasource),
+                            self.writer
+                        );
+                        self.writer
+                            .write(XmlEvent::start_element(Sql::Transaction))?;
+                    }
+                    Target::Jelly => {
+                        let datasource = format!("${{{datasource}}}");
+                        self.writer.write(
+                            XmlEvent::start_element(Sql::Transaction)
+                                .attr("dataSource", &datasource),
+                        )?;
+                    }
+                }
+
+                self.in_transaction = true;
+                let body = self.transpile_vec(body);
+                self.in_transaction = false;
+                body?;
+
+                close!(self.writer);
+                Ok(())
+            }
+            Stmt::SetProperties { object, props } => {
+                let object = object.as_value(Context::Text);
+                let props = props
+                    .iter()
+                    .map(|(k, v)| Ok((k, self.attribute(Span::default(), v)?)))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let mut tag = XmlEvent::start_element(Core::SetProperties).attr("object", &object);
+                for (k, v) in &props {
+                    tag = tag.attr(k.as_ref(), v);
+                }
+
+                auto_close!(tag, self.writer);
+                Ok(())
+            }
+            Stmt::Out { value } => {
+                let value = self.attribute(Span::default(), &value)?;
+                if self.target == Target::Jelly {
+                    // the text of a plain jelly script is its output
+                    self.writer.write(XmlEvent::characters(&value))?;
+                    return Ok(());
+                }
+
+                self.writer.write(XmlEvent::start_element(Gel::Out))?;
+                self.writer.write(XmlEvent::characters(&value))?;
+
+                close!(self.writer);
+                Ok(())
+            }
             Stmt::ForEach { .. } => self.for_each(stmt),
+            Stmt::Log { level, message } if self.target == Target::Jelly => {
+                self.writer
+                    .write(XmlEvent::start_element(Log::from(&level)))?;
+                self.writer.write(XmlEvent::characters(&message))?;
+
+                close!(self.writer);
+                Ok(())
+            }
             Stmt::Log { level, message } => {
                 auto_close!(
                     XmlEvent::start_element(Gel::Log)
@@ -140,11 +645,18 @@ impl<W: io::Write> Transpiler<W> {
         }
     }
 
-    fn let_stmt(&mut self, stmt: Stmt) -> xml::writer::Result<()> {
+    #[allow(clippy::too_many_lines)]
+    fn let_stmt(&mut self, stmt: Stmt) -> Result<()> {
         match stmt {
             Stmt::Let(name, query @ Expr::Query { .. }) => self.query(&name, query),
-            Stmt::Let(name, value @ Expr::Value(_)) => {
-                let str = value.as_value(Context::Text);
+            Stmt::Let(name, Expr::Batch(queries)) => {
+                for query in queries {
+                    self.query(&name, query)?;
+                }
+                Ok(())
+            }
+            Stmt::Let(name, value @ Expr::Value { .. }) => {
+                let str = self.attribute(name.span(), &value)?;
                 auto_close!(
                     XmlEvent::start_element(Core::Set)
                         .attr("value", &str)
@@ -154,6 +666,24 @@ impl<W: io::Write> Transpiler<W> {
 
                 Ok(())
             }
+            Stmt::Let(name, Expr::XPath { source, path }) => {
+                let select = format!("${source}{path}");
+                let tag = match self.target {
+                    Target::Gel => Gel::Set.as_str(),
+                    Target::Jelly => Xml::Set.as_str(),
+                };
+                auto_close!(
+                    XmlEvent::start_element(tag)
+                        .ns("xog", XOG_NS)
+                        .ns("soapenv", SOAPENV_NS)
+                        .attr("var", name.as_str())
+                        .attr("select", &select)
+                        .attr("asString", "true"),
+                    self.writer
+                );
+
+                Ok(())
+            }
             Stmt::Let(name, Expr::Instance { class, args }) => {
                 self.writer.write(
                     XmlEvent::start_element(Core::New)
@@ -167,8 +697,21 @@ impl<W: io::Write> Transpiler<W> {
 
                 Ok(())
             }
+            Stmt::Let(
+                name,
+                Expr::Invoke {
+                    object,
+                    method,
+                    args,
+                },
+            ) => self.invoke(Some(&name), &object, &method, args),
             Stmt::Let(name, Expr::StaticField(Name::Dotted { parent, mut attrs })) => {
-                assert!(attrs.len() == 1);
+                if attrs.len() != 1 {
+                    return Err(self.error(
+                        attrs[1].span(),
+                        "expected a single field, as in `Class.FIELD`",
+                    ));
+                }
                 let Some(Name::Ident(attr)) = attrs.pop() else {
                     unreachable!()
                 };
@@ -208,7 +751,14 @@ impl<W: io::Write> Transpiler<W> {
                 Ok(())
             }
             Stmt::Let(name, expr) => {
-                let expr = expr.as_value(Context::Text);
+                if !expr.is_inline() {
+                    return Err(self.error(
+                        name.span(),
+                        "this can't be bound here, macros are only bound on their own at the top level",
+                    ));
+                }
+
+                let expr = self.attribute(name.span(), &expr)?;
                 auto_close!(
                     XmlEvent::start_element(Core::Set)
                         .attr("var", name.as_str())
@@ -219,11 +769,11 @@ impl<W: io::Write> Transpiler<W> {
                 Ok(())
             }
 
-            _ => unreachable!(),
+            _ => unreachable!("`transpile_node` only passes `let`s"),
         }
     }
 
-    fn for_each(&mut self, stmt: Stmt) -> xml::writer::Result<()> {
+    fn for_each(&mut self, stmt: Stmt) -> Result<()> {
         match stmt {
             Stmt::ForEach {
                 var,
@@ -249,10 +799,11 @@ impl<W: io::Write> Transpiler<W> {
             Stmt::ForEach { var, items, body } => {
                 // <core:forEach var='i' items='1, 2, 3'>
 
+                let items = self.attribute(var.span(), &items)?;
                 self.writer.write(
                     XmlEvent::start_element(Core::ForEach)
                         .attr("var", var.as_str())
-                        .attr("items", items.as_value(Context::Text).borrow()),
+                        .attr("items", items.borrow()),
                 )?;
 
                 self.transpile_vec(body)?;
@@ -260,11 +811,11 @@ impl<W: io::Write> Transpiler<W> {
                 close!(self.writer);
                 Ok(())
             }
-            _ => unreachable!(),
+            _ => unreachable!("`transpile_node` only passes `for`s"),
         }
     }
 
-    fn if_stmt(&mut self, stmt: Stmt) -> xml::writer::Result<()> {
+    fn if_stmt(&mut self, stmt: Stmt) -> Result<()> {
         match stmt {
             // if/else case,
             // make it into a core:choose.
@@ -273,11 +824,10 @@ impl<W: io::Write> Transpiler<W> {
                 body,
                 alt: Some(alt),
             } => {
+                let test = self.attribute(Span::default(), &test)?;
                 self.writer.write(XmlEvent::start_element(Core::Choose))?;
-                self.writer.write(
-                    XmlEvent::start_element(Core::When)
-                        .attr("test", test.as_value(Context::Text).borrow()),
-                )?;
+                self.writer
+                    .write(XmlEvent::start_element(Core::When).attr("test", test.borrow()))?;
 
                 self.transpile_vec(body)?;
 
@@ -297,10 +847,9 @@ impl<W: io::Write> Transpiler<W> {
                 body,
                 alt: None,
             } => {
-                self.writer.write(
-                    XmlEvent::start_element(Core::If)
-                        .attr("test", test.as_value(Context::Text).borrow()),
-                )?;
+                let test = self.attribute(Span::default(), &test)?;
+                self.writer
+                    .write(XmlEvent::start_element(Core::If).attr("test", test.borrow()))?;
 
                 self.transpile_vec(body)?;
 
@@ -308,39 +857,88 @@ impl<W: io::Write> Transpiler<W> {
                 Ok(())
             }
 
-            _ => unreachable!(),
+            _ => unreachable!("`transpile_node` only passes `if`s"),
         }
     }
 
-    fn query(&mut self, name: &Ident, expr: Expr) -> xml::writer::Result<()> {
+    fn query(&mut self, name: &Ident, expr: Expr) -> Result<()> {
         match expr {
             Expr::Query {
                 datasource,
                 r#type,
                 query,
+                text,
                 params,
+                max_rows,
+                start_row,
             } => {
                 let tag = match r#type {
                     QueryType::SELECT => Sql::Query,
                     QueryType::UPDATE | QueryType::INSERT | QueryType::DELETE => Sql::Update,
                 };
 
-                auto_close!(
-                    XmlEvent::start_element(Gel::SetDatasource)
-                        .attr("dbId", datasource.to_string().as_str()),
-                    self.writer
-                );
-
-                self.writer
-                    .write(XmlEvent::start_element(tag).attr("var", name.as_str()))?;
+                // switching datasources would leave the transaction's connection
+                if !self.in_transaction && self.target == Target::Gel {
+                    auto_close!(
+                        XmlEvent::start_element(Gel::SetDatasource)
+                            .attr("dbId", datasource.to_string().as_str()),
+                        self.writer
+                    );
+                }
 
-                self.writer
-                    .write(XmlEvent::cdata(query.to_string().as_str()))?;
+                let max_rows = max_rows
+                    .map(|rows| Ok::<_, Error>(self.attribute(name.span(), &rows)?.into_owned()))
+                    .transpose()?;
+                let start_row = start_row
+                    .map(|row| Ok::<_, Error>(self.attribute(name.span(), &row)?.into_owned()))
+                    .transpose()?;
+                let datasource = format!("${{{datasource}}}");
+
+                let mut start = XmlEvent::start_element(tag).attr("var", name.as_str());
+                if !self.in_transaction && self.target == Target::Jelly {
+                    start = start.attr("dataSource", &datasource);
+                }
+                if let Some(max_rows) = &max_rows {
+                    start = start.attr("maxRows", max_rows);
+                }
+                if let Some(start_row) = &start_row {
+                    start = start.attr("startRow", start_row);
+                }
+                self.writer.write(start)?;
 
-                for param in params {
+                let sql = match self.sql_format {
+                    SqlFormat::Compact => query.to_string(),
+                    SqlFormat::Pretty => sql::pretty(&query.to_string()),
+                    SqlFormat::Verbatim => text.to_string(),
+                };
+                self.writer.write(XmlEvent::cdata(&sql))?;
+
+                for SqlParam { value, r#type } in params {
+                    // a null needs its type to be bound, so it defaults to a varchar
+                    let r#type = match (&value, r#type) {
+                        (
+                            Expr::Value {
+                                value: Value::Nothing,
+                                ..
+                            },
+                            None,
+                        ) => Some("VARCHAR".into()),
+                        (_, r#type) => r#type,
+                    };
+                    let value = match value {
+                        Expr::Value {
+                            value: Value::Nothing,
+                            ..
+                        } => Cow::Borrowed("${null}"),
+                        ref value => self.attribute(name.span(), value)?,
+                    };
+
+                    let param = XmlEvent::start_element(Sql::Param).attr("value", &value);
                     auto_close!(
-                        XmlEvent::start_element(Sql::Param)
-                            .attr("value", &param.as_value(Context::Text)),
+                        match r#type.as_deref() {
+                            Some(r#type) => param.attr("type", r#type),
+                            None => param,
+                        },
                         self.writer
                     );
                 }
@@ -349,11 +947,43 @@ impl<W: io::Write> Transpiler<W> {
 
                 Ok(())
             }
-            _ => unreachable!(),
+            _ => unreachable!("`let_stmt` only passes queries"),
         }
     }
 
-    fn transpile_vec(&mut self, body: Vec<Stmt>) -> xml::writer::Result<()> {
+    /// Lists the parameters of the script in a comment, with their types and descriptions,
+    /// for the process designers mapping them.
+    fn document_params(&mut self, nodes: &[Spanned<Node>]) -> Result<()> {
+        let mut lines = vec![];
+        for node in nodes {
+            if let Node::Stmt(Stmt::Param {
+                name,
+                r#type,
+                description,
+            }) = &node.node
+            {
+                let mut line = format!("  {name}");
+                if let Some(r#type) = r#type {
+                    line.push_str(": ");
+                    line.push_str(r#type.as_str());
+                }
+                if let Some(description) = description {
+                    line.push_str(" - ");
+                    line.push_str(description);
+                }
+                lines.push(line);
+            }
+        }
+
+        if lines.is_empty() {
+            return Ok(());
+        }
+        let text = format!("parameters:\n{}\n", lines.join("\n"));
+        self.writer.write(XmlEvent::comment(&comment_text(&text)))?;
+        Ok(())
+    }
+
+    fn transpile_vec(&mut self, body: Vec<Stmt>) -> Result<()> {
         for stmt in body {
             self.transpile_node(stmt)?;
         }
@@ -361,9 +991,9 @@ impl<W: io::Write> Transpiler<W> {
         Ok(())
     }
 
-    fn transpile_args(&mut self, args: Vec<Expr>) -> xml::writer::Result<()> {
+    fn transpile_args(&mut self, args: Vec<Expr>) -> Result<()> {
         for arg in args {
-            let arg = arg.as_value(Context::Text);
+            let arg = self.attribute(Span::default(), &arg)?;
             auto_close!(
                 XmlEvent::start_element(Core::Arg).attr("value", arg.borrow()),
                 self.writer
@@ -373,7 +1003,44 @@ impl<W: io::Write> Transpiler<W> {
         Ok(())
     }
 
-    fn transpile_soap(&mut self, name: &Ident, soap: Expr) -> Result<(), xml::writer::Error> {
+    /// Calls `method` on `object` with a `core:invoke`, binding what it returns to `var`.
+    fn invoke(
+        &mut self,
+        var: Option<&Ident>,
+        object: &Name,
+        method: &Ident,
+        args: Vec<TypedArg>,
+    ) -> Result<()> {
+        let on = object.as_value(Context::Text);
+        let mut start = XmlEvent::start_element(Core::Invoke)
+            .attr("on", &on)
+            .attr("method", method.as_str());
+        if let Some(var) = var {
+            start = start.attr("var", var.as_str());
+        }
+        self.writer.write(start)?;
+
+        for TypedArg { value, r#type } in args {
+            let value = self.attribute(Span::default(), &value)?;
+            let mut arg = XmlEvent::start_element(Core::Arg);
+            if let Some(r#type) = &r#type {
+                arg = arg.attr("type", r#type);
+            }
+            auto_close!(arg.attr("value", &value), self.writer);
+        }
+
+        close!(self.writer);
+        Ok(())
+    }
+
+    fn transpile_soap(&mut self, name: &Ident, soap: Expr) -> Result<()> {
+        if self.target == Target::Jelly {
+            return Err(self.error(
+                Span::default(),
+                "soap requests need the soap tags of Clarity, plain jelly doesn't have them",
+            ));
+        }
+
         //       <soapenv:Body>
         //                     <NikuDataBus xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:noNamespaceSchemaLocation="../xsd/nikuxog_user.xsd">
         //                             <Header action="write" externalSource="NIKU" objectType="user" version="16.0.2.861" />
@@ -388,12 +1055,14 @@ impl<W: io::Write> Transpiler<W> {
             endpoint,
             header,
             body,
+            ..
         } = soap
         else {
-            unreachable!()
+            unreachable!("`transpile_node` only passes soap requests")
         };
 
         //   <soap:invoke endpoint="internal" var="result">
+        let endpoint = self.attribute(name.span(), &endpoint)?;
         self.writer.write(
             XmlEvent::start_element(Soap::Invoke.as_str())
                 .attr("endpoint", &endpoint)
@@ -407,8 +1076,8 @@ impl<W: io::Write> Transpiler<W> {
         //     <soapenv:Envelope xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/" xmlns:xog="http://www.niku.com/xog">
         self.writer.write(
             XmlEvent::start_element(SoapEnv::Envelope.as_str())
-                .ns("soapenv", "http://schemas.xmlsoap.org/soap/envelope/")
-                .ns("xog", "http://www.niku.com/xog"),
+                .ns("soapenv", SOAPENV_NS)
+                .ns("xog", XOG_NS),
         )?;
 
         if let Some(header) = header {
@@ -455,3 +1124,68 @@ impl<W: io::Write> Transpiler<W> {
         Ok(())
     }
 }
+
+/// Pads `text` with spaces, breaking up any `--` as it is not allowed inside of a comment.
+fn comment_text(text: &str) -> String {
+    let mut text = format!(" {text} ");
+    while text.contains("--") {
+        text = text.replace("--", "- -");
+    }
+
+    text
+}
+
+/// Formats `time` as an ISO 8601 UTC timestamp, e.g. `2024-03-01T12:30:00Z`.
+fn utc_timestamp(time: std::time::SystemTime) -> String {
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(feature = "fs")]
+impl Transpiler<Vec<u8>> {
+    /// Takes what was written so far, leaving the sink empty.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(self.writer.inner_mut())
+    }
+}
+
+impl<W: io::Write> Backend for Transpiler<W> {
+    fn transpile(&mut self, nodes: Vec<Spanned<Node>>) -> Result<()> {
+        self.write_script(nodes)
+    }
+
+    fn emit(&mut self, node: Node) -> Result<()> {
+        let restrictions = Restrictions {
+            platform: self.platform,
+            classes: self.classes.as_ref(),
+        };
+        if let Some(message) = restrictions::unsupported(&node, restrictions) {
+            return Err(self.error(Span::default(), &message));
+        }
+
+        match node {
+            Node::Stmt(stmt) => self.transpile_node(stmt),
+            Node::Expr(expr) => self.transpile_node(Stmt::Expr { expr }),
+        }
+    }
+}
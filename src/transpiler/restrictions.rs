@@ -0,0 +1,228 @@
+//! The constructs a script isn't allowed: the ones the platforms of Clarity don't run, as
+//! `SaaS` keeps scripts off the file system and only lets them use the classes of some
+//! packages, and older on-premise versions run on a Java without the classes of the newer
+//! ones, and the classes a [`ClassPolicy`] denies.
+//!
+//! This runs over the expanded script, so the classes and tags of the macros are checked
+//! too.
+
+use xml::reader::XmlEvent;
+
+use crate::{
+    gelatin::ast::{Call, Expr, Name, Node, Stmt},
+    Platform, Version,
+};
+
+use super::{
+    classes::{covers, ClassPolicy},
+    libraries::Libraries,
+};
+
+/// What a script is checked against.
+#[derive(Clone, Copy)]
+pub struct Restrictions<'a> {
+    pub platform: Option<Platform>,
+    pub classes: Option<&'a ClassPolicy>,
+}
+
+/// The packages of the classes `SaaS` lets scripts create and call, the ones under them
+/// included.
+const SAAS_PACKAGES: [&str; 8] = [
+    "java.lang",
+    "java.util",
+    "java.text",
+    "java.math",
+    "java.time",
+    "java.net",
+    "java.io",
+    "org.json",
+];
+
+/// The classes and packages of [`SAAS_PACKAGES`] `SaaS` doesn't let scripts use anyway, as
+/// they reach the file system or other processes, or look into classes.
+const SAAS_DENIED: [&str; 10] = [
+    "java.io.File",
+    "java.io.FileInputStream",
+    "java.io.FileOutputStream",
+    "java.io.FileReader",
+    "java.io.FileWriter",
+    "java.io.RandomAccessFile",
+    "java.lang.ClassLoader",
+    "java.lang.ProcessBuilder",
+    "java.lang.Runtime",
+    "java.lang.reflect",
+];
+
+/// The classes and packages Java 8 added.
+const JAVA_8: [&str; 5] = [
+    "java.time",
+    "java.util.Base64",
+    "java.util.Optional",
+    "java.util.function",
+    "java.util.stream",
+];
+
+/// The first on-premise version of Clarity running on Java 8.
+const JAVA_8_SINCE: Version = Version {
+    major: 14,
+    minor: 3,
+    patch: 0,
+};
+
+/// Why `node` isn't allowed, for the first of its constructs `restrictions` don't allow.
+pub fn unsupported(node: &Node, restrictions: Restrictions<'_>) -> Option<String> {
+    match node {
+        Node::Stmt(stmt) => self::stmt(stmt, restrictions),
+        Node::Expr(expr) => self::expr(expr, restrictions),
+    }
+}
+
+fn stmt(stmt: &Stmt, restrictions: Restrictions<'_>) -> Option<String> {
+    match stmt {
+        Stmt::WriteFile { .. } if restrictions.platform == Some(Platform::Saas) => {
+            Some("Clarity SaaS doesn't let scripts write files".to_string())
+        }
+        Stmt::Let(_, expr) | Stmt::Expr { expr } | Stmt::Out { value: expr } => {
+            self::expr(expr, restrictions)
+        }
+        Stmt::Block(body)
+        | Stmt::Whitespace(body)
+        | Stmt::Catch { body, .. }
+        | Stmt::Transaction { body, .. } => self::body(body, restrictions),
+        Stmt::ForEach {
+            items: test, body, ..
+        }
+        | Stmt::While { test, body }
+        | Stmt::WriteFile {
+            path: test, body, ..
+        } => self::expr(test, restrictions).or_else(|| self::body(body, restrictions)),
+        Stmt::If { test, body, alt } => self::expr(test, restrictions)
+            .or_else(|| self::body(body, restrictions))
+            .or_else(|| alt.as_deref().and_then(|alt| self::body(alt, restrictions))),
+        Stmt::SetProperties { props, .. } => props
+            .values()
+            .find_map(|value| self::expr(value, restrictions)),
+        Stmt::Xml(events) => xml(events, restrictions),
+        Stmt::Log { .. }
+        | Stmt::Alias { .. }
+        | Stmt::Namespace { .. }
+        | Stmt::Comment(_)
+        | Stmt::Template { .. }
+        | Stmt::Param { .. } => None,
+    }
+}
+
+fn body(body: &[Stmt], restrictions: Restrictions<'_>) -> Option<String> {
+    body.iter().find_map(|stmt| self::stmt(stmt, restrictions))
+}
+
+fn exprs(exprs: &[Expr], restrictions: Restrictions<'_>) -> Option<String> {
+    exprs.iter().find_map(|expr| self::expr(expr, restrictions))
+}
+
+fn expr(expr: &Expr, restrictions: Restrictions<'_>) -> Option<String> {
+    match expr {
+        Expr::ReadLines { .. } | Expr::Csv { .. }
+            if restrictions.platform == Some(Platform::Saas) =>
+        {
+            Some("Clarity SaaS doesn't let scripts read files".to_string())
+        }
+        Expr::Instance { class, args } => {
+            class_of(&class.to_string(), restrictions).or_else(|| exprs(args, restrictions))
+        }
+        Expr::Static(Call {
+            name: Name::Dotted { parent, .. },
+            args,
+        }) => class_of(&parent.to_string(), restrictions).or_else(|| exprs(args, restrictions)),
+        Expr::StaticField(Name::Dotted { parent, .. }) => {
+            class_of(&parent.to_string(), restrictions)
+        }
+        Expr::Call(Call { args, .. }) | Expr::Static(Call { args, .. }) => {
+            exprs(args, restrictions)
+        }
+        Expr::Invoke { args, .. } => args
+            .iter()
+            .find_map(|arg| self::expr(&arg.value, restrictions)),
+        Expr::Infix { lhs, rhs, .. } => {
+            self::expr(lhs, restrictions).or_else(|| self::expr(rhs, restrictions))
+        }
+        Expr::Named { value, .. } => self::expr(value, restrictions),
+        Expr::Dict(dict) => dict
+            .values()
+            .find_map(|value| self::expr(value, restrictions)),
+        Expr::Func { body, .. } => self::body(body, restrictions),
+        Expr::Batch(queries) => exprs(queries, restrictions),
+        Expr::Http { url, body, .. } => {
+            self::expr(url, restrictions).or_else(|| self::body(body, restrictions))
+        }
+        Expr::Soap {
+            header,
+            body,
+            on_fault,
+            ..
+        } => header
+            .iter()
+            .chain(body)
+            .find_map(|events| xml(events, restrictions))
+            .or_else(|| {
+                on_fault
+                    .as_deref()
+                    .and_then(|on_fault| self::expr(on_fault, restrictions))
+            }),
+        _ => None,
+    }
+}
+
+/// Why the platform can't run the tags of `events` that reach the file system.
+fn xml(events: &[XmlEvent], restrictions: Restrictions<'_>) -> Option<String> {
+    if restrictions.platform != Some(Platform::Saas) {
+        return None;
+    }
+
+    let file_libraries = [Libraries::File, Libraries::Ftp].map(Libraries::uri);
+    events.iter().find_map(|event| {
+        let XmlEvent::StartElement { name, .. } = event else {
+            return None;
+        };
+        let uri = name.namespace.as_deref()?;
+        let is_file = file_libraries.contains(&uri)
+            || (uri == Libraries::Core.uri() && name.local_name == "file");
+        is_file.then(|| {
+            let tag = name.prefix.as_ref().map_or_else(
+                || name.local_name.clone(),
+                |prefix| format!("{prefix}:{}", name.local_name),
+            );
+            format!("Clarity SaaS doesn't let scripts reach the file system, as `<{tag}>` does")
+        })
+    })
+}
+
+/// Why the script can't use the methods and fields of `class`.
+fn class_of(class: &str, restrictions: Restrictions<'_>) -> Option<String> {
+    if let Some(message) = restrictions
+        .classes
+        .and_then(|classes| classes.denial(class))
+    {
+        return Some(message);
+    }
+
+    let within = |package: &&str| covers(package, class);
+
+    match restrictions.platform? {
+        Platform::Saas if SAAS_DENIED.iter().any(within) => Some(format!(
+            "Clarity SaaS doesn't let scripts use `{class}`, which reaches the file system, \
+             other processes or the internals of classes"
+        )),
+        Platform::Saas if !SAAS_PACKAGES.iter().any(within) => Some(format!(
+            "Clarity SaaS only lets scripts use the classes of {}, `{class}` isn't one of them",
+            SAAS_PACKAGES.join(", ")
+        )),
+        Platform::OnPrem(Some(version)) if version < JAVA_8_SINCE && JAVA_8.iter().any(within) => {
+            Some(format!(
+                "`{class}` needs Java 8, which on-premise Clarity runs on from {JAVA_8_SINCE}, \
+                 not {version}"
+            ))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt::Write as _;
+
+use xml::{
+    common::Position,
+    namespace::Namespace,
+    reader::{ParserConfig, XmlEvent},
+};
+
+use crate::gelatin::ast::{Node, Spanned};
+
+use super::libraries::Libraries;
+
+/// Fixed tags written at the start or the end of every script, inside its root tag.
+#[derive(Debug, Clone)]
+pub enum Snippet {
+    /// Xml copied into the script.
+    Xml(Vec<XmlEvent>),
+    /// The top-level nodes of a gelatin script, their tags are written as the ones of the
+    /// script are.
+    Script(Vec<Spanned<Node>>),
+}
+
+impl Snippet {
+    /// Reads the xml `fragment`, see [`read_fragment`].
+    ///
+    /// # Errors
+    /// Returns the 0-based row and column, and the message, of the first syntax error.
+    pub fn from_xml(fragment: &str) -> Result<Self, (usize, usize, String)> {
+        read_fragment(fragment, &Namespace::empty()).map(Self::Xml)
+    }
+
+    /// The `(prefix, uri)` namespaces of the tags of the xml, which have to be declared on
+    /// the script.
+    #[must_use]
+    pub(crate) fn namespaces(&self) -> Vec<(&str, &str)> {
+        let Self::Xml(events) = self else {
+            return vec![];
+        };
+
+        let mut namespaces = vec![];
+        for event in events {
+            let XmlEvent::StartElement {
+                name, attributes, ..
+            } = event
+            else {
+                continue;
+            };
+
+            for name in std::iter::once(name).chain(attributes.iter().map(|a| &a.name)) {
+                if let (Some(prefix), Some(uri)) = (&name.prefix, &name.namespace) {
+                    if !namespaces.contains(&(prefix.as_str(), uri.as_str())) {
+                        namespaces.push((prefix.as_str(), uri.as_str()));
+                    }
+                }
+            }
+        }
+        namespaces
+    }
+
+    /// The top-level nodes of the gelatin script, none for xml.
+    #[must_use]
+    pub(crate) fn nodes(&self) -> &[Spanned<Node>] {
+        match self {
+            Self::Xml(_) => &[],
+            Self::Script(nodes) => nodes,
+        }
+    }
+}
+
+/// Reads the xml `fragment`, which can have several top-level tags and use the prefixes
+/// of the tag libraries, save for `xml`, and `namespaces` without declaring them. Comments
+/// are kept.
+///
+/// # Errors
+/// Returns the 0-based row and column, and the message, of the first syntax error.
+pub fn read_fragment(
+    fragment: &str,
+    namespaces: &Namespace,
+) -> Result<Vec<XmlEvent>, (usize, usize, String)> {
+    // readers don't let the reserved `xml` prefix be rebound
+    let libraries = Libraries::ALL.into_iter().filter(|l| *l != Libraries::Xml);
+    let mut declared = Namespace::empty();
+    for library in libraries.clone() {
+        declared.put(library.prefix(), library.uri());
+    }
+    for (prefix, uri) in namespaces {
+        declared.put(prefix, uri);
+    }
+
+    let mut wrapper = String::from("<gelatin");
+    for (prefix, uri) in &declared {
+        let _ = write!(wrapper, " xmlns:{prefix}=\"{uri}\"");
+    }
+    wrapper.push('>');
+
+    let source = format!("{wrapper}{fragment}</gelatin>");
+    let mut events = ParserConfig::new()
+        .trim_whitespace(true)
+        .ignore_comments(false)
+        .create_reader(source.as_bytes())
+        .into_iter()
+        .filter(|event| !matches!(event, Ok(XmlEvent::Whitespace(_))))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| {
+            let xml::common::TextPosition { row, column } = err.position();
+            let row = usize::try_from(row).expect("a valid usize");
+            let mut column = usize::try_from(column).expect("a valid usize");
+            if row == 0 {
+                column = column.saturating_sub(wrapper.len());
+            }
+
+            (row, column, err.msg().to_string())
+        })?;
+
+    // StartDocument and the wrapper, then their closing events
+    events.truncate(events.len() - 2);
+    events.drain(..2);
+
+    // the libraries are declared on the script, and the other namespaces on the tags
+    // using them, rather than all of them on each tag
+    for event in &mut events {
+        if let XmlEvent::StartElement {
+            name,
+            attributes,
+            namespace,
+        } = event
+        {
+            let used: Vec<Option<String>> = std::iter::once(&*name)
+                .chain(attributes.iter().map(|a| &a.name))
+                .map(|name| name.prefix.clone())
+                .collect();
+            namespace.0.retain(|prefix, uri| {
+                used.iter()
+                    .any(|used| used.as_deref() == Some(prefix.as_str()))
+                    && !libraries
+                        .clone()
+                        .any(|library| library.prefix() == prefix && library.uri() == uri)
+            });
+        }
+    }
+
+    Ok(events)
+}
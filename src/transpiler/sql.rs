@@ -0,0 +1,106 @@
+use sqlparser::{
+    dialect::GenericDialect,
+    keywords::Keyword,
+    tokenizer::{Token, Tokenizer, Whitespace},
+};
+
+/// Keywords starting a clause, each of them goes on its own line.
+const CLAUSES: [Keyword; 15] = [
+    Keyword::FROM,
+    Keyword::WHERE,
+    Keyword::GROUP,
+    Keyword::HAVING,
+    Keyword::ORDER,
+    Keyword::LIMIT,
+    Keyword::OFFSET,
+    Keyword::FETCH,
+    Keyword::UNION,
+    Keyword::EXCEPT,
+    Keyword::INTERSECT,
+    Keyword::SET,
+    Keyword::VALUES,
+    Keyword::RETURNING,
+    Keyword::JOIN,
+];
+
+/// Keywords that can start a join.
+const JOINS: [Keyword; 6] = [
+    Keyword::LEFT,
+    Keyword::RIGHT,
+    Keyword::FULL,
+    Keyword::INNER,
+    Keyword::CROSS,
+    Keyword::NATURAL,
+];
+
+/// Puts each clause of the round-tripped `sql` on its own line, subqueries are indented
+/// under the clause they are in. `sql` is returned as is if it can't be tokenized.
+pub fn pretty(sql: &str) -> String {
+    let Ok(tokens) = Tokenizer::new(&GenericDialect {}, sql).tokenize() else {
+        return sql.to_string();
+    };
+
+    let mut buff = String::with_capacity(sql.len() * 2);
+    // whether each open paren holds a subquery
+    let mut parens: Vec<bool> = Vec::new();
+    let mut prev = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let keyword = match token {
+            Token::Word(word) => Some(word.keyword),
+            _ => None,
+        };
+        let next = tokens[i + 1..]
+            .iter()
+            .find(|token| !matches!(token, Token::Whitespace(_)))
+            .and_then(|token| match token {
+                Token::Word(word) => Some(word.keyword),
+                _ => None,
+            });
+
+        match token {
+            Token::LParen => parens.push(false),
+            Token::RParen => {
+                parens.pop();
+            }
+            Token::Word(word) if word.keyword == Keyword::SELECT => {
+                if let Some(subquery) = parens.last_mut() {
+                    *subquery = true;
+                }
+            }
+            _ => {}
+        }
+
+        // function arguments like `extract(year FROM x)` stay on their line
+        let in_query = parens.last().is_none_or(|subquery| *subquery);
+        let breaks = keyword.is_some_and(|keyword| {
+            let joins =
+                JOINS.contains(&keyword) && matches!(next, Some(Keyword::JOIN | Keyword::OUTER));
+            // the `JOIN` of `LEFT JOIN` is on the line of its `LEFT`, as is the `FROM` of
+            // `DELETE FROM`
+            let clause = CLAUSES.contains(&keyword)
+                && !(keyword == Keyword::JOIN
+                    && prev.is_some_and(|prev| JOINS.contains(&prev) || prev == Keyword::OUTER))
+                && !(keyword == Keyword::FROM && prev == Some(Keyword::DELETE));
+            joins || clause
+        });
+
+        if in_query && breaks {
+            buff.truncate(buff.trim_end().len());
+            buff.push('\n');
+            buff.extend(std::iter::repeat_n("  ", parens.len()));
+        } else if matches!(token, Token::Whitespace(Whitespace::Space))
+            && buff.ends_with(|c: char| c.is_whitespace())
+        {
+            continue;
+        }
+
+        buff.push_str(&token.to_string());
+
+        if !matches!(token, Token::Whitespace(_)) {
+            prev = keyword;
+        }
+    }
+
+    buff
+}
@@ -1,5 +1,7 @@
 use xml::name::Name;
 
+use crate::gelatin::ast::LogLevel;
+
 #[derive(Debug, Clone, Copy)]
 pub enum Gel {
     /// # Example:
@@ -17,6 +19,27 @@ pub enum Gel {
     /// <gel:script .../>
     /// ```
     Script,
+    /// Sets a variable from an `XPath` expression.
+    ///
+    /// # Example:
+    /// ```xml
+    /// <gel:set var='status' select='$result//Status/@state' asString='true'/>
+    /// ```
+    Set,
+    /// Outputs its text, e.g. into the enclosing `core:file`.
+    ///
+    /// # Example:
+    /// ```xml
+    /// <gel:out>${row.name};${row.email}</gel:out>
+    /// ```
+    Out,
+    /// Declares a parameter of the script, set by the process running it.
+    ///
+    /// # Example:
+    /// ```xml
+    /// <gel:parameter var='start_date'/>
+    /// ```
+    Parameter,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -24,6 +47,41 @@ pub enum Sql {
     Query,
     Update,
     Param,
+    /// Runs the queries inside it on one connection with auto-commit off, committing at the
+    /// end and rolling back if any of them throws.
+    ///
+    /// # Example:
+    /// ```xml
+    /// <sql:transaction>
+    ///     <sql:update>...</sql:update>
+    /// </sql:transaction>
+    /// ```
+    Transaction,
+}
+
+/// The tags of the jelly `log` library, which plain jelly scripts log with.
+///
+/// # Example:
+/// ```xml
+/// <log:info>{message}</log:info>
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub enum Log {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Xml {
+    /// Sets a variable from an `XPath` expression, the `gel:set` of plain jelly.
+    ///
+    /// # Example:
+    /// ```xml
+    /// <xml:set var='status' select='$result//Status/@state' asString='true'/>
+    /// ```
+    Set,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -160,6 +218,15 @@ pub enum Core {
     While,
     //A simple tag used to preserve whitespace inside its body
     Whitespace,
+    /// The root of a plain jelly script.
+    ///
+    /// # Example:
+    /// ```xml
+    /// <core:jelly xmlns:core='jelly:core'>
+    ///
+    /// </core:jelly>
+    /// ```
+    Jelly,
 }
 
 impl From<Core> for Name<'static> {
@@ -174,6 +241,18 @@ impl From<Gel> for Name<'static> {
     }
 }
 
+impl From<Log> for Name<'static> {
+    fn from(value: Log) -> Self {
+        value.as_str().into()
+    }
+}
+
+impl From<Xml> for Name<'static> {
+    fn from(value: Xml) -> Self {
+        value.as_str().into()
+    }
+}
+
 impl From<Sql> for Name<'static> {
     fn from(value: Sql) -> Self {
         value.as_str().into()
@@ -207,6 +286,7 @@ impl Core {
             Self::UseList => "core:useList",
             Self::While => "core:while",
             Self::Whitespace => "core:whitespace",
+            Self::Jelly => "core:jelly",
         }
     }
 }
@@ -217,6 +297,39 @@ impl Gel {
             Self::Log => "gel:log",
             Self::SetDatasource => "gel:setDataSource",
             Self::Script => "gel:script",
+            Self::Set => "gel:set",
+            Self::Out => "gel:out",
+            Self::Parameter => "gel:parameter",
+        }
+    }
+}
+
+impl Log {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Debug => "log:debug",
+            Self::Info => "log:info",
+            Self::Warn => "log:warn",
+            Self::Error => "log:error",
+        }
+    }
+}
+
+impl From<&LogLevel> for Log {
+    fn from(level: &LogLevel) -> Self {
+        match level {
+            LogLevel::Debug => Self::Debug,
+            LogLevel::Info => Self::Info,
+            LogLevel::Warn => Self::Warn,
+            LogLevel::Error => Self::Error,
+        }
+    }
+}
+
+impl Xml {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Set => "xml:set",
         }
     }
 }
@@ -246,6 +359,7 @@ impl Sql {
             Self::Query => "sql:query",
             Self::Update => "sql:update",
             Self::Param => "sql:param",
+            Self::Transaction => "sql:transaction",
         }
     }
 }
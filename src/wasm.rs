@@ -0,0 +1,156 @@
+//! The bindings of the web playground, which builds the scripts written in the browser
+//! with [`compile`]. Nothing is read from files there, the scripts can't import or embed
+//! any.
+
+use clap::ValueEnum;
+use miette::{GraphicalReportHandler, GraphicalTheme};
+use wasm_bindgen::prelude::*;
+
+use crate::{compile_str, ClassPolicy, CompileOptions, Snippet};
+
+/// How [`compile`] builds a script, see [`CompileOptions`]. The provenance comment isn't
+/// written, the browser doesn't tell the time the way it needs.
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct Options(CompileOptions);
+
+// wasm_bindgen can't export const fns
+#[allow(clippy::missing_const_for_fn)]
+#[wasm_bindgen]
+impl Options {
+    #[wasm_bindgen(constructor)]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The dialect of the queries, as the `--dialect` flag of the command line.
+    ///
+    /// # Errors
+    /// Returns `Err` if `dialect` isn't one of them.
+    #[wasm_bindgen(js_name = setDialect)]
+    pub fn set_dialect(&mut self, dialect: &str) -> Result<(), JsValue> {
+        self.0.dialect = value_of(dialect)?;
+        Ok(())
+    }
+
+    /// How the sql of queries is written, as `--sql-format`.
+    ///
+    /// # Errors
+    /// Returns `Err` if `format` isn't one of them.
+    #[wasm_bindgen(js_name = setSqlFormat)]
+    pub fn set_sql_format(&mut self, format: &str) -> Result<(), JsValue> {
+        self.0.sql_format = value_of(format)?;
+        Ok(())
+    }
+
+    /// The kind of script to generate, as `--target`.
+    ///
+    /// # Errors
+    /// Returns `Err` if `target` isn't one of them.
+    #[wasm_bindgen(js_name = setTarget)]
+    pub fn set_target(&mut self, target: &str) -> Result<(), JsValue> {
+        self.0.target = value_of(target)?;
+        Ok(())
+    }
+
+    /// The Clarity the script runs on, as `--platform`.
+    ///
+    /// # Errors
+    /// Returns `Err` if `platform` isn't `saas`, `onprem` or `onprem:<version>`.
+    #[wasm_bindgen(js_name = setPlatform)]
+    pub fn set_platform(&mut self, platform: &str) -> Result<(), JsValue> {
+        self.0.platform = Some(platform.parse().map_err(|err: String| js_error(&err))?);
+        Ok(())
+    }
+
+    /// The Java classes the script is allowed to use, written as the file of
+    /// `--class-policy` is.
+    ///
+    /// # Errors
+    /// Returns `Err` if a line of `policy` isn't an entry.
+    #[wasm_bindgen(js_name = setClassPolicy)]
+    pub fn set_class_policy(&mut self, policy: &str) -> Result<(), JsValue> {
+        let policy = ClassPolicy::parse(policy)
+            .map_err(|(line, err)| js_error(&format!("line {}: {err}", line + 1)))?;
+        self.0.classes = Some(policy);
+        Ok(())
+    }
+
+    /// The xml written at the start of the script, inside its root tag.
+    ///
+    /// # Errors
+    /// Returns `Err` if `xml` isn't valid.
+    #[wasm_bindgen(js_name = setPrologue)]
+    pub fn set_prologue(&mut self, xml: &str) -> Result<(), JsValue> {
+        self.0.prologue = Some(snippet(xml)?);
+        Ok(())
+    }
+
+    /// The xml written at the end of the script, inside its root tag.
+    ///
+    /// # Errors
+    /// Returns `Err` if `xml` isn't valid.
+    #[wasm_bindgen(js_name = setEpilogue)]
+    pub fn set_epilogue(&mut self, xml: &str) -> Result<(), JsValue> {
+        self.0.epilogue = Some(snippet(xml)?);
+        Ok(())
+    }
+
+    /// Whether to write a tag per line, indented.
+    #[wasm_bindgen(js_name = setPrettify)]
+    pub fn set_prettify(&mut self, prettify: bool) {
+        self.0.layout.prettify = prettify;
+    }
+
+    /// Whether to write a comment pointing at the source line before each top-level tag.
+    #[wasm_bindgen(js_name = setSourceMap)]
+    pub fn set_source_map(&mut self, source_map: bool) {
+        self.0.source_map = source_map;
+    }
+
+    /// Whether to remove the `let`s of names that are never read.
+    #[wasm_bindgen(js_name = setEliminateDeadCode)]
+    pub fn set_eliminate_dead_code(&mut self, eliminate: bool) {
+        self.0.eliminate_dead_code = eliminate;
+    }
+
+    /// Whether to bind the method calls written several times in the body of a loop before
+    /// it.
+    #[wasm_bindgen(js_name = setHoistRepeatedExpressions)]
+    pub fn set_hoist_repeated_expressions(&mut self, hoist: bool) {
+        self.0.hoist_repeated_expressions = hoist;
+    }
+}
+
+/// Builds the GEL script of the gelatin `source`.
+///
+/// # Errors
+/// Returns an `Error` whose message is the diagnostic, drawn as the command line draws it
+/// without colors, if `source` can't be parsed or transpiled.
+#[wasm_bindgen]
+pub fn compile(source: &str, options: &Options) -> Result<String, JsValue> {
+    compile_str(source, &options.0).map_err(|report| {
+        let mut message = String::new();
+        let rendered = GraphicalReportHandler::new_themed(GraphicalTheme::unicode_nocolor())
+            .render_report(&mut message, report.as_ref());
+        if rendered.is_err() {
+            message = report.to_string();
+        }
+        js_error(&message)
+    })
+}
+
+/// The value of `T` called `name` on the command line.
+fn value_of<T: ValueEnum>(name: &str) -> Result<T, JsValue> {
+    T::from_str(name, false).map_err(|err| js_error(&err))
+}
+
+fn snippet(xml: &str) -> Result<Snippet, JsValue> {
+    Snippet::from_xml(xml)
+        .map_err(|(row, column, err)| js_error(&format!("{}:{}: {err}", row + 1, column + 1)))
+}
+
+fn js_error(message: &str) -> JsValue {
+    JsError::new(message).into()
+}
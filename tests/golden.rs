@@ -0,0 +1,15 @@
+//! Builds the scripts of `tests/golden` and compares them with the `.xml` files next to
+//! them, run with `GELATIN_BLESS=1` to write the ones that changed.
+
+use gel_lang::{testing, CompileOptions};
+
+#[test]
+fn golden_scripts() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+    let bless = std::env::var_os("GELATIN_BLESS").is_some();
+
+    let mismatches = testing::run_golden(dir, &CompileOptions::default(), bless)
+        .unwrap_or_else(|err| panic!("{err:?}"));
+    let mismatches: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n\n"));
+}
@@ -0,0 +1,66 @@
+//! Builds the inputs of the benchmarks, so a change that makes building them much slower,
+//! as in quadratic in their size, fails the tests rather than waiting for a `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use gel_lang::{
+    compile_str,
+    testing::{huge_sql, long_script, nested_dicts},
+    CompileOptions,
+};
+
+/// How long building an input may take, generous enough for unoptimized builds on slow
+/// machines.
+const BUDGET: Duration = Duration::from_secs(20);
+
+/// The stack the inputs are built on. Unoptimized builds take far more of it for each
+/// nested expression than the optimized ones the benchmarks run.
+const STACK: usize = 256 * 1024 * 1024;
+
+/// Builds `source`, returning how long the fastest of a few builds took.
+fn build(name: &str, source: &str) -> Duration {
+    let source = source.to_string();
+    let name = name.to_string();
+    std::thread::Builder::new()
+        .stack_size(STACK)
+        .spawn(move || {
+            (0..3)
+                .map(|_| {
+                    let started = Instant::now();
+                    compile_str(&source, &CompileOptions::default())
+                        .unwrap_or_else(|err| panic!("{name} doesn't build: {err:?}"));
+                    started.elapsed()
+                })
+                .min()
+                .unwrap()
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[test]
+fn large_inputs_build_in_time() {
+    let inputs = [
+        ("nested dicts", nested_dicts(64)),
+        ("long script", long_script(1000)),
+        ("huge sql", huge_sql(2000)),
+    ];
+
+    for (name, source) in &inputs {
+        let took = build(name, source);
+        assert!(took < BUDGET, "{name} took {took:?} to build");
+    }
+}
+
+#[test]
+fn building_grows_linearly() {
+    // a script four times as long takes about four times as long, a quadratic build sixteen
+    let short = build("long script", &long_script(250));
+    let long = build("long script", &long_script(1000));
+
+    assert!(
+        long < short * 8,
+        "250 statements took {short:?} and 1000 took {long:?}"
+    );
+}